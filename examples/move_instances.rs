@@ -0,0 +1,128 @@
+// std
+use std::cell::Cell;
+use std::sync::Arc;
+// pbrt
+use rs_pbrt::accelerators::bvh::{BVHAccel, SplitMethod};
+use rs_pbrt::core::geometry::{Point3f, Ray, Vector3f};
+use rs_pbrt::core::interaction::SurfaceInteraction;
+use rs_pbrt::core::pbrt::Float;
+use rs_pbrt::core::primitive::{GeometricPrimitive, Primitive, TransformedPrimitive};
+use rs_pbrt::core::scene::{Scene, TlasUpdate};
+use rs_pbrt::core::shape::Shape;
+use rs_pbrt::core::transform::{AnimatedTransform, Transform};
+use rs_pbrt::shapes::sphere::Sphere;
+
+/// Distance between the instances in the initial row.
+const SPACING: Float = 3.0;
+
+fn instance_to_world(center: &Vector3f) -> AnimatedTransform {
+    let t: Transform = Transform::translate(center);
+    AnimatedTransform::new(&t, 0.0 as Float, &t, 1.0 as Float)
+}
+
+/// Shoots a ray along the z axis through (|x|, |y|) and returns the
+/// hit point, if any.
+fn trace(scene: &Scene, x: Float, y: Float) -> Option<Point3f> {
+    let ray: Ray = Ray {
+        o: Point3f { x, y, z: -10.0 },
+        d: Vector3f {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        },
+        t_max: Cell::new(std::f32::INFINITY),
+        time: 0.0 as Float,
+        medium: None,
+        differential: None,
+    };
+    let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+    if scene.intersect(&ray, &mut isect) {
+        Some(isect.common.p)
+    } else {
+        None
+    }
+}
+
+/// Checks that a ray through the center of every instance hits its
+/// unit sphere in front.
+fn check_instances(scene: &Scene, centers: &[Vector3f]) {
+    for (i, center) in centers.iter().enumerate() {
+        match trace(scene, center.x, center.y) {
+            Some(p) if (p.z - (center.z - 1.0)).abs() < 1.0e-3 => {}
+            hit => panic!("instance {} at {:?}: unexpected hit {:?}", i, center, hit),
+        }
+    }
+}
+
+fn main() {
+    // one unit sphere in its own (bottom-level) BVH, instanced in a row
+    let sphere = Arc::new(Shape::Sphr(Sphere::new(
+        Transform::default(),
+        Transform::default(),
+        false,
+        1.0 as Float,
+        -1.0 as Float,
+        1.0 as Float,
+        360.0 as Float,
+    )));
+    let object = Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
+        sphere, None, None, None,
+    ))));
+    let blas = Arc::new(Primitive::BVH(Box::new(BVHAccel::new(
+        vec![object],
+        4,
+        SplitMethod::SAH,
+    ))));
+    let n_instances: usize = 16;
+    let mut centers: Vec<Vector3f> = (0..n_instances)
+        .map(|i| Vector3f {
+            x: i as Float * SPACING,
+            y: 0.0,
+            z: 0.0,
+        })
+        .collect();
+    let instances: Vec<Arc<Primitive>> = centers
+        .iter()
+        .map(|center| {
+            Arc::new(Primitive::Transformed(Box::new(TransformedPrimitive::new(
+                blas.clone(),
+                instance_to_world(center),
+            ))))
+        })
+        .collect();
+    let tlas = Arc::new(Primitive::BVH(Box::new(BVHAccel::new(
+        instances.clone(),
+        4,
+        SplitMethod::SAH,
+    ))));
+    let mut scene: Scene = Scene::new(tlas, Vec::new());
+    scene.set_instances(instances);
+    check_instances(&scene, &centers);
+    // a small move keeps the tree, only its bounds are refit
+    let old_center: Vector3f = centers[0];
+    centers[0].y += 2.5;
+    let update = scene.update_instance_transforms(&[(0, instance_to_world(&centers[0]))]);
+    println!("moved instance 0 by 2.5: {:?}", update);
+    assert_eq!(update, Some(TlasUpdate::Refit));
+    check_instances(&scene, &centers);
+    assert!(trace(&scene, old_center.x, old_center.y - 0.9).is_none());
+    // scattering neighbors over the row makes the old tree useless,
+    // it gets rebuilt
+    centers = (0..n_instances)
+        .map(|i| Vector3f {
+            x: ((i * 7) % n_instances) as Float * SPACING,
+            y: 0.0,
+            z: 0.0,
+        })
+        .collect();
+    let updates: Vec<(usize, AnimatedTransform)> = centers
+        .iter()
+        .enumerate()
+        .map(|(i, center)| (i, instance_to_world(center)))
+        .collect();
+    let update = scene.update_instance_transforms(&updates);
+    println!("scattered the instances: {:?}", update);
+    assert_eq!(update, Some(TlasUpdate::Rebuild));
+    check_instances(&scene, &centers);
+    println!("rays follow the instances");
+}
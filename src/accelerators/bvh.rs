@@ -1,16 +1,19 @@
 // std
+use std::collections::HashSet;
 use std::sync::Arc;
 // others
+use rayon::prelude::*;
+use smallvec::SmallVec;
 // use time::PreciseTime;
 use typed_arena::Arena;
 // pbrt
-use crate::core::geometry::{bnd3_union_bnd3f, bnd3_union_pnt3f};
+use crate::core::geometry::{bnd3_intersect_bnd3f, bnd3_union_bnd3f, bnd3_union_pnt3f};
 use crate::core::geometry::{Bounds3f, Point3f, Ray, Vector3f, XYZEnum};
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::light::Light;
 use crate::core::material::Material;
 use crate::core::paramset::ParamSet;
-use crate::core::pbrt::Float;
+use crate::core::pbrt::{clamp_t, gamma, Float};
 use crate::core::primitive::Primitive;
 
 // see bvh.h
@@ -21,8 +24,23 @@ pub enum SplitMethod {
     HLBVH,
     Middle,
     EqualCounts,
+    /// SAH with spatial splits (see **recursive_build_sbvh()**), which
+    /// may add up to |split_budget| times the number of primitives as
+    /// extra references.
+    SBVH {
+        split_budget: Float,
+    },
 }
 
+/// Number of bins along each axis to evaluate spatial splits.
+const SBVH_SPATIAL_BINS: usize = 32;
+/// Spatial splits are only tried if the children of the best object
+/// split overlap by more than this fraction of the root surface area.
+const SBVH_ALPHA: Float = 1e-5;
+/// Each motion segment is subdivided this often to find linear bounds
+/// of moving primitives.
+const MOTION_SUBSTEPS: usize = 8;
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct BVHPrimitiveInfo {
     primitive_number: usize,
@@ -73,6 +91,31 @@ struct BucketInfo {
     bounds: Bounds3f,
 }
 
+#[derive(Default, Debug, Copy, Clone)]
+struct MortonPrimitive {
+    primitive_index: usize,
+    morton_code: u32,
+}
+
+/// A node of a treelet of the HLBVH. Treelets are built in parallel,
+/// so their nodes refer to each other by index into the treelet's
+/// nodes until they are moved into the arena.
+#[derive(Default, Debug, Copy, Clone)]
+struct LBVHNode {
+    bounds: Bounds3f,
+    children: [usize; 2],
+    split_axis: u8,
+    first_prim_offset: usize,
+    n_primitives: usize,
+}
+
+#[derive(Default, Debug, Clone)]
+struct LBVHTreelet {
+    start_index: usize,
+    n_primitives: usize,
+    nodes: Vec<LBVHNode>,
+}
+
 #[derive(Debug, Default, Clone)]
 pub struct LinearBVHNode {
     bounds: Bounds3f,
@@ -84,12 +127,247 @@ pub struct LinearBVHNode {
     // pad: u8,
 }
 
+/// A node of a collapsed BVH with up to *N* children. The bounds of
+/// the children are stored as a structure of arrays, so a ray can be
+/// tested against all of them in one loop over the lanes, which the
+/// compiler turns into SIMD instructions (SSE, AVX, NEON). Unused
+/// lanes have empty bounds, which no ray hits.
+#[derive(Debug, Copy, Clone)]
+pub struct WideBVHNode<const N: usize> {
+    min_x: [Float; N],
+    min_y: [Float; N],
+    min_z: [Float; N],
+    max_x: [Float; N],
+    max_y: [Float; N],
+    max_z: [Float; N],
+    // offset of the first primitive (leaf) or of the child node (interior)
+    offset: [u32; N],
+    // zero for interior children
+    n_primitives: [u16; N],
+}
+
+impl<const N: usize> WideBVHNode<N> {
+    fn empty() -> Self {
+        WideBVHNode {
+            min_x: [Float::INFINITY; N],
+            min_y: [Float::INFINITY; N],
+            min_z: [Float::INFINITY; N],
+            max_x: [-Float::INFINITY; N],
+            max_y: [-Float::INFINITY; N],
+            max_z: [-Float::INFINITY; N],
+            offset: [0_u32; N],
+            n_primitives: [0_u16; N],
+        }
+    }
+    fn set_bounds(&mut self, lane: usize, b: &Bounds3f) {
+        self.min_x[lane] = b.p_min.x;
+        self.min_y[lane] = b.p_min.y;
+        self.min_z[lane] = b.p_min.z;
+        self.max_x[lane] = b.p_max.x;
+        self.max_y[lane] = b.p_max.y;
+        self.max_z[lane] = b.p_max.z;
+    }
+    fn bounds(&self, lane: usize) -> Bounds3f {
+        Bounds3f {
+            p_min: Point3f {
+                x: self.min_x[lane],
+                y: self.min_y[lane],
+                z: self.min_z[lane],
+            },
+            p_max: Point3f {
+                x: self.max_x[lane],
+                y: self.max_y[lane],
+                z: self.max_z[lane],
+            },
+        }
+    }
+    /// Tests the ray against the bounds of all children at once and
+    /// returns the entry distance for each child, or infinity if the
+    /// child is missed (see **Bounds3::intersect_p()**).
+    fn intersect_children(
+        &self,
+        o: &[Float; 3],
+        inv_dir: &[Float; 3],
+        dir_is_neg: &[u8; 3],
+        ray_t_max: Float,
+    ) -> [Float; N] {
+        let (x0, x1) = if dir_is_neg[0] == 1_u8 {
+            (&self.max_x, &self.min_x)
+        } else {
+            (&self.min_x, &self.max_x)
+        };
+        let (y0, y1) = if dir_is_neg[1] == 1_u8 {
+            (&self.max_y, &self.min_y)
+        } else {
+            (&self.min_y, &self.max_y)
+        };
+        let (z0, z1) = if dir_is_neg[2] == 1_u8 {
+            (&self.max_z, &self.min_z)
+        } else {
+            (&self.min_z, &self.max_z)
+        };
+        // ensure robust bounds intersection like for binary nodes
+        let robust: Float = 1.0 as Float + 2.0 as Float * gamma(3_i32);
+        // clip the ray segment against the slabs of all lanes, the
+        // comparisons ignore NaNs (from 0 * infinity)
+        let mut t_min: [Float; N] = [0.0 as Float; N];
+        let mut t_max: [Float; N] = [ray_t_max; N];
+        for (axis, (b0, b1)) in [(x0, x1), (y0, y1), (z0, z1)].iter().enumerate() {
+            for i in 0..N {
+                let t0: Float = (b0[i] - o[axis]) * inv_dir[axis];
+                let t1: Float = (b1[i] - o[axis]) * inv_dir[axis] * robust;
+                t_min[i] = if t0 > t_min[i] { t0 } else { t_min[i] };
+                t_max[i] = if t1 < t_max[i] { t1 } else { t_max[i] };
+            }
+        }
+        let mut t_hit: [Float; N] = [Float::INFINITY; N];
+        for i in 0..N {
+            if t_min[i] <= t_max[i] {
+                t_hit[i] = t_min[i];
+            }
+        }
+        t_hit
+    }
+}
+
+/// The nodes of a BVH collapsed to 4 or 8 children per node.
+pub enum WideBVHNodes {
+    None,
+    Four(Vec<WideBVHNode<4>>),
+    Eight(Vec<WideBVHNode<8>>),
+}
+
+/// A child of a wide node waiting for traversal.
+#[derive(Debug, Default, Copy, Clone)]
+struct WideTodo {
+    offset: u32,
+    n_primitives: u16,
+    t_min: Float,
+}
+
+/// Node bounds for motion blur. The time range in which primitives
+/// move is split into segments, and every node stores its bounds at
+/// the start and at the end of each segment. In between, the bounds
+/// are interpolated linearly by the time of the ray, which keeps them
+/// tight for fast moving primitives.
+pub struct BVHMotionBounds {
+    start_time: Float,
+    end_time: Float,
+    n_segments: usize,
+    // *n_segments* pairs of bounds per node
+    bounds: Vec<[Bounds3f; 2]>,
+}
+
+impl BVHMotionBounds {
+    fn new(
+        nodes: &[LinearBVHNode],
+        primitives: &[Arc<Primitive>],
+        start_time: Float,
+        end_time: Float,
+        n_segments: usize,
+    ) -> Self {
+        let mut motion_bounds: BVHMotionBounds = BVHMotionBounds {
+            start_time,
+            end_time,
+            n_segments,
+            bounds: vec![[Bounds3f::default(); 2]; nodes.len() * n_segments],
+        };
+        let segment_times: Vec<Float> = (0..=n_segments)
+            .map(|i| lerp_float(i as Float / n_segments as Float, start_time, end_time))
+            .collect();
+        // leaves bound their primitives
+        motion_bounds
+            .bounds
+            .par_chunks_mut(n_segments)
+            .zip(nodes.par_iter())
+            .for_each(|(node_bounds, node)| {
+                if node.n_primitives == 0 {
+                    return;
+                }
+                let first: usize = node.offset as usize;
+                let last: usize = first + node.n_primitives as usize;
+                for primitive in &primitives[first..last] {
+                    if primitive.time_range().is_none() {
+                        let b: Bounds3f = primitive.world_bound();
+                        for pair in node_bounds.iter_mut() {
+                            *pair = union_motion_bounds(pair, &[b, b]);
+                        }
+                        continue;
+                    }
+                    for (segment, pair) in node_bounds.iter_mut().enumerate() {
+                        let b: [Bounds3f; 2] = linear_motion_bounds(
+                            primitive,
+                            segment_times[segment],
+                            segment_times[segment + 1],
+                        );
+                        *pair = union_motion_bounds(pair, &b);
+                    }
+                }
+            });
+        // interior nodes bound their children, which come later
+        for i in (0..nodes.len()).rev() {
+            if nodes[i].n_primitives > 0 {
+                continue;
+            }
+            let child1: usize = i + 1;
+            let child2: usize = nodes[i].offset as usize;
+            for segment in 0..n_segments {
+                motion_bounds.bounds[i * n_segments + segment] = union_motion_bounds(
+                    &motion_bounds.bounds[child1 * n_segments + segment],
+                    &motion_bounds.bounds[child2 * n_segments + segment],
+                );
+            }
+        }
+        motion_bounds
+    }
+    /// The segment containing |time| and the interpolation parameter
+    /// within it.
+    fn segment(&self, time: Float) -> (usize, Float) {
+        let duration: Float = self.end_time - self.start_time;
+        if duration <= 0.0 as Float {
+            return (0, 0.0 as Float);
+        }
+        let u: Float = clamp_t(
+            (time - self.start_time) / duration,
+            0.0 as Float,
+            1.0 as Float,
+        ) * self.n_segments as Float;
+        let segment: usize = (u as usize).min(self.n_segments - 1);
+        (segment, u - segment as Float)
+    }
+    fn node_bounds(&self, node: usize, segment: usize, t: Float) -> Bounds3f {
+        let pair: &[Bounds3f; 2] = &self.bounds[node * self.n_segments + segment];
+        Bounds3f {
+            p_min: pair[0].p_min + (pair[1].p_min - pair[0].p_min) * t,
+            p_max: pair[0].p_max + (pair[1].p_max - pair[0].p_max) * t,
+        }
+    }
+    /// Bounds of the root node from |time0| to |time1|, the hull of
+    /// the interpolated bounds at the ends of all parts of segments
+    /// in between.
+    fn motion_bound(&self, time0: Float, time1: Float) -> Bounds3f {
+        let (segment0, t0) = self.segment(time0);
+        let (segment1, t1) = self.segment(time1);
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for segment in segment0..=segment1 {
+            let t_start: Float = if segment == segment0 { t0 } else { 0.0 };
+            let t_end: Float = if segment == segment1 { t1 } else { 1.0 };
+            bounds = bnd3_union_bnd3f(&bounds, &self.node_bounds(0, segment, t_start));
+            bounds = bnd3_union_bnd3f(&bounds, &self.node_bounds(0, segment, t_end));
+        }
+        bounds
+    }
+}
+
 // BVHAccel -> Aggregate -> Primitive
 pub struct BVHAccel {
     max_prims_in_node: usize,
     split_method: SplitMethod,
     pub primitives: Vec<Arc<Primitive>>,
     pub nodes: Vec<LinearBVHNode>,
+    pub wide_nodes: WideBVHNodes,
+    pub motion_bounds: Option<BVHMotionBounds>,
+    motion_segments: usize,
 }
 
 impl BVHAccel {
@@ -103,32 +381,62 @@ impl BVHAccel {
             split_method: split_method.clone(),
             primitives: p,
             nodes: Vec::new(),
+            wide_nodes: WideBVHNodes::None,
+            motion_bounds: None,
+            motion_segments: 1,
         });
         let num_prims = bvh.primitives.len();
         if num_prims == 0_usize {
             let unwrapped = Arc::try_unwrap(bvh);
             return unwrapped.ok().unwrap();
         }
-        let mut primitive_info = vec![BVHPrimitiveInfo::default(); num_prims];
-        for (i, item) in primitive_info.iter_mut().enumerate().take(num_prims) {
-            let world_bound = bvh.primitives[i].world_bound();
-            *item = BVHPrimitiveInfo::new(i, world_bound);
-        }
-        // TODO: if (splitMethod == SplitMethod::HLBVH)
+        let mut primitive_info: Vec<BVHPrimitiveInfo> = bvh
+            .primitives
+            .par_iter()
+            .enumerate()
+            .map(|(i, primitive)| BVHPrimitiveInfo::new(i, primitive.world_bound()))
+            .collect();
         let arena: Arena<BVHBuildNode> = Arena::with_capacity(1024 * 1024);
         let mut total_nodes: usize = 0;
         let mut ordered_prims: Vec<Arc<Primitive>> = Vec::with_capacity(num_prims);
         // println!("BVHAccel::recursive_build(..., {}, ...)", num_prims);
         // let start = PreciseTime::now();
-        let root = BVHAccel::recursive_build(
-            bvh, // instead of self
-            &arena,
-            &mut primitive_info,
-            0,
-            num_prims,
-            &mut total_nodes,
-            &mut ordered_prims,
-        );
+        let root = if let SplitMethod::HLBVH = split_method {
+            BVHAccel::hlbvh_build(
+                &bvh,
+                &arena,
+                &primitive_info,
+                &mut total_nodes,
+                &mut ordered_prims,
+            )
+        } else if let SplitMethod::SBVH { split_budget } = split_method {
+            // references to primitives get split, so they can't be
+            // partitioned in place
+            let mut root_bounds: Bounds3f = Bounds3f::default();
+            for item in &primitive_info {
+                root_bounds = bnd3_union_bnd3f(&root_bounds, &item.bounds);
+            }
+            let mut budget: usize = (split_budget.max(0.0 as Float) * num_prims as Float) as usize;
+            BVHAccel::recursive_build_sbvh(
+                &bvh,
+                &arena,
+                primitive_info,
+                root_bounds.surface_area(),
+                &mut budget,
+                &mut total_nodes,
+                &mut ordered_prims,
+            )
+        } else {
+            BVHAccel::recursive_build(
+                bvh.clone(), // instead of self
+                &arena,
+                &mut primitive_info,
+                0,
+                num_prims,
+                &mut total_nodes,
+                &mut ordered_prims,
+            )
+        };
         // let end = PreciseTime::now();
         // println!("{} seconds for building BVH ...", start.to(end));
         // flatten first
@@ -146,6 +454,9 @@ impl BVHAccel {
             split_method,
             primitives: ordered_prims,
             nodes,
+            wide_nodes: WideBVHNodes::None,
+            motion_bounds: None,
+            motion_segments: 1,
         });
         let unwrapped = Arc::try_unwrap(bvh_ordered_prims);
         unwrapped.ok().unwrap()
@@ -161,6 +472,10 @@ impl BVHAccel {
             split_method = SplitMethod::Middle;
         } else if split_method_name == "equal" {
             split_method = SplitMethod::EqualCounts;
+        } else if split_method_name == "sbvh" {
+            split_method = SplitMethod::SBVH {
+                split_budget: ps.find_one_float("splitbudget", 0.5 as Float),
+            };
         } else {
             println!(
                 "WARNING: BVH split method \"{}\" unknown.  Using \"sah\".",
@@ -169,11 +484,207 @@ impl BVHAccel {
             split_method = SplitMethod::SAH;
         }
         let max_prims_in_node: i32 = ps.find_one_int("maxnodeprims", 4);
-        Primitive::BVH(Box::new(BVHAccel::new(
-            prims,
-            max_prims_in_node as usize,
-            split_method,
-        )))
+        let width: i32 = ps.find_one_int("width", 2);
+        let motion_segments: i32 = ps.find_one_int("motionsegments", 4);
+        let mut bvh: BVHAccel = BVHAccel::new(prims, max_prims_in_node as usize, split_method);
+        if motion_segments > 1 {
+            bvh.init_motion_bounds(motion_segments as usize);
+        }
+        if (width == 4 || width == 8) && bvh.motion_bounds.is_some() {
+            println!(
+                "WARNING: BVH width {} not supported for moving primitives.  Using 2.",
+                width
+            );
+        } else if width == 4 || width == 8 {
+            bvh.collapse(width as usize);
+        } else if width != 2 {
+            println!(
+                "WARNING: BVH width {} not supported (2, 4, or 8).  Using 2.",
+                width
+            );
+        }
+        Primitive::BVH(Box::new(bvh))
+    }
+    /// Adds node bounds for |n_segments| time segments if any of the
+    /// primitives move.
+    pub fn init_motion_bounds(&mut self, n_segments: usize) {
+        self.motion_segments = n_segments;
+        self.motion_bounds = None;
+        if self.nodes.is_empty() {
+            return;
+        }
+        let mut time_range: Option<(Float, Float)> = None;
+        for primitive in &self.primitives {
+            if let Some((t0, t1)) = primitive.time_range() {
+                time_range = Some(match time_range {
+                    Some((start_time, end_time)) => (start_time.min(t0), end_time.max(t1)),
+                    None => (t0, t1),
+                });
+            }
+        }
+        if let Some((start_time, end_time)) = time_range {
+            self.motion_bounds = Some(BVHMotionBounds::new(
+                &self.nodes,
+                &self.primitives,
+                start_time,
+                end_time,
+                n_segments,
+            ));
+        }
+    }
+    /// Updates the bounds of all nodes after primitives moved (see
+    /// **replace_primitive()**), keeping the tree as it is. This is
+    /// much cheaper than a rebuild, but the tree gets worse the more
+    /// the primitives move (see **sah_cost()**).
+    pub fn refit(&mut self) {
+        let primitives: &[Arc<Primitive>] = &self.primitives;
+        match self.wide_nodes {
+            WideBVHNodes::Four(ref mut wide_nodes) => refit_wide(wide_nodes, primitives),
+            WideBVHNodes::Eight(ref mut wide_nodes) => refit_wide(wide_nodes, primitives),
+            WideBVHNodes::None => {
+                // children come after their parents
+                for i in (0..self.nodes.len()).rev() {
+                    let node: &LinearBVHNode = &self.nodes[i];
+                    let bounds: Bounds3f = if node.n_primitives > 0 {
+                        primitives_bound(primitives, node.offset as usize, node.n_primitives)
+                    } else {
+                        bnd3_union_bnd3f(
+                            &self.nodes[i + 1].bounds,
+                            &self.nodes[node.offset as usize].bounds,
+                        )
+                    };
+                    self.nodes[i].bounds = bounds;
+                }
+            }
+        }
+        if self.motion_segments > 1 {
+            self.init_motion_bounds(self.motion_segments);
+        }
+    }
+    /// Builds the tree again from the current primitives, with the
+    /// same split method, width, and motion segments.
+    pub fn rebuild(&mut self) {
+        let width: usize = match self.wide_nodes {
+            WideBVHNodes::Four(_) => 4,
+            WideBVHNodes::Eight(_) => 8,
+            WideBVHNodes::None => 2,
+        };
+        // spatial splits may reference primitives more than once
+        let mut seen: HashSet<*const Primitive> = HashSet::new();
+        let primitives: Vec<Arc<Primitive>> = self
+            .primitives
+            .iter()
+            .filter(|primitive| seen.insert(Arc::as_ptr(primitive)))
+            .cloned()
+            .collect();
+        let mut bvh: BVHAccel = BVHAccel::new(
+            primitives,
+            self.max_prims_in_node,
+            self.split_method.clone(),
+        );
+        if self.motion_segments > 1 {
+            bvh.init_motion_bounds(self.motion_segments);
+        }
+        if width > 2 && bvh.motion_bounds.is_none() {
+            bvh.collapse(width);
+        }
+        *self = bvh;
+    }
+    /// Replaces the primitive at |index| (in the order of
+    /// *primitives*). Call **refit()** or **rebuild()** afterwards.
+    pub fn replace_primitive(&mut self, index: usize, primitive: Arc<Primitive>) {
+        self.primitives[index] = primitive;
+    }
+    /// The expected cost of tracing a ray with the surface area
+    /// heuristic, relative to intersecting a single primitive.
+    pub fn sah_cost(&self) -> Float {
+        match self.wide_nodes {
+            WideBVHNodes::Four(ref wide_nodes) => wide_sah_cost(wide_nodes),
+            WideBVHNodes::Eight(ref wide_nodes) => wide_sah_cost(wide_nodes),
+            WideBVHNodes::None => {
+                if self.nodes.is_empty() {
+                    return 0.0 as Float;
+                }
+                let root_area: Float = self.nodes[0].bounds.surface_area();
+                let mut cost: Float = 0.0 as Float;
+                for node in &self.nodes {
+                    let n: Float = if node.n_primitives > 0 {
+                        node.n_primitives as Float
+                    } else {
+                        1.0 as Float
+                    };
+                    cost += n * node.bounds.surface_area();
+                }
+                safe_cost_ratio(cost, root_area)
+            }
+        }
+    }
+    /// Collapses the binary BVH into one with |width| (4 or 8)
+    /// children per node, which replaces the binary nodes.
+    pub fn collapse(&mut self, width: usize) {
+        if self.nodes.is_empty() {
+            return;
+        }
+        if width == 4 {
+            let mut wide_nodes: Vec<WideBVHNode<4>> = Vec::new();
+            self.collapse_node(0, &mut wide_nodes);
+            self.wide_nodes = WideBVHNodes::Four(wide_nodes);
+        } else {
+            let mut wide_nodes: Vec<WideBVHNode<8>> = Vec::new();
+            self.collapse_node(0, &mut wide_nodes);
+            self.wide_nodes = WideBVHNodes::Eight(wide_nodes);
+        }
+        self.nodes = Vec::new();
+    }
+    fn collapse_node<const N: usize>(
+        &self,
+        node_index: usize,
+        wide_nodes: &mut Vec<WideBVHNode<N>>,
+    ) -> usize {
+        // pull up grandchildren of the interior child with the
+        // largest surface area until all lanes are used
+        let mut children: Vec<usize> = Vec::with_capacity(N);
+        let node: &LinearBVHNode = &self.nodes[node_index];
+        if node.n_primitives > 0 {
+            children.push(node_index);
+        } else {
+            children.push(node_index + 1);
+            children.push(node.offset as usize);
+        }
+        while children.len() < N {
+            let mut best: Option<usize> = None;
+            let mut best_area: Float = -1.0 as Float;
+            for (i, child) in children.iter().enumerate() {
+                let child_node: &LinearBVHNode = &self.nodes[*child];
+                if child_node.n_primitives == 0 && child_node.bounds.surface_area() > best_area {
+                    best = Some(i);
+                    best_area = child_node.bounds.surface_area();
+                }
+            }
+            if let Some(i) = best {
+                let child: usize = children.swap_remove(i);
+                children.push(child + 1);
+                children.push(self.nodes[child].offset as usize);
+            } else {
+                break;
+            }
+        }
+        // allocate the wide node before its children (root first)
+        let my_offset: usize = wide_nodes.len();
+        wide_nodes.push(WideBVHNode::empty());
+        let mut wide_node: WideBVHNode<N> = WideBVHNode::empty();
+        for (lane, child) in children.iter().enumerate() {
+            let child_node: &LinearBVHNode = &self.nodes[*child];
+            wide_node.set_bounds(lane, &child_node.bounds);
+            if child_node.n_primitives > 0 {
+                wide_node.offset[lane] = child_node.offset as u32;
+                wide_node.n_primitives[lane] = child_node.n_primitives;
+            } else {
+                wide_node.offset[lane] = self.collapse_node(*child, wide_nodes) as u32;
+            }
+        }
+        wide_nodes[my_offset] = wide_node;
+        my_offset
     }
     pub fn recursive_build<'a>(
         bvh: Arc<BVHAccel>,
@@ -234,7 +745,7 @@ impl BVHAccel {
                     SplitMethod::EqualCounts => {
                         // TODO
                     }
-                    SplitMethod::SAH | SplitMethod::HLBVH => {
+                    SplitMethod::SAH | SplitMethod::HLBVH | SplitMethod::SBVH { .. } => {
                         if n_primitives <= 2 {
                             mid = (start + end) / 2;
                             if start != end - 1
@@ -355,6 +866,514 @@ impl BVHAccel {
         }
         node
     }
+    /// Builds a spatial split BVH (see "Spatial Splits in Bounding
+    /// Volume Hierarchies", Stich et al. 2009). Where the children of
+    /// the best SAH object split overlap, splitting space instead is
+    /// evaluated too: references straddling the split plane go to
+    /// both children, with their bounds clipped to the geometry on
+    /// each side. Every duplicated reference is taken from |budget|.
+    fn recursive_build_sbvh<'a>(
+        bvh: &BVHAccel,
+        arena: &'a Arena<BVHBuildNode<'a>>,
+        refs: Vec<BVHPrimitiveInfo>,
+        root_area: Float,
+        budget: &mut usize,
+        total_nodes: &mut usize,
+        ordered_prims: &mut Vec<Arc<Primitive>>,
+    ) -> &'a BVHBuildNode<'a> {
+        let node: &mut BVHBuildNode<'a> = arena.alloc(BVHBuildNode::default());
+        *total_nodes += 1_usize;
+        // compute bounds of all references in BVH node
+        let mut bounds: Bounds3f = Bounds3f::default();
+        let mut centroid_bounds: Bounds3f = Bounds3f::default();
+        for item in &refs {
+            bounds = bnd3_union_bnd3f(&bounds, &item.bounds);
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &item.centroid);
+        }
+        let n_primitives: usize = refs.len();
+        let area: Float = bounds.surface_area();
+        // find the best object split
+        let mut object_cost: Float = Float::INFINITY;
+        let mut object_split: Option<(u8, usize)> = None;
+        let mut overlap: Float = 0.0 as Float;
+        let dim: u8 = centroid_bounds.maximum_extent();
+        let dim_i: XYZEnum = xyz_enum(dim);
+        let n_buckets: usize = 12;
+        if n_primitives > 1 && centroid_bounds.p_max[dim_i] > centroid_bounds.p_min[dim_i] {
+            let mut buckets: [BucketInfo; 12] = [BucketInfo::default(); 12];
+            for item in &refs {
+                let b: usize = bucket_index(&centroid_bounds, &item.centroid, dim_i, n_buckets);
+                buckets[b].count += 1;
+                buckets[b].bounds = bnd3_union_bnd3f(&buckets[b].bounds, &item.bounds);
+            }
+            for i in 0..(n_buckets - 1) {
+                let mut b0: Bounds3f = Bounds3f::default();
+                let mut b1: Bounds3f = Bounds3f::default();
+                let mut count0: usize = 0;
+                let mut count1: usize = 0;
+                for item in buckets.iter().take(i + 1) {
+                    b0 = bnd3_union_bnd3f(&b0, &item.bounds);
+                    count0 += item.count;
+                }
+                for item in buckets.iter().skip(i + 1) {
+                    b1 = bnd3_union_bnd3f(&b1, &item.bounds);
+                    count1 += item.count;
+                }
+                if count0 == 0 || count1 == 0 {
+                    continue;
+                }
+                let cost: Float = 1.0
+                    + (count0 as Float * b0.surface_area() + count1 as Float * b1.surface_area())
+                        / area;
+                if cost < object_cost {
+                    object_cost = cost;
+                    object_split = Some((dim, i));
+                    overlap = surface_area_or_zero(&bnd3_intersect_bnd3f(&b0, &b1));
+                }
+            }
+        }
+        // find the best spatial split if the object split overlaps
+        let mut spatial_cost: Float = Float::INFINITY;
+        let mut spatial_split: Option<(usize, Float)> = None;
+        if n_primitives > 1 && *budget > 0 && overlap / root_area > SBVH_ALPHA {
+            for axis in 0..3 {
+                let axis_i: XYZEnum = xyz_enum(axis as u8);
+                let lo: Float = bounds.p_min[axis_i];
+                let extent: Float = bounds.p_max[axis_i] - lo;
+                if extent <= 0.0 as Float {
+                    continue;
+                }
+                let bin_width: Float = extent / SBVH_SPATIAL_BINS as Float;
+                let mut bin_bounds: [Bounds3f; SBVH_SPATIAL_BINS] =
+                    [Bounds3f::default(); SBVH_SPATIAL_BINS];
+                let mut entries: [usize; SBVH_SPATIAL_BINS] = [0; SBVH_SPATIAL_BINS];
+                let mut exits: [usize; SBVH_SPATIAL_BINS] = [0; SBVH_SPATIAL_BINS];
+                // scratch space for clipping references
+                let mut planes: [Float; SBVH_SPATIAL_BINS] = [0.0 as Float; SBVH_SPATIAL_BINS];
+                let mut clipped: [Bounds3f; SBVH_SPATIAL_BINS] =
+                    [Bounds3f::default(); SBVH_SPATIAL_BINS];
+                for item in &refs {
+                    let first: usize = spatial_bin(item.bounds.p_min[axis_i], lo, bin_width);
+                    let last: usize = spatial_bin(item.bounds.p_max[axis_i], lo, bin_width);
+                    entries[first] += 1;
+                    exits[last] += 1;
+                    if first == last {
+                        bin_bounds[first] = bnd3_union_bnd3f(&bin_bounds[first], &item.bounds);
+                        continue;
+                    }
+                    // clip the reference to the slab of each bin
+                    for (i, plane) in planes.iter_mut().enumerate().take(last - first) {
+                        *plane = lo + (first + i + 1) as Float * bin_width;
+                    }
+                    bvh.primitives[item.primitive_number].split_world_bounds(
+                        &item.bounds,
+                        axis,
+                        &planes[..(last - first)],
+                        &mut clipped,
+                    );
+                    for (bin, b) in bin_bounds[first..=last].iter_mut().zip(clipped.iter()) {
+                        if is_valid(b) {
+                            *bin = bnd3_union_bnd3f(bin, b);
+                        }
+                    }
+                }
+                // sweep from the right to get the cost of each plane
+                let mut right_bounds: [Bounds3f; SBVH_SPATIAL_BINS] =
+                    [Bounds3f::default(); SBVH_SPATIAL_BINS];
+                let mut b1: Bounds3f = Bounds3f::default();
+                for i in (1..SBVH_SPATIAL_BINS).rev() {
+                    b1 = bnd3_union_bnd3f(&b1, &bin_bounds[i]);
+                    right_bounds[i] = b1;
+                }
+                let mut b0: Bounds3f = Bounds3f::default();
+                let mut count0: usize = 0;
+                let mut count1: usize = n_primitives;
+                for i in 0..(SBVH_SPATIAL_BINS - 1) {
+                    b0 = bnd3_union_bnd3f(&b0, &bin_bounds[i]);
+                    count0 += entries[i];
+                    count1 -= exits[i];
+                    if count0 == 0 || count1 == 0 {
+                        continue;
+                    }
+                    let cost: Float = 1.0
+                        + (count0 as Float * b0.surface_area()
+                            + count1 as Float * right_bounds[i + 1].surface_area())
+                            / area;
+                    if cost < spatial_cost {
+                        spatial_cost = cost;
+                        spatial_split = Some((axis, lo + (i + 1) as Float * bin_width));
+                    }
+                }
+            }
+        }
+        // either create leaf or split references
+        let leaf_cost: Float = n_primitives as Float;
+        let min_cost: Float = object_cost.min(spatial_cost);
+        let mut children: Option<(u8, Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>)> = None;
+        if n_primitives > bvh.max_prims_in_node || min_cost < leaf_cost {
+            if spatial_cost < object_cost {
+                if let Some((axis, position)) = spatial_split {
+                    children = BVHAccel::spatial_partition(bvh, &refs, axis, position, budget);
+                }
+            }
+            if children.is_none() {
+                if let Some((dim, split_bucket)) = object_split {
+                    let dim_i: XYZEnum = xyz_enum(dim);
+                    let (left, right): (Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>) =
+                        refs.iter().partition(|item| {
+                            bucket_index(&centroid_bounds, &item.centroid, dim_i, n_buckets)
+                                <= split_bucket
+                        });
+                    children = Some((dim, left, right));
+                }
+            }
+        }
+        if let Some((axis, left, right)) = children {
+            drop(refs);
+            let c0 = BVHAccel::recursive_build_sbvh(
+                bvh,
+                arena,
+                left,
+                root_area,
+                budget,
+                total_nodes,
+                ordered_prims,
+            );
+            let c1 = BVHAccel::recursive_build_sbvh(
+                bvh,
+                arena,
+                right,
+                root_area,
+                budget,
+                total_nodes,
+                ordered_prims,
+            );
+            node.init_interior(axis, c0, c1);
+        } else {
+            // create leaf _BVHBuildNode_
+            let first_prim_offset: usize = ordered_prims.len();
+            for item in &refs {
+                ordered_prims.push(bvh.primitives[item.primitive_number].clone());
+            }
+            node.init_leaf(first_prim_offset, n_primitives, &bounds);
+        }
+        node
+    }
+    /// Splits the references at |position| along |axis|, duplicating
+    /// (and clipping) the ones straddling the plane. Returns *None* if
+    /// that would exceed the |budget| or leave one side empty.
+    fn spatial_partition(
+        bvh: &BVHAccel,
+        refs: &[BVHPrimitiveInfo],
+        axis: usize,
+        position: Float,
+        budget: &mut usize,
+    ) -> Option<(u8, Vec<BVHPrimitiveInfo>, Vec<BVHPrimitiveInfo>)> {
+        let axis_i: XYZEnum = xyz_enum(axis as u8);
+        let mut left: Vec<BVHPrimitiveInfo> = Vec::with_capacity(refs.len());
+        let mut right: Vec<BVHPrimitiveInfo> = Vec::with_capacity(refs.len());
+        let mut duplicates: usize = 0;
+        for item in refs {
+            if item.bounds.p_max[axis_i] <= position {
+                left.push(*item);
+            } else if item.bounds.p_min[axis_i] >= position {
+                right.push(*item);
+            } else {
+                let primitive: &Arc<Primitive> = &bvh.primitives[item.primitive_number];
+                let b0: Bounds3f = primitive.clipped_world_bound(&slab_bounds(
+                    &item.bounds,
+                    axis_i,
+                    item.bounds.p_min[axis_i],
+                    position,
+                ));
+                let b1: Bounds3f = primitive.clipped_world_bound(&slab_bounds(
+                    &item.bounds,
+                    axis_i,
+                    position,
+                    item.bounds.p_max[axis_i],
+                ));
+                match (is_valid(&b0), is_valid(&b1)) {
+                    (true, true) => {
+                        left.push(BVHPrimitiveInfo::new(item.primitive_number, b0));
+                        right.push(BVHPrimitiveInfo::new(item.primitive_number, b1));
+                        duplicates += 1;
+                    }
+                    (true, false) => left.push(BVHPrimitiveInfo::new(item.primitive_number, b0)),
+                    (false, true) => right.push(BVHPrimitiveInfo::new(item.primitive_number, b1)),
+                    // numerically empty on both sides, keep it as is
+                    (false, false) => left.push(*item),
+                }
+            }
+        }
+        if duplicates > *budget || left.is_empty() || right.is_empty() {
+            return None;
+        }
+        *budget -= duplicates;
+        Some((axis as u8, left, right))
+    }
+    /// Builds the BVH from the Morton codes of the primitive
+    /// centroids: primitives sorted by their codes are grouped into
+    /// treelets by the high bits, which are built in parallel from the
+    /// remaining bits. The SAH then combines the treelets.
+    fn hlbvh_build<'a>(
+        bvh: &BVHAccel,
+        arena: &'a Arena<BVHBuildNode<'a>>,
+        primitive_info: &[BVHPrimitiveInfo],
+        total_nodes: &mut usize,
+        ordered_prims: &mut Vec<Arc<Primitive>>,
+    ) -> &'a BVHBuildNode<'a> {
+        // compute bounding box of all primitive centroids
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for item in primitive_info {
+            bounds = bnd3_union_pnt3f(&bounds, &item.centroid);
+        }
+        // compute Morton indices of primitives
+        let morton_bits: u32 = 10;
+        let morton_scale: Float = (1 << morton_bits) as Float;
+        let mut morton_prims: Vec<MortonPrimitive> = primitive_info
+            .par_iter()
+            .map(|item| {
+                let centroid_offset: Vector3f = bounds.offset(&item.centroid);
+                MortonPrimitive {
+                    primitive_index: item.primitive_number,
+                    morton_code: encode_morton_3(&(centroid_offset * morton_scale)),
+                }
+            })
+            .collect();
+        // radix sort primitive Morton indices
+        radix_sort(&mut morton_prims);
+        // create LBVH treelets at bottom of BVH
+
+        // find intervals of primitives for each treelet
+        let mut treelets_to_build: Vec<LBVHTreelet> = Vec::new();
+        let mut start: usize = 0;
+        for end in 1..=morton_prims.len() {
+            let mask: u32 = 0b0011_1111_1111_1100_0000_0000_0000_0000;
+            if end == morton_prims.len()
+                || (morton_prims[start].morton_code & mask)
+                    != (morton_prims[end].morton_code & mask)
+            {
+                // add entry to _treelets_to_build_ for this treelet
+                let n_primitives: usize = end - start;
+                treelets_to_build.push(LBVHTreelet {
+                    start_index: start,
+                    n_primitives,
+                    nodes: Vec::with_capacity(2 * n_primitives - 1),
+                });
+                start = end;
+            }
+        }
+        // create LBVHs for treelets in parallel
+        let first_bit_index: i32 = 29 - 12;
+        treelets_to_build.par_iter_mut().for_each(|treelet| {
+            let morton_slice: &[MortonPrimitive] =
+                &morton_prims[treelet.start_index..(treelet.start_index + treelet.n_primitives)];
+            BVHAccel::emit_lbvh(
+                bvh,
+                &mut treelet.nodes,
+                primitive_info,
+                morton_slice,
+                treelet.start_index,
+                first_bit_index,
+            );
+        });
+        // the leaves of the treelets reference the primitives in
+        // Morton order
+        ordered_prims.extend(
+            morton_prims
+                .iter()
+                .map(|item| bvh.primitives[item.primitive_index].clone()),
+        );
+        // create and return SAH BVH from LBVH treelets
+        let mut finished_treelets: Vec<&'a BVHBuildNode<'a>> =
+            Vec::with_capacity(treelets_to_build.len());
+        for treelet in &treelets_to_build {
+            *total_nodes += treelet.nodes.len();
+            finished_treelets.push(BVHAccel::treelet_to_build_nodes(arena, &treelet.nodes, 0));
+        }
+        let n_treelets: usize = finished_treelets.len();
+        BVHAccel::build_upper_sah(arena, &mut finished_treelets, 0, n_treelets, total_nodes)
+    }
+    /// Appends the nodes of the treelet for |morton_prims| to |nodes|
+    /// (depth first) and returns the index of its root. Leaf offsets
+    /// are relative to the Morton order of all primitives, where
+    /// |morton_prims| starts at |prims_offset|.
+    fn emit_lbvh(
+        bvh: &BVHAccel,
+        nodes: &mut Vec<LBVHNode>,
+        primitive_info: &[BVHPrimitiveInfo],
+        morton_prims: &[MortonPrimitive],
+        prims_offset: usize,
+        bit_index: i32,
+    ) -> usize {
+        let n_primitives: usize = morton_prims.len();
+        assert!(n_primitives > 0);
+        if bit_index == -1 || n_primitives < bvh.max_prims_in_node {
+            // create and return leaf node of LBVH treelet
+            let mut bounds: Bounds3f = Bounds3f::default();
+            for item in morton_prims {
+                bounds = bnd3_union_bnd3f(&bounds, &primitive_info[item.primitive_index].bounds);
+            }
+            nodes.push(LBVHNode {
+                bounds,
+                first_prim_offset: prims_offset,
+                n_primitives,
+                ..Default::default()
+            });
+            nodes.len() - 1
+        } else {
+            let mask: u32 = 1 << bit_index;
+            // advance to next subtree level if there's no LBVH split for this bit
+            if (morton_prims[0].morton_code & mask)
+                == (morton_prims[n_primitives - 1].morton_code & mask)
+            {
+                return BVHAccel::emit_lbvh(
+                    bvh,
+                    nodes,
+                    primitive_info,
+                    morton_prims,
+                    prims_offset,
+                    bit_index - 1,
+                );
+            }
+            // find LBVH split point for this dimension
+            let split_offset: usize =
+                morton_prims.partition_point(|item| item.morton_code & mask == 0);
+            // create and return interior LBVH node
+            nodes.push(LBVHNode::default());
+            let node: usize = nodes.len() - 1;
+            let c0: usize = BVHAccel::emit_lbvh(
+                bvh,
+                nodes,
+                primitive_info,
+                &morton_prims[..split_offset],
+                prims_offset,
+                bit_index - 1,
+            );
+            let c1: usize = BVHAccel::emit_lbvh(
+                bvh,
+                nodes,
+                primitive_info,
+                &morton_prims[split_offset..],
+                prims_offset + split_offset,
+                bit_index - 1,
+            );
+            let axis: u8 = (bit_index % 3) as u8;
+            nodes[node] = LBVHNode {
+                bounds: bnd3_union_bnd3f(&nodes[c0].bounds, &nodes[c1].bounds),
+                children: [c0, c1],
+                split_axis: axis,
+                ..Default::default()
+            };
+            node
+        }
+    }
+    fn treelet_to_build_nodes<'a>(
+        arena: &'a Arena<BVHBuildNode<'a>>,
+        nodes: &[LBVHNode],
+        index: usize,
+    ) -> &'a BVHBuildNode<'a> {
+        let lbvh_node: &LBVHNode = &nodes[index];
+        let node: &mut BVHBuildNode<'a> = arena.alloc(BVHBuildNode::default());
+        if lbvh_node.n_primitives > 0 {
+            node.init_leaf(
+                lbvh_node.first_prim_offset,
+                lbvh_node.n_primitives,
+                &lbvh_node.bounds,
+            );
+        } else {
+            let c0 = BVHAccel::treelet_to_build_nodes(arena, nodes, lbvh_node.children[0]);
+            let c1 = BVHAccel::treelet_to_build_nodes(arena, nodes, lbvh_node.children[1]);
+            node.init_interior(lbvh_node.split_axis, c0, c1);
+        }
+        node
+    }
+    fn build_upper_sah<'a>(
+        arena: &'a Arena<BVHBuildNode<'a>>,
+        treelet_roots: &mut [&'a BVHBuildNode<'a>],
+        start: usize,
+        end: usize,
+        total_nodes: &mut usize,
+    ) -> &'a BVHBuildNode<'a> {
+        assert!(start < end);
+        let n_nodes: usize = end - start;
+        if n_nodes == 1 {
+            return treelet_roots[start];
+        }
+        *total_nodes += 1_usize;
+        let node: &mut BVHBuildNode<'a> = arena.alloc(BVHBuildNode::default());
+        // compute bounds of all nodes under this HLBVH node
+        let mut bounds: Bounds3f = Bounds3f::default();
+        let mut centroid_bounds: Bounds3f = Bounds3f::default();
+        for root in treelet_roots.iter().take(end).skip(start) {
+            bounds = bnd3_union_bnd3f(&bounds, &root.bounds);
+            let centroid: Point3f = (root.bounds.p_min + root.bounds.p_max) * 0.5 as Float;
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &centroid);
+        }
+        let dim: u8 = centroid_bounds.maximum_extent();
+        let dim_i: XYZEnum = xyz_enum(dim);
+        let mut mid: usize = (start + end) / 2;
+        // treelets with the same centroid are split in the middle
+        if centroid_bounds.p_max[dim_i] > centroid_bounds.p_min[dim_i] {
+            // allocate _BucketInfo_ for SAH partition buckets
+            let n_buckets: usize = 12;
+            let mut buckets: [BucketInfo; 12] = [BucketInfo::default(); 12];
+            let bucket = |root: &BVHBuildNode| -> usize {
+                let centroid: Point3f = (root.bounds.p_min + root.bounds.p_max) * 0.5 as Float;
+                bucket_index(&centroid_bounds, &centroid, dim_i, n_buckets)
+            };
+            // initialize _BucketInfo_ for HLBVH SAH partition buckets
+            for root in treelet_roots.iter().take(end).skip(start) {
+                let b: usize = bucket(root);
+                buckets[b].count += 1;
+                buckets[b].bounds = bnd3_union_bnd3f(&buckets[b].bounds, &root.bounds);
+            }
+            // compute costs for splitting after each bucket
+            let mut min_cost: Float = Float::INFINITY;
+            let mut min_cost_split_bucket: usize = 0;
+            for i in 0..(n_buckets - 1) {
+                let mut b0: Bounds3f = Bounds3f::default();
+                let mut b1: Bounds3f = Bounds3f::default();
+                let mut count0: usize = 0;
+                let mut count1: usize = 0;
+                for item in buckets.iter().take(i + 1) {
+                    b0 = bnd3_union_bnd3f(&b0, &item.bounds);
+                    count0 += item.count;
+                }
+                for item in buckets.iter().skip(i + 1) {
+                    b1 = bnd3_union_bnd3f(&b1, &item.bounds);
+                    count1 += item.count;
+                }
+                if count0 == 0 || count1 == 0 {
+                    continue;
+                }
+                let cost: Float = 0.125
+                    + (count0 as Float * b0.surface_area() + count1 as Float * b1.surface_area())
+                        / bounds.surface_area();
+                if cost < min_cost {
+                    min_cost = cost;
+                    min_cost_split_bucket = i;
+                }
+            }
+            // split nodes and create interior HLBVH SAH node
+            let (left, right): (Vec<&'a BVHBuildNode<'a>>, Vec<&'a BVHBuildNode<'a>>) =
+                treelet_roots[start..end]
+                    .iter()
+                    .partition(|root| bucket(root) <= min_cost_split_bucket);
+            mid = start + left.len();
+            for (slot, root) in treelet_roots[start..end]
+                .iter_mut()
+                .zip(left.into_iter().chain(right))
+            {
+                *slot = root;
+            }
+        }
+        assert!(mid > start && mid < end);
+        let c0 = BVHAccel::build_upper_sah(arena, treelet_roots, start, mid, total_nodes);
+        let c1 = BVHAccel::build_upper_sah(arena, treelet_roots, mid, end, total_nodes);
+        node.init_interior(dim, c0, c1);
+        node
+    }
     pub fn flatten_bvh_tree(
         node: &BVHBuildNode,
         nodes: &mut Vec<LinearBVHNode>,
@@ -392,13 +1411,40 @@ impl BVHAccel {
     }
     // Primitive
     pub fn world_bound(&self) -> Bounds3f {
-        if !self.nodes.is_empty() {
-            self.nodes[0].bounds
+        match self.wide_nodes {
+            WideBVHNodes::Four(ref wide_nodes) => wide_world_bound(wide_nodes),
+            WideBVHNodes::Eight(ref wide_nodes) => wide_world_bound(wide_nodes),
+            WideBVHNodes::None => {
+                if !self.nodes.is_empty() {
+                    self.nodes[0].bounds
+                } else {
+                    Bounds3f::default()
+                }
+            }
+        }
+    }
+    pub fn time_range(&self) -> Option<(Float, Float)> {
+        self.motion_bounds
+            .as_ref()
+            .map(|motion_bounds| (motion_bounds.start_time, motion_bounds.end_time))
+    }
+    pub fn motion_bound(&self, time0: Float, time1: Float) -> Bounds3f {
+        if let Some(ref motion_bounds) = self.motion_bounds {
+            motion_bounds.motion_bound(time0, time1)
         } else {
-            Bounds3f::default()
+            self.world_bound()
         }
     }
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
+        match self.wide_nodes {
+            WideBVHNodes::Four(ref wide_nodes) => {
+                return self.intersect_wide(wide_nodes, ray, Some(isect));
+            }
+            WideBVHNodes::Eight(ref wide_nodes) => {
+                return self.intersect_wide(wide_nodes, ray, Some(isect));
+            }
+            WideBVHNodes::None => {}
+        }
         if self.nodes.is_empty() {
             return false;
         }
@@ -414,14 +1460,25 @@ impl BVHAccel {
             (inv_dir.y < 0.0) as u8,
             (inv_dir.z < 0.0) as u8,
         ];
+        let motion: Option<(&BVHMotionBounds, usize, Float)> =
+            self.motion_bounds.as_ref().map(|motion_bounds| {
+                let (segment, t) = motion_bounds.segment(ray.time);
+                (motion_bounds, segment, t)
+            });
         // follow ray through BVH nodes to find primitive intersections
         let mut to_visit_offset: u32 = 0;
         let mut current_node_index: u32 = 0;
         let mut nodes_to_visit: [u32; 64] = [0_u32; 64];
         loop {
             let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
-            // check ray against BVH node
-            if node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg) {
+            // check ray against BVH node (at the time of the ray)
+            let hit_bounds: bool = match motion {
+                Some((motion_bounds, segment, t)) => motion_bounds
+                    .node_bounds(current_node_index as usize, segment, t)
+                    .intersect_p(ray, &inv_dir, &dir_is_neg),
+                None => node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg),
+            };
+            if hit_bounds {
                 if node.n_primitives > 0 {
                     // intersect ray with primitives in leaf BVH node
                     for i in 0..node.n_primitives {
@@ -461,6 +1518,15 @@ impl BVHAccel {
         hit
     }
     pub fn intersect_p(&self, ray: &Ray) -> bool {
+        match self.wide_nodes {
+            WideBVHNodes::Four(ref wide_nodes) => {
+                return self.intersect_wide(wide_nodes, ray, None);
+            }
+            WideBVHNodes::Eight(ref wide_nodes) => {
+                return self.intersect_wide(wide_nodes, ray, None);
+            }
+            WideBVHNodes::None => {}
+        }
         if self.nodes.is_empty() {
             return false;
         }
@@ -475,12 +1541,23 @@ impl BVHAccel {
             (inv_dir.y < 0.0) as u8,
             (inv_dir.z < 0.0) as u8,
         ];
+        let motion: Option<(&BVHMotionBounds, usize, Float)> =
+            self.motion_bounds.as_ref().map(|motion_bounds| {
+                let (segment, t) = motion_bounds.segment(ray.time);
+                (motion_bounds, segment, t)
+            });
         let mut to_visit_offset: u32 = 0;
         let mut current_node_index: u32 = 0;
         let mut nodes_to_visit: [u32; 64] = [0_u32; 64];
         loop {
             let node: &LinearBVHNode = &self.nodes[current_node_index as usize];
-            if node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg) {
+            let hit_bounds: bool = match motion {
+                Some((motion_bounds, segment, t)) => motion_bounds
+                    .node_bounds(current_node_index as usize, segment, t)
+                    .intersect_p(ray, &inv_dir, &dir_is_neg),
+                None => node.bounds.intersect_p(ray, &inv_dir, &dir_is_neg),
+            };
+            if hit_bounds {
                 // process BVH node _node_ for traversal
                 if node.n_primitives > 0 {
                     for i in 0..node.n_primitives {
@@ -512,6 +1589,67 @@ impl BVHAccel {
         }
         false
     }
+    /// Traverses the wide nodes nearest child first. Without |isect|
+    /// any hit ends the traversal (shadow rays).
+    fn intersect_wide<const N: usize>(
+        &self,
+        wide_nodes: &[WideBVHNode<N>],
+        ray: &Ray,
+        mut isect: Option<&mut SurfaceInteraction>,
+    ) -> bool {
+        let mut hit: bool = false;
+        let o: [Float; 3] = [ray.o.x, ray.o.y, ray.o.z];
+        let inv_dir: [Float; 3] = [1.0 / ray.d.x, 1.0 / ray.d.y, 1.0 / ray.d.z];
+        let dir_is_neg: [u8; 3] = [
+            (inv_dir[0] < 0.0) as u8,
+            (inv_dir[1] < 0.0) as u8,
+            (inv_dir[2] < 0.0) as u8,
+        ];
+        let mut to_visit: SmallVec<[WideTodo; 64]> = SmallVec::new();
+        to_visit.push(WideTodo::default());
+        while let Some(todo) = to_visit.pop() {
+            // skip children behind the closest hit so far
+            if todo.t_min > ray.t_max.get() {
+                continue;
+            }
+            if todo.n_primitives > 0 {
+                // intersect ray with primitives in leaf
+                let first: usize = todo.offset as usize;
+                for primitive in &self.primitives[first..first + todo.n_primitives as usize] {
+                    if let Some(ref mut isect) = isect {
+                        if primitive.intersect(ray, isect) {
+                            hit = true;
+                        }
+                    } else if primitive.intersect_p(ray) {
+                        return true;
+                    }
+                }
+                continue;
+            }
+            let node: &WideBVHNode<N> = &wide_nodes[todo.offset as usize];
+            let t_hit: [Float; N] =
+                node.intersect_children(&o, &inv_dir, &dir_is_neg, ray.t_max.get());
+            // push the children hit far to near, so the nearest is
+            // visited first
+            let first: usize = to_visit.len();
+            for (lane, t_min) in t_hit.iter().enumerate() {
+                if *t_min == Float::INFINITY {
+                    continue;
+                }
+                to_visit.push(WideTodo {
+                    offset: node.offset[lane],
+                    n_primitives: node.n_primitives[lane],
+                    t_min: *t_min,
+                });
+                let mut i: usize = to_visit.len() - 1;
+                while i > first && to_visit[i - 1].t_min < to_visit[i].t_min {
+                    to_visit.swap(i - 1, i);
+                    i -= 1;
+                }
+            }
+        }
+        hit
+    }
     pub fn get_material(&self) -> Option<Arc<Material>> {
         None
     }
@@ -519,3 +1657,228 @@ impl BVHAccel {
         None
     }
 }
+
+/// Linear bounds of |primitive| from |time0| to |time1|: the bounds
+/// at both times, grown until the bounds interpolated in between
+/// contain the bounds of the primitive while it moves over each of
+/// *MOTION_SUBSTEPS* parts of the time range.
+fn linear_motion_bounds(primitive: &Primitive, time0: Float, time1: Float) -> [Bounds3f; 2] {
+    let b0: Bounds3f = primitive.motion_bound(time0, time0);
+    let b1: Bounds3f = primitive.motion_bound(time1, time1);
+    // by how much to grow the bounds on each side
+    let mut grow_min: Point3f = Point3f::default();
+    let mut grow_max: Point3f = Point3f::default();
+    for step in 0..MOTION_SUBSTEPS {
+        let s0: Float = step as Float / MOTION_SUBSTEPS as Float;
+        let s1: Float = (step + 1) as Float / MOTION_SUBSTEPS as Float;
+        let b: Bounds3f =
+            primitive.motion_bound(lerp_float(s0, time0, time1), lerp_float(s1, time0, time1));
+        for axis in [XYZEnum::X, XYZEnum::Y, XYZEnum::Z] {
+            // the interpolated bounds are linear over the part
+            let lo: Float = lerp_float(s0, b0.p_min[axis], b1.p_min[axis]).max(lerp_float(
+                s1,
+                b0.p_min[axis],
+                b1.p_min[axis],
+            ));
+            let hi: Float = lerp_float(s0, b0.p_max[axis], b1.p_max[axis]).min(lerp_float(
+                s1,
+                b0.p_max[axis],
+                b1.p_max[axis],
+            ));
+            grow_min[axis] = grow_min[axis].max(lo - b.p_min[axis]);
+            grow_max[axis] = grow_max[axis].max(b.p_max[axis] - hi);
+        }
+    }
+    // cover rounding errors of the transformation and interpolation
+    for axis in [XYZEnum::X, XYZEnum::Y, XYZEnum::Z] {
+        grow_min[axis] += gamma(3_i32) * b0.p_min[axis].abs().max(b1.p_min[axis].abs());
+        grow_max[axis] += gamma(3_i32) * b0.p_max[axis].abs().max(b1.p_max[axis].abs());
+    }
+    let grow_min: Vector3f = Vector3f::from(grow_min);
+    let grow_max: Vector3f = Vector3f::from(grow_max);
+    [
+        Bounds3f {
+            p_min: b0.p_min - grow_min,
+            p_max: b0.p_max + grow_max,
+        },
+        Bounds3f {
+            p_min: b1.p_min - grow_min,
+            p_max: b1.p_max + grow_max,
+        },
+    ]
+}
+
+/// Linear bounds containing both linear bounds |b1| and |b2|.
+fn union_motion_bounds(b1: &[Bounds3f; 2], b2: &[Bounds3f; 2]) -> [Bounds3f; 2] {
+    [
+        bnd3_union_bnd3f(&b1[0], &b2[0]),
+        bnd3_union_bnd3f(&b1[1], &b2[1]),
+    ]
+}
+
+/// Interpolates like **node_bounds()**, which is exact for *a == b*.
+fn lerp_float(t: Float, a: Float, b: Float) -> Float {
+    a + (b - a) * t
+}
+
+fn xyz_enum(dim: u8) -> XYZEnum {
+    match dim {
+        0 => XYZEnum::X,
+        1 => XYZEnum::Y,
+        _ => XYZEnum::Z,
+    }
+}
+
+fn bucket_index(centroid_bounds: &Bounds3f, p: &Point3f, dim: XYZEnum, n_buckets: usize) -> usize {
+    let b: usize = (n_buckets as Float * centroid_bounds.offset(p)[dim]) as usize;
+    b.min(n_buckets - 1)
+}
+
+// see pbrt.h (pbrt-v3)
+
+fn left_shift_3(x: u32) -> u32 {
+    let mut x: u32 = x;
+    if x == (1 << 10) {
+        x -= 1;
+    }
+    x = (x | (x << 16)) & 0b0000_0011_0000_0000_0000_0000_1111_1111;
+    x = (x | (x << 8)) & 0b0000_0011_0000_0000_1111_0000_0000_1111;
+    x = (x | (x << 4)) & 0b0000_0011_0000_1100_0011_0000_1100_0011;
+    x = (x | (x << 2)) & 0b0000_1001_0010_0100_1001_0010_0100_1001;
+    x
+}
+
+fn encode_morton_3(v: &Vector3f) -> u32 {
+    assert!(v.x >= 0.0 as Float);
+    assert!(v.y >= 0.0 as Float);
+    assert!(v.z >= 0.0 as Float);
+    (left_shift_3(v.z as u32) << 2) | (left_shift_3(v.y as u32) << 1) | left_shift_3(v.x as u32)
+}
+
+fn radix_sort(v: &mut Vec<MortonPrimitive>) {
+    let mut temp_vector: Vec<MortonPrimitive> = vec![MortonPrimitive::default(); v.len()];
+    const BITS_PER_PASS: u32 = 6;
+    const N_BITS: u32 = 30;
+    const N_PASSES: u32 = N_BITS / BITS_PER_PASS;
+    const N_BUCKETS: usize = 1 << BITS_PER_PASS;
+    for pass in 0..N_PASSES {
+        // perform one pass of radix sort, sorting _BITS_PER_PASS_ bits
+        let low_bit: u32 = pass * BITS_PER_PASS;
+        // set in and out vector references for radix sort pass
+        let (v_in, v_out): (&Vec<MortonPrimitive>, &mut Vec<MortonPrimitive>) = if pass & 1 == 1 {
+            (&temp_vector, v)
+        } else {
+            (v, &mut temp_vector)
+        };
+        // count number of zero bits in array for current radix sort bit
+        let bit_mask: u32 = (1 << BITS_PER_PASS) - 1;
+        let mut bucket_count: [usize; N_BUCKETS] = [0; N_BUCKETS];
+        for mp in v_in.iter() {
+            let bucket: usize = ((mp.morton_code >> low_bit) & bit_mask) as usize;
+            bucket_count[bucket] += 1;
+        }
+        // compute starting index in output array for each bucket
+        let mut out_index: [usize; N_BUCKETS] = [0; N_BUCKETS];
+        for i in 1..N_BUCKETS {
+            out_index[i] = out_index[i - 1] + bucket_count[i - 1];
+        }
+        // store sorted values in output array
+        for mp in v_in.iter() {
+            let bucket: usize = ((mp.morton_code >> low_bit) & bit_mask) as usize;
+            v_out[out_index[bucket]] = *mp;
+            out_index[bucket] += 1;
+        }
+    }
+    // copy final result from _temp_vector_, if needed
+    if N_PASSES & 1 == 1 {
+        std::mem::swap(v, &mut temp_vector);
+    }
+}
+
+fn spatial_bin(x: Float, lo: Float, bin_width: Float) -> usize {
+    let b: Float = ((x - lo) / bin_width).max(0.0 as Float);
+    (b as usize).min(SBVH_SPATIAL_BINS - 1)
+}
+
+/// |b| restricted to the slab between |lo| and |hi| along |axis|.
+fn slab_bounds(b: &Bounds3f, axis: XYZEnum, lo: Float, hi: Float) -> Bounds3f {
+    let mut slab: Bounds3f = *b;
+    slab.p_min[axis] = slab.p_min[axis].max(lo);
+    slab.p_max[axis] = slab.p_max[axis].min(hi);
+    slab
+}
+
+fn is_valid(b: &Bounds3f) -> bool {
+    b.p_min.x <= b.p_max.x && b.p_min.y <= b.p_max.y && b.p_min.z <= b.p_max.z
+}
+
+fn surface_area_or_zero(b: &Bounds3f) -> Float {
+    if is_valid(b) {
+        b.surface_area()
+    } else {
+        0.0 as Float
+    }
+}
+
+fn primitives_bound(primitives: &[Arc<Primitive>], offset: usize, n_primitives: u16) -> Bounds3f {
+    let mut bounds: Bounds3f = Bounds3f::default();
+    for primitive in &primitives[offset..(offset + n_primitives as usize)] {
+        bounds = bnd3_union_bnd3f(&bounds, &primitive.world_bound());
+    }
+    bounds
+}
+
+fn safe_cost_ratio(cost: Float, root_area: Float) -> Float {
+    if root_area > 0.0 as Float {
+        cost / root_area
+    } else {
+        0.0 as Float
+    }
+}
+
+fn refit_wide<const N: usize>(wide_nodes: &mut [WideBVHNode<N>], primitives: &[Arc<Primitive>]) {
+    // children come after their parents
+    for i in (0..wide_nodes.len()).rev() {
+        for lane in 0..N {
+            let offset: usize = wide_nodes[i].offset[lane] as usize;
+            let n_primitives: u16 = wide_nodes[i].n_primitives[lane];
+            let bounds: Bounds3f = if n_primitives > 0 {
+                primitives_bound(primitives, offset, n_primitives)
+            } else if offset > 0 {
+                wide_world_bound(&wide_nodes[offset..])
+            } else {
+                // unused lane (the root is nobody's child)
+                continue;
+            };
+            wide_nodes[i].set_bounds(lane, &bounds);
+        }
+    }
+}
+
+fn wide_sah_cost<const N: usize>(wide_nodes: &[WideBVHNode<N>]) -> Float {
+    if wide_nodes.is_empty() {
+        return 0.0 as Float;
+    }
+    let mut cost: Float = wide_world_bound(wide_nodes).surface_area();
+    for wide_node in wide_nodes {
+        for lane in 0..N {
+            if wide_node.n_primitives[lane] > 0 {
+                cost +=
+                    wide_node.n_primitives[lane] as Float * wide_node.bounds(lane).surface_area();
+            } else if wide_node.offset[lane] > 0 {
+                cost += wide_node.bounds(lane).surface_area();
+            }
+        }
+    }
+    safe_cost_ratio(cost, wide_world_bound(wide_nodes).surface_area())
+}
+
+fn wide_world_bound<const N: usize>(wide_nodes: &[WideBVHNode<N>]) -> Bounds3f {
+    let mut bounds: Bounds3f = Bounds3f::default();
+    for lane in 0..N {
+        if wide_nodes[0].min_x[lane] <= wide_nodes[0].max_x[lane] {
+            bounds = bnd3_union_bnd3f(&bounds, &wide_nodes[0].bounds(lane));
+        }
+    }
+    bounds
+}
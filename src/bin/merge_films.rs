@@ -0,0 +1,73 @@
+// command line options
+use clap::Parser;
+// std
+use std::path::PathBuf;
+// pbrt
+use rs_pbrt::core::geometry::Bounds2i;
+use rs_pbrt::core::imageio::{write_image_layers, ImageFileFormat};
+use rs_pbrt::core::pbrt::Float;
+use rs_pbrt::core::rawfilm::{merge_raw_films, RawFilm};
+
+pub const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// Merge raw films (see the "rawfilename" parameter of the Film) of
+/// the same scene into one image.
+#[derive(clap::Parser, Debug)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// output image (png, pfm, hdr, or exr)
+    #[arg(short = 'o', long = "output")]
+    output: String,
+    /// bit depth of PNG images (8 or 16)
+    #[arg(long = "bitdepth", default_value = "8")]
+    bitdepth: i32,
+    /// raw films to merge
+    #[arg(required = true)]
+    inputs: Vec<PathBuf>,
+}
+
+fn main() {
+    let git_describe = option_env!("GIT_DESCRIBE").unwrap_or("unknown");
+    println!("merge_films version {} ({})", VERSION, git_describe);
+    println!();
+    // handle command line options
+    let args = Args::parse();
+    let mut films: Vec<RawFilm> = Vec::with_capacity(args.inputs.len());
+    for input in args.inputs.iter() {
+        let filename: String = input.to_string_lossy().to_string();
+        match RawFilm::read(&filename) {
+            Ok(film) => {
+                println!(
+                    "Reading raw film {:?} with bounds {:?}",
+                    filename, film.pixel_bounds
+                );
+                films.push(film);
+            }
+            Err(err) => {
+                println!("ERROR: Unable to read raw film {:?}: {}", filename, err);
+                std::process::exit(1);
+            }
+        }
+    }
+    let format: ImageFileFormat = match ImageFileFormat::from_filename(&args.output, args.bitdepth)
+    {
+        Some(format) => format,
+        None => {
+            println!(
+                "ERROR: Can't determine image file type from suffix of filename {:?}",
+                args.output
+            );
+            std::process::exit(1);
+        }
+    };
+    let merged: Option<(Bounds2i, Vec<Float>)> = merge_raw_films(&films);
+    if let Some((bounds, rgb)) = merged {
+        println!("Writing image {:?} with bounds {:?}", args.output, bounds);
+        if let Err(err) = write_image_layers(&args.output, format, &rgb, &[], &bounds) {
+            println!("ERROR: Unable to write image {:?}: {}", args.output, err);
+            std::process::exit(1);
+        }
+    } else {
+        std::process::exit(1);
+    }
+}
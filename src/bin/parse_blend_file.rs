@@ -295,6 +295,7 @@ impl SceneDescriptionBuilder {
             uv,
             alpha.clone(),
             alpha.clone(),
+            Vec::new(),
         ));
         self.meshes.push(triangle_mesh);
         self.triangle_colors.push(triangle_colors);
@@ -529,6 +530,7 @@ impl RenderOptions {
                             index,
                             bump_map: None,
                             remap_roughness: true,
+                            dispersion: None,
                         })));
                         shapes.push(cylinder.clone());
                         shape_materials.push(glass.clone());
@@ -709,6 +711,7 @@ impl RenderOptions {
                             index,
                             bump_map: None,
                             remap_roughness: true,
+                            dispersion: None,
                         })));
                         shapes.push(disk.clone());
                         shape_materials.push(glass.clone());
@@ -889,6 +892,7 @@ impl RenderOptions {
                             index,
                             bump_map: None,
                             remap_roughness: true,
+                            dispersion: None,
                         })));
                         shapes.push(sphere.clone());
                         shape_materials.push(glass.clone());
@@ -1079,6 +1083,7 @@ impl RenderOptions {
                             index,
                             bump_map: None,
                             remap_roughness: true,
+                            dispersion: None,
                         })));
                         for _i in 0..triangles.len() {
                             shape_materials.push(glass.clone());
@@ -1624,6 +1629,7 @@ fn make_integrator(
                         pixel_bounds,
                         rr_threshold,
                         light_strategy,
+                        false,
                     ),
                 )));
                 some_integrator = Some(integrator);
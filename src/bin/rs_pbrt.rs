@@ -24,9 +24,14 @@ use rs_pbrt::core::api::{
     pbrt_world_begin,
 };
 use rs_pbrt::core::api::{ApiState, BsdfState};
+use rs_pbrt::core::checkpoint::{extend_scene_hash, scene_hash};
+use rs_pbrt::core::checkpoint::{CheckpointKey, CheckpointSettings};
+use rs_pbrt::core::distributed::DistributedMode;
 use rs_pbrt::core::geometry::{Normal3f, Point2f, Point3f, Vector3f};
+use rs_pbrt::core::integrator::{AdaptiveSettings, ProgressiveSettings, RenderBudget};
 use rs_pbrt::core::paramset::ParamSet;
 use rs_pbrt::core::pbrt::{Float, Spectrum};
+use rs_pbrt::core::spectrum::SpectrumType;
 use rs_pbrt::core::transform::Transform;
 // std
 use std::env;
@@ -52,7 +57,7 @@ struct Args {
     /// Specify an image crop window <x0 x1 y0 y1>
     #[structopt(long, default_value = "1.0")]
     cropy1: f32,
-    /// ao, directlighting, whitted, path, bdpt, mlt, sppm, volpath
+    /// ao, directlighting, whitted, path, guidedpath, bdpt, mlt, sppm, volpath
     #[structopt(short = 'i', long = "integrator")]
     integrator: Option<String>,
     /// use specified number of threads for rendering
@@ -61,6 +66,45 @@ struct Args {
     /// pixel samples
     #[structopt(short = 's', long = "samples", default_value = "0")]
     samples: u32,
+    /// render progressively in passes of <n> pixel samples
+    #[structopt(long = "progressive", default_value = "0")]
+    progressive: u32,
+    /// write an intermediate image every <n> seconds (progressive)
+    #[structopt(long = "snapshot-seconds", default_value = "0.0")]
+    snapshot_seconds: f32,
+    /// write an intermediate image every <n> passes (progressive)
+    #[structopt(long = "snapshot-passes", default_value = "0")]
+    snapshot_passes: u32,
+    /// stop rendering after <n> seconds (wall-clock)
+    #[structopt(long = "time-limit", default_value = "0.0")]
+    time_limit: f32,
+    /// stop sampling a pixel below this relative variance
+    #[structopt(long = "variance-threshold", default_value = "0.0")]
+    variance_threshold: f32,
+    /// minimum pixel samples before the variance threshold applies
+    #[structopt(long = "min-samples", default_value = "16")]
+    min_samples: u32,
+    /// distribute <n> pixel samples on average by variance
+    #[structopt(long = "adaptive", default_value = "0")]
+    adaptive: u32,
+    /// pixel samples every pixel gets first (adaptive)
+    #[structopt(long = "adaptive-initial", default_value = "8")]
+    adaptive_initial: u32,
+    /// write a checkpoint every <n> seconds
+    #[structopt(long = "checkpoint-seconds", default_value = "0.0")]
+    checkpoint_seconds: f32,
+    /// resume the render from its last checkpoint
+    #[structopt(long = "resume")]
+    resume: bool,
+    /// hand out tiles to workers connecting to <address> (e.g. 0.0.0.0:7878)
+    #[structopt(long = "coordinator")]
+    coordinator: Option<String>,
+    /// render tiles for the coordinator at <address>
+    #[structopt(long = "worker")]
+    worker: Option<String>,
+    /// number of tiles a worker renders per request (coordinator)
+    #[structopt(long = "tiles-per-request", default_value = "16")]
+    tiles_per_request: usize,
     /// The path to the file to read
     #[arg(long, short)]
     path: std::path::PathBuf,
@@ -369,11 +413,17 @@ fn extract_params(key_word: String, pairs: pest::iterators::Pair<Rule>) -> Param
                                 pbrt_float_parameter(&mut parameter_pair.into_inner());
                             let string: String = tuple.0;
                             let floats: Vec<Float> = tuple.1;
+                            // emitted radiance and intensity are illuminants
+                            let spectrum_type: SpectrumType = match string.as_str() {
+                                "L" | "I" => SpectrumType::Illuminant,
+                                _ => SpectrumType::Reflectance,
+                            };
                             params.add_rgb_spectrum(
                                 string,
-                                Spectrum {
-                                    c: [floats[0], floats[1], floats[2]],
-                                },
+                                Spectrum::from_rgb_type(
+                                    &[floats[0], floats[1], floats[2]],
+                                    spectrum_type,
+                                ),
                             );
                         }
                         Rule::spectrum_param => {
@@ -539,6 +589,13 @@ fn parse_line(
                             }
                             let todo: Vec<&str> = for_printing.splitn(3, '"').collect();
                             println!("Include {:?}", include_file);
+                            if let Some(ref mut checkpoint) = api_state.checkpoint {
+                                // don't resume if an included file changed
+                                let content: Vec<u8> =
+                                    std::fs::read(&include_file).unwrap_or_default();
+                                checkpoint.key.scene_hash =
+                                    extend_scene_hash(checkpoint.key.scene_hash, &content);
+                            }
                             parse_file(
                                 include_file,
                                 api_state,
@@ -914,6 +971,54 @@ fn main() {
         cropy0,
         cropy1,
     );
+    if args.progressive > 0 {
+        api_state.progressive = Some(ProgressiveSettings {
+            samples_per_pass: args.progressive,
+            snapshot_seconds: args.snapshot_seconds,
+            snapshot_passes: args.snapshot_passes,
+        });
+    }
+    if args.time_limit > 0.0 || args.variance_threshold > 0.0 {
+        api_state.budget = Some(RenderBudget {
+            time_limit: args.time_limit,
+            variance_threshold: args.variance_threshold,
+            min_samples: args.min_samples,
+        });
+    }
+    if args.adaptive > 0 {
+        api_state.adaptive = Some(AdaptiveSettings {
+            average_samples: args.adaptive,
+            initial_samples: args.adaptive_initial,
+        });
+    }
+    if args.checkpoint_seconds > 0.0 || args.resume {
+        let content: Vec<u8> = std::fs::read(&args.path).unwrap_or_default();
+        api_state.checkpoint = Some(CheckpointSettings {
+            seconds: args.checkpoint_seconds,
+            resume: args.resume,
+            key: CheckpointKey {
+                scene: args.path.display().to_string(),
+                scene_hash: scene_hash(&content),
+                ..Default::default()
+            },
+        });
+    }
+    match (args.coordinator, args.worker) {
+        (Some(_), Some(_)) => {
+            println!("ERROR: a process can't be coordinator and worker at the same time");
+            return;
+        }
+        (Some(address), None) => {
+            api_state.distributed = Some(DistributedMode::Coordinator {
+                address,
+                blocks_per_request: args.tiles_per_request,
+            });
+        }
+        (None, Some(address)) => {
+            api_state.distributed = Some(DistributedMode::Worker { address });
+        }
+        (None, None) => {}
+    }
     parse_file(
         args.path.into_os_string().into_string().unwrap(),
         &mut api_state,
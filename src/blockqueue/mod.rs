@@ -3,6 +3,7 @@
 //! not changed after creation we simply work through it with an
 //! atomic counter to track the index of the next block to work on.
 
+use std::collections::HashSet;
 use std::sync::atomic::{AtomicUsize, Ordering};
 
 // see github/tray_rust/src/sampler/block_queue.rs
@@ -51,6 +52,12 @@ impl BlockQueue {
             next: AtomicUsize::new(0),
         }
     }
+    /// Remove blocks which don't need to be worked on anymore (e.g.
+    /// blocks restored from a checkpoint)
+    pub fn remove_blocks(&mut self, blocks: &[(u32, u32)]) {
+        let remove: HashSet<(u32, u32)> = blocks.iter().cloned().collect();
+        self.blocks.retain(|b| !remove.contains(b));
+    }
     /// Get the dimensions of an individual block in the queue
     pub fn block_dim(&self) -> (u32, u32) {
         self.dimensions
@@ -15,16 +15,21 @@ use crate::cameras::orthographic::OrthographicCamera;
 use crate::cameras::perspective::PerspectiveCamera;
 use crate::cameras::realistic::RealisticCamera;
 use crate::core::camera::Camera;
+use crate::core::checkpoint::CheckpointSettings;
+use crate::core::distributed::{run_coordinator, run_worker, DistributedMode};
 use crate::core::film::Film;
 use crate::core::filter::Filter;
 use crate::core::geometry::{vec3_coordinate_system, vec3_cross_vec3};
 use crate::core::geometry::{Bounds2f, Bounds2i, Normal3f, Point2f, Point2i, Point3f, Vector3f};
-use crate::core::integrator::{Integrator, SamplerIntegrator};
+use crate::core::integrator::{
+    AdaptiveSettings, Integrator, ProgressiveSettings, RenderBudget, SamplerIntegrator,
+};
 use crate::core::light::Light;
 use crate::core::material::Material;
 use crate::core::medium::get_medium_scattering_properties;
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::mipmap::ImageWrap;
+use crate::core::nanovdb::{read_nanovdb_grids, NanoVdbGrid};
 use crate::core::paramset::{ParamSet, TextureParams};
 use crate::core::pbrt::{clamp_t, lerp};
 use crate::core::pbrt::{Float, Spectrum};
@@ -46,6 +51,7 @@ use crate::filters::triangle::TriangleFilter;
 use crate::integrators::ao::AOIntegrator;
 use crate::integrators::bdpt::BDPTIntegrator;
 use crate::integrators::directlighting::{DirectLightingIntegrator, LightStrategy};
+use crate::integrators::guidedpath::GuidedPathIntegrator;
 use crate::integrators::mlt::MLTIntegrator;
 use crate::integrators::path::PathIntegrator;
 use crate::integrators::sppm::SPPMIntegrator;
@@ -54,7 +60,7 @@ use crate::integrators::whitted::WhittedIntegrator;
 use crate::lights::diffuse::DiffuseAreaLight;
 use crate::lights::distant::DistantLight;
 use crate::lights::goniometric::GonioPhotometricLight;
-use crate::lights::infinite::InfiniteAreaLight;
+use crate::lights::infinite::{InfiniteAreaLight, Portal};
 use crate::lights::point::PointLight;
 use crate::lights::projection::ProjectionLight;
 use crate::lights::spot::SpotLight;
@@ -71,20 +77,25 @@ use crate::materials::substrate::SubstrateMaterial;
 use crate::materials::subsurface::SubsurfaceMaterial;
 use crate::materials::translucent::TranslucentMaterial;
 use crate::materials::uber::UberMaterial;
-use crate::media::grid::GridDensityMedium;
+use crate::media::grid::{GridDensityMedium, GridEmission};
 use crate::media::homogeneous::HomogeneousMedium;
+use crate::media::nanovdb::{NanoVdbMedium, VdbEmission};
 use crate::samplers::halton::HaltonSampler;
 use crate::samplers::maxmin::MaxMinDistSampler;
 use crate::samplers::random::RandomSampler;
 use crate::samplers::sobol::SobolSampler;
 use crate::samplers::stratified::StratifiedSampler;
 use crate::samplers::zerotwosequence::ZeroTwoSequenceSampler;
+use crate::shapes::cone::Cone;
 use crate::shapes::curve::create_curve_shape;
 use crate::shapes::cylinder::Cylinder;
 use crate::shapes::disk::Disk;
+use crate::shapes::heightfield::Heightfield;
+use crate::shapes::hyperboloid::Hyperboloid;
 use crate::shapes::loopsubdiv::loop_subdivide;
 use crate::shapes::nurbs::nurbs_evaluate_surface;
 use crate::shapes::nurbs::Homogeneous3;
+use crate::shapes::paraboloid::Paraboloid;
 use crate::shapes::plymesh::create_ply_mesh;
 use crate::shapes::sphere::Sphere;
 use crate::shapes::triangle::{Triangle, TriangleMesh};
@@ -96,6 +107,7 @@ use crate::textures::imagemap::ImageTexture;
 use crate::textures::imagemap::{convert_to_float, convert_to_spectrum};
 use crate::textures::marble::MarbleTexture;
 use crate::textures::mix::MixTexture;
+use crate::textures::ptex::PtexTexture;
 use crate::textures::scale::ScaleTexture;
 use crate::textures::windy::WindyTexture;
 use crate::textures::wrinkled::WrinkledTexture;
@@ -111,6 +123,11 @@ pub struct ApiState {
     pixelsamples: u32,
     number_of_threads: u8,
     pub search_directory: Option<Box<PathBuf>>,
+    pub progressive: Option<ProgressiveSettings>,
+    pub budget: Option<RenderBudget>,
+    pub adaptive: Option<AdaptiveSettings>,
+    pub checkpoint: Option<CheckpointSettings>,
+    pub distributed: Option<DistributedMode>,
     cur_transform: TransformSet,
     active_transform_bits: u8,
     named_coordinate_systems: HashMap<&'static str, TransformSet>,
@@ -120,6 +137,9 @@ pub struct ApiState {
     pushed_transforms: Vec<TransformSet>,
     pushed_active_transform_bits: Vec<u8>,
     param_set: ParamSet,
+    // ids for AOVs, 0 is reserved for the background
+    last_object_id: u32,
+    material_ids: HashMap<usize, u32>,
 }
 
 impl Default for ApiState {
@@ -128,6 +148,11 @@ impl Default for ApiState {
             pixelsamples: 0_u32,
             number_of_threads: 0_u8,
             search_directory: None,
+            progressive: None,
+            budget: None,
+            adaptive: None,
+            checkpoint: None,
+            distributed: None,
             cur_transform: TransformSet {
                 t: [Transform {
                     m: Matrix4x4 {
@@ -156,6 +181,8 @@ impl Default for ApiState {
             pushed_transforms: Vec::new(),
             pushed_active_transform_bits: Vec::new(),
             param_set: ParamSet::default(),
+            last_object_id: 0_u32,
+            material_ids: HashMap::new(),
         }
     }
 }
@@ -196,6 +223,8 @@ pub struct RenderOptions {
     pub lights: Vec<Arc<Light>>,
     pub primitives: Vec<Arc<Primitive>>,
     pub instances: HashMap<String, Vec<Arc<Primitive>>>,
+    /// object instances in the order of the **ObjectInstance** calls
+    pub instance_uses: Vec<Arc<Primitive>>,
     pub current_instance: String,
     pub have_scattering_media: bool, // false
     pub crop_window: Bounds2f,
@@ -319,6 +348,33 @@ impl RenderOptions {
                         ),
                     )));
                     some_integrator = Some(integrator);
+                } else if integrator_name == "guidedpath" {
+                    let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
+                    let pixel_bounds: Bounds2i = camera.get_film().get_sample_bounds();
+                    let rr_threshold: Float = self
+                        .integrator_params
+                        .find_one_float("rrthreshold", 1.0 as Float);
+                    let light_strategy: String = self
+                        .integrator_params
+                        .find_one_string("lightsamplestrategy", String::from("spatial"));
+                    let training_passes: i32 =
+                        self.integrator_params.find_one_int("trainingpasses", 5);
+                    let bsdf_sampling_fraction: Float = self
+                        .integrator_params
+                        .find_one_float("bsdfsamplingfraction", 0.5 as Float);
+                    let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::GuidedPath(
+                        GuidedPathIntegrator::new(
+                            max_depth as u32,
+                            camera,
+                            sampler,
+                            pixel_bounds,
+                            rr_threshold,
+                            light_strategy,
+                            training_passes.max(0) as u32,
+                            bsdf_sampling_fraction,
+                        ),
+                    )));
+                    some_integrator = Some(integrator);
                 } else if integrator_name == "volpath" {
                     // CreateVolPathIntegrator
                     let max_depth: i32 = self.integrator_params.find_one_int("maxdepth", 5);
@@ -345,6 +401,9 @@ impl RenderOptions {
                     let light_strategy: String = self
                         .integrator_params
                         .find_one_string("lightsamplestrategy", String::from("spatial"));
+                    let null_scattering: bool = self
+                        .integrator_params
+                        .find_one_bool("nullscattering", false);
                     let integrator = Box::new(Integrator::Sampler(SamplerIntegrator::VolPath(
                         VolPathIntegrator::new(
                             max_depth as u32,
@@ -353,6 +412,7 @@ impl RenderOptions {
                             pixel_bounds,
                             rr_threshold,
                             light_strategy,
+                            null_scattering,
                         ),
                     )));
                     some_integrator = Some(integrator);
@@ -472,16 +532,52 @@ impl RenderOptions {
         some_integrator
     }
     pub fn make_scene(&self) -> Scene {
-        let some_accelerator = make_accelerator(
-            &self.accelerator_name,
-            &self.primitives,
-            &self.accelerator_params,
-        );
-        if let Some(accelerator) = some_accelerator {
-            Scene::new(accelerator, self.lights.clone())
-        } else {
-            panic!("Unable to create accelerator.");
+        if self.instance_uses.is_empty() {
+            let some_accelerator = make_accelerator(
+                &self.accelerator_name,
+                &self.primitives,
+                &self.accelerator_params,
+            );
+            if let Some(accelerator) = some_accelerator {
+                return Scene::new(accelerator, self.lights.clone());
+            } else {
+                panic!("Unable to create accelerator.");
+            }
         }
+        // two levels: the bottom-level accelerators of the instanced
+        // objects (and of all other primitives) are built once, the
+        // top-level BVH over them can be refit when instances move
+        let mut tlas_primitives: Vec<Arc<Primitive>> = Vec::new();
+        if !self.primitives.is_empty() {
+            let some_accelerator = make_accelerator(
+                &self.accelerator_name,
+                &self.primitives,
+                &self.accelerator_params,
+            );
+            if let Some(accelerator) = some_accelerator {
+                tlas_primitives.push(accelerator);
+            } else {
+                panic!("Unable to create accelerator.");
+            }
+        }
+        tlas_primitives.extend(self.instance_uses.iter().cloned());
+        let tlas: Primitive = if self.accelerator_name == "bvh" {
+            BVHAccel::create(tlas_primitives, &self.accelerator_params)
+        } else {
+            // only a BVH can be refit when instances move
+            println!(
+                "WARNING: Accelerator \"{}\" can't be used over object instances.  Using \"bvh\" for the top level.",
+                self.accelerator_name
+            );
+            Primitive::BVH(Box::new(BVHAccel::new(
+                tlas_primitives,
+                4,
+                SplitMethod::SAH,
+            )))
+        };
+        let mut scene: Scene = Scene::new(Arc::new(tlas), self.lights.clone());
+        scene.set_instances(self.instance_uses.clone());
+        scene
     }
     pub fn make_camera(&self) -> Option<Arc<Camera>> {
         let mut some_camera: Option<Arc<Camera>> = None;
@@ -555,6 +651,7 @@ impl Default for RenderOptions {
             lights: Vec::new(),
             primitives: Vec::new(),
             instances: HashMap::new(),
+            instance_uses: Vec::new(),
             current_instance: String::from(""),
             have_scattering_media: false,
             crop_window: Bounds2f {
@@ -937,13 +1034,44 @@ fn make_light(api_state: &mut ApiState, medium_interface: &MediumInterface) {
         let n_samples: i32 = api_state.param_set.find_one_int("nsamples", 1_i32);
         // TODO: if (PbrtOptions.quickRender) nSamples = std::max(1, nSamples / 4);
 
+        // orient the environment map (around its up axis) without
+        // changing the transformation of the portals
+        let rotation: Float = api_state.param_set.find_one_float("rotation", 0.0 as Float);
+        let flip: bool = api_state.param_set.find_one_bool("flip", false);
+        let mut light2world: Transform =
+            api_state.cur_transform.t[0] * Transform::rotate_z(rotation);
+        if flip {
+            light2world = light2world * Transform::scale(1.0 as Float, -1.0 as Float, 1.0 as Float);
+        }
+        // four corners per portal
+        let portal_points: Vec<Point3f> = api_state.param_set.find_point3f("portal");
+        let mut portals: Vec<Portal> = Vec::new();
+        if portal_points.len() % 4 != 0 {
+            println!(
+                "ERROR: \"portal\" needs four corners per portal, got {} points. Ignoring portals.",
+                portal_points.len()
+            );
+        } else {
+            for corners in portal_points.chunks(4) {
+                let corners: Vec<Point3f> = corners
+                    .iter()
+                    .map(|p| api_state.cur_transform.t[0].transform_point(p))
+                    .collect();
+                if let Some(portal) = Portal::new(&corners) {
+                    portals.push(portal);
+                } else {
+                    println!(
+                        "ERROR: Portal {:?} is not a rectangle. Ignoring it.",
+                        corners
+                    );
+                }
+            }
+        }
         // return std::make_shared<InfiniteAreaLight>(light2world, L * sc, nSamples, texmap);
-        let infinte_light = Arc::new(Light::InfiniteArea(Box::new(InfiniteAreaLight::new(
-            &api_state.cur_transform.t[0],
-            &(l * sc),
-            n_samples,
-            texmap,
-        ))));
+        let mut infinite_area_light: InfiniteAreaLight =
+            InfiniteAreaLight::new(&light2world, &(l * sc), n_samples, texmap);
+        infinite_area_light.portals = portals;
+        let infinte_light = Arc::new(Light::InfiniteArea(Box::new(infinite_area_light)));
         api_state.render_options.lights.push(infinte_light);
     } else {
         panic!("MakeLight: unknown name {}", api_state.param_set.name);
@@ -972,10 +1100,16 @@ fn make_medium(api_state: &mut ApiState) {
     let g: Float = api_state.param_set.find_one_float("g", 0.0 as Float);
     sig_a = api_state.param_set.find_one_spectrum("sigma_a", sig_a) * scale;
     sig_s = api_state.param_set.find_one_spectrum("sigma_s", sig_s) * scale;
+    // emission
+    let le_scale: Float = api_state.param_set.find_one_float("Lescale", 1.0 as Float);
+    let le: Spectrum = api_state
+        .param_set
+        .find_one_spectrum("Le", Spectrum::default())
+        * le_scale;
     let some_medium: Option<Arc<Medium>>;
     if medium_type == "homogeneous" {
         some_medium = Some(Arc::new(Medium::Homogeneous(HomogeneousMedium::new(
-            &sig_a, &sig_s, g,
+            &sig_a, &sig_s, g, &le,
         ))));
     } else if medium_type == "heterogeneous" {
         let data: Arc<Vec<Float>> = Arc::new(api_state.param_set.find_float("density"));
@@ -1013,6 +1147,8 @@ fn make_medium(api_state: &mut ApiState) {
                 let data_2_medium: Transform = Transform::translate(&Vector3f::from(p0))
                     * Transform::scale(p1.x - p0.x, p1.y - p0.y, p1.z - p0.z);
                 let medium_2_world = api_state.cur_transform.t[0];
+                let emission: GridEmission =
+                    make_grid_emission(api_state, &le, le_scale, data.len());
                 some_medium = Some(Arc::new(Medium::GridDensity(GridDensityMedium::new(
                     &sig_a,
                     &sig_s,
@@ -1022,9 +1158,21 @@ fn make_medium(api_state: &mut ApiState) {
                     nz,
                     &(medium_2_world * data_2_medium),
                     data,
+                    emission,
                 ))));
             }
         }
+    } else if medium_type == "nanovdb" {
+        let mut filename: String = api_state
+            .param_set
+            .find_one_filename("filename", String::new());
+        if let Some(ref search_directory) = api_state.search_directory {
+            let mut path_buf: PathBuf = PathBuf::from("/");
+            path_buf.push(search_directory.as_ref());
+            path_buf.push(filename);
+            filename = String::from(path_buf.to_str().unwrap());
+        }
+        some_medium = make_nanovdb_medium(api_state, &filename, &sig_a, &sig_s, g, &le, le_scale);
     } else {
         panic!("MakeMedium: unknown name {}", medium_type);
     }
@@ -1036,6 +1184,134 @@ fn make_medium(api_state: &mut ApiState) {
     }
 }
 
+/// Emission of a heterogeneous medium: blackbody emission of a
+/// "temperature" grid, or "Le" scaled by an optional "emission" grid.
+fn make_grid_emission(
+    api_state: &mut ApiState,
+    le: &Spectrum,
+    le_scale: Float,
+    n_voxels: usize,
+) -> GridEmission {
+    let temperature: Vec<Float> = api_state.param_set.find_float("temperature");
+    let emission: Vec<Float> = api_state.param_set.find_float("emission");
+    if !temperature.is_empty() {
+        if temperature.len() != n_voxels {
+            println!(
+                "ERROR: GridDensityMedium has {} temperature values; expected nx*ny*nz = {}",
+                temperature.len(),
+                n_voxels
+            );
+            return GridEmission::None;
+        }
+        let offset: Float = api_state
+            .param_set
+            .find_one_float("temperatureoffset", 0.0 as Float);
+        let temperature_scale: Float = api_state
+            .param_set
+            .find_one_float("temperaturescale", 1.0 as Float);
+        return GridEmission::temperature(
+            Arc::new(temperature),
+            offset,
+            temperature_scale,
+            le_scale,
+        );
+    }
+    if le.is_black() {
+        if !emission.is_empty() {
+            println!("WARNING: \"emission\" values without \"Le\" are ignored.");
+        }
+        return GridEmission::None;
+    }
+    if emission.is_empty() {
+        GridEmission::Scaled {
+            le: *le,
+            scale: None,
+        }
+    } else if emission.len() != n_voxels {
+        println!(
+            "ERROR: GridDensityMedium has {} emission values; expected nx*ny*nz = {}",
+            emission.len(),
+            n_voxels
+        );
+        GridEmission::None
+    } else {
+        GridEmission::Scaled {
+            le: *le,
+            scale: Some(Arc::new(emission)),
+        }
+    }
+}
+
+/// A medium from the float grids of a NanoVDB file: "densitygrid"
+/// (default "density") and optionally "temperaturegrid" (default
+/// "temperature") or "emissiongrid" (default "emission"), see
+/// make_grid_emission().
+fn make_nanovdb_medium(
+    api_state: &mut ApiState,
+    filename: &str,
+    sig_a: &Spectrum,
+    sig_s: &Spectrum,
+    g: Float,
+    le: &Spectrum,
+    le_scale: Float,
+) -> Option<Arc<Medium>> {
+    let grids: Vec<NanoVdbGrid> = match read_nanovdb_grids(filename) {
+        Ok(grids) => grids,
+        Err(e) => {
+            println!("ERROR: Unable to read NanoVDB file \"{}\": {}", filename, e);
+            return None;
+        }
+    };
+    let mut grids: HashMap<String, Arc<NanoVdbGrid>> = grids
+        .into_iter()
+        .map(|grid| (grid.name.clone(), Arc::new(grid)))
+        .collect();
+    let density_name: String = api_state
+        .param_set
+        .find_one_string("densitygrid", String::from("density"));
+    let density: Arc<NanoVdbGrid> = match grids.remove(&density_name) {
+        Some(density) => density,
+        None => {
+            println!(
+                "ERROR: No grid \"{}\" in NanoVDB file \"{}\"",
+                density_name, filename
+            );
+            return None;
+        }
+    };
+    let temperature_name: String = api_state
+        .param_set
+        .find_one_string("temperaturegrid", String::from("temperature"));
+    let emission_name: String = api_state
+        .param_set
+        .find_one_string("emissiongrid", String::from("emission"));
+    let emission: VdbEmission = if let Some(temperature) = grids.remove(&temperature_name) {
+        let offset: Float = api_state
+            .param_set
+            .find_one_float("temperatureoffset", 0.0 as Float);
+        let temperature_scale: Float = api_state
+            .param_set
+            .find_one_float("temperaturescale", 1.0 as Float);
+        VdbEmission::temperature(temperature, offset, temperature_scale, le_scale)
+    } else if le.is_black() {
+        VdbEmission::None
+    } else {
+        VdbEmission::Scaled {
+            le: *le,
+            scale: grids.remove(&emission_name),
+        }
+    };
+    let medium_2_world = api_state.cur_transform.t[0];
+    Some(Arc::new(Medium::NanoVdb(NanoVdbMedium::new(
+        sig_a,
+        sig_s,
+        g,
+        &medium_2_world,
+        density,
+        emission,
+    ))))
+}
+
 fn make_texture(api_state: &mut ApiState) {
     // pbrtTexture (api.cpp:1049)
     let mut geom_params: ParamSet = ParamSet::default();
@@ -1275,7 +1551,18 @@ fn make_texture(api_state: &mut ApiState) {
             Arc::make_mut(&mut api_state.graphics_state.float_textures)
                 .insert(api_state.param_set.name.clone(), ft);
         } else if api_state.param_set.tex_name == "ptex" {
-            println!("TODO: CreatePtexFloatTexture");
+            // CreatePtexFloatTexture
+            let mut filename: String = tp.find_filename("filename", String::new());
+            if let Some(ref search_directory) = api_state.search_directory {
+                let mut path_buf: PathBuf = PathBuf::from("/");
+                path_buf.push(search_directory.as_ref());
+                path_buf.push(filename);
+                filename = String::from(path_buf.to_str().unwrap());
+            }
+            let gamma: Float = tp.find_float("gamma", 2.2 as Float);
+            let pt = Arc::new(PtexTexture::<Float>::new(filename, gamma));
+            Arc::make_mut(&mut api_state.graphics_state.float_textures)
+                .insert(api_state.param_set.name.clone(), pt);
         } else {
             println!(
                 "Float texture \"{}\" unknown.",
@@ -1591,6 +1878,19 @@ fn make_texture(api_state: &mut ApiState) {
             let ft = Arc::new(WindyTexture::new(map));
             Arc::make_mut(&mut api_state.graphics_state.spectrum_textures)
                 .insert(api_state.param_set.name.clone(), ft);
+        } else if api_state.param_set.tex_name == "ptex" {
+            // CreatePtexSpectrumTexture
+            let mut filename: String = tp.find_filename("filename", String::new());
+            if let Some(ref search_directory) = api_state.search_directory {
+                let mut path_buf: PathBuf = PathBuf::from("/");
+                path_buf.push(search_directory.as_ref());
+                path_buf.push(filename);
+                filename = String::from(path_buf.to_str().unwrap());
+            }
+            let gamma: Float = tp.find_float("gamma", 2.2 as Float);
+            let pt = Arc::new(PtexTexture::<Spectrum>::new(filename, gamma));
+            Arc::make_mut(&mut api_state.graphics_state.spectrum_textures)
+                .insert(api_state.param_set.name.clone(), pt);
         } else {
             println!(
                 "Spectrum texture \"{}\" unknown.",
@@ -1839,11 +2139,74 @@ fn get_shapes_and_materials(
         shapes.push(disk);
         materials.push(mtl);
     } else if api_state.param_set.name == "cone" {
-        println!("TODO: CreateConeShape");
+        let radius: Float = api_state.param_set.find_one_float("radius", 1.0);
+        let height: Float = api_state.param_set.find_one_float("height", 1.0);
+        let phi_max: Float = api_state.param_set.find_one_float("phimax", 360.0);
+        let cone = Arc::new(Shape::Cn(Cone::new(
+            obj_to_world,
+            world_to_obj,
+            false,
+            height,
+            radius,
+            phi_max,
+        )));
+        let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
+        shapes.push(cone);
+        materials.push(mtl);
     } else if api_state.param_set.name == "paraboloid" {
-        println!("TODO: CreateParaboloidShape");
+        let radius: Float = api_state.param_set.find_one_float("radius", 1.0);
+        let z_min: Float = api_state.param_set.find_one_float("zmin", 0.0);
+        let z_max: Float = api_state.param_set.find_one_float("zmax", 1.0);
+        let phi_max: Float = api_state.param_set.find_one_float("phimax", 360.0);
+        let paraboloid = Arc::new(Shape::Prbld(Paraboloid::new(
+            obj_to_world,
+            world_to_obj,
+            false,
+            radius,
+            z_min,
+            z_max,
+            phi_max,
+        )));
+        let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
+        shapes.push(paraboloid);
+        materials.push(mtl);
     } else if api_state.param_set.name == "hyperboloid" {
-        println!("TODO: CreateHyperboloidShape");
+        let p1: Point3f = api_state.param_set.find_one_point3f(
+            "p1",
+            Point3f {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+        );
+        let p2: Point3f = api_state.param_set.find_one_point3f(
+            "p2",
+            Point3f {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+        );
+        let phi_max: Float = api_state.param_set.find_one_float("phimax", 360.0);
+        if p1.z == p2.z {
+            // the implicit function coefficients can't be solved for
+            println!(
+                "ERROR: \"hyperboloid\" needs \"p1\" and \"p2\" at different heights, both are at z = {}.  Ignoring shape.",
+                p1.z
+            );
+        } else {
+            let hyperboloid = Arc::new(Shape::Hyprbld(Hyperboloid::new(
+                obj_to_world,
+                world_to_obj,
+                false,
+                p1,
+                p2,
+                phi_max,
+            )));
+            let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
+            shapes.push(hyperboloid);
+            materials.push(mtl);
+        }
     } else if api_state.param_set.name == "curve" {
         let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
         let curve_shapes: Vec<Arc<Shape>> = create_curve_shape(
@@ -1962,6 +2325,19 @@ fn get_shapes_and_materials(
         {
             shadow_alpha_tex = Some(Arc::new(ConstantTexture::new(0.0 as Float)));
         }
+        let face_indices: Vec<i32> = api_state.param_set.find_int("faceIndices");
+        if !face_indices.is_empty() && face_indices.len() != vi.len() / 3 {
+            println!(
+                "WARNING: Number of face indices, {}, doesn't match number of faces, {}",
+                face_indices.len(),
+                vi.len() / 3
+            );
+        }
+        let face_indices: Vec<i32> = if face_indices.len() == vi.len() / 3 {
+            face_indices
+        } else {
+            Vec::new()
+        };
         // CreateTriangleMesh
         // transform mesh vertices to world space
         let mut p_ws: Vec<Point3f> = Vec::new();
@@ -1969,12 +2345,69 @@ fn get_shapes_and_materials(
         for item in p.iter().take(n_vertices) {
             p_ws.push(obj_to_world.transform_point(item));
         }
+        // further vertex positions "P1", "P2", ... for deformation
+        // motion blur
+        let mut p_motion: Vec<Vec<Point3f>> = Vec::new();
+        loop {
+            let name: String = format!("P{}", p_motion.len() + 1);
+            let p_step: Vec<Point3f> = api_state.param_set.find_point3f(&name);
+            if p_step.is_empty() {
+                break;
+            }
+            if p_step.len() != n_vertices {
+                println!(
+                    "WARNING: \"{}\" has {} values, but \"P\" has {}.  Ignoring motion.",
+                    name,
+                    p_step.len(),
+                    n_vertices
+                );
+                p_motion.clear();
+                break;
+            }
+            p_motion.push(
+                p_step
+                    .iter()
+                    .map(|item| obj_to_world.transform_point(item))
+                    .collect(),
+            );
+        }
+        // normals at the same times, "N1", "N2", ...
+        let mut n_motion: Vec<Vec<Normal3f>> = Vec::new();
+        if !p_motion.is_empty() && !n_ws.is_empty() {
+            for step in 1..=p_motion.len() {
+                let name: String = format!("N{}", step);
+                let n_step: Vec<Normal3f> = api_state.param_set.find_normal3f(&name);
+                if n_step.len() != n_vertices {
+                    println!(
+                        "WARNING: \"{}\" has {} values, but \"N\" has {}.  Ignoring normals.",
+                        name,
+                        n_step.len(),
+                        n_ws.len()
+                    );
+                    n_ws.clear();
+                    n_motion.clear();
+                    break;
+                }
+                n_motion.push(
+                    n_step
+                        .iter()
+                        .map(|item| obj_to_world.transform_normal(item))
+                        .collect(),
+                );
+            }
+        }
+        // area lights sample the triangles at the positions of "P" only
+        if !p_motion.is_empty() && !api_state.graphics_state.area_light.is_empty() {
+            println!("WARNING: Area lights can't deform.  Ignoring motion of \"trianglemesh\".");
+            p_motion.clear();
+            n_motion.clear();
+        }
         // vertex indices are expected as usize, not i32
         let mut vertex_indices: Vec<u32> = Vec::new();
         for item in &vi {
             vertex_indices.push(*item as u32);
         }
-        let mesh = Arc::new(TriangleMesh::new(
+        let mut mesh: TriangleMesh = TriangleMesh::new(
             obj_to_world,
             world_to_obj,
             api_state.graphics_state.reverse_orientation,
@@ -1987,7 +2420,17 @@ fn get_shapes_and_materials(
             uvs,
             alpha_tex,
             shadow_alpha_tex,
-        ));
+            face_indices,
+        );
+        if !p_motion.is_empty() {
+            mesh.set_motion(
+                p_motion, // in world space
+                n_motion, // in world space
+                api_state.render_options.transform_start_time,
+                api_state.render_options.transform_end_time,
+            );
+        }
+        let mesh = Arc::new(mesh);
         let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
         for id in 0..mesh.n_triangles {
             let triangle = Arc::new(Shape::Trngl(Triangle::new(mesh.clone(), id)));
@@ -2014,7 +2457,35 @@ fn get_shapes_and_materials(
             panic!("No search directory for plymesh.");
         }
     } else if api_state.param_set.name == "heightfield" {
-        println!("TODO: CreateHeightfield");
+        // CreateHeightfield
+        let nu: i32 = api_state.param_set.find_one_int("nu", -1);
+        let nv: i32 = api_state.param_set.find_one_int("nv", -1);
+        let pz: Vec<Float> = api_state.param_set.find_float("Pz");
+        if nu < 2_i32 || nv < 2_i32 {
+            println!("ERROR: Must provide \"nu\" and \"nv\" (at least 2) with heightfield shape.");
+            return (shapes, materials);
+        }
+        let n_values: Option<usize> = (nu as usize).checked_mul(nv as usize);
+        if n_values != Some(pz.len()) {
+            println!(
+                "ERROR: Expected {} x {} \"Pz\" values for heightfield shape, found {}.",
+                nu,
+                nv,
+                pz.len()
+            );
+            return (shapes, materials);
+        }
+        let heightfield = Arc::new(Shape::Hghtfld(Heightfield::new(
+            obj_to_world,
+            world_to_obj,
+            api_state.graphics_state.reverse_orientation,
+            nu as usize,
+            nv as usize,
+            pz,
+        )));
+        let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
+        shapes.push(heightfield);
+        materials.push(mtl);
     } else if api_state.param_set.name == "loopsubdiv" {
         // CreateLoopSubdiv
         let n_levels: i32 = api_state
@@ -2221,6 +2692,7 @@ fn get_shapes_and_materials(
             uvs,
             None,
             None,
+            Vec::new(),
         ));
         let mtl: Option<Arc<Material>> = create_material(api_state, bsdf_state);
         for id in 0..mesh.n_triangles {
@@ -2364,9 +2836,95 @@ pub fn pbrt_cleanup(api_state: &ApiState, integrator_arg: &Option<String>) {
         .render_options
         .make_integrator(api_state.pixelsamples, integrator_arg);
     if let Some(mut integrator) = some_integrator {
+        if let Some(ref mode) = api_state.distributed {
+            if api_state.progressive.is_some()
+                || api_state.budget.is_some()
+                || api_state.adaptive.is_some()
+                || api_state.checkpoint.is_some()
+            {
+                println!(
+                    "WARNING: progressive rendering, render budgets, adaptive sampling and checkpoints are ignored for distributed rendering"
+                );
+            }
+            match mode {
+                DistributedMode::Coordinator {
+                    address,
+                    blocks_per_request,
+                } => {
+                    // the coordinator only merges tiles
+                    run_coordinator(&integrator, address, *blocks_per_request);
+                }
+                DistributedMode::Worker { address } => {
+                    let scene = api_state.render_options.make_scene();
+                    run_worker(
+                        &mut integrator,
+                        &scene,
+                        api_state.number_of_threads,
+                        address,
+                    );
+                }
+            }
+            return;
+        }
         let scene = api_state.render_options.make_scene();
         let num_threads: u8 = api_state.number_of_threads;
-        integrator.render(&scene, num_threads);
+        let mut checkpoint: CheckpointSettings = api_state.checkpoint.clone().unwrap_or_default();
+        checkpoint.key.integrator = integrator_arg
+            .clone()
+            .unwrap_or_else(|| api_state.render_options.integrator_name.clone());
+        checkpoint.key.sampler = api_state.render_options.sampler_name.clone();
+        checkpoint.key.samples_per_pixel = match *integrator {
+            Integrator::BDPT(ref integrator) => integrator.get_sampler().get_samples_per_pixel(),
+            Integrator::Sampler(ref integrator) => integrator.get_sampler().get_samples_per_pixel(),
+            // MLT and SPPM take their sample counts from the scene file
+            _ => 0,
+        };
+        if api_state.progressive.is_some()
+            || api_state.budget.is_some()
+            || api_state.adaptive.is_some()
+        {
+            if let Integrator::Sampler(ref mut sampler_integrator) = *integrator {
+                let budget: RenderBudget = api_state.budget.unwrap_or_default();
+                if (checkpoint.is_enabled() || checkpoint.resume)
+                    && (api_state.adaptive.is_some()
+                        || api_state.progressive.is_some()
+                        || budget.time_limit > 0.0 as Float)
+                {
+                    println!(
+                        "WARNING: checkpoints are not supported for progressive rendering, time limits and adaptive sampling"
+                    );
+                }
+                if let Some(ref settings) = api_state.adaptive {
+                    if api_state.progressive.is_some() {
+                        println!("WARNING: progressive rendering is ignored for adaptive sampling");
+                    }
+                    sampler_integrator.render_adaptive(&scene, num_threads, settings, &budget);
+                } else if let Some(ref settings) = api_state.progressive {
+                    sampler_integrator.render_progressive(&scene, num_threads, settings, &budget);
+                } else if budget.time_limit > 0.0 as Float {
+                    // a time limit needs passes over the whole image
+                    let samples_per_pixel: i64 =
+                        sampler_integrator.get_sampler().get_samples_per_pixel();
+                    let settings: ProgressiveSettings = ProgressiveSettings {
+                        samples_per_pass: std::cmp::max(samples_per_pixel / 16, 1) as u32,
+                        ..Default::default()
+                    };
+                    sampler_integrator.render_progressive(&scene, num_threads, &settings, &budget);
+                } else {
+                    sampler_integrator.render_with_budget(
+                        &scene,
+                        num_threads,
+                        &budget,
+                        &checkpoint,
+                    );
+                }
+                return;
+            }
+            println!(
+                "WARNING: progressive rendering, render budgets and adaptive sampling are only supported by sampler integrators"
+            );
+        }
+        integrator.render_with_checkpoints(&scene, num_threads, &checkpoint);
     } else {
         panic!("Unable to create integrator.");
     }
@@ -2789,10 +3347,24 @@ pub fn pbrt_area_light_source(api_state: &mut ApiState, params: ParamSet) {
         .copy_from(&api_state.param_set);
 }
 
+fn get_material_id(api_state: &mut ApiState, material: &Option<Arc<Material>>) -> u32 {
+    if let Some(material) = material {
+        let next_id: u32 = api_state.material_ids.len() as u32 + 1;
+        *api_state
+            .material_ids
+            .entry(Arc::as_ptr(material) as usize)
+            .or_insert(next_id)
+    } else {
+        0_u32
+    }
+}
+
 pub fn pbrt_shape(api_state: &mut ApiState, bsdf_state: &mut BsdfState, params: ParamSet) {
     // println!("Shape \"{}\"", params.name);
     // print_params(&params);
     api_state.param_set = params;
+    api_state.last_object_id += 1;
+    let object_id: u32 = api_state.last_object_id;
     // collect area lights
     let mut prims: Vec<Arc<Primitive>> = Vec::new();
     let mut area_lights: Vec<Arc<Light>> = Vec::new();
@@ -2842,12 +3414,15 @@ pub fn pbrt_shape(api_state: &mut ApiState, bsdf_state: &mut BsdfState, params:
                         two_sided,
                     ))));
                 area_lights.push(area_light.clone());
-                let geo_prim = Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
+                let mut geometric_primitive: GeometricPrimitive = GeometricPrimitive::new(
                     shape.clone(),
                     material.clone(),
                     Some(area_light.clone()),
                     Some(Arc::new(mi.clone())),
-                ))));
+                );
+                geometric_primitive.object_id = object_id;
+                geometric_primitive.material_id = get_material_id(api_state, material);
+                let geo_prim = Arc::new(Primitive::Geometric(Box::new(geometric_primitive)));
                 prims.push(geo_prim.clone());
             }
         }
@@ -2860,12 +3435,15 @@ pub fn pbrt_shape(api_state: &mut ApiState, bsdf_state: &mut BsdfState, params:
         for i in 0..shapes.len() {
             let shape = &shapes[i];
             let material = &materials[i];
-            let geo_prim = Arc::new(Primitive::Geometric(Box::new(GeometricPrimitive::new(
+            let mut geometric_primitive: GeometricPrimitive = GeometricPrimitive::new(
                 shape.clone(),
                 material.clone(),
                 None,
                 Some(Arc::new(mi.clone())),
-            ))));
+            );
+            geometric_primitive.object_id = object_id;
+            geometric_primitive.material_id = get_material_id(api_state, material);
+            let geo_prim = Arc::new(Primitive::Geometric(Box::new(geometric_primitive)));
             prims.push(geo_prim.clone());
         }
         // animated?
@@ -3042,36 +3620,10 @@ pub fn pbrt_object_instance(api_state: &mut ApiState, params: ParamSet) {
             // create aggregate for instance _Primitive_s
             if api_state.render_options.accelerator_name == "bvh" {
                 //  CreateBVHAccelerator
-                let split_method_name: String = api_state
-                    .render_options
-                    .accelerator_params
-                    .find_one_string("splitmethod", String::from("sah"));
-                let split_method;
-                if split_method_name == "sah" {
-                    split_method = SplitMethod::SAH;
-                } else if split_method_name == "hlbvh" {
-                    split_method = SplitMethod::HLBVH;
-                } else if split_method_name == "middle" {
-                    split_method = SplitMethod::Middle;
-                } else if split_method_name == "equal" {
-                    split_method = SplitMethod::EqualCounts;
-                } else {
-                    println!(
-                        "WARNING: BVH split method \"{}\" unknown.  Using \"sah\".",
-                        split_method_name
-                    );
-                    split_method = SplitMethod::SAH;
-                }
-                let max_prims_in_node: i32 = api_state
-                    .render_options
-                    .accelerator_params
-                    .find_one_int("maxnodeprims", 4);
-                let accelerator: Arc<Primitive> =
-                    Arc::new(Primitive::BVH(Box::new(BVHAccel::new(
-                        instance_vec.clone(),
-                        max_prims_in_node as usize,
-                        split_method,
-                    ))));
+                let accelerator: Arc<Primitive> = Arc::new(BVHAccel::create(
+                    instance_vec.clone(),
+                    &api_state.render_options.accelerator_params,
+                ));
                 instance_vec.clear();
                 instance_vec.push(accelerator);
             } else if api_state.render_options.accelerator_name == "kdtree" {
@@ -3099,7 +3651,7 @@ pub fn pbrt_object_instance(api_state: &mut ApiState, params: ParamSet) {
         let prim: Arc<Primitive> = Arc::new(Primitive::Transformed(Box::new(
             TransformedPrimitive::new(instance_vec[0].clone(), animated_instance_to_world),
         )));
-        api_state.render_options.primitives.push(prim);
+        api_state.render_options.instance_uses.push(prim);
     } else {
         println!(
             "ERROR: Unable to find instance named {:?}",
@@ -25,7 +25,6 @@ use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::{cos_theta, fr_dielectric};
 use crate::core::reflection::{Bsdf, Bxdf, BxdfType};
 use crate::core::scene::Scene;
-use crate::core::spectrum::RGBEnum;
 
 pub struct TabulatedBssrdf {
     // BSSRDF Protected Data
@@ -57,20 +56,12 @@ impl TabulatedBssrdf {
     ) -> Self {
         let sigma_t: Spectrum = *sigma_a + *sigma_s;
         let mut rho: Spectrum = Spectrum::new(0.0 as Float);
-        if sigma_t[RGBEnum::Red] != 0.0 as Float {
-            rho[RGBEnum::Red] = sigma_s[RGBEnum::Red] / sigma_t[RGBEnum::Red];
-        } else {
-            rho[RGBEnum::Red] = 0.0 as Float;
-        }
-        if sigma_t[RGBEnum::Green] != 0.0 as Float {
-            rho[RGBEnum::Green] = sigma_s[RGBEnum::Green] / sigma_t[RGBEnum::Green];
-        } else {
-            rho[RGBEnum::Green] = 0.0 as Float;
-        }
-        if sigma_t[RGBEnum::Blue] != 0.0 as Float {
-            rho[RGBEnum::Blue] = sigma_s[RGBEnum::Blue] / sigma_t[RGBEnum::Blue];
-        } else {
-            rho[RGBEnum::Blue] = 0.0 as Float;
+        for ch in 0..Spectrum::N_SAMPLES {
+            if sigma_t[ch] != 0.0 as Float {
+                rho[ch] = sigma_s[ch] / sigma_t[ch];
+            } else {
+                rho[ch] = 0.0 as Float;
+            }
         }
         let ns: Normal3f = po.shading.n;
         let ss: Vector3f = po.shading.dpdu.normalize();
@@ -125,9 +116,9 @@ impl TabulatedBssrdf {
         // return combined probability from all BSSRDF sampling strategies
         let mut pdf: Float = 0.0;
         let axis_prob: [Float; 3] = [0.25 as Float, 0.25 as Float, 0.5 as Float];
-        let ch_prob: Float = 1.0 as Float / 3.0 as Float;
+        let ch_prob: Float = 1.0 as Float / Spectrum::N_SAMPLES as Float;
         for axis in XYZEnum::iter() {
-            for ch in RGBEnum::iter() {
+            for ch in 0..Spectrum::N_SAMPLES {
                 pdf += self.pdf_sr(ch, r_proj[axis as usize])
                     * n_local[axis].abs()
                     * ch_prob
@@ -170,21 +161,21 @@ impl TabulatedBssrdf {
             u1 = (u1 - 0.75 as Float) * 4.0 as Float;
         }
         // choose spectral channel for BSSRDF sampling
-        let ch: u8 = clamp_t((u1 * 3.0 as Float) as u8, 0_u8, 2_u8);
-        let ch_enum: RGBEnum = match ch {
-            0 => RGBEnum::Red,
-            1 => RGBEnum::Green,
-            _ => RGBEnum::Blue,
-        };
-        u1 = u1 * 3.0 as Float - ch as Float;
+        let n_samples: usize = Spectrum::N_SAMPLES;
+        let ch: usize = clamp_t(
+            (u1 * n_samples as Float) as usize,
+            0_usize,
+            n_samples - 1_usize,
+        );
+        u1 = u1 * n_samples as Float - ch as Float;
         // sample BSSRDF profile in polar coordinates
-        let r: Float = self.sample_sr(ch_enum, u2.x);
+        let r: Float = self.sample_sr(ch, u2.x);
         if r < 0.0 as Float {
             return Spectrum::default();
         }
         let phi: Float = 2.0 as Float * PI * u2.y;
         // compute BSSRDF profile bounds and intersection height
-        let r_max: Float = self.sample_sr(ch_enum, 0.999 as Float);
+        let r_max: Float = self.sample_sr(ch, 0.999 as Float);
         if r >= r_max {
             return Spectrum::default();
         }
@@ -294,7 +285,7 @@ impl TabulatedBssrdf {
     }
     pub fn sr(&self, r: Float) -> Spectrum {
         let mut sr: Spectrum = Spectrum::default();
-        for ch in 0..3_usize {
+        for ch in 0..Spectrum::N_SAMPLES {
             // convert $r$ into unitless optical radius $r_{\roman{optical}}$
             let r_optical: Float = r * self.sigma_t.c[ch];
             // compute spline weights to interpolate BSSRDF on channel _ch_
@@ -338,7 +329,7 @@ impl TabulatedBssrdf {
         sr *= self.sigma_t * self.sigma_t;
         sr.clamp(0.0 as Float, std::f32::INFINITY as Float)
     }
-    pub fn pdf_sr(&self, ch: RGBEnum, r: Float) -> Float {
+    pub fn pdf_sr(&self, ch: usize, r: Float) -> Float {
         // convert $r$ into unitless optical radius $r_{\roman{optical}}$
         let r_optical: Float = r * self.sigma_t[ch];
         // compute spline weights to interpolate BSSRDF density on channel _ch_
@@ -384,7 +375,7 @@ impl TabulatedBssrdf {
         }
         (0.0 as Float).max(sr * self.sigma_t[ch] * self.sigma_t[ch] / rho_eff)
     }
-    pub fn sample_sr(&self, ch: RGBEnum, u: Float) -> Float {
+    pub fn sample_sr(&self, ch: usize, u: Float) -> Float {
         if self.sigma_t[ch] == 0.0 as Float {
             return -1.0 as Float;
         }
@@ -0,0 +1,611 @@
+//! On-disk checkpoints of a running render. A checkpoint holds the
+//! pixel state of the **Film** (XYZ, filter weight sum, splats) and
+//! the state of the integrator which is needed to continue the
+//! render: the set of completed image tiles for the tile based
+//! integrators, the per pixel statistics of **SPPM**, or the state
+//! of the Markov chains of **MLT**. Checkpoints are written next to
+//! the output image (e.g. *pbrt.exr.ckpt*) and are removed once the
+//! render finished.
+
+// std
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+// others
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+// pbrt
+use crate::core::film::{Film, Pixel};
+use crate::core::geometry::Point2f;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::rng::Rng;
+use crate::integrators::mlt::{MLTSampler, PrimarySample};
+
+const CHECKPOINT_MAGIC: &[u8; 8] = b"RSPBRTCK";
+const CHECKPOINT_VERSION: u32 = 2;
+/// longest string stored in a checkpoint
+const CHECKPOINT_MAX_STRING: usize = 4096;
+
+/// Identifies the render a checkpoint was written for. A checkpoint
+/// is only resumed if all fields match the current render.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct CheckpointKey {
+    /// the scene file
+    pub scene: String,
+    /// hash of the content of the scene file and the files it
+    /// includes (see *scene_hash()*)
+    pub scene_hash: u64,
+    pub integrator: String,
+    /// the sampler (which seeds its sample sequences per tile)
+    pub sampler: String,
+    /// 0 for integrators which don't use the sampler's pixel samples
+    pub samples_per_pixel: i64,
+}
+
+impl CheckpointKey {
+    /// Names of the fields which differ from *other*.
+    fn mismatches(&self, other: &CheckpointKey) -> Vec<&'static str> {
+        let mut fields: Vec<&'static str> = Vec::new();
+        if self.scene != other.scene {
+            fields.push("scene file");
+        }
+        if self.scene_hash != other.scene_hash {
+            fields.push("scene content");
+        }
+        if self.integrator != other.integrator {
+            fields.push("integrator");
+        }
+        if self.sampler != other.sampler {
+            fields.push("sampler");
+        }
+        if self.samples_per_pixel != other.samples_per_pixel {
+            fields.push("pixel samples");
+        }
+        fields
+    }
+}
+
+/// When to write checkpoints and whether to resume from an existing
+/// one.
+#[derive(Debug, Default, Clone)]
+pub struct CheckpointSettings {
+    /// write a checkpoint every *seconds* seconds (0 disables checkpoints)
+    pub seconds: Float,
+    /// continue the render from the checkpoint of a previous run
+    pub resume: bool,
+    /// the render checkpoints are written for
+    pub key: CheckpointKey,
+}
+
+impl CheckpointSettings {
+    pub fn is_enabled(&self) -> bool {
+        self.seconds > 0.0 as Float
+    }
+}
+
+/// A hash (64 bit FNV-1a) of the scene file's content, which stays
+/// the same across builds.
+pub fn scene_hash(content: &[u8]) -> u64 {
+    extend_scene_hash(0xcbf2_9ce4_8422_2325, content)
+}
+
+/// Continues a [scene_hash()](fn.scene_hash.html) with the content of
+/// a file included by the scene.
+pub fn extend_scene_hash(hash: u64, content: &[u8]) -> u64 {
+    let mut hash: u64 = hash;
+    for byte in content.iter() {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    hash
+}
+
+/// The name of the checkpoint file for a given **Film**.
+pub fn checkpoint_filename(film: &Film) -> String {
+    format!("{}.ckpt", film.filename)
+}
+
+/// The statistics of an SPPM pixel which persist across iterations.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SPPMPixelState {
+    pub radius: Float,
+    pub ld: Spectrum,
+    pub n: Float,
+    pub tau: Spectrum,
+}
+
+/// The state of an MLT Markov chain between two mutations.
+#[derive(Clone)]
+pub struct MLTChainState {
+    pub chain: u32,
+    /// index of the next mutation
+    pub mutation: u64,
+    pub depth: u32,
+    pub p_current: Point2f,
+    pub l_current: Spectrum,
+    pub rng: Rng,
+    pub sampler: MLTSampler,
+}
+
+/// Integrator specific part of a checkpoint.
+pub enum IntegratorState {
+    /// tiles (see **BlockQueue**) already merged into the film
+    Tiles { completed: Vec<(u32, u32)> },
+    /// the next iteration and the statistics of all pixels
+    SPPM {
+        iteration: i32,
+        pixels: Vec<SPPMPixelState>,
+    },
+    /// finished chains and the state of all interrupted chains
+    MLT {
+        completed: Vec<u32>,
+        chains: Vec<MLTChainState>,
+    },
+}
+
+impl IntegratorState {
+    pub fn name(&self) -> &'static str {
+        match self {
+            IntegratorState::Tiles { .. } => "tiles",
+            IntegratorState::SPPM { .. } => "sppm",
+            IntegratorState::MLT { .. } => "mlt",
+        }
+    }
+}
+
+pub struct Checkpoint {
+    pub key: CheckpointKey,
+    pub pixels: Vec<Pixel>,
+    pub aov_pixels: Vec<Float>,
+    pub state: IntegratorState,
+}
+
+impl Checkpoint {
+    /// Copies the current pixel state of the **Film**.
+    pub fn from_film(film: &Film, key: &CheckpointKey, state: IntegratorState) -> Self {
+        Checkpoint {
+            key: key.clone(),
+            pixels: film.pixels.read().unwrap().clone(),
+            aov_pixels: film.aov_pixels.read().unwrap().clone(),
+            state,
+        }
+    }
+    /// Copies the pixel state back into the **Film**. Returns false
+    /// (and leaves the film untouched) if the checkpoint was written
+    /// for a film of different size.
+    pub fn restore_film(&self, film: &Film) -> bool {
+        let mut pixels = film.pixels.write().unwrap();
+        let mut aov_pixels = film.aov_pixels.write().unwrap();
+        if pixels.len() != self.pixels.len() || aov_pixels.len() != self.aov_pixels.len() {
+            println!("WARNING: checkpoint does not match the film resolution or AOVs");
+            return false;
+        }
+        pixels.clone_from_slice(&self.pixels);
+        aov_pixels.copy_from_slice(&self.aov_pixels);
+        true
+    }
+    /// Writes the checkpoint to a temporary file first and renames it
+    /// afterwards, so an interrupted write never destroys the
+    /// previous checkpoint.
+    pub fn write(&self, filename: &str) -> std::io::Result<()> {
+        let tmp_filename: String = format!("{}.tmp", filename);
+        {
+            let mut w = BufWriter::new(File::create(&tmp_filename)?);
+            w.write_all(CHECKPOINT_MAGIC)?;
+            w.write_u32::<LittleEndian>(CHECKPOINT_VERSION)?;
+            w.write_u32::<LittleEndian>(Spectrum::N_SAMPLES as u32)?;
+            write_string(&mut w, &self.key.scene)?;
+            w.write_u64::<LittleEndian>(self.key.scene_hash)?;
+            write_string(&mut w, &self.key.integrator)?;
+            write_string(&mut w, &self.key.sampler)?;
+            w.write_i64::<LittleEndian>(self.key.samples_per_pixel)?;
+            w.write_u64::<LittleEndian>(self.pixels.len() as u64)?;
+            for pixel in self.pixels.iter() {
+                pixel.write_state(&mut w)?;
+            }
+            w.write_u64::<LittleEndian>(self.aov_pixels.len() as u64)?;
+            for v in self.aov_pixels.iter() {
+                w.write_f32::<LittleEndian>(*v)?;
+            }
+            match &self.state {
+                IntegratorState::Tiles { completed } => {
+                    w.write_u8(0)?;
+                    w.write_u64::<LittleEndian>(completed.len() as u64)?;
+                    for (x, y) in completed.iter() {
+                        w.write_u32::<LittleEndian>(*x)?;
+                        w.write_u32::<LittleEndian>(*y)?;
+                    }
+                }
+                IntegratorState::SPPM { iteration, pixels } => {
+                    w.write_u8(1)?;
+                    w.write_i32::<LittleEndian>(*iteration)?;
+                    w.write_u64::<LittleEndian>(pixels.len() as u64)?;
+                    for p in pixels.iter() {
+                        w.write_f32::<LittleEndian>(p.radius)?;
+                        write_spectrum(&mut w, &p.ld)?;
+                        w.write_f32::<LittleEndian>(p.n)?;
+                        write_spectrum(&mut w, &p.tau)?;
+                    }
+                }
+                IntegratorState::MLT { completed, chains } => {
+                    w.write_u8(2)?;
+                    w.write_u64::<LittleEndian>(completed.len() as u64)?;
+                    for chain in completed.iter() {
+                        w.write_u32::<LittleEndian>(*chain)?;
+                    }
+                    w.write_u64::<LittleEndian>(chains.len() as u64)?;
+                    for chain in chains.iter() {
+                        write_mlt_chain(&mut w, chain)?;
+                    }
+                }
+            }
+            w.flush()?;
+        }
+        std::fs::rename(&tmp_filename, filename)
+    }
+    pub fn read(filename: &str) -> std::io::Result<Self> {
+        let mut r = BufReader::new(File::open(filename)?);
+        let mut magic: [u8; 8] = [0_u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != CHECKPOINT_MAGIC {
+            return Err(invalid_data("not a checkpoint file"));
+        }
+        if r.read_u32::<LittleEndian>()? != CHECKPOINT_VERSION {
+            return Err(invalid_data("unsupported checkpoint version"));
+        }
+        if r.read_u32::<LittleEndian>()? != Spectrum::N_SAMPLES as u32 {
+            return Err(invalid_data(
+                "checkpoint written with a different spectrum type",
+            ));
+        }
+        let key: CheckpointKey = CheckpointKey {
+            scene: read_string(&mut r)?,
+            scene_hash: r.read_u64::<LittleEndian>()?,
+            integrator: read_string(&mut r)?,
+            sampler: read_string(&mut r)?,
+            samples_per_pixel: r.read_i64::<LittleEndian>()?,
+        };
+        // the counts below are not trusted for pre-allocation, a
+        // truncated or corrupt file fails when reading the elements
+        let n_pixels: usize = r.read_u64::<LittleEndian>()? as usize;
+        let mut pixels: Vec<Pixel> = Vec::new();
+        for _ in 0..n_pixels {
+            pixels.push(Pixel::read_state(&mut r)?);
+        }
+        let n_aov: usize = r.read_u64::<LittleEndian>()? as usize;
+        let mut aov_pixels: Vec<Float> = Vec::new();
+        for _ in 0..n_aov {
+            aov_pixels.push(r.read_f32::<LittleEndian>()?);
+        }
+        let state: IntegratorState = match r.read_u8()? {
+            0 => {
+                let n: usize = r.read_u64::<LittleEndian>()? as usize;
+                let mut completed: Vec<(u32, u32)> = Vec::new();
+                for _ in 0..n {
+                    let x: u32 = r.read_u32::<LittleEndian>()?;
+                    let y: u32 = r.read_u32::<LittleEndian>()?;
+                    completed.push((x, y));
+                }
+                IntegratorState::Tiles { completed }
+            }
+            1 => {
+                let iteration: i32 = r.read_i32::<LittleEndian>()?;
+                let n: usize = r.read_u64::<LittleEndian>()? as usize;
+                let mut pixels: Vec<SPPMPixelState> = Vec::new();
+                for _ in 0..n {
+                    let radius: Float = r.read_f32::<LittleEndian>()?;
+                    let ld: Spectrum = read_spectrum(&mut r)?;
+                    let n: Float = r.read_f32::<LittleEndian>()?;
+                    let tau: Spectrum = read_spectrum(&mut r)?;
+                    pixels.push(SPPMPixelState { radius, ld, n, tau });
+                }
+                IntegratorState::SPPM { iteration, pixels }
+            }
+            2 => {
+                let n: usize = r.read_u64::<LittleEndian>()? as usize;
+                let mut completed: Vec<u32> = Vec::new();
+                for _ in 0..n {
+                    completed.push(r.read_u32::<LittleEndian>()?);
+                }
+                let n: usize = r.read_u64::<LittleEndian>()? as usize;
+                let mut chains: Vec<MLTChainState> = Vec::new();
+                for _ in 0..n {
+                    chains.push(read_mlt_chain(&mut r)?);
+                }
+                IntegratorState::MLT { completed, chains }
+            }
+            _ => return Err(invalid_data("unknown integrator state")),
+        };
+        Ok(Checkpoint {
+            key,
+            pixels,
+            aov_pixels,
+            state,
+        })
+    }
+}
+
+/// Reads the checkpoint of the **Film** (if resuming was requested)
+/// and returns the integrator state if it was written for the same
+/// render (see **CheckpointKey**) by the same kind of integrator (see
+/// *IntegratorState::name()*). The film's pixels are restored in that
+/// case.
+pub fn load_checkpoint(
+    film: &Film,
+    settings: &CheckpointSettings,
+    name: &str,
+) -> Option<IntegratorState> {
+    if !settings.resume {
+        return None;
+    }
+    let filename: String = checkpoint_filename(film);
+    match Checkpoint::read(&filename) {
+        Ok(checkpoint) => {
+            let mismatches: Vec<&'static str> = checkpoint.key.mismatches(&settings.key);
+            if !mismatches.is_empty() {
+                println!(
+                    "WARNING: checkpoint {:?} was written for a different render ({} changed), starting from scratch",
+                    filename,
+                    mismatches.join(", ")
+                );
+                None
+            } else if checkpoint.state.name() != name {
+                println!(
+                    "WARNING: checkpoint {:?} was written by another integrator ({}), starting from scratch",
+                    filename,
+                    checkpoint.state.name()
+                );
+                None
+            } else if checkpoint.restore_film(film) {
+                println!("Resuming render from {:?} ...", filename);
+                Some(checkpoint.state)
+            } else {
+                None
+            }
+        }
+        Err(e) => {
+            println!(
+                "WARNING: can't resume from {:?} ({}), starting from scratch",
+                filename, e
+            );
+            None
+        }
+    }
+}
+
+/// Returns the tiles completed according to the checkpoint of the
+/// **Film** (see *load_checkpoint()*).
+pub fn load_completed_tiles(film: &Film, settings: &CheckpointSettings) -> Vec<(u32, u32)> {
+    match load_checkpoint(film, settings, "tiles") {
+        Some(IntegratorState::Tiles { completed }) => completed,
+        _ => Vec::new(),
+    }
+}
+
+/// Writes a checkpoint of the **Film** and the integrator state.
+pub fn save_checkpoint(film: &Film, settings: &CheckpointSettings, state: IntegratorState) {
+    let filename: String = checkpoint_filename(film);
+    let checkpoint: Checkpoint = Checkpoint::from_film(film, &settings.key, state);
+    if let Err(e) = checkpoint.write(&filename) {
+        println!("ERROR: writing checkpoint {:?} failed: {}", filename, e);
+    }
+}
+
+/// Removes the checkpoint of a finished render.
+pub fn remove_checkpoint(film: &Film, settings: &CheckpointSettings) {
+    if settings.is_enabled() || settings.resume {
+        let filename: String = checkpoint_filename(film);
+        if std::path::Path::new(&filename).exists() {
+            if let Err(e) = std::fs::remove_file(&filename) {
+                println!("WARNING: removing checkpoint {:?} failed: {}", filename, e);
+            }
+        }
+    }
+}
+
+/// Used by the thread collecting image tiles: records merged tiles
+/// and writes a checkpoint whenever it is due. Tiles which are still
+/// being rendered are neither in the film nor in the completed set,
+/// so a checkpoint is always consistent.
+pub struct TileCheckpointer<'f> {
+    film: &'f Film,
+    settings: CheckpointSettings,
+    completed: Vec<(u32, u32)>,
+    last: Instant,
+}
+
+impl<'f> TileCheckpointer<'f> {
+    pub fn new(film: &'f Film, settings: &CheckpointSettings, completed: Vec<(u32, u32)>) -> Self {
+        TileCheckpointer {
+            film,
+            settings: settings.clone(),
+            completed,
+            last: Instant::now(),
+        }
+    }
+    /// Call after the tile was merged into the film.
+    pub fn tile_done(&mut self, tile: (u32, u32)) {
+        if !self.settings.is_enabled() {
+            return;
+        }
+        self.completed.push(tile);
+        if self.last.elapsed().as_secs_f32() >= self.settings.seconds {
+            save_checkpoint(
+                self.film,
+                &self.settings,
+                IntegratorState::Tiles {
+                    completed: self.completed.clone(),
+                },
+            );
+            self.last = Instant::now();
+        }
+    }
+}
+
+/// Lets worker threads run until a checkpoint is requested. Workers
+/// *enter()* before they change shared state and *leave()* when the
+/// state is consistent again; while the gate is paused no worker
+/// enters, so the checkpoint can be written once the gate is idle.
+#[derive(Default)]
+pub struct CheckpointGate {
+    paused: AtomicBool,
+    active: AtomicUsize,
+}
+
+impl CheckpointGate {
+    pub fn enter(&self) {
+        loop {
+            self.active.fetch_add(1, Ordering::SeqCst);
+            if !self.paused.load(Ordering::SeqCst) {
+                return;
+            }
+            self.active.fetch_sub(1, Ordering::SeqCst);
+            while self.paused.load(Ordering::SeqCst) {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+    pub fn leave(&self) {
+        self.active.fetch_sub(1, Ordering::SeqCst);
+    }
+    pub fn is_paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+    /// Pauses the gate and waits until all workers left.
+    pub fn pause(&self) {
+        self.paused.store(true, Ordering::SeqCst);
+        while self.active.load(Ordering::SeqCst) > 0 {
+            std::thread::sleep(Duration::from_millis(1));
+        }
+    }
+    pub fn resume(&self) {
+        self.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
+
+fn write_string<W: Write>(w: &mut W, s: &str) -> std::io::Result<()> {
+    w.write_u64::<LittleEndian>(s.len() as u64)?;
+    w.write_all(s.as_bytes())
+}
+
+fn read_string<R: Read>(r: &mut R) -> std::io::Result<String> {
+    let len: u64 = r.read_u64::<LittleEndian>()?;
+    if len > CHECKPOINT_MAX_STRING as u64 {
+        return Err(invalid_data("string too long"));
+    }
+    let mut bytes: Vec<u8> = vec![0_u8; len as usize];
+    r.read_exact(&mut bytes)?;
+    String::from_utf8(bytes).map_err(|_| invalid_data("invalid string"))
+}
+
+fn write_spectrum<W: Write>(w: &mut W, s: &Spectrum) -> std::io::Result<()> {
+    for i in 0..Spectrum::N_SAMPLES {
+        w.write_f32::<LittleEndian>(s[i])?;
+    }
+    Ok(())
+}
+
+fn read_spectrum<R: Read>(r: &mut R) -> std::io::Result<Spectrum> {
+    let mut s: Spectrum = Spectrum::default();
+    for i in 0..Spectrum::N_SAMPLES {
+        s[i] = r.read_f32::<LittleEndian>()?;
+    }
+    Ok(s)
+}
+
+fn write_rng<W: Write>(w: &mut W, rng: &Rng) -> std::io::Result<()> {
+    let (state, inc) = rng.get_state();
+    w.write_u64::<LittleEndian>(state)?;
+    w.write_u64::<LittleEndian>(inc)
+}
+
+fn read_rng<R: Read>(r: &mut R) -> std::io::Result<Rng> {
+    let state: u64 = r.read_u64::<LittleEndian>()?;
+    let inc: u64 = r.read_u64::<LittleEndian>()?;
+    let mut rng: Rng = Rng::default();
+    rng.set_state(state, inc);
+    Ok(rng)
+}
+
+fn write_mlt_chain<W: Write>(w: &mut W, chain: &MLTChainState) -> std::io::Result<()> {
+    w.write_u32::<LittleEndian>(chain.chain)?;
+    w.write_u64::<LittleEndian>(chain.mutation)?;
+    w.write_u32::<LittleEndian>(chain.depth)?;
+    w.write_f32::<LittleEndian>(chain.p_current.x)?;
+    w.write_f32::<LittleEndian>(chain.p_current.y)?;
+    write_spectrum(w, &chain.l_current)?;
+    write_rng(w, &chain.rng)?;
+    // the MLTSampler doesn't use sample arrays, store the rest
+    let sampler: &MLTSampler = &chain.sampler;
+    w.write_i64::<LittleEndian>(sampler.samples_per_pixel)?;
+    write_rng(w, &sampler.rng)?;
+    w.write_f32::<LittleEndian>(sampler.sigma)?;
+    w.write_f32::<LittleEndian>(sampler.large_step_probability)?;
+    w.write_i32::<LittleEndian>(sampler.stream_count)?;
+    w.write_u64::<LittleEndian>(sampler.x.len() as u64)?;
+    for xi in sampler.x.iter() {
+        w.write_f32::<LittleEndian>(xi.value)?;
+        w.write_i64::<LittleEndian>(xi.last_modification_iteration)?;
+        w.write_f32::<LittleEndian>(xi.value_backup)?;
+        w.write_i64::<LittleEndian>(xi.modify_backup)?;
+    }
+    w.write_i64::<LittleEndian>(sampler.current_iteration)?;
+    w.write_u8(sampler.large_step as u8)?;
+    w.write_i64::<LittleEndian>(sampler.last_large_step_iteration)?;
+    w.write_i32::<LittleEndian>(sampler.stream_index)?;
+    w.write_i32::<LittleEndian>(sampler.sample_index)
+}
+
+fn read_mlt_chain<R: Read>(r: &mut R) -> std::io::Result<MLTChainState> {
+    let chain: u32 = r.read_u32::<LittleEndian>()?;
+    let mutation: u64 = r.read_u64::<LittleEndian>()?;
+    let depth: u32 = r.read_u32::<LittleEndian>()?;
+    let x: Float = r.read_f32::<LittleEndian>()?;
+    let y: Float = r.read_f32::<LittleEndian>()?;
+    let l_current: Spectrum = read_spectrum(r)?;
+    let rng: Rng = read_rng(r)?;
+    let samples_per_pixel: i64 = r.read_i64::<LittleEndian>()?;
+    let sampler_rng: Rng = read_rng(r)?;
+    let sigma: Float = r.read_f32::<LittleEndian>()?;
+    let large_step_probability: Float = r.read_f32::<LittleEndian>()?;
+    let stream_count: i32 = r.read_i32::<LittleEndian>()?;
+    let mut sampler: MLTSampler = MLTSampler::new(
+        samples_per_pixel,
+        0,
+        sigma,
+        large_step_probability,
+        stream_count,
+    );
+    sampler.rng = sampler_rng;
+    let n: usize = r.read_u64::<LittleEndian>()? as usize;
+    for _ in 0..n {
+        let value: Float = r.read_f32::<LittleEndian>()?;
+        let last_modification_iteration: i64 = r.read_i64::<LittleEndian>()?;
+        let value_backup: Float = r.read_f32::<LittleEndian>()?;
+        let modify_backup: i64 = r.read_i64::<LittleEndian>()?;
+        sampler.x.push(PrimarySample {
+            value,
+            last_modification_iteration,
+            value_backup,
+            modify_backup,
+        });
+    }
+    sampler.current_iteration = r.read_i64::<LittleEndian>()?;
+    sampler.large_step = r.read_u8()? != 0;
+    sampler.last_large_step_iteration = r.read_i64::<LittleEndian>()?;
+    sampler.stream_index = r.read_i32::<LittleEndian>()?;
+    sampler.sample_index = r.read_i32::<LittleEndian>()?;
+    Ok(MLTChainState {
+        chain,
+        mutation,
+        depth,
+        p_current: Point2f { x, y },
+        l_current,
+        rng,
+        sampler,
+    })
+}
@@ -0,0 +1,259 @@
+//! A CPU-only denoiser for low sample count renders. The image is
+//! filtered with an edge-avoiding à-trous wavelet transform (Dammertz
+//! et al., "Edge-Avoiding À-Trous Wavelet Transform for fast Global
+//! Illumination Filtering", 2010) whose edge stopping functions follow
+//! SVGF (Schied et al., 2017):
+//!
+//! - The color is divided by the first-hit albedo before filtering and
+//!   multiplied again afterwards, so textures stay sharp.
+//! - Luminance differences are compared against the standard
+//!   deviation of the pixel estimate, so noisy regions are blurred
+//!   more than converged ones.
+//! - Normals and albedo of the first hit keep geometric and material
+//!   edges.
+
+// others
+use rayon::prelude::*;
+// pbrt
+use crate::core::pbrt::Float;
+
+const ATROUS_ITERATIONS: usize = 5;
+// B3 spline kernel 1/16 [1 4 6 4 1]
+const ATROUS_KERNEL: [Float; 3] = [3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+const SIGMA_LUMINANCE: Float = 4.0;
+const NORMAL_EXPONENT: i32 = 128;
+const SIGMA_ALBEDO: Float = 0.1;
+const MIN_ALBEDO: Float = 1e-3;
+
+/// The auxiliary buffers guiding the denoiser, one entry (or three
+/// for RGB and normals) per pixel.
+pub struct DenoiseGuides {
+    /// First-hit albedo (RGB), zero where nothing was hit
+    pub albedo: Option<Vec<Float>>,
+    /// First-hit shading normal, zero where nothing was hit
+    pub normal: Option<Vec<Float>>,
+    /// Variance of the pixel's luminance estimate, negative if unknown
+    pub variance: Vec<Float>,
+}
+
+fn luminance(c: &[Float]) -> Float {
+    0.212_671 * c[0] + 0.715_160 * c[1] + 0.072_169 * c[2]
+}
+
+/// Denoises *rgb* (three values per pixel, *width* x *height* pixels).
+pub fn denoise(rgb: &[Float], width: usize, height: usize, guides: &DenoiseGuides) -> Vec<Float> {
+    let n_pixels: usize = width * height;
+    assert_eq!(rgb.len(), 3 * n_pixels);
+    assert_eq!(guides.variance.len(), n_pixels);
+    if n_pixels == 0 {
+        return Vec::new();
+    }
+    // demodulate albedo
+    let mut modulation: Vec<Float> = vec![1.0 as Float; 3 * n_pixels];
+    if let Some(ref albedo) = guides.albedo {
+        for (m, a) in modulation.iter_mut().zip(albedo.iter()) {
+            if *a > MIN_ALBEDO {
+                *m = *a;
+            }
+        }
+    }
+    let mut color: Vec<Float> = rgb
+        .iter()
+        .zip(modulation.iter())
+        .map(|(c, m)| c / m)
+        .collect();
+    let mut variance: Vec<Float> = (0..n_pixels)
+        .map(|i| {
+            let lum: Float = luminance(&modulation[3 * i..3 * i + 3]).max(MIN_ALBEDO);
+            guides.variance[i] / (lum * lum)
+        })
+        .collect();
+    estimate_missing_variance(&color, &mut variance, width, height);
+    let normal: Option<Vec<Float>> = guides.normal.as_ref().map(|n| normalize_normals(n));
+    let guide: FilterGuide = FilterGuide {
+        albedo: guides.albedo.as_deref(),
+        normal: normal.as_deref(),
+        width,
+        height,
+    };
+    for iteration in 0..ATROUS_ITERATIONS {
+        let step: usize = 1_usize << iteration;
+        let filtered_variance: Vec<Float> = blur_variance(&variance, width, height);
+        let mut next_color: Vec<Float> = vec![0.0 as Float; 3 * n_pixels];
+        let mut next_variance: Vec<Float> = vec![0.0 as Float; n_pixels];
+        next_color
+            .par_chunks_mut(3 * width)
+            .zip(next_variance.par_chunks_mut(width))
+            .enumerate()
+            .for_each(|(y, (color_row, variance_row))| {
+                for x in 0..width {
+                    let (c, v) =
+                        guide.filter_pixel(x, y, step, &color, &variance, &filtered_variance);
+                    color_row[3 * x..3 * x + 3].copy_from_slice(&c);
+                    variance_row[x] = v;
+                }
+            });
+        color = next_color;
+        variance = next_variance;
+    }
+    // remodulate albedo
+    color
+        .iter()
+        .zip(modulation.iter())
+        .map(|(c, m)| (c * m).max(0.0 as Float))
+        .collect()
+}
+
+struct FilterGuide<'a> {
+    albedo: Option<&'a [Float]>,
+    normal: Option<&'a [Float]>,
+    width: usize,
+    height: usize,
+}
+
+impl<'a> FilterGuide<'a> {
+    fn filter_pixel(
+        &self,
+        x: usize,
+        y: usize,
+        step: usize,
+        color: &[Float],
+        variance: &[Float],
+        filtered_variance: &[Float],
+    ) -> ([Float; 3], Float) {
+        let p: usize = y * self.width + x;
+        let l_p: Float = luminance(&color[3 * p..3 * p + 3]);
+        let sigma_l: Float =
+            SIGMA_LUMINANCE * filtered_variance[p].max(0.0 as Float).sqrt() + 1e-4 as Float;
+        let mut c_sum: [Float; 3] = [0.0 as Float; 3];
+        let mut v_sum: Float = 0.0 as Float;
+        let mut w_sum: Float = 0.0 as Float;
+        for dy in -2_i32..=2 {
+            let qy: i64 = y as i64 + (dy as i64) * step as i64;
+            if qy < 0 || qy >= self.height as i64 {
+                continue;
+            }
+            for dx in -2_i32..=2 {
+                let qx: i64 = x as i64 + (dx as i64) * step as i64;
+                if qx < 0 || qx >= self.width as i64 {
+                    continue;
+                }
+                let q: usize = qy as usize * self.width + qx as usize;
+                let h: Float = ATROUS_KERNEL[dx.unsigned_abs() as usize]
+                    * ATROUS_KERNEL[dy.unsigned_abs() as usize];
+                let c_q: &[Float] = &color[3 * q..3 * q + 3];
+                let w_l: Float = (-(l_p - luminance(c_q)).abs() / sigma_l).exp();
+                let w: Float = h * w_l * self.normal_weight(p, q) * self.albedo_weight(p, q);
+                for c in 0..3 {
+                    c_sum[c] += w * c_q[c];
+                }
+                v_sum += w * w * variance[q];
+                w_sum += w;
+            }
+        }
+        if w_sum <= 0.0 as Float {
+            let mut c_p: [Float; 3] = [0.0 as Float; 3];
+            c_p.copy_from_slice(&color[3 * p..3 * p + 3]);
+            return (c_p, variance[p]);
+        }
+        let inv_w_sum: Float = 1.0 as Float / w_sum;
+        (
+            [
+                c_sum[0] * inv_w_sum,
+                c_sum[1] * inv_w_sum,
+                c_sum[2] * inv_w_sum,
+            ],
+            v_sum * inv_w_sum * inv_w_sum,
+        )
+    }
+    fn normal_weight(&self, p: usize, q: usize) -> Float {
+        if let Some(normal) = self.normal {
+            let n_p: &[Float] = &normal[3 * p..3 * p + 3];
+            let n_q: &[Float] = &normal[3 * q..3 * q + 3];
+            let hit_p: bool = n_p.iter().any(|v| *v != 0.0 as Float);
+            let hit_q: bool = n_q.iter().any(|v| *v != 0.0 as Float);
+            if hit_p != hit_q {
+                // don't mix background and geometry
+                return 0.0 as Float;
+            }
+            if hit_p {
+                let d: Float = n_p[0] * n_q[0] + n_p[1] * n_q[1] + n_p[2] * n_q[2];
+                return d.max(0.0 as Float).powi(NORMAL_EXPONENT);
+            }
+        }
+        1.0 as Float
+    }
+    fn albedo_weight(&self, p: usize, q: usize) -> Float {
+        if let Some(albedo) = self.albedo {
+            let mut dist2: Float = 0.0 as Float;
+            for c in 0..3 {
+                let d: Float = albedo[3 * p + c] - albedo[3 * q + c];
+                dist2 += d * d;
+            }
+            return (-dist2 / (SIGMA_ALBEDO * SIGMA_ALBEDO)).exp();
+        }
+        1.0 as Float
+    }
+}
+
+fn normalize_normals(normal: &[Float]) -> Vec<Float> {
+    let mut result: Vec<Float> = normal.to_vec();
+    for n in result.chunks_mut(3) {
+        let length: Float = (n[0] * n[0] + n[1] * n[1] + n[2] * n[2]).sqrt();
+        if length > 0.0 as Float {
+            for v in n.iter_mut() {
+                *v /= length;
+            }
+        }
+    }
+    result
+}
+
+/// Pixels without a variance estimate (e.g. only splats or a single
+/// sample) use the luminance variance of their 3x3 neighborhood.
+fn estimate_missing_variance(color: &[Float], variance: &mut [Float], width: usize, height: usize) {
+    for y in 0..height {
+        for x in 0..width {
+            let p: usize = y * width + x;
+            if variance[p] >= 0.0 as Float {
+                continue;
+            }
+            let mut sum: Float = 0.0 as Float;
+            let mut sum2: Float = 0.0 as Float;
+            let mut n: Float = 0.0 as Float;
+            for qy in y.saturating_sub(1)..(y + 2).min(height) {
+                for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let q: usize = qy * width + qx;
+                    let l: Float = luminance(&color[3 * q..3 * q + 3]);
+                    sum += l;
+                    sum2 += l * l;
+                    n += 1.0 as Float;
+                }
+            }
+            let mean: Float = sum / n;
+            variance[p] = (sum2 / n - mean * mean).max(0.0 as Float);
+        }
+    }
+}
+
+/// 3x3 Gaussian blur of the variance to make the edge stopping
+/// function more robust.
+fn blur_variance(variance: &[Float], width: usize, height: usize) -> Vec<Float> {
+    let kernel: [Float; 2] = [0.25, 0.125];
+    let mut result: Vec<Float> = vec![0.0 as Float; variance.len()];
+    for y in 0..height {
+        for x in 0..width {
+            let mut sum: Float = 0.0 as Float;
+            let mut w_sum: Float = 0.0 as Float;
+            for qy in y.saturating_sub(1)..(y + 2).min(height) {
+                for qx in x.saturating_sub(1)..(x + 2).min(width) {
+                    let w: Float = kernel[(qx != x) as usize] * kernel[(qy != y) as usize];
+                    sum += w * variance[qy * width + qx];
+                    w_sum += w;
+                }
+            }
+            result[y * width + x] = sum / w_sum;
+        }
+    }
+    result
+}
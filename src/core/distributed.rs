@@ -0,0 +1,369 @@
+//! Spreads the tiles of one frame over several processes, on one or
+//! more machines. A coordinator listens on a TCP address and hands
+//! out ranges of the tiles of a [BlockQueue] (see *select_blocks*)
+//! to workers, which send the rendered film tiles back. The
+//! coordinator merges them into its **Film** and writes the image
+//! once every tile arrived. All processes parse the same scene file.
+//!
+//! If a worker disconnects before its range is finished, the range is
+//! handed out again. Tiles render the same in every process, and
+//! tiles that already arrived are ignored.
+
+// std
+use std::collections::{HashSet, VecDeque};
+use std::io::{BufReader, BufWriter, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::Mutex;
+use std::time::Duration;
+// others
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+// pbrt
+use crate::blockqueue::BlockQueue;
+use crate::core::film::{Film, FilmTile};
+use crate::core::geometry::{Bounds2i, Point2f, Point2i, Vector2i};
+use crate::core::integrator::Integrator;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::scene::Scene;
+
+// worker -> coordinator
+const MSG_REQUEST: u8 = 0;
+const MSG_TILE: u8 = 1;
+// coordinator -> worker
+const REPLY_DONE: u8 = 0;
+const REPLY_RANGE: u8 = 1;
+const REPLY_WAIT: u8 = 2;
+
+/// The size of the (square) tiles used by the tile based integrators.
+const TILE_SIZE: i32 = 16;
+
+#[derive(Debug, Clone)]
+pub enum DistributedMode {
+    /// hand out *blocks_per_request* tiles at a time to workers
+    /// connecting to *address* and merge the results
+    Coordinator {
+        address: String,
+        blocks_per_request: usize,
+    },
+    /// render tiles for the coordinator at *address*
+    Worker { address: String },
+}
+
+struct CoordinatorState {
+    /// tile ranges (start, count) not handed out yet
+    pending: VecDeque<(usize, usize)>,
+    /// tiles merged into the film
+    merged: HashSet<(u32, u32)>,
+    progress: pbr::ProgressBar<std::io::Stdout>,
+}
+
+/// The same queue of 16x16 tiles the tile based integrators use.
+fn film_block_queue(film: &Film, select_blocks: (usize, usize)) -> BlockQueue {
+    let sample_extent: Vector2i = film.get_sample_bounds().diagonal();
+    let n_x_tiles: i32 = (sample_extent.x + TILE_SIZE - 1) / TILE_SIZE;
+    let n_y_tiles: i32 = (sample_extent.y + TILE_SIZE - 1) / TILE_SIZE;
+    BlockQueue::new(
+        (
+            (n_x_tiles * TILE_SIZE) as u32,
+            (n_y_tiles * TILE_SIZE) as u32,
+        ),
+        (TILE_SIZE as u32, TILE_SIZE as u32),
+        select_blocks,
+    )
+}
+
+fn tile_bounds(film: &Film, block: (u32, u32)) -> Bounds2i {
+    let sample_bounds: Bounds2i = film.get_sample_bounds();
+    let x0: i32 = sample_bounds.p_min.x + block.0 as i32 * TILE_SIZE;
+    let x1: i32 = std::cmp::min(x0 + TILE_SIZE, sample_bounds.p_max.x);
+    let y0: i32 = sample_bounds.p_min.y + block.1 as i32 * TILE_SIZE;
+    let y1: i32 = std::cmp::min(y0 + TILE_SIZE, sample_bounds.p_max.y);
+    Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 })
+}
+
+/// The splat scale used by the integrator to write its image, or
+/// None if it doesn't render in tiles.
+fn splat_scale(integrator: &Integrator) -> Option<Float> {
+    match integrator {
+        Integrator::BDPT(integrator) => {
+            Some(1.0 as Float / integrator.get_sampler().get_samples_per_pixel() as Float)
+        }
+        Integrator::Sampler(_) => Some(1.0 as Float),
+        _ => None,
+    }
+}
+
+/// Hands out the tiles of the integrator's film to workers and
+/// writes the image once all tiles were merged. The scene itself is
+/// not needed by the coordinator.
+pub fn run_coordinator(integrator: &Integrator, address: &str, blocks_per_request: usize) {
+    let splat_scale: Float = match splat_scale(integrator) {
+        Some(scale) => scale,
+        None => {
+            println!(
+                "ERROR: distributed rendering is only supported by BDPT and sampler integrators"
+            );
+            return;
+        }
+    };
+    let film = integrator.get_camera().get_film();
+    let n_blocks: usize = film_block_queue(&film, (0, 0)).len();
+    let blocks_per_request: usize = std::cmp::max(blocks_per_request, 1);
+    let listener: TcpListener = match TcpListener::bind(address) {
+        Ok(listener) => listener,
+        Err(e) => {
+            println!("ERROR: can't listen on {:?}: {}", address, e);
+            return;
+        }
+    };
+    if let Err(e) = listener.set_nonblocking(true) {
+        println!("ERROR: {}", e);
+        return;
+    }
+    println!(
+        "Waiting for workers on {:?} to render {} tiles ...",
+        address, n_blocks
+    );
+    let state: Mutex<CoordinatorState> = Mutex::new(CoordinatorState {
+        pending: (0..n_blocks)
+            .step_by(blocks_per_request)
+            .map(|start| (start, std::cmp::min(blocks_per_request, n_blocks - start)))
+            .collect(),
+        merged: HashSet::new(),
+        progress: pbr::ProgressBar::new(n_blocks as u64),
+    });
+    {
+        let film = &film;
+        let state = &state;
+        crossbeam::scope(|scope| {
+            while state.lock().unwrap().merged.len() < n_blocks {
+                match listener.accept() {
+                    Ok((stream, peer)) => {
+                        scope.spawn(move |_| {
+                            if let Err(e) = serve_worker(stream, film, state, n_blocks) {
+                                println!("WARNING: worker {} failed: {}", peer, e);
+                            }
+                        });
+                    }
+                    Err(ref e) if e.kind() == std::io::ErrorKind::WouldBlock => {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    Err(e) => {
+                        println!("ERROR: accepting workers failed: {}", e);
+                        break;
+                    }
+                }
+            }
+        })
+        .unwrap();
+    }
+    let mut state = state.into_inner().unwrap();
+    if state.merged.len() == n_blocks {
+        state.progress.finish();
+        film.write_image(splat_scale);
+    }
+}
+
+fn serve_worker(
+    stream: TcpStream,
+    film: &Film,
+    state: &Mutex<CoordinatorState>,
+    n_blocks: usize,
+) -> std::io::Result<()> {
+    // the range the worker is rendering
+    let mut current: Option<(usize, usize)> = None;
+    let result: std::io::Result<()> = serve_requests(stream, film, state, n_blocks, &mut current);
+    // hand out an unfinished range again
+    if let Some((start, count)) = current {
+        let mut state = state.lock().unwrap();
+        let unfinished: bool = film_block_queue(film, (start, count))
+            .iter()
+            .any(|block| !state.merged.contains(&block));
+        if unfinished {
+            state.pending.push_front((start, count));
+        }
+    }
+    result
+}
+
+fn serve_requests(
+    stream: TcpStream,
+    film: &Film,
+    state: &Mutex<CoordinatorState>,
+    n_blocks: usize,
+    current: &mut Option<(usize, usize)>,
+) -> std::io::Result<()> {
+    stream.set_nonblocking(false)?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        let msg: u8 = match reader.read_u8() {
+            Ok(msg) => msg,
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof => return Ok(()),
+            Err(e) => return Err(e),
+        };
+        match msg {
+            MSG_REQUEST => {
+                // the previous range (if any) is complete
+                *current = None;
+                let mut state = state.lock().unwrap();
+                if let Some((start, count)) = state.pending.pop_front() {
+                    *current = Some((start, count));
+                    writer.write_u8(REPLY_RANGE)?;
+                    writer.write_u64::<LittleEndian>(start as u64)?;
+                    writer.write_u64::<LittleEndian>(count as u64)?;
+                } else if state.merged.len() < n_blocks {
+                    // ranges of other workers might be handed out again
+                    writer.write_u8(REPLY_WAIT)?;
+                } else {
+                    writer.write_u8(REPLY_DONE)?;
+                }
+                writer.flush()?;
+            }
+            MSG_TILE => {
+                let block: (u32, u32) = (
+                    reader.read_u32::<LittleEndian>()?,
+                    reader.read_u32::<LittleEndian>()?,
+                );
+                // only accept tiles of the range handed to this worker
+                // (which also rejects tiles outside of the film)
+                let in_range: bool = match *current {
+                    Some(range) => film_block_queue(film, range).iter().any(|b| b == block),
+                    None => false,
+                };
+                if !in_range {
+                    return Err(std::io::Error::new(
+                        std::io::ErrorKind::InvalidData,
+                        format!("tile {:?} is not part of the worker's range", block),
+                    ));
+                }
+                let mut film_tile: FilmTile = film.get_film_tile(&tile_bounds(film, block));
+                film_tile.read_pixels(&mut reader)?;
+                let n_splats: usize = reader.read_u64::<LittleEndian>()? as usize;
+                // don't trust _n_splats_ for pre-allocation
+                let mut splats: Vec<(Point2f, Spectrum)> = Vec::new();
+                for _ in 0..n_splats {
+                    let x: Float = reader.read_f32::<LittleEndian>()?;
+                    let y: Float = reader.read_f32::<LittleEndian>()?;
+                    let mut l: Spectrum = Spectrum::default();
+                    for i in 0..Spectrum::N_SAMPLES {
+                        l[i] = reader.read_f32::<LittleEndian>()?;
+                    }
+                    splats.push((Point2f { x, y }, l));
+                }
+                let mut state = state.lock().unwrap();
+                if state.merged.insert(block) {
+                    // merge image tile into _Film_
+                    film.merge_film_tile(&film_tile);
+                    for (p_film, l) in splats.iter() {
+                        film.add_splat(*p_film, l);
+                    }
+                    state.progress.inc();
+                }
+            }
+            _ => {
+                return Err(std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    "unknown message",
+                ));
+            }
+        }
+    }
+}
+
+fn write_tile<W: Write>(
+    w: &mut W,
+    block: (u32, u32),
+    film_tile: &FilmTile,
+    splats: &[(Point2f, Spectrum)],
+) -> std::io::Result<()> {
+    w.write_u8(MSG_TILE)?;
+    w.write_u32::<LittleEndian>(block.0)?;
+    w.write_u32::<LittleEndian>(block.1)?;
+    film_tile.write_pixels(w)?;
+    w.write_u64::<LittleEndian>(splats.len() as u64)?;
+    for (p_film, l) in splats.iter() {
+        w.write_f32::<LittleEndian>(p_film.x)?;
+        w.write_f32::<LittleEndian>(p_film.y)?;
+        for i in 0..Spectrum::N_SAMPLES {
+            w.write_f32::<LittleEndian>(l[i])?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders tile ranges for the coordinator at *address* until all
+/// tiles of the frame are done.
+pub fn run_worker(integrator: &mut Integrator, scene: &Scene, num_threads: u8, address: &str) {
+    if splat_scale(integrator).is_none() {
+        println!("ERROR: distributed rendering is only supported by BDPT and sampler integrators");
+        return;
+    }
+    if let Integrator::BDPT(_) = integrator {
+        if scene.lights.is_empty() {
+            println!("ERROR: BDPT needs at least one light");
+            return;
+        }
+    }
+    // the coordinator might still be parsing the scene
+    let mut stream: Option<TcpStream> = None;
+    for _ in 0..100 {
+        match TcpStream::connect(address) {
+            Ok(s) => {
+                stream = Some(s);
+                break;
+            }
+            Err(_) => std::thread::sleep(Duration::from_millis(100)),
+        }
+    }
+    let stream: TcpStream = match stream {
+        Some(stream) => stream,
+        None => {
+            println!("ERROR: can't connect to coordinator {:?}", address);
+            return;
+        }
+    };
+    integrator.preprocess(scene);
+    if let Err(e) = work(integrator, scene, num_threads, stream) {
+        println!("ERROR: lost connection to coordinator {:?}: {}", address, e);
+    }
+}
+
+fn work(
+    integrator: &Integrator,
+    scene: &Scene,
+    num_threads: u8,
+    stream: TcpStream,
+) -> std::io::Result<()> {
+    let film = integrator.get_camera().get_film();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut writer = BufWriter::new(stream);
+    loop {
+        writer.write_u8(MSG_REQUEST)?;
+        writer.flush()?;
+        match reader.read_u8()? {
+            REPLY_RANGE => {
+                let start: usize = reader.read_u64::<LittleEndian>()? as usize;
+                let count: usize = reader.read_u64::<LittleEndian>()? as usize;
+                println!("Rendering tiles {} to {} ...", start, start + count - 1);
+                let block_queue: BlockQueue = film_block_queue(&film, (start, count));
+                let mut result: std::io::Result<()> = Ok(());
+                let writer = &mut writer;
+                integrator.render_tiles(
+                    scene,
+                    num_threads,
+                    &block_queue,
+                    |block, film_tile, splats| {
+                        if result.is_ok() {
+                            result = write_tile(writer, block, film_tile, splats);
+                        }
+                    },
+                );
+                result?;
+            }
+            REPLY_WAIT => std::thread::sleep(Duration::from_millis(100)),
+            _ => break,
+        }
+    }
+    println!("All tiles done.");
+    Ok(())
+}
@@ -63,6 +63,9 @@ impl EFloat {
     pub fn upper_bound(&self) -> f32 {
         self.high
     }
+    pub fn get_absolute_error(&self) -> f32 {
+        next_float_up((self.high - self.v).abs().max((self.v - self.low).abs()))
+    }
 }
 
 impl PartialEq for EFloat {
@@ -10,35 +10,82 @@
 //!
 
 // std
-use std::ops::{DerefMut, Index};
+use std::io::{Read, Write};
+use std::ops::DerefMut;
 use std::path::Path;
 use std::sync::{Arc, RwLock, RwLockWriteGuard};
 
 // others
-#[cfg(feature = "openexr")]
-use openexr::{FrameBuffer, Header, PixelType, ScanlineOutputFile};
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 use smallvec::SmallVec;
 // pbrt
+use crate::core::denoise::{denoise, DenoiseGuides};
 use crate::core::filter::Filter;
 use crate::core::geometry::{
     bnd2_intersect_bnd2i, pnt2_ceil, pnt2_floor, pnt2_inside_exclusivei, pnt2_max_pnt2i,
     pnt2_min_pnt2i,
 };
 use crate::core::geometry::{Bounds2f, Bounds2i, Point2f, Point2i, Vector2f};
+use crate::core::imageio::{write_image, write_image_layers, ImageFileFormat, ImageLayer};
 use crate::core::paramset::ParamSet;
-use crate::core::pbrt::{clamp_t, gamma_correct};
+use crate::core::pbrt::clamp_t;
 use crate::core::pbrt::{Float, Spectrum};
+use crate::core::rawfilm::RawFilm;
 use crate::core::spectrum::xyz_to_rgb;
 
 // see film.h
 
 const FILTER_TABLE_WIDTH: usize = 16;
 
+/// Running mean and variance (Welford) of the luminance of the
+/// samples taken within a single pixel.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PixelEstimate {
+    pub n: u32,
+    pub mean: Float,
+    pub m2: Float,
+}
+
+impl PixelEstimate {
+    pub fn add(&mut self, y: Float) {
+        self.n += 1;
+        let delta: Float = y - self.mean;
+        self.mean += delta / self.n as Float;
+        self.m2 += delta * (y - self.mean);
+    }
+    /// Combines two estimates of the same pixel (Chan et al.).
+    pub fn merge(&mut self, other: &PixelEstimate) {
+        if other.n == 0 {
+            return;
+        }
+        let n: u32 = self.n + other.n;
+        let delta: Float = other.mean - self.mean;
+        self.mean += delta * other.n as Float / n as Float;
+        self.m2 += other.m2 + delta * delta * self.n as Float * other.n as Float / n as Float;
+        self.n = n;
+    }
+    pub fn variance(&self) -> Float {
+        if self.n < 2 {
+            return 0.0 as Float;
+        }
+        self.m2 / (self.n - 1) as Float
+    }
+    /// Variance of the mean relative to the squared mean, dark pixels
+    /// are compared against a small floor instead of zero.
+    pub fn relative_variance(&self) -> Float {
+        if self.n == 0 {
+            return Float::INFINITY;
+        }
+        self.variance() / (self.n as Float * (self.mean * self.mean).max(1e-3 as Float))
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Pixel {
     xyz: [Float; 3],
     filter_weight_sum: Float,
     splat_xyz: [Float; 3],
+    estimate: PixelEstimate,
     // pad: Float,
 }
 
@@ -48,15 +95,193 @@ impl Default for Pixel {
             xyz: [0.0 as Float; 3],
             filter_weight_sum: 0.0 as Float,
             splat_xyz: [Float::default(), Float::default(), Float::default()],
+            estimate: PixelEstimate::default(),
             // pad: 0.0 as Float,
         }
     }
 }
 
+impl Pixel {
+    /// Writes the pixel state (XYZ, filter weight sum, splats and
+    /// estimate) in little endian byte order.
+    pub fn write_state<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        for v in self.xyz.iter() {
+            w.write_f32::<LittleEndian>(*v)?;
+        }
+        w.write_f32::<LittleEndian>(self.filter_weight_sum)?;
+        for v in self.splat_xyz.iter() {
+            w.write_f32::<LittleEndian>(*v)?;
+        }
+        w.write_u32::<LittleEndian>(self.estimate.n)?;
+        w.write_f32::<LittleEndian>(self.estimate.mean)?;
+        w.write_f32::<LittleEndian>(self.estimate.m2)?;
+        Ok(())
+    }
+    /// Adds the samples and splats of another pixel.
+    pub fn add(&mut self, other: &Pixel) {
+        for i in 0..3 {
+            self.xyz[i] += other.xyz[i];
+            self.splat_xyz[i] += other.splat_xyz[i];
+        }
+        self.filter_weight_sum += other.filter_weight_sum;
+        self.estimate.merge(&other.estimate);
+    }
+    /// True if any splat was added to the pixel.
+    pub fn has_splats(&self) -> bool {
+        self.splat_xyz.iter().any(|v| *v != 0.0 as Float)
+    }
+    /// Converts the pixel to RGB: the filtered samples normalized by
+    /// the filter weight sum plus the splats scaled by *splat_scale*.
+    pub fn to_rgb(&self, splat_scale: Float) -> [Float; 3] {
+        let mut rgb: [Float; 3] = [0.0 as Float; 3];
+        // convert pixel XYZ color to RGB
+        xyz_to_rgb(&self.xyz, &mut rgb);
+        // normalize pixel with weight sum
+        let filter_weight_sum: Float = self.filter_weight_sum;
+        if filter_weight_sum != 0.0 as Float {
+            let inv_wt: Float = 1.0 as Float / filter_weight_sum;
+            rgb[0] = (rgb[0] * inv_wt).max(0.0 as Float);
+            rgb[1] = (rgb[1] * inv_wt).max(0.0 as Float);
+            rgb[2] = (rgb[2] * inv_wt).max(0.0 as Float);
+        }
+        // add splat value at pixel
+        let mut splat_rgb: [Float; 3] = [0.0 as Float; 3];
+        xyz_to_rgb(&self.splat_xyz, &mut splat_rgb);
+        rgb[0] += splat_scale * splat_rgb[0];
+        rgb[1] += splat_scale * splat_rgb[1];
+        rgb[2] += splat_scale * splat_rgb[2];
+        rgb
+    }
+    /// Reads a pixel state written by *write_state()*.
+    pub fn read_state<R: Read>(r: &mut R) -> std::io::Result<Pixel> {
+        let mut pixel: Pixel = Pixel::default();
+        for v in pixel.xyz.iter_mut() {
+            *v = r.read_f32::<LittleEndian>()?;
+        }
+        pixel.filter_weight_sum = r.read_f32::<LittleEndian>()?;
+        for v in pixel.splat_xyz.iter_mut() {
+            *v = r.read_f32::<LittleEndian>()?;
+        }
+        pixel.estimate.n = r.read_u32::<LittleEndian>()?;
+        pixel.estimate.mean = r.read_f32::<LittleEndian>()?;
+        pixel.estimate.m2 = r.read_f32::<LittleEndian>()?;
+        Ok(pixel)
+    }
+}
+
+/// Arbitrary output variables (AOVs) which can be written next to
+/// the rendered image. They are computed from the first
+/// **SurfaceInteraction** along each camera ray and requested via
+/// the **Film**'s *aovs* parameter, a list of names separated by
+/// commas or spaces, e.g. `"string aovs" [ "depth normal albedo" ]`.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Aov {
+    /// Distance from the camera to the first hit
+    Depth,
+    /// Shading normal (world space) at the first hit
+    Normal,
+    /// Reflectance of the BSDF at the first hit
+    Albedo,
+    /// World space position of the first hit
+    Position,
+    /// Identifies the **Shape** statement of the first hit (0 for background)
+    PrimitiveId,
+    /// Identifies the material of the first hit (0 for background)
+    MaterialId,
+    /// Number of camera samples taken within the pixel
+    SampleCount,
+}
+
+impl Aov {
+    pub fn from_name(name: &str) -> Option<Aov> {
+        match name {
+            "depth" => Some(Aov::Depth),
+            "normal" => Some(Aov::Normal),
+            "albedo" => Some(Aov::Albedo),
+            "position" => Some(Aov::Position),
+            "primid" => Some(Aov::PrimitiveId),
+            "materialid" => Some(Aov::MaterialId),
+            "samplecount" => Some(Aov::SampleCount),
+            _ => None,
+        }
+    }
+    pub fn name(&self) -> &'static str {
+        match self {
+            Aov::Depth => "depth",
+            Aov::Normal => "normal",
+            Aov::Albedo => "albedo",
+            Aov::Position => "position",
+            Aov::PrimitiveId => "primid",
+            Aov::MaterialId => "materialid",
+            Aov::SampleCount => "samplecount",
+        }
+    }
+    pub fn channel_names(&self) -> Vec<&'static str> {
+        match self {
+            Aov::Normal | Aov::Position => vec!["X", "Y", "Z"],
+            Aov::Albedo => vec!["R", "G", "B"],
+            Aov::Depth => vec!["Z"],
+            Aov::PrimitiveId | Aov::MaterialId => vec!["id"],
+            Aov::SampleCount => vec!["count"],
+        }
+    }
+    /// Number of values an integrator provides per camera sample.
+    pub fn num_values(&self) -> usize {
+        match self {
+            Aov::Normal | Aov::Position | Aov::Albedo => 3,
+            Aov::Depth | Aov::PrimitiveId | Aov::MaterialId => 1,
+            Aov::SampleCount => 0,
+        }
+    }
+    /// Number of floats stored per pixel. Ids are not filtered, the
+    /// id of the sample with the largest filter weight wins, so the
+    /// weight is stored as well.
+    fn storage_size(&self) -> usize {
+        match self {
+            Aov::PrimitiveId | Aov::MaterialId => 2,
+            Aov::SampleCount => 1,
+            _ => self.num_values(),
+        }
+    }
+}
+
+// The AOVs of a pixel start with the filter weight sum of the samples
+// which recorded AOVs, which normalizes the filtered AOVs.
+const AOV_WEIGHT_SUM_SIZE: usize = 1;
+
+fn aovs_storage_size(aovs: &[Aov]) -> usize {
+    if aovs.is_empty() {
+        return 0;
+    }
+    AOV_WEIGHT_SUM_SIZE + aovs.iter().map(|aov| aov.storage_size()).sum::<usize>()
+}
+
+fn merge_aovs(aovs: &[Aov], dst: &mut [Float], src: &[Float]) {
+    dst[0] += src[0];
+    let mut offset: usize = AOV_WEIGHT_SUM_SIZE;
+    for aov in aovs {
+        match aov {
+            Aov::PrimitiveId | Aov::MaterialId => {
+                if src[offset + 1] > dst[offset + 1] {
+                    dst[offset] = src[offset];
+                    dst[offset + 1] = src[offset + 1];
+                }
+            }
+            _ => {
+                for i in offset..offset + aov.storage_size() {
+                    dst[i] += src[i];
+                }
+            }
+        }
+        offset += aov.storage_size();
+    }
+}
+
 #[derive(Debug, Default, Copy, Clone)]
 pub struct FilmTilePixel {
     contrib_sum: Spectrum,
     filter_weight_sum: Float,
+    estimate: PixelEstimate,
 }
 
 pub struct FilmTile<'a> {
@@ -67,6 +292,8 @@ pub struct FilmTile<'a> {
     filter_table_size: usize,
     pixels: Vec<FilmTilePixel>,
     max_sample_luminance: Float,
+    aovs: &'a [Aov],
+    aov_pixels: Vec<Float>,
 }
 
 impl<'a> FilmTile<'a> {
@@ -76,6 +303,7 @@ impl<'a> FilmTile<'a> {
         filter_table: &'a [Float; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
         filter_table_size: usize,
         max_sample_luminance: Float,
+        aovs: &'a [Aov],
     ) -> Self {
         FilmTile {
             pixel_bounds,
@@ -89,6 +317,60 @@ impl<'a> FilmTile<'a> {
             // TODO: pixels = std::vector<FilmTilePixel>(std::max(0, pixelBounds.Area()));
             pixels: vec![FilmTilePixel::default(); pixel_bounds.area() as usize],
             max_sample_luminance,
+            aovs,
+            aov_pixels: vec![0.0 as Float; pixel_bounds.area() as usize * aovs_storage_size(aovs)],
+        }
+    }
+    pub fn has_aovs(&self) -> bool {
+        !self.aovs.is_empty()
+    }
+    /// Add the AOV *values* of a camera sample (in the order of the
+    /// requested AOVs, see **Aov::num_values()**). Filtered AOVs are
+    /// normalized by the filter weights of these samples only.
+    pub fn add_aov_sample(&mut self, p_film: Point2f, values: &[Float]) {
+        if self.aovs.is_empty() {
+            return;
+        }
+        let storage_size: usize = aovs_storage_size(self.aovs);
+        for (idx, filter_weight) in self.filter_footprint(p_film) {
+            let pixel_aovs: &mut [Float] =
+                &mut self.aov_pixels[idx * storage_size..(idx + 1) * storage_size];
+            pixel_aovs[0] += filter_weight;
+            let mut offset: usize = AOV_WEIGHT_SUM_SIZE;
+            let mut value_offset: usize = 0;
+            for aov in self.aovs {
+                match aov {
+                    Aov::PrimitiveId | Aov::MaterialId => {
+                        if filter_weight > pixel_aovs[offset + 1] {
+                            pixel_aovs[offset] = values[value_offset];
+                            pixel_aovs[offset + 1] = filter_weight;
+                        }
+                    }
+                    Aov::SampleCount => {}
+                    _ => {
+                        for i in 0..aov.num_values() {
+                            pixel_aovs[offset + i] += values[value_offset + i] * filter_weight;
+                        }
+                    }
+                }
+                offset += aov.storage_size();
+                value_offset += aov.num_values();
+            }
+        }
+        // count the sample for the pixel it was taken in
+        let pi: Point2i = Point2i {
+            x: p_film.x.floor() as i32,
+            y: p_film.y.floor() as i32,
+        };
+        if pnt2_inside_exclusivei(pi, &self.pixel_bounds) {
+            let idx: usize = self.get_pixel_index(pi.x, pi.y);
+            let mut offset: usize = AOV_WEIGHT_SUM_SIZE;
+            for aov in self.aovs {
+                if *aov == Aov::SampleCount {
+                    self.aov_pixels[idx * storage_size + offset] += 1.0 as Float;
+                }
+                offset += aov.storage_size();
+            }
         }
     }
     pub fn add_sample(&mut self, p_film: Point2f, l: &mut Spectrum, sample_weight: Float) {
@@ -96,6 +378,82 @@ impl<'a> FilmTile<'a> {
         if l.y() > self.max_sample_luminance {
             *l *= Spectrum::new(self.max_sample_luminance / l.y());
         }
+        for (idx, filter_weight) in self.filter_footprint(p_film) {
+            // update pixel values with filtered sample contribution
+            let pixel = &mut self.pixels[idx];
+            pixel.contrib_sum += *l * Spectrum::new(sample_weight) * Spectrum::new(filter_weight);
+            pixel.filter_weight_sum += filter_weight;
+        }
+        // update the estimate of the pixel the sample was taken in
+        let pi: Point2i = Point2i {
+            x: p_film.x.floor() as i32,
+            y: p_film.y.floor() as i32,
+        };
+        if pnt2_inside_exclusivei(pi, &self.pixel_bounds) {
+            let idx: usize = self.get_pixel_index(pi.x, pi.y);
+            self.pixels[idx].estimate.add(l.y() * sample_weight);
+        }
+    }
+    /// Returns the estimate of the samples taken within pixel *p*
+    /// which were added to this tile.
+    pub fn get_pixel_estimate(&self, p: Point2i) -> PixelEstimate {
+        if pnt2_inside_exclusivei(p, &self.pixel_bounds) {
+            self.pixels[self.get_pixel_index(p.x, p.y)].estimate
+        } else {
+            PixelEstimate::default()
+        }
+    }
+    /// Writes the accumulated pixel values of the tile in little
+    /// endian byte order (e.g. to send it to another process).
+    pub fn write_pixels<W: Write>(&self, w: &mut W) -> std::io::Result<()> {
+        w.write_u64::<LittleEndian>(self.pixels.len() as u64)?;
+        for pixel in self.pixels.iter() {
+            for i in 0..Spectrum::N_SAMPLES {
+                w.write_f32::<LittleEndian>(pixel.contrib_sum[i])?;
+            }
+            w.write_f32::<LittleEndian>(pixel.filter_weight_sum)?;
+            w.write_u32::<LittleEndian>(pixel.estimate.n)?;
+            w.write_f32::<LittleEndian>(pixel.estimate.mean)?;
+            w.write_f32::<LittleEndian>(pixel.estimate.m2)?;
+        }
+        w.write_u64::<LittleEndian>(self.aov_pixels.len() as u64)?;
+        for v in self.aov_pixels.iter() {
+            w.write_f32::<LittleEndian>(*v)?;
+        }
+        Ok(())
+    }
+    /// Reads pixel values written by *write_pixels()* for a tile with
+    /// the same bounds.
+    pub fn read_pixels<R: Read>(&mut self, r: &mut R) -> std::io::Result<()> {
+        if r.read_u64::<LittleEndian>()? != self.pixels.len() as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "film tile size mismatch",
+            ));
+        }
+        for pixel in self.pixels.iter_mut() {
+            for i in 0..Spectrum::N_SAMPLES {
+                pixel.contrib_sum[i] = r.read_f32::<LittleEndian>()?;
+            }
+            pixel.filter_weight_sum = r.read_f32::<LittleEndian>()?;
+            pixel.estimate.n = r.read_u32::<LittleEndian>()?;
+            pixel.estimate.mean = r.read_f32::<LittleEndian>()?;
+            pixel.estimate.m2 = r.read_f32::<LittleEndian>()?;
+        }
+        if r.read_u64::<LittleEndian>()? != self.aov_pixels.len() as u64 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "film tile AOV size mismatch",
+            ));
+        }
+        for v in self.aov_pixels.iter_mut() {
+            *v = r.read_f32::<LittleEndian>()?;
+        }
+        Ok(())
+    }
+    /// Returns the pixel indices and filter weights of all pixels
+    /// within the filter support around *p_film*.
+    fn filter_footprint(&self, p_film: Point2f) -> SmallVec<[(usize, Float); 16]> {
         // compute sample's raster bounds
         let p_film_discrete: Point2f = p_film - Vector2f { x: 0.5, y: 0.5 };
         let p0f: Point2f = pnt2_ceil(p_film_discrete - self.filter_radius);
@@ -130,20 +488,17 @@ impl<'a> FilmTile<'a> {
                 .abs();
             ify.push(fy.floor().min(self.filter_table_size as Float - 1.0) as usize);
         }
+        let mut footprint: SmallVec<[(usize, Float); 16]> = SmallVec::new();
         for y in p0.y..p1.y {
             for x in p0.x..p1.x {
                 // evaluate filter value at $(x,y)$ pixel
                 let offset: usize =
                     ify[(y - p0.y) as usize] * self.filter_table_size + ifx[(x - p0.x) as usize];
                 let filter_weight: Float = self.filter_table[offset];
-                // update pixel values with filtered sample contribution
-                let idx = self.get_pixel_index(x, y);
-                let pixel = &mut self.pixels[idx];
-                pixel.contrib_sum +=
-                    *l * Spectrum::new(sample_weight) * Spectrum::new(filter_weight);
-                pixel.filter_weight_sum += filter_weight;
+                footprint.push((self.get_pixel_index(x, y), filter_weight));
             }
         }
+        footprint
     }
     fn get_pixel_index(&self, x: i32, y: i32) -> usize {
         let width: i32 = self.pixel_bounds.p_max.x - self.pixel_bounds.p_min.x;
@@ -162,11 +517,22 @@ pub struct Film {
     pub filter: Box<Filter>,
     /// The filename of the output image
     pub filename: String,
+    /// The file format of the output image (derived from the filename)
+    pub file_format: ImageFileFormat,
     /// A crop window that may specify a subset of the image to render
     pub cropped_pixel_bounds: Bounds2i,
+    /// Arbitrary output variables written next to the image
+    pub aovs: Vec<Aov>,
+    /// Optional file for the unnormalized pixel values (see
+    /// [RawFilm](../rawfilm/struct.RawFilm.html))
+    pub raw_filename: String,
+    /// Write a denoised copy of the image (see
+    /// [denoise](../denoise/fn.denoise.html))
+    pub denoise: bool,
 
     // Film Private Data
     pub pixels: RwLock<Vec<Pixel>>,
+    pub aov_pixels: RwLock<Vec<Float>>,
     filter_table: [Float; FILTER_TABLE_WIDTH * FILTER_TABLE_WIDTH],
     scale: Float,
     max_sample_luminance: Float,
@@ -179,8 +545,10 @@ impl Film {
         filter: Box<Filter>,
         diagonal: Float,
         filename: String,
+        file_format: ImageFileFormat,
         scale: Float,
         max_sample_luminance: Float,
+        aovs: Vec<Aov>,
     ) -> Self {
         let cropped_pixel_bounds: Bounds2i = Bounds2i {
             p_min: Point2i {
@@ -214,15 +582,41 @@ impl Film {
             diagonal: diagonal * 0.001,
             filter,
             filename,
+            file_format,
             cropped_pixel_bounds,
             pixels: RwLock::new(vec![Pixel::default(); cropped_pixel_bounds.area() as usize]),
+            aov_pixels: RwLock::new(vec![
+                0.0 as Float;
+                cropped_pixel_bounds.area() as usize
+                    * aovs_storage_size(&aovs)
+            ]),
+            aovs,
+            raw_filename: String::new(),
+            denoise: false,
             filter_table,
             scale,
             max_sample_luminance,
         }
     }
     pub fn create(params: &ParamSet, filter: Box<Filter>, crop_window: &Bounds2f) -> Arc<Film> {
-        let filename: String = params.find_one_string("filename", String::new());
+        let mut filename: String = params.find_one_string("filename", String::new());
+        if filename.is_empty() {
+            filename = String::from("pbrt.exr");
+        }
+        let bit_depth: i32 = params.find_one_int("bitdepth", 8);
+        let file_format: ImageFileFormat = match ImageFileFormat::from_filename(
+            &filename, bit_depth,
+        ) {
+            Some(format) => format,
+            None => {
+                println!(
+                    "WARNING: Can't determine image file type from suffix of filename {:?}; writing \"pbrt.exr\" instead.",
+                    filename
+                );
+                filename = String::from("pbrt.exr");
+                ImageFileFormat::Exr
+            }
+        };
         let xres: i32 = params.find_one_int("xresolution", 1280);
         let yres: i32 = params.find_one_int("yresolution", 720);
         let resolution: Point2i = Point2i { x: xres, y: yres };
@@ -250,15 +644,47 @@ impl Film {
         let diagonal: Float = params.find_one_float("diagonal", 35.0);
         let max_sample_luminance: Float =
             params.find_one_float("maxsampleluminance", std::f32::INFINITY);
-        Arc::new(Film::new(
+        let mut aovs: Vec<Aov> = Vec::new();
+        let aov_names: String = params.find_one_string("aovs", String::new());
+        for name in aov_names.split(|c: char| c == ',' || c.is_whitespace()) {
+            if name.is_empty() {
+                continue;
+            }
+            match Aov::from_name(name) {
+                Some(aov) => {
+                    if !aovs.contains(&aov) {
+                        aovs.push(aov);
+                    }
+                }
+                None => println!("WARNING: Ignoring unknown AOV {:?}", name),
+            }
+        }
+        // the denoiser is guided by the albedo and normals of the first hit
+        let denoise: bool = params.find_one_bool("denoise", false);
+        if denoise {
+            for aov in &[Aov::Albedo, Aov::Normal] {
+                if !aovs.contains(aov) {
+                    aovs.push(*aov);
+                }
+            }
+        }
+        let mut film: Film = Film::new(
             resolution,
             crop,
             filter,
             diagonal,
             filename,
+            file_format,
             scale,
             max_sample_luminance,
-        ))
+            aovs,
+        );
+        film.raw_filename = params.find_one_string("rawfilename", String::new());
+        film.denoise = denoise;
+        Arc::new(film)
+    }
+    pub fn get_scale(&self) -> Float {
+        self.scale
     }
     pub fn get_cropped_pixel_bounds(&self) -> Bounds2i {
         self.cropped_pixel_bounds
@@ -341,6 +767,7 @@ impl Film {
             &self.filter_table,
             FILTER_TABLE_WIDTH,
             self.max_sample_luminance,
+            &self.aovs,
         )
     }
     pub fn merge_film_tile(&self, tile: &FilmTile) {
@@ -365,9 +792,37 @@ impl Film {
                 merge_pixel.xyz[i] += item;
             }
             merge_pixel.filter_weight_sum += tile_pixel.filter_weight_sum;
+            merge_pixel.estimate.merge(&tile_pixel.estimate);
             // write pixel back
             // pixels_write[offset as usize] = *merge_pixel;
         }
+        if tile.has_aovs() {
+            let storage_size: usize = aovs_storage_size(&self.aovs);
+            let width: i32 = self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x;
+            let mut aov_pixels_write = self.aov_pixels.write().unwrap();
+            for pixel in &tile.pixel_bounds {
+                let idx = tile.get_pixel_index(pixel.x, pixel.y);
+                let offset: usize = ((pixel.x - self.cropped_pixel_bounds.p_min.x)
+                    + (pixel.y - self.cropped_pixel_bounds.p_min.y) * width)
+                    as usize;
+                merge_aovs(
+                    &self.aovs,
+                    &mut aov_pixels_write[offset * storage_size..(offset + 1) * storage_size],
+                    &tile.aov_pixels[idx * storage_size..(idx + 1) * storage_size],
+                );
+            }
+        }
+    }
+    /// Returns the luminance estimate (sample count, mean and
+    /// variance) of all samples merged into pixel *p* so far.
+    pub fn get_pixel_estimate(&self, p: Point2i) -> PixelEstimate {
+        if !pnt2_inside_exclusivei(p, &self.cropped_pixel_bounds) {
+            return PixelEstimate::default();
+        }
+        let width: i32 = self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x;
+        let offset: i32 = (p.x - self.cropped_pixel_bounds.p_min.x)
+            + (p.y - self.cropped_pixel_bounds.p_min.y) * width;
+        self.pixels.read().unwrap()[offset as usize].estimate
     }
     pub fn set_image(&self, img: &[Spectrum]) {
         let n_pixels: i32 = self.cropped_pixel_bounds.area();
@@ -434,216 +889,152 @@ impl Film {
         splat_xyz[1] += xyz[1];
         splat_xyz[2] += xyz[2];
     }
-    #[cfg(not(feature = "openexr"))]
     pub fn write_image(&self, splat_scale: Float) {
         let mut rgb: Vec<Float> =
             vec![0.0 as Float; (3 * self.cropped_pixel_bounds.area()) as usize];
         let mut offset;
         for p in &self.cropped_pixel_bounds {
-            // convert pixel XYZ color to RGB
             assert!(pnt2_inside_exclusivei(p, &self.cropped_pixel_bounds));
             let width: i32 = self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x;
             offset = ((p.x - self.cropped_pixel_bounds.p_min.x)
                 + (p.y - self.cropped_pixel_bounds.p_min.y) * width) as usize;
             let pixel: &Pixel = &self.pixels.read().unwrap()[offset];
-
-            let start: usize = 3 * offset;
-            let mut rgb_array: [Float; 3] = [0.0 as Float; 3];
-            xyz_to_rgb(&pixel.xyz, &mut rgb_array); // TODO: Use 'rgb' directly.
-            rgb[start] = rgb_array[0];
-            rgb[start + 1] = rgb_array[1];
-            rgb[start + 2] = rgb_array[2];
-            // normalize pixel with weight sum
-            let filter_weight_sum: Float = pixel.filter_weight_sum;
-            if filter_weight_sum != 0.0 as Float {
-                let inv_wt: Float = 1.0 as Float / filter_weight_sum;
-                rgb[start] = (rgb[start] * inv_wt).max(0.0 as Float);
-                rgb[start + 1] = (rgb[start + 1] * inv_wt).max(0.0 as Float);
-                rgb[start + 2] = (rgb[start + 2] * inv_wt).max(0.0 as Float);
-            }
-            // add splat value at pixel
-            let mut splat_rgb: [Float; 3] = [0.0 as Float; 3];
-            let pixel_splat_xyz: &[Float; 3] = &pixel.splat_xyz;
-            let splat_xyz: [Float; 3] = [
-                *pixel_splat_xyz.index(0),
-                *pixel_splat_xyz.index(1),
-                *pixel_splat_xyz.index(2),
-            ];
-            xyz_to_rgb(&splat_xyz, &mut splat_rgb);
-            rgb[start] += splat_scale * splat_rgb[0];
-            rgb[start + 1] += splat_scale * splat_rgb[1];
-            rgb[start + 2] += splat_scale * splat_rgb[2];
+            let rgb_array: [Float; 3] = pixel.to_rgb(splat_scale);
             // scale pixel value by _scale_
-            rgb[start] *= self.scale;
-            rgb[start + 1] *= self.scale;
-            rgb[start + 2] *= self.scale;
+            let start: usize = 3 * offset;
+            rgb[start] = rgb_array[0] * self.scale;
+            rgb[start + 1] = rgb_array[1] * self.scale;
+            rgb[start + 2] = rgb_array[2] * self.scale;
         }
-        let filename = "pbrt.png";
         println!(
             "Writing image {:?} with bounds {:?}",
-            filename, // TODO: self.filename,
-            self.cropped_pixel_bounds
+            self.filename, self.cropped_pixel_bounds
         );
-        // TODO: pbrt::WriteImage(filename, &rgb[0], croppedPixelBounds, fullResolution);
-        let mut buffer: Vec<u8> = vec![0.0 as u8; (3 * self.cropped_pixel_bounds.area()) as usize];
-        // 8-bit format; apply gamma (see WriteImage(...) in imageio.cpp)
-        let width: u32 =
-            (self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x) as u32;
-        let height: u32 =
-            (self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y) as u32;
-        for y in 0..height {
-            for x in 0..width {
-                // red
-                let index: usize = (3 * (y * width + x)) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
-                // green
-                let index: usize = (3 * (y * width + x) + 1) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
-                // blue
-                let index: usize = (3 * (y * width + x) + 2) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
+        let layers: Vec<ImageLayer> = self.get_aov_layers();
+        if let Err(err) = write_image_layers(
+            &self.filename,
+            self.file_format,
+            &rgb,
+            &layers,
+            &self.cropped_pixel_bounds,
+        ) {
+            println!("ERROR: Unable to write image {:?}: {}", self.filename, err);
+        }
+        if self.denoise {
+            self.write_denoised_image(&rgb, &layers, splat_scale);
+        }
+        if !self.raw_filename.is_empty() {
+            println!("Writing raw film {:?}", self.raw_filename);
+            let raw: RawFilm = RawFilm::from_film(self, splat_scale);
+            if let Err(err) = raw.write(&self.raw_filename) {
+                println!(
+                    "ERROR: Unable to write raw film {:?}: {}",
+                    self.raw_filename, err
+                );
             }
         }
-        // write "pbrt.png" to disk
-        image::save_buffer(
-            Path::new("pbrt.png"),
-            &buffer,
-            width,
-            height,
-            image::ColorType::Rgb8,
-        )
-        .unwrap();
     }
-    #[cfg(feature = "openexr")]
-    pub fn write_image(&self, splat_scale: Float) {
-        let mut rgb: Vec<Float> =
-            vec![0.0 as Float; (3 * self.cropped_pixel_bounds.area()) as usize];
-        let mut exr: Vec<(Float, Float, Float)> = // copy data for OpenEXR image
-            vec![(0.0_f32, 0.0_f32, 0.0_f32); self.cropped_pixel_bounds.area() as usize];
-        let mut offset;
-        for p in &self.cropped_pixel_bounds {
-            // convert pixel XYZ color to RGB
-            assert!(pnt2_inside_exclusivei(p, &self.cropped_pixel_bounds));
-            let width: i32 = self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x;
-            offset = ((p.x - self.cropped_pixel_bounds.p_min.x)
-                + (p.y - self.cropped_pixel_bounds.p_min.y) * width) as usize;
-            let pixel: &Pixel = &self.pixels.read().unwrap()[offset];
-            let start = 3 * offset;
-            let mut rgb_array: [Float; 3] = [0.0 as Float; 3];
-            xyz_to_rgb(&pixel.xyz, &mut rgb_array); // TODO: Use 'rgb' directly.
-            rgb[start] = rgb_array[0];
-            rgb[start + 1] = rgb_array[1];
-            rgb[start + 2] = rgb_array[2];
-            // normalize pixel with weight sum
-            let filter_weight_sum: Float = pixel.filter_weight_sum;
-            if filter_weight_sum != 0.0 as Float {
-                let inv_wt: Float = 1.0 as Float / filter_weight_sum;
-                rgb[start] = (rgb[start] * inv_wt).max(0.0 as Float);
-                rgb[start + 1] = (rgb[start + 1] * inv_wt).max(0.0 as Float);
-                rgb[start + 2] = (rgb[start + 2] * inv_wt).max(0.0 as Float);
-            }
-            // add splat value at pixel
-            let mut splat_rgb: [Float; 3] = [0.0 as Float; 3];
-            let pixel_splat_xyz: &[Float; 3] = &pixel.splat_xyz;
-            let splat_xyz: [Float; 3] = [
-                *pixel_splat_xyz.index(0),
-                *pixel_splat_xyz.index(1),
-                *pixel_splat_xyz.index(2),
-            ];
-            xyz_to_rgb(&splat_xyz, &mut splat_rgb);
-            rgb[start] += splat_scale * splat_rgb[0];
-            rgb[start + 1] += splat_scale * splat_rgb[1];
-            rgb[start + 2] += splat_scale * splat_rgb[2];
-            // scale pixel value by _scale_
-            rgb[start] *= self.scale;
-            rgb[start + 1] *= self.scale;
-            rgb[start + 2] *= self.scale;
-            // copy data for OpenEXR image
-            exr[offset].0 = rgb[start];
-            exr[offset].1 = rgb[start + 1];
-            exr[offset].2 = rgb[start + 2];
-        }
-        let filename = "pbrt.png";
-        println!(
-            "Writing image {:?} with bounds {:?}",
-            filename, // TODO: self.filename,
-            self.cropped_pixel_bounds
-        );
-        // TODO: pbrt::WriteImage(filename, &rgb[0], croppedPixelBounds, fullResolution);
-        let mut buffer: Vec<u8> = vec![0.0 as u8; (3 * self.cropped_pixel_bounds.area()) as usize];
-        // 8-bit format; apply gamma (see WriteImage(...) in imageio.cpp)
-        let width: u32 =
-            (self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x) as u32;
-        let height: u32 =
-            (self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y) as u32;
-        // OpenEXR
-        let filename = "pbrt_rust.exr";
-        println!(
-            "Writing image {:?} with bounds {:?}",
-            filename, // TODO: self.filename,
-            self.cropped_pixel_bounds
-        );
-        let mut file = std::fs::File::create("pbrt_rust.exr").unwrap();
-        let mut output_file = ScanlineOutputFile::new(
-            &mut file,
-            Header::new()
-                .set_resolution(width, height)
-                .add_channel("R", PixelType::FLOAT)
-                .add_channel("G", PixelType::FLOAT)
-                .add_channel("B", PixelType::FLOAT),
-        )
-        .unwrap();
-        let mut fb = FrameBuffer::new(width as u32, height as u32);
-        fb.insert_channels(&["R", "G", "B"], &exr);
-        output_file.write_pixels(&fb).unwrap();
-
-        // OpenEXR
-        for y in 0..height {
-            for x in 0..width {
-                // red
-                let index: usize = (3 * (y * width + x)) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
-                // green
-                let index: usize = (3 * (y * width + x) + 1) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
-                // blue
-                let index: usize = (3 * (y * width + x) + 2) as usize;
-                buffer[index] = clamp_t(
-                    255.0 as Float * gamma_correct(rgb[index]) + 0.5,
-                    0.0 as Float,
-                    255.0 as Float,
-                ) as u8;
+    /// Denoise *rgb* guided by the albedo and normal AOVs and write
+    /// it next to the image (e.g. *pbrt.denoised.exr*).
+    fn write_denoised_image(&self, rgb: &[Float], layers: &[ImageLayer], splat_scale: Float) {
+        let find_layer = |aov: Aov| -> Option<Vec<Float>> {
+            layers
+                .iter()
+                .find(|layer| layer.name == aov.name())
+                .map(|layer| layer.data.clone())
+        };
+        // variance of each pixel's mean, unknown for splats
+        let scale2: Float = self.scale * self.scale;
+        let variance: Vec<Float> = self
+            .pixels
+            .read()
+            .unwrap()
+            .iter()
+            .map(|pixel| {
+                if pixel.estimate.n < 2 || (splat_scale > 0.0 && pixel.has_splats()) {
+                    -1.0 as Float
+                } else {
+                    pixel.estimate.variance() / pixel.estimate.n as Float * scale2
+                }
+            })
+            .collect();
+        let guides: DenoiseGuides = DenoiseGuides {
+            albedo: find_layer(Aov::Albedo),
+            normal: find_layer(Aov::Normal),
+            variance,
+        };
+        let width: usize =
+            (self.cropped_pixel_bounds.p_max.x - self.cropped_pixel_bounds.p_min.x) as usize;
+        let height: usize =
+            (self.cropped_pixel_bounds.p_max.y - self.cropped_pixel_bounds.p_min.y) as usize;
+        let denoised: Vec<Float> = denoise(rgb, width, height, &guides);
+        let path: &Path = Path::new(&self.filename);
+        let stem: String = path
+            .file_stem()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let extension: String = path
+            .extension()
+            .map(|s| s.to_string_lossy().to_string())
+            .unwrap_or_default();
+        let denoised_filename: String = path
+            .with_file_name(format!("{}.denoised.{}", stem, extension))
+            .to_string_lossy()
+            .to_string();
+        println!("Writing denoised image {:?}", denoised_filename);
+        if let Err(err) = write_image(
+            &denoised_filename,
+            self.file_format,
+            &denoised,
+            &self.cropped_pixel_bounds,
+        ) {
+            println!(
+                "ERROR: Unable to write image {:?}: {}",
+                denoised_filename, err
+            );
+        }
+    }
+    /// Normalize the accumulated AOVs by the filter weight sums of the
+    /// samples which recorded AOVs.
+    fn get_aov_layers(&self) -> Vec<ImageLayer> {
+        let mut layers: Vec<ImageLayer> = Vec::with_capacity(self.aovs.len());
+        if self.aovs.is_empty() {
+            return layers;
+        }
+        let n_pixels: usize = self.cropped_pixel_bounds.area() as usize;
+        let storage_size: usize = aovs_storage_size(&self.aovs);
+        let aov_pixels = self.aov_pixels.read().unwrap();
+        let mut offset: usize = AOV_WEIGHT_SUM_SIZE;
+        for aov in &self.aovs {
+            let channel_names: Vec<&'static str> = aov.channel_names();
+            let n_channels: usize = channel_names.len();
+            let mut data: Vec<Float> = vec![0.0 as Float; n_pixels * n_channels];
+            for i in 0..n_pixels {
+                let pixel_aovs: &[Float] = &aov_pixels[i * storage_size..(i + 1) * storage_size];
+                for c in 0..n_channels {
+                    data[i * n_channels + c] = match aov {
+                        Aov::PrimitiveId | Aov::MaterialId | Aov::SampleCount => {
+                            pixel_aovs[offset + c]
+                        }
+                        _ => {
+                            let filter_weight_sum: Float = pixel_aovs[0];
+                            if filter_weight_sum != 0.0 as Float {
+                                pixel_aovs[offset + c] / filter_weight_sum
+                            } else {
+                                0.0 as Float
+                            }
+                        }
+                    };
+                }
             }
+            layers.push(ImageLayer {
+                name: String::from(aov.name()),
+                channel_names,
+                data,
+            });
+            offset += aov.storage_size();
         }
-        // write "pbrt.png" to disk
-        image::save_buffer(
-            &Path::new("pbrt.png"),
-            &buffer,
-            width,
-            height,
-            image::ColorType::Rgb8,
-        )
-        .unwrap();
+        layers
     }
     // pub fn get_pixel<'a>(&self, p: &Point2i) -> &'a Pixel {
     //     assert!(pnt2_inside_exclusivei(p, &self.cropped_pixel_bounds));
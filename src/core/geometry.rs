@@ -986,6 +986,14 @@ pub fn bnd2_union_pnt2(b: &Bounds2f, p: Point2f) -> Bounds2f {
     Bounds2f { p_min, p_max }
 }
 
+/// Returns a new bounding box that encompasses both boxes.
+pub fn bnd2_union_bnd2i(b1: &Bounds2i, b2: &Bounds2i) -> Bounds2i {
+    Bounds2i {
+        p_min: pnt2_min_pnt2i(b1.p_min, b2.p_min),
+        p_max: pnt2_max_pnt2i(b1.p_max, b2.p_max),
+    }
+}
+
 /// Determine if a given point is inside the bounding box.
 pub fn pnt2_inside_bnd2f(pt: Point2f, b: &Bounds2f) -> bool {
     pt.x >= b.p_min.x && pt.x <= b.p_max.x && pt.y >= b.p_min.y && pt.y <= b.p_max.y
@@ -2326,6 +2334,42 @@ pub fn bnd3_union_bnd3f(b1: &Bounds3f, b2: &Bounds3f) -> Bounds3f {
     Bounds3f { p_min, p_max }
 }
 
+/// The intersection of two bounding boxes, which is empty (minimum
+/// greater than maximum) if they don't overlap.
+pub fn bnd3_intersect_bnd3f(b1: &Bounds3f, b2: &Bounds3f) -> Bounds3f {
+    let p_min: Point3f = Point3f {
+        x: b1.p_min.x.max(b2.p_min.x),
+        y: b1.p_min.y.max(b2.p_min.y),
+        z: b1.p_min.z.max(b2.p_min.z),
+    };
+    let p_max: Point3f = Point3f {
+        x: b1.p_max.x.min(b2.p_max.x),
+        y: b1.p_max.y.min(b2.p_max.y),
+        z: b1.p_max.z.min(b2.p_max.z),
+    };
+    Bounds3f { p_min, p_max }
+}
+
+/// Cuts |b| at the |planes| (in increasing order) perpendicular to
+/// |axis| and stores the slabs in between in |slabs| (one more than
+/// planes).
+pub fn bnd3_split_slabs(b: &Bounds3f, axis: usize, planes: &[Float], slabs: &mut [Bounds3f]) {
+    let axis: XYZEnum = match axis {
+        0 => XYZEnum::X,
+        1 => XYZEnum::Y,
+        _ => XYZEnum::Z,
+    };
+    for (i, slab) in slabs.iter_mut().enumerate().take(planes.len() + 1) {
+        *slab = *b;
+        if i > 0 {
+            slab.p_min[axis] = slab.p_min[axis].max(planes[i - 1]);
+        }
+        if i < planes.len() {
+            slab.p_max[axis] = slab.p_max[axis].min(planes[i]);
+        }
+    }
+}
+
 /// Determine if a given point is inside the bounding box.
 pub fn pnt3_inside_bnd3(p: &Point3f, b: &Bounds3f) -> bool {
     p.x >= b.p_min.x
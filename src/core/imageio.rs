@@ -0,0 +1,246 @@
+//! Write the final image of a render to disk. The file format is
+//! chosen from the extension of the filename, which is either given
+//! by the **Film**'s *filename* parameter or defaults to *pbrt.exr*
+//! (like pbrt-v3). Supported are 8-bit and 16-bit PNG, PFM, Radiance
+//! HDR, and OpenEXR (written by a pure Rust encoder, no system
+//! library needed).
+
+// std
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+// others
+use byteorder::{LittleEndian, WriteBytesExt};
+use exr::prelude::{
+    AnyChannel, AnyChannels, Encoding, FlatSamples, Image, Layer, LayerAttributes, WritableImage,
+};
+use image::codecs::hdr::HdrEncoder;
+use image::Rgb;
+use smallvec::SmallVec;
+// pbrt
+use crate::core::geometry::Bounds2i;
+use crate::core::pbrt::{clamp_t, gamma_correct, Float};
+
+// see imageio.h
+
+/// Additional image data written next to the RGB values, e.g. the
+/// arbitrary output variables (AOVs) of the **Film**.
+pub struct ImageLayer {
+    /// The name of the layer (prefix of the channel names in OpenEXR)
+    pub name: String,
+    /// The channel names within the layer (e.g. *X*, *Y*, *Z*)
+    pub channel_names: Vec<&'static str>,
+    /// Interleaved channel values, row by row
+    pub data: Vec<Float>,
+}
+
+/// The image file formats **Film::write_image()** can produce.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFileFormat {
+    /// 8-bit per channel, gamma corrected
+    Png,
+    /// 16-bit per channel, gamma corrected
+    Png16,
+    /// Portable float map, linear
+    Pfm,
+    /// Radiance RGBE, linear
+    Hdr,
+    /// OpenEXR with 32-bit float channels, linear
+    Exr,
+}
+
+impl ImageFileFormat {
+    /// Select the file format based on the extension of *name*. PNG
+    /// files are written with 16 bits per channel if *bit_depth* is
+    /// 16, with 8 bits otherwise.
+    pub fn from_filename(name: &str, bit_depth: i32) -> Option<ImageFileFormat> {
+        let extension: String = match Path::new(name).extension() {
+            Some(ext) => ext.to_string_lossy().to_lowercase(),
+            None => return None,
+        };
+        match extension.as_str() {
+            "png" => {
+                if bit_depth == 16 {
+                    Some(ImageFileFormat::Png16)
+                } else {
+                    Some(ImageFileFormat::Png)
+                }
+            }
+            "pfm" => Some(ImageFileFormat::Pfm),
+            "hdr" => Some(ImageFileFormat::Hdr),
+            "exr" => Some(ImageFileFormat::Exr),
+            _ => None,
+        }
+    }
+}
+
+/// Write linear RGB values (three floats per pixel, row by row) for
+/// the pixels within *output_bounds* to the file *name*.
+pub fn write_image(
+    name: &str,
+    format: ImageFileFormat,
+    rgb: &[Float],
+    output_bounds: &Bounds2i,
+) -> std::io::Result<()> {
+    let width: u32 = (output_bounds.p_max.x - output_bounds.p_min.x) as u32;
+    let height: u32 = (output_bounds.p_max.y - output_bounds.p_min.y) as u32;
+    assert_eq!(rgb.len(), (3 * width * height) as usize);
+    match format {
+        ImageFileFormat::Png => {
+            // 8-bit format; apply gamma (see WriteImage(...) in imageio.cpp)
+            let buffer: Vec<u8> = rgb
+                .iter()
+                .map(|v| clamp_t(255.0 as Float * gamma_correct(*v) + 0.5, 0.0, 255.0) as u8)
+                .collect();
+            image::save_buffer_with_format(
+                Path::new(name),
+                &buffer,
+                width,
+                height,
+                image::ColorType::Rgb8,
+                image::ImageFormat::Png,
+            )
+            .map_err(to_io_error)
+        }
+        ImageFileFormat::Png16 => {
+            let buffer: Vec<u8> = rgb
+                .iter()
+                .flat_map(|v| {
+                    let v16: u16 =
+                        clamp_t(65535.0 as Float * gamma_correct(*v) + 0.5, 0.0, 65535.0) as u16;
+                    // image expects native endian 16-bit samples
+                    v16.to_ne_bytes()
+                })
+                .collect();
+            image::save_buffer_with_format(
+                Path::new(name),
+                &buffer,
+                width,
+                height,
+                image::ColorType::Rgb16,
+                image::ImageFormat::Png,
+            )
+            .map_err(to_io_error)
+        }
+        ImageFileFormat::Pfm => write_image_pfm(name, rgb, width, height),
+        ImageFileFormat::Hdr => {
+            let pixels: Vec<Rgb<f32>> = rgb
+                .chunks_exact(3)
+                .map(|c| Rgb([c[0], c[1], c[2]]))
+                .collect();
+            let file = BufWriter::new(File::create(name)?);
+            HdrEncoder::new(file)
+                .encode(&pixels, width as usize, height as usize)
+                .map_err(to_io_error)
+        }
+        ImageFileFormat::Exr => {
+            exr::prelude::write_rgb_file(name, width as usize, height as usize, |x, y| {
+                let start: usize = 3 * (y * width as usize + x);
+                (rgb[start], rgb[start + 1], rgb[start + 2])
+            })
+            .map_err(|e| std::io::Error::other(e.to_string()))
+        }
+    }
+}
+
+/// Write RGB values plus additional layers. OpenEXR files store all
+/// layers in a single multi-layer file (channels are named
+/// *layer.channel*), all other formats write one file per layer with
+/// the layer name inserted before the extension
+/// (e.g. *pbrt.depth.pfm*).
+pub fn write_image_layers(
+    name: &str,
+    format: ImageFileFormat,
+    rgb: &[Float],
+    layers: &[ImageLayer],
+    output_bounds: &Bounds2i,
+) -> std::io::Result<()> {
+    if layers.is_empty() {
+        return write_image(name, format, rgb, output_bounds);
+    }
+    let width: usize = (output_bounds.p_max.x - output_bounds.p_min.x) as usize;
+    let height: usize = (output_bounds.p_max.y - output_bounds.p_min.y) as usize;
+    if format == ImageFileFormat::Exr {
+        let mut channels: SmallVec<[AnyChannel<FlatSamples>; 4]> = SmallVec::new();
+        for (c, channel_name) in ["R", "G", "B"].iter().enumerate() {
+            let samples: Vec<f32> = rgb.iter().skip(c).step_by(3).copied().collect();
+            channels.push(AnyChannel::new(*channel_name, FlatSamples::F32(samples)));
+        }
+        for layer in layers {
+            let n_channels: usize = layer.channel_names.len();
+            for (c, channel_name) in layer.channel_names.iter().enumerate() {
+                let samples: Vec<f32> = layer
+                    .data
+                    .iter()
+                    .skip(c)
+                    .step_by(n_channels)
+                    .copied()
+                    .collect();
+                channels.push(AnyChannel::new(
+                    format!("{}.{}", layer.name, channel_name).as_str(),
+                    FlatSamples::F32(samples),
+                ));
+            }
+        }
+        let image = Image::from_layer(Layer::new(
+            (width, height),
+            LayerAttributes::default(),
+            Encoding::FAST_LOSSLESS,
+            AnyChannels::sort(channels),
+        ));
+        return image
+            .write()
+            .to_file(name)
+            .map_err(|e| std::io::Error::other(e.to_string()));
+    }
+    write_image(name, format, rgb, output_bounds)?;
+    let path: &Path = Path::new(name);
+    let stem: String = path
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let extension: String = path
+        .extension()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    for layer in layers {
+        // replicate single channel layers to gray RGB
+        let n_channels: usize = layer.channel_names.len();
+        let mut layer_rgb: Vec<Float> = vec![0.0 as Float; 3 * width * height];
+        for (i, value) in layer_rgb.iter_mut().enumerate() {
+            let c: usize = if n_channels == 1 {
+                0
+            } else {
+                (i % 3).min(n_channels - 1)
+            };
+            *value = layer.data[(i / 3) * n_channels + c];
+        }
+        let layer_name = path.with_file_name(format!("{}.{}.{}", stem, layer.name, extension));
+        write_image(
+            &layer_name.to_string_lossy(),
+            format,
+            &layer_rgb,
+            output_bounds,
+        )?;
+    }
+    Ok(())
+}
+
+/// Write a little-endian portable float map. Scanlines are stored
+/// from bottom to top (see WriteImagePFM(...) in imageio.cpp).
+fn write_image_pfm(name: &str, rgb: &[Float], width: u32, height: u32) -> std::io::Result<()> {
+    let mut file = BufWriter::new(File::create(name)?);
+    // a negative scale marks the data as little-endian
+    write!(file, "PF\n{} {}\n-1\n", width, height)?;
+    for y in (0..height as usize).rev() {
+        let start: usize = 3 * y * width as usize;
+        for v in &rgb[start..start + 3 * width as usize] {
+            file.write_f32::<LittleEndian>(*v)?;
+        }
+    }
+    file.flush()
+}
+
+fn to_io_error(err: image::ImageError) -> std::io::Error {
+    std::io::Error::other(err.to_string())
+}
@@ -2,16 +2,25 @@
 //! class that implements the **Integrator** interface.
 
 // std
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::Arc;
+use std::time::Instant;
+// others
+use smallvec::SmallVec;
 // pbrt
 use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
+use crate::core::checkpoint::{load_completed_tiles, remove_checkpoint};
+use crate::core::checkpoint::{CheckpointSettings, TileCheckpointer};
+use crate::core::film::{Aov, Film, FilmTile, PixelEstimate};
 use crate::core::geometry::{pnt2_inside_exclusivei, vec3_abs_dot_nrmf};
-use crate::core::geometry::{Bounds2i, Point2f, Point2i, Ray, Vector2i, Vector3f};
+use crate::core::geometry::{Bounds2i, Normal3f, Point2f, Point2i, Point3f, Ray};
+use crate::core::geometry::{Vector2i, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
 use crate::core::light::is_delta_light;
 use crate::core::light::{Light, VisibilityTester};
-use crate::core::pbrt::{Float, Spectrum};
+use crate::core::lightdistrib::LightDistribution;
+use crate::core::pbrt::{clamp_t, round_up_pow2_64, Float, Spectrum};
 use crate::core::reflection::BxdfType;
 use crate::core::sampler::Sampler;
 use crate::core::sampling::power_heuristic;
@@ -20,6 +29,7 @@ use crate::core::scene::Scene;
 use crate::integrators::ao::AOIntegrator;
 use crate::integrators::bdpt::BDPTIntegrator;
 use crate::integrators::directlighting::DirectLightingIntegrator;
+use crate::integrators::guidedpath::GuidedPathIntegrator;
 use crate::integrators::mlt::MLTIntegrator;
 use crate::integrators::path::PathIntegrator;
 use crate::integrators::sppm::SPPMIntegrator;
@@ -28,6 +38,49 @@ use crate::integrators::whitted::WhittedIntegrator;
 
 // see integrator.h
 
+/// Settings for
+/// [render_progressive()](enum.SamplerIntegrator.html#method.render_progressive),
+/// a value of zero disables the corresponding snapshot trigger.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct ProgressiveSettings {
+    pub samples_per_pass: u32,
+    pub snapshot_seconds: Float,
+    pub snapshot_passes: u32,
+}
+
+/// Render budget for the [SamplerIntegrators](enum.SamplerIntegrator.html),
+/// a value of zero disables the corresponding limit.
+///
+/// A pixel stops sampling once at least *min_samples* were taken and
+/// the relative variance of its mean luminance drops below
+/// *variance_threshold*. A *time_limit* (in seconds) is checked
+/// between the passes of
+/// [render_progressive()](enum.SamplerIntegrator.html#method.render_progressive).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct RenderBudget {
+    pub time_limit: Float,
+    pub variance_threshold: Float,
+    pub min_samples: u32,
+}
+
+/// Settings for
+/// [render_adaptive()](enum.SamplerIntegrator.html#method.render_adaptive).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct AdaptiveSettings {
+    /// samples per pixel on average (the sampler's count is the maximum)
+    pub average_samples: u32,
+    /// samples every pixel gets before the variance is looked at
+    pub initial_samples: u32,
+}
+
+impl RenderBudget {
+    pub fn is_converged(&self, estimate: &PixelEstimate) -> bool {
+        self.variance_threshold > 0.0 as Float
+            && estimate.n >= std::cmp::max(self.min_samples, 2)
+            && estimate.relative_variance() < self.variance_threshold
+    }
+}
+
 pub enum Integrator {
     BDPT(BDPTIntegrator),
     MLT(MLTIntegrator),
@@ -37,11 +90,67 @@ pub enum Integrator {
 
 impl Integrator {
     pub fn render(&mut self, scene: &Scene, num_threads: u8) {
+        self.render_with_checkpoints(scene, num_threads, &CheckpointSettings::default());
+    }
+    /// Same as [render()](enum.Integrator.html#method.render), but
+    /// writes checkpoints while rendering and/or resumes from the
+    /// checkpoint of a previous run (see
+    /// [CheckpointSettings](../checkpoint/struct.CheckpointSettings.html)).
+    pub fn render_with_checkpoints(
+        &mut self,
+        scene: &Scene,
+        num_threads: u8,
+        checkpoint: &CheckpointSettings,
+    ) {
         match self {
-            Integrator::BDPT(integrator) => integrator.render(scene, num_threads),
-            Integrator::MLT(integrator) => integrator.render(scene, num_threads),
-            Integrator::SPPM(integrator) => integrator.render(scene, num_threads),
-            Integrator::Sampler(integrator) => integrator.render(scene, num_threads),
+            Integrator::BDPT(integrator) => integrator.render(scene, num_threads, checkpoint),
+            Integrator::MLT(integrator) => integrator.render(scene, num_threads, checkpoint),
+            Integrator::SPPM(integrator) => integrator.render(scene, num_threads, checkpoint),
+            Integrator::Sampler(integrator) => integrator.render_with_budget(
+                scene,
+                num_threads,
+                &RenderBudget::default(),
+                checkpoint,
+            ),
+        }
+    }
+    pub fn preprocess(&mut self, scene: &Scene) {
+        if let Integrator::Sampler(integrator) = self {
+            integrator.preprocess(scene);
+        }
+    }
+    /// Renders the tiles of a [BlockQueue](../../blockqueue/struct.BlockQueue.html),
+    /// see [SamplerIntegrator::render_tiles()](enum.SamplerIntegrator.html#method.render_tiles).
+    /// Returns false for integrators which don't render in tiles
+    /// (**MLT**, **SPPM**).
+    pub fn render_tiles<F>(
+        &self,
+        scene: &Scene,
+        num_threads: u8,
+        bq: &BlockQueue,
+        collect: F,
+    ) -> bool
+    where
+        F: FnMut((u32, u32), &FilmTile, &[(Point2f, Spectrum)]) + Send,
+    {
+        match self {
+            Integrator::BDPT(integrator) => {
+                integrator.render_tiles(scene, num_threads, bq, collect);
+                true
+            }
+            Integrator::Sampler(integrator) => {
+                integrator.render_tiles(scene, num_threads, bq, &RenderBudget::default(), collect);
+                true
+            }
+            _ => false,
+        }
+    }
+    pub fn get_camera(&self) -> Arc<Camera> {
+        match self {
+            Integrator::BDPT(integrator) => integrator.get_camera(),
+            Integrator::MLT(integrator) => integrator.get_camera(),
+            Integrator::SPPM(integrator) => integrator.get_camera(),
+            Integrator::Sampler(integrator) => integrator.get_camera(),
         }
     }
 }
@@ -49,6 +158,7 @@ impl Integrator {
 pub enum SamplerIntegrator {
     AO(AOIntegrator),
     DirectLighting(DirectLightingIntegrator),
+    GuidedPath(GuidedPathIntegrator),
     Path(PathIntegrator),
     VolPath(VolPathIntegrator),
     Whitted(WhittedIntegrator),
@@ -59,6 +169,7 @@ impl SamplerIntegrator {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.preprocess(scene),
             SamplerIntegrator::DirectLighting(integrator) => integrator.preprocess(scene),
+            SamplerIntegrator::GuidedPath(integrator) => integrator.preprocess(scene),
             SamplerIntegrator::Path(integrator) => integrator.preprocess(scene),
             SamplerIntegrator::VolPath(integrator) => integrator.preprocess(scene),
             SamplerIntegrator::Whitted(integrator) => integrator.preprocess(scene),
@@ -68,6 +179,24 @@ impl SamplerIntegrator {
     /// same render loop, but call an individual
     /// [li()](enum.SamplerIntegrator.html#method.li) method.
     pub fn render(&mut self, scene: &Scene, num_threads: u8) {
+        self.render_with_budget(
+            scene,
+            num_threads,
+            &RenderBudget::default(),
+            &CheckpointSettings::default(),
+        );
+    }
+    /// Same as [render()](enum.SamplerIntegrator.html#method.render),
+    /// but stops sampling a pixel once its estimate converged (see
+    /// [RenderBudget](struct.RenderBudget.html)). Tiles already
+    /// rendered before a checkpoint are skipped when resuming.
+    pub fn render_with_budget(
+        &mut self,
+        scene: &Scene,
+        num_threads: u8,
+        budget: &RenderBudget,
+        checkpoint: &CheckpointSettings,
+    ) {
         let film = self.get_camera().get_film();
         let sample_bounds: Bounds2i = film.get_sample_bounds();
         self.preprocess(scene);
@@ -84,7 +213,7 @@ impl SamplerIntegrator {
         };
         println!("Rendering with {:?} thread(s) ...", num_cores);
         {
-            let block_queue = BlockQueue::new(
+            let mut block_queue = BlockQueue::new(
                 (
                     (n_tiles.x * tile_size) as u32,
                     (n_tiles.y * tile_size) as u32,
@@ -92,147 +221,588 @@ impl SamplerIntegrator {
                 (tile_size as u32, tile_size as u32),
                 (0, 0),
             );
-            let integrator = &self;
-            let bq = &block_queue;
-            let sampler = &self.get_sampler();
-            let camera = &self.get_camera();
+            let completed: Vec<(u32, u32)> = load_completed_tiles(&film, checkpoint);
+            block_queue.remove_blocks(&completed);
             let film = &film;
-            let pixel_bounds = &self.get_pixel_bounds();
-            crossbeam::scope(|scope| {
-                let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
-                // spawn worker threads
-                for _ in 0..num_cores {
-                    let pixel_tx = pixel_tx.clone();
-                    let mut tile_sampler: Box<Sampler> = sampler.clone_with_seed(0_u64);
-                    scope.spawn(move |_| {
-                        while let Some((x, y)) = bq.next() {
-                            let tile: Point2i = Point2i {
-                                x: x as i32,
-                                y: y as i32,
-                            };
-                            let seed: i32 = tile.y * n_tiles.x + tile.x;
-                            tile_sampler.reseed(seed as u64);
-                            let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
-                            let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
-                            let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
-                            let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
-                            let tile_bounds: Bounds2i =
-                                Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 });
-                            // println!("Starting image tile {:?}", tile_bounds);
-                            let mut film_tile = film.get_film_tile(&tile_bounds);
-                            for pixel in &tile_bounds {
-                                tile_sampler.start_pixel(pixel);
-                                if !pnt2_inside_exclusivei(pixel, pixel_bounds) {
-                                    continue;
-                                }
-                                let mut done: bool = false;
-                                while !done {
-                                    // let's use the copy_arena crate instead of pbrt's MemoryArena
-                                    // let mut arena: Arena = Arena::with_capacity(262144); // 256kB
-
-                                    // initialize _CameraSample_ for current sample
-                                    let camera_sample: CameraSample =
-                                        tile_sampler.get_camera_sample(pixel);
-                                    // generate camera ray for current sample
-                                    let mut ray: Ray = Ray::default();
-                                    let ray_weight: Float =
-                                        camera.generate_ray_differential(&camera_sample, &mut ray);
-                                    ray.scale_differentials(
-                                        1.0 as Float
-                                            / (tile_sampler.get_samples_per_pixel() as Float)
-                                                .sqrt(),
-                                    );
-                                    // TODO: ++nCameraRays;
-                                    // evaluate radiance along camera ray
-                                    let mut l: Spectrum = Spectrum::new(0.0 as Float);
-                                    let y: Float = l.y();
-                                    if ray_weight > 0.0 {
-                                        // ADDED
-                                        let clipping_start: Float = camera.get_clipping_start();
-                                        if clipping_start > 0.0 as Float {
-                                            // adjust ray origin for near clipping
-                                            camera
-                                                .adjust_to_clipping_start(&camera_sample, &mut ray);
-                                        }
-                                        // ADDED
-                                        l = integrator.li(
-                                            &mut ray,
-                                            scene,
-                                            &mut tile_sampler, // &mut arena,
-                                            0_i32,
-                                        );
-                                    }
-                                    if l.has_nans() {
-                                        println!(
-                                            "Not-a-number radiance value returned for pixel \
-                                                     ({:?}, {:?}), sample {:?}. Setting to black.",
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    } else if y < -10.0e-5 as Float {
-                                        println!(
-                                            "Negative luminance value, {:?}, returned for pixel \
-                                                 ({:?}, {:?}), sample {:?}. Setting to black.",
-                                            y,
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    } else if y.is_infinite() {
-                                        println!(
-                                            "Infinite luminance value returned for pixel ({:?}, \
-                                                 {:?}), sample {:?}. Setting to black.",
-                                            pixel.x,
-                                            pixel.y,
-                                            tile_sampler.get_current_sample_number()
-                                        );
-                                        l = Spectrum::new(0.0);
-                                    }
-                                    // println!("Camera sample: {:?} -> ray: {:?} -> L = {:?}",
-                                    //          camera_sample, ray, l);
-                                    // add camera ray's contribution to image
-                                    film_tile.add_sample(camera_sample.p_film, &mut l, ray_weight);
-                                    done = !tile_sampler.start_next_sample();
-                                } // arena is dropped here !
-                            }
-                            // send the tile through the channel to main thread
-                            pixel_tx
-                                .send(film_tile)
-                                .unwrap_or_else(|_| panic!("Failed to send tile"));
-                        }
-                    });
+            let mut checkpointer = TileCheckpointer::new(film, checkpoint, completed);
+            self.render_tiles(
+                scene,
+                num_threads,
+                &block_queue,
+                budget,
+                |block, film_tile, _| {
+                    // merge image tile into _Film_
+                    film.merge_film_tile(film_tile);
+                    checkpointer.tile_done(block);
+                },
+            );
+        }
+        film.write_image(1.0 as Float);
+        remove_checkpoint(&film, checkpoint);
+    }
+    /// Renders the tiles of a [BlockQueue](../../blockqueue/struct.BlockQueue.html)
+    /// (16x16 pixels each) and hands every finished tile to *collect*,
+    /// which is called from a single thread. The tile's seed only
+    /// depends on its position, so a tile renders the same no matter
+    /// which queue (or process) it came from. Expects
+    /// [preprocess()](enum.SamplerIntegrator.html#method.preprocess)
+    /// to be called before.
+    pub fn render_tiles<F>(
+        &self,
+        scene: &Scene,
+        num_threads: u8,
+        bq: &BlockQueue,
+        budget: &RenderBudget,
+        collect: F,
+    ) where
+        F: FnMut((u32, u32), &FilmTile, &[(Point2f, Spectrum)]) + Send,
+    {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let n_x_tiles: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let mut collect = collect;
+        let integrator = &self;
+        let camera = &self.get_camera();
+        let film = &film;
+        let pixel_bounds = &self.get_pixel_bounds();
+        let mut pb = pbr::ProgressBar::new(bq.len() as u64);
+        self.for_each_tile(
+            self.get_sampler(),
+            num_threads,
+            bq,
+            |tile_sampler, tile, tile_bounds, film_tile| {
+                let seed: i32 = tile.y * n_x_tiles + tile.x;
+                tile_sampler.reseed(seed as u64);
+                for pixel in tile_bounds {
+                    tile_sampler.start_pixel(pixel);
+                    if !pnt2_inside_exclusivei(pixel, pixel_bounds) {
+                        continue;
+                    }
+                    let mut done: bool = false;
+                    while !done {
+                        integrator.render_sample(
+                            scene,
+                            camera,
+                            film,
+                            tile_sampler,
+                            pixel,
+                            film_tile,
+                        );
+                        done = !tile_sampler.start_next_sample()
+                            || budget.is_converged(&film_tile.get_pixel_estimate(pixel));
+                    }
                 }
-                // spawn thread to collect pixels and render image to file
+            },
+            |block, film_tile| {
+                collect(block, &film_tile, &[]);
+                pb.inc();
+            },
+        );
+    }
+    /// Hands the tiles of a [BlockQueue](../../blockqueue/struct.BlockQueue.html)
+    /// to *num_threads* worker threads (all cores for zero), each with
+    /// its own clone of *sampler*. The worker calls *render_tile* with
+    /// the tile's position in the queue and its pixel bounds to fill
+    /// a [FilmTile](../film/struct.FilmTile.html), every finished tile
+    /// is passed on to *collect*, which runs on a single thread.
+    fn for_each_tile<R, C>(
+        &self,
+        sampler: &Sampler,
+        num_threads: u8,
+        bq: &BlockQueue,
+        render_tile: R,
+        collect: C,
+    ) where
+        R: Fn(&mut Sampler, Point2i, &Bounds2i, &mut FilmTile) + Sync,
+        C: FnMut((u32, u32), FilmTile) + Send,
+    {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        let tile_size: i32 = 16;
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        let mut collect = collect;
+        let render_tile = &render_tile;
+        let film = &film;
+        crossbeam::scope(|scope| {
+            let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
+            // spawn worker threads
+            for _ in 0..num_cores {
+                let pixel_tx = pixel_tx.clone();
+                let mut tile_sampler: Box<Sampler> = sampler.clone_with_seed(0_u64);
                 scope.spawn(move |_| {
-                    for _ in pbr::PbIter::new(0..bq.len()) {
-                        let film_tile = pixel_rx.recv().unwrap();
-                        // merge image tile into _Film_
-                        film.merge_film_tile(&film_tile);
+                    while let Some((x, y)) = bq.next() {
+                        let tile: Point2i = Point2i {
+                            x: x as i32,
+                            y: y as i32,
+                        };
+                        let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
+                        let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                        let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
+                        let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+                        let tile_bounds: Bounds2i =
+                            Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 });
+                        // println!("Starting image tile {:?}", tile_bounds);
+                        let mut film_tile = film.get_film_tile(&tile_bounds);
+                        render_tile(&mut tile_sampler, tile, &tile_bounds, &mut film_tile);
+                        // send the tile through the channel to main thread
+                        pixel_tx
+                            .send(((x, y), film_tile))
+                            .unwrap_or_else(|_| panic!("Failed to send tile"));
                     }
                 });
-            })
-            .unwrap();
+            }
+            // spawn thread to collect the finished tiles
+            scope.spawn(move |_| {
+                for _ in 0..bq.len() {
+                    let (block, film_tile) = pixel_rx.recv().unwrap();
+                    collect(block, film_tile);
+                }
+            });
+        })
+        .unwrap();
+    }
+    /// Renders the image in passes of *samples_per_pass* samples per
+    /// pixel over the whole film, merging every pass into the
+    /// [Film](../film/struct.Film.html) before the next one starts,
+    /// and writes an intermediate image after a given number of
+    /// passes or seconds. The first pass uses the same seeds as
+    /// [render()](enum.SamplerIntegrator.html#method.render); global
+    /// samplers (**Halton**, **Sobol**) continue their sequence
+    /// across passes, pixel samplers draw a fresh sample set per pass.
+    /// No further pass is started if it would exceed the time limit of
+    /// the [RenderBudget](struct.RenderBudget.html), and converged
+    /// pixels are skipped.
+    pub fn render_progressive(
+        &mut self,
+        scene: &Scene,
+        num_threads: u8,
+        settings: &ProgressiveSettings,
+        budget: &RenderBudget,
+    ) {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        self.preprocess(scene);
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let x: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let y: i32 = (sample_extent.y + tile_size - 1) / tile_size;
+        let n_tiles: Point2i = Point2i { x, y };
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        let samples_per_pixel: i64 = self.get_sampler().get_samples_per_pixel();
+        let samples_per_pass: i64 = std::cmp::min(
+            std::cmp::max(settings.samples_per_pass as i64, 1),
+            samples_per_pixel,
+        );
+        let n_passes: i64 = (samples_per_pixel + samples_per_pass - 1) / samples_per_pass;
+        println!(
+            "Rendering {:?} pass(es) of {:?} sample(s) with {:?} thread(s) ...",
+            n_passes, samples_per_pass, num_cores
+        );
+        let integrator = &self;
+        let sampler = &self.get_sampler();
+        let camera = &self.get_camera();
+        let film = &film;
+        let pixel_bounds = &self.get_pixel_bounds();
+        let n_tiles_total: i64 = (n_tiles.x * n_tiles.y) as i64;
+        let mut pb = pbr::ProgressBar::new((n_tiles_total * n_passes) as u64);
+        let start: Instant = Instant::now();
+        let mut last_snapshot: Instant = Instant::now();
+        let mut last_pass_seconds: Float = 0.0 as Float;
+        let mut passes_since_snapshot: u32 = 0;
+        for pass in 0..n_passes {
+            if pass > 0 && budget.time_limit > 0.0 as Float {
+                let elapsed: Float = start.elapsed().as_secs_f32();
+                if elapsed + last_pass_seconds > budget.time_limit {
+                    println!(
+                        "\nTime limit of {:?} seconds reached after {:?} pass(es)",
+                        budget.time_limit, pass
+                    );
+                    break;
+                }
+            }
+            let pass_start: Instant = Instant::now();
+            let active_pixels: AtomicUsize = AtomicUsize::new(0);
+            let active_pixels = &active_pixels;
+            let first_sample: i64 = pass * samples_per_pass;
+            let last_sample: i64 =
+                std::cmp::min(first_sample + samples_per_pass, samples_per_pixel);
+            let block_queue = BlockQueue::new(
+                (
+                    (n_tiles.x * tile_size) as u32,
+                    (n_tiles.y * tile_size) as u32,
+                ),
+                (tile_size as u32, tile_size as u32),
+                (0, 0),
+            );
+            let pb = &mut pb;
+            self.for_each_tile(
+                sampler,
+                num_threads,
+                &block_queue,
+                |tile_sampler, tile, tile_bounds, film_tile| {
+                    let seed: i64 = pass * n_tiles_total + (tile.y * n_tiles.x + tile.x) as i64;
+                    tile_sampler.reseed(seed as u64);
+                    for pixel in tile_bounds {
+                        tile_sampler.start_pixel(pixel);
+                        if !pnt2_inside_exclusivei(pixel, pixel_bounds) {
+                            continue;
+                        }
+                        // estimate of the previous passes
+                        let prior: PixelEstimate = film.get_pixel_estimate(pixel);
+                        if budget.is_converged(&prior) {
+                            continue;
+                        }
+                        active_pixels.fetch_add(1, Ordering::Relaxed);
+                        for sample_num in first_sample..last_sample {
+                            if !tile_sampler.set_sample_number(sample_num) {
+                                break;
+                            }
+                            integrator.render_sample(
+                                scene,
+                                camera,
+                                film,
+                                tile_sampler,
+                                pixel,
+                                film_tile,
+                            );
+                            let mut estimate: PixelEstimate = prior;
+                            estimate.merge(&film_tile.get_pixel_estimate(pixel));
+                            if budget.is_converged(&estimate) {
+                                break;
+                            }
+                        }
+                    }
+                },
+                |_, film_tile| {
+                    // merge image tile into _Film_
+                    film.merge_film_tile(&film_tile);
+                    pb.inc();
+                },
+            );
+            last_pass_seconds = pass_start.elapsed().as_secs_f32();
+            if active_pixels.load(Ordering::Relaxed) == 0 {
+                println!("\nAll pixels converged after {:?} pass(es)", pass);
+                break;
+            }
+            passes_since_snapshot += 1;
+            if pass + 1 < n_passes {
+                let passes_due: bool = settings.snapshot_passes > 0
+                    && passes_since_snapshot >= settings.snapshot_passes;
+                let seconds_due: bool = settings.snapshot_seconds > 0.0 as Float
+                    && last_snapshot.elapsed().as_secs_f32() >= settings.snapshot_seconds;
+                if passes_due || seconds_due {
+                    film.write_image(1.0 as Float);
+                    last_snapshot = Instant::now();
+                    passes_since_snapshot = 0;
+                }
+            }
+        }
+        pb.finish();
+        film.write_image(1.0 as Float);
+    }
+    /// Distributes an average number of samples per pixel according
+    /// to the luminance variance of the pixels. All pixels get
+    /// *initial_samples* first, afterwards the pixels with the highest
+    /// relative variance double their sample count in each round until
+    /// the budget is used. Every pixel therefore uses a power-of-two
+    /// prefix of its sample sequence (capped by the sampler's count),
+    /// which keeps **Sobol**, **Halton** and **ZeroTwoSequence**
+    /// samples stratified. Converged pixels (see
+    /// [RenderBudget](struct.RenderBudget.html)) are not refined and no
+    /// round is started that would exceed the time limit.
+    pub fn render_adaptive(
+        &mut self,
+        scene: &Scene,
+        num_threads: u8,
+        settings: &AdaptiveSettings,
+        budget: &RenderBudget,
+    ) {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        self.preprocess(scene);
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let x: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let y: i32 = (sample_extent.y + tile_size - 1) / tile_size;
+        let n_tiles: Point2i = Point2i { x, y };
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        let max_samples: i64 = self.get_sampler().get_samples_per_pixel();
+        let initial_samples: i64 = std::cmp::min(
+            round_up_pow2_64(settings.initial_samples.max(1) as i64),
+            max_samples,
+        );
+        let average_samples: i64 = clamp_t(
+            settings.average_samples as i64,
+            initial_samples,
+            max_samples,
+        );
+        println!(
+            "Rendering adaptively with {:?} sample(s) per pixel on average (at most {:?}) with {:?} thread(s) ...",
+            average_samples, max_samples, num_cores
+        );
+        let integrator = &self;
+        // nested shuffling keeps the power-of-two prefixes stratified
+        let mut nested_sampler: Box<Sampler> = self.get_sampler().clone_with_seed(0_u64);
+        nested_sampler.set_nested(true);
+        let camera = &self.get_camera();
+        let film = &film;
+        let pixel_bounds: Bounds2i = self.get_pixel_bounds();
+        let width: i32 = pixel_bounds.p_max.x - pixel_bounds.p_min.x;
+        let n_pixels: usize = std::cmp::max(pixel_bounds.area(), 0) as usize;
+        // samples taken so far and to be taken after the current round
+        let mut counts: Vec<i64> = vec![0_i64; n_pixels];
+        let mut targets: Vec<i64> = vec![initial_samples; n_pixels];
+        let total_samples: i64 = average_samples * n_pixels as i64;
+        let mut used_samples: i64 = 0;
+        let start: Instant = Instant::now();
+        let mut round: u64 = 0;
+        loop {
+            let round_start: Instant = Instant::now();
+            let block_queue = BlockQueue::new(
+                (
+                    (n_tiles.x * tile_size) as u32,
+                    (n_tiles.y * tile_size) as u32,
+                ),
+                (tile_size as u32, tile_size as u32),
+                (0, 0),
+            );
+            let pixel_bounds = &pixel_bounds;
+            let counts_ref = &counts;
+            let targets_ref = &targets;
+            let mut pb = pbr::ProgressBar::new(block_queue.len() as u64);
+            self.for_each_tile(
+                &nested_sampler,
+                num_threads,
+                &block_queue,
+                |tile_sampler, _, tile_bounds, film_tile| {
+                    for pixel in tile_bounds {
+                        if !pnt2_inside_exclusivei(pixel, pixel_bounds) {
+                            continue;
+                        }
+                        let idx: usize = ((pixel.y - pixel_bounds.p_min.y) * width + pixel.x
+                            - pixel_bounds.p_min.x)
+                            as usize;
+                        let first_sample: i64 = counts_ref[idx];
+                        let last_sample: i64 = targets_ref[idx];
+                        if first_sample >= last_sample {
+                            continue;
+                        }
+                        // the same per pixel seed in every round
+                        // reproduces the pixel's sample set ...
+                        tile_sampler.reseed(idx as u64);
+                        tile_sampler.start_pixel(pixel);
+                        // ... but other random numbers should differ
+                        tile_sampler.reseed((round + 1) * n_pixels as u64 + idx as u64);
+                        for sample_num in first_sample..last_sample {
+                            if !tile_sampler.set_sample_number(sample_num) {
+                                break;
+                            }
+                            integrator.render_sample(
+                                scene,
+                                camera,
+                                film,
+                                tile_sampler,
+                                pixel,
+                                film_tile,
+                            );
+                        }
+                    }
+                },
+                |_, film_tile| {
+                    // merge image tile into _Film_
+                    film.merge_film_tile(&film_tile);
+                    pb.inc();
+                },
+            );
+            let last_round_seconds: Float = round_start.elapsed().as_secs_f32();
+            for (count, target) in counts.iter_mut().zip(targets.iter()) {
+                used_samples += *target - *count;
+                *count = *target;
+            }
+            let remaining: i64 = total_samples - used_samples;
+            if remaining <= 0 {
+                break;
+            }
+            if budget.time_limit > 0.0 as Float
+                && start.elapsed().as_secs_f32() + last_round_seconds > budget.time_limit
+            {
+                println!(
+                    "Time limit of {:?} seconds reached after {:?} round(s)",
+                    budget.time_limit,
+                    round + 1
+                );
+                break;
+            }
+            // refine the pixels with the highest relative variance
+            let mut candidates: Vec<(Float, usize)> = Vec::new();
+            for (idx, count) in counts.iter().enumerate() {
+                if *count >= max_samples {
+                    continue;
+                }
+                let p: Point2i = Point2i {
+                    x: pixel_bounds.p_min.x + idx as i32 % width,
+                    y: pixel_bounds.p_min.y + idx as i32 / width,
+                };
+                let estimate: PixelEstimate = film.get_pixel_estimate(p);
+                if budget.is_converged(&estimate) {
+                    continue;
+                }
+                candidates.push((estimate.relative_variance(), idx));
+            }
+            candidates.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(std::cmp::Ordering::Equal));
+            // spend about half of the remaining samples per round
+            let mut round_samples: i64 =
+                std::cmp::min(remaining, std::cmp::max(remaining / 2, initial_samples));
+            let mut n_refined: usize = 0;
+            for (_error, idx) in candidates {
+                let target: i64 = std::cmp::min(counts[idx] * 2, max_samples);
+                let cost: i64 = target - counts[idx];
+                if cost <= round_samples {
+                    targets[idx] = target;
+                    round_samples -= cost;
+                    n_refined += 1;
+                }
+            }
+            if n_refined == 0 {
+                break;
+            }
+            round += 1;
+            println!("Round {:?}: refining {:?} pixel(s)", round, n_refined);
         }
+        println!(
+            "{:?} sample(s) per pixel on average",
+            used_samples as Float / std::cmp::max(n_pixels, 1) as Float
+        );
         film.write_image(1.0 as Float);
     }
-    pub fn li(&self, ray: &mut Ray, scene: &Scene, sampler: &mut Sampler, depth: i32) -> Spectrum {
+    /// Traces a single camera sample for the sampler's current pixel
+    /// sample and adds its contribution to the film tile.
+    fn render_sample(
+        &self,
+        scene: &Scene,
+        camera: &Arc<Camera>,
+        film: &Film,
+        tile_sampler: &mut Sampler,
+        pixel: Point2i,
+        film_tile: &mut FilmTile,
+    ) {
+        // let's use the copy_arena crate instead of pbrt's MemoryArena
+        // let mut arena: Arena = Arena::with_capacity(262144); // 256kB
+
+        // initialize _CameraSample_ for current sample
+        let camera_sample: CameraSample = tile_sampler.get_camera_sample(pixel);
+        // generate camera ray for current sample
+        let mut ray: Ray = Ray::default();
+        let ray_weight: Float = camera.generate_ray_differential(&camera_sample, &mut ray);
+        ray.scale_differentials(
+            1.0 as Float / (tile_sampler.get_samples_per_pixel() as Float).sqrt(),
+        );
+        // TODO: ++nCameraRays;
+        // the integrator records the AOVs at the first intersection
+        let mut aov: Option<AovSample> = None;
+        if film_tile.has_aovs() && ray_weight > 0.0 {
+            aov = Some(AovSample::new(&film.aovs, &ray.o));
+        }
+        // evaluate radiance along camera ray
+        let mut l: Spectrum = Spectrum::new(0.0 as Float);
+        let y: Float = l.y();
+        if ray_weight > 0.0 {
+            // ADDED
+            let clipping_start: Float = camera.get_clipping_start();
+            if clipping_start > 0.0 as Float {
+                // adjust ray origin for near clipping
+                camera.adjust_to_clipping_start(&camera_sample, &mut ray);
+            }
+            // ADDED
+            l = self.li(
+                &mut ray,
+                scene,
+                tile_sampler, // &mut arena,
+                0_i32,
+                &mut aov,
+            );
+        }
+        if l.has_nans() {
+            println!(
+                "Not-a-number radiance value returned for pixel \
+                         ({:?}, {:?}), sample {:?}. Setting to black.",
+                pixel.x,
+                pixel.y,
+                tile_sampler.get_current_sample_number()
+            );
+            l = Spectrum::new(0.0);
+        } else if y < -10.0e-5 as Float {
+            println!(
+                "Negative luminance value, {:?}, returned for pixel \
+                     ({:?}, {:?}), sample {:?}. Setting to black.",
+                y,
+                pixel.x,
+                pixel.y,
+                tile_sampler.get_current_sample_number()
+            );
+            l = Spectrum::new(0.0);
+        } else if y.is_infinite() {
+            println!(
+                "Infinite luminance value returned for pixel ({:?}, \
+                     {:?}), sample {:?}. Setting to black.",
+                pixel.x,
+                pixel.y,
+                tile_sampler.get_current_sample_number()
+            );
+            l = Spectrum::new(0.0);
+        }
+        // println!("Camera sample: {:?} -> ray: {:?} -> L = {:?}",
+        //          camera_sample, ray, l);
+        // add camera ray's contribution to image
+        film_tile.add_sample(camera_sample.p_film, &mut l, ray_weight);
+        if let Some(ref aov) = aov {
+            film_tile.add_aov_sample(camera_sample.p_film, &aov.values());
+        }
+    }
+    pub fn li(
+        &self,
+        ray: &mut Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        depth: i32,
+        aov: &mut Option<AovSample>,
+    ) -> Spectrum {
         match self {
-            SamplerIntegrator::AO(integrator) => integrator.li(ray, scene, sampler, depth),
+            SamplerIntegrator::AO(integrator) => integrator.li(ray, scene, sampler, depth, aov),
             SamplerIntegrator::DirectLighting(integrator) => {
-                integrator.li(ray, scene, sampler, depth)
+                integrator.li(ray, scene, sampler, depth, aov)
+            }
+            SamplerIntegrator::GuidedPath(integrator) => {
+                integrator.li(ray, scene, sampler, depth, aov)
+            }
+            SamplerIntegrator::Path(integrator) => integrator.li(ray, scene, sampler, depth, aov),
+            SamplerIntegrator::VolPath(integrator) => {
+                integrator.li(ray, scene, sampler, depth, aov)
+            }
+            SamplerIntegrator::Whitted(integrator) => {
+                integrator.li(ray, scene, sampler, depth, aov)
             }
-            SamplerIntegrator::Path(integrator) => integrator.li(ray, scene, sampler, depth),
-            SamplerIntegrator::VolPath(integrator) => integrator.li(ray, scene, sampler, depth),
-            SamplerIntegrator::Whitted(integrator) => integrator.li(ray, scene, sampler, depth),
         }
     }
     pub fn get_camera(&self) -> Arc<Camera> {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.get_camera(),
             SamplerIntegrator::DirectLighting(integrator) => integrator.get_camera(),
+            SamplerIntegrator::GuidedPath(integrator) => integrator.get_camera(),
             SamplerIntegrator::Path(integrator) => integrator.get_camera(),
             SamplerIntegrator::VolPath(integrator) => integrator.get_camera(),
             SamplerIntegrator::Whitted(integrator) => integrator.get_camera(),
@@ -242,6 +812,7 @@ impl SamplerIntegrator {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.get_sampler(),
             SamplerIntegrator::DirectLighting(integrator) => integrator.get_sampler(),
+            SamplerIntegrator::GuidedPath(integrator) => integrator.get_sampler(),
             SamplerIntegrator::Path(integrator) => integrator.get_sampler(),
             SamplerIntegrator::VolPath(integrator) => integrator.get_sampler(),
             SamplerIntegrator::Whitted(integrator) => integrator.get_sampler(),
@@ -251,6 +822,7 @@ impl SamplerIntegrator {
         match self {
             SamplerIntegrator::AO(integrator) => integrator.get_pixel_bounds(),
             SamplerIntegrator::DirectLighting(integrator) => integrator.get_pixel_bounds(),
+            SamplerIntegrator::GuidedPath(integrator) => integrator.get_pixel_bounds(),
             SamplerIntegrator::Path(integrator) => integrator.get_pixel_bounds(),
             SamplerIntegrator::VolPath(integrator) => integrator.get_pixel_bounds(),
             SamplerIntegrator::Whitted(integrator) => integrator.get_pixel_bounds(),
@@ -294,6 +866,88 @@ impl SamplerIntegrator {
     }
 }
 
+/// The values of the requested AOVs (see
+/// [Aov](../film/enum.Aov.html)) of a camera sample. The integrators
+/// record them at the first intersection of the camera ray (like
+/// pbrt-v4's *VisibleSurface*), a ray which misses the scene results
+/// in zero values.
+pub struct AovSample<'a> {
+    aovs: &'a [Aov],
+    camera_origin: Point3f,
+    values: Option<SmallVec<[Float; 16]>>,
+}
+
+impl<'a> AovSample<'a> {
+    pub fn new(aovs: &'a [Aov], camera_origin: &Point3f) -> Self {
+        AovSample {
+            aovs,
+            camera_origin: *camera_origin,
+            values: None,
+        }
+    }
+    /// Records the values at |isect|, once its scattering functions
+    /// are computed. Only the first call counts, so integrators can
+    /// call this for every intersection of the camera ray, including
+    /// skipped medium boundaries.
+    pub fn record(&mut self, isect: &SurfaceInteraction) {
+        if self.values.is_some() {
+            return;
+        }
+        let mut albedo: [Float; 3] = [0.0 as Float; 3];
+        if self.aovs.contains(&Aov::Albedo) {
+            if let Some(ref bsdf) = isect.bsdf {
+                // use a fixed stratified pattern to estimate the reflectance
+                let mut u: [Point2f; 16] = [Point2f::default(); 16];
+                for (i, sample) in u.iter_mut().enumerate() {
+                    sample.x = ((i % 4) as Float + 0.5 as Float) / 4.0 as Float;
+                    sample.y = ((i / 4) as Float + 0.5 as Float) / 4.0 as Float;
+                }
+                let rho: Spectrum = bsdf.rho_hd(&isect.common.wo, &u, BxdfType::BsdfAll as u8);
+                rho.to_rgb(&mut albedo);
+            }
+        }
+        let (object_id, material_id): (u32, u32) = match isect.primitive {
+            Some(primitive_raw) => {
+                let primitive = unsafe { &*primitive_raw };
+                primitive.get_ids()
+            }
+            None => (0_u32, 0_u32),
+        };
+        let mut values: SmallVec<[Float; 16]> = SmallVec::new();
+        for aov in self.aovs {
+            match aov {
+                Aov::Depth => values.push((isect.common.p - self.camera_origin).length()),
+                Aov::Normal => values.extend_from_slice(&[
+                    isect.shading.n.x,
+                    isect.shading.n.y,
+                    isect.shading.n.z,
+                ]),
+                Aov::Albedo => values.extend_from_slice(&albedo),
+                Aov::Position => values.extend_from_slice(&[
+                    isect.common.p.x,
+                    isect.common.p.y,
+                    isect.common.p.z,
+                ]),
+                Aov::PrimitiveId => values.push(object_id as Float),
+                Aov::MaterialId => values.push(material_id as Float),
+                Aov::SampleCount => {}
+            }
+        }
+        self.values = Some(values);
+    }
+    /// The recorded values in the order of the requested AOVs (see
+    /// **Aov::num_values()**).
+    pub fn values(&self) -> SmallVec<[Float; 16]> {
+        match self.values {
+            Some(ref values) => values.clone(),
+            None => {
+                let n_values: usize = self.aovs.iter().map(|aov| aov.num_values()).sum();
+                SmallVec::from_elem(0.0 as Float, n_values)
+            }
+        }
+    }
+}
+
 // see integrator.cpp
 
 /// Most basic direct lighting strategy.
@@ -402,6 +1056,49 @@ pub fn uniform_sample_one_light(
     ) / pdf
 }
 
+/// Like uniform_sample_one_light(), but the light is chosen by a
+/// LightDistribution, which may take the position and the shading
+/// normal of the interaction into account.
+pub fn sample_one_light(
+    it: &dyn Interaction,
+    scene: &Scene,
+    sampler: &mut Sampler,
+    handle_media: bool,
+    light_distribution: &LightDistribution,
+) -> Spectrum {
+    // TODO: ProfilePhase p(Prof::DirectLighting);
+    if scene.lights.is_empty() {
+        return Spectrum::default();
+    }
+    let n: Normal3f = if let Some(shading_n) = it.get_shading_n() {
+        *shading_n
+    } else {
+        Normal3f::default()
+    };
+    let mut pdf: Float = 0.0 as Float;
+    let light_num: usize =
+        match light_distribution.sample(it.get_p(), &n, sampler.get_1d(), &mut pdf) {
+            Some(light_num) => light_num,
+            None => return Spectrum::default(),
+        };
+    if pdf == 0.0 as Float {
+        return Spectrum::default();
+    }
+    let light = &scene.lights[light_num];
+    let u_light: Point2f = sampler.get_2d();
+    let u_scattering: Point2f = sampler.get_2d();
+    estimate_direct(
+        it,
+        u_scattering,
+        light,
+        u_light,
+        scene,
+        sampler,
+        handle_media,
+        false,
+    ) / pdf
+}
+
 /// Computes a direct lighting estimate for a single light source sample.
 pub fn estimate_direct(
     it: &dyn Interaction,
@@ -243,6 +243,8 @@ pub struct SurfaceInteraction<'a> {
     pub bsdf: Option<Bsdf>,
     pub bssrdf: Option<TabulatedBssrdf>,
     pub shape: Option<&'a Shape>,
+    /// the face index of the hit triangle (used by Ptex textures)
+    pub face_index: i32,
 }
 
 impl<'a> SurfaceInteraction<'a> {
@@ -306,6 +308,7 @@ impl<'a> SurfaceInteraction<'a> {
                 bsdf: None,
                 bssrdf: None,
                 shape: Some(shape),
+                face_index: 0,
             }
         } else {
             SurfaceInteraction {
@@ -326,6 +329,7 @@ impl<'a> SurfaceInteraction<'a> {
                 bsdf: None,
                 bssrdf: None,
                 shape: None,
+                face_index: 0,
             }
         }
     }
@@ -2,12 +2,17 @@
 //! source of illumination so that some light is reflected from them
 //! to the camera sensor.
 
+// std
+use std::f32::consts::PI;
 // pbrt
-use crate::core::geometry::{Normal3f, Point2f, Ray, Vector3f};
+use crate::core::geometry::{bnd3_union_bnd3f, pnt3_distance_squaredf};
+use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_cross_vec3, vec3_dot_vec3f};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
-use crate::core::pbrt::{Float, Spectrum};
+use crate::core::pbrt::{degrees, Float, Spectrum};
 use crate::core::sampler::Sampler;
 use crate::core::scene::Scene;
+use crate::core::transform::Transform;
 use crate::lights::diffuse::DiffuseAreaLight;
 use crate::lights::distant::DistantLight;
 use crate::lights::goniometric::GonioPhotometricLight;
@@ -164,6 +169,19 @@ impl Light {
             Light::Spot(light) => light.get_n_samples(),
         }
     }
+    /// Returns the spatial and directional bounds of the light's
+    /// emission, or None for lights at infinity.
+    pub fn bounds(&self) -> Option<LightBounds> {
+        match self {
+            Light::DiffuseArea(light) => light.bounds(),
+            Light::Distant(light) => light.bounds(),
+            Light::GonioPhotometric(light) => light.bounds(),
+            Light::InfiniteArea(light) => light.bounds(),
+            Light::Point(light) => light.bounds(),
+            Light::Projection(light) => light.bounds(),
+            Light::Spot(light) => light.bounds(),
+        }
+    }
     // AreaLight
     pub fn l(&self, intr: &InteractionCommon, w: &Vector3f) -> Spectrum {
         match self {
@@ -187,6 +205,202 @@ pub fn is_delta_light(flags: u8) -> bool {
     pos || dir
 }
 
+/// Bounds of the emission of a light (or a cluster of lights) used
+/// by the light BVH: a bounding box, the emitted power *phi*, and a
+/// cone of emitting normals around *w* with spread *theta_o*, past
+/// which emission falls off to zero within *theta_e* (see pbrt-v4).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct LightBounds {
+    pub bounds: Bounds3f,
+    pub phi: Float,
+    pub w: Vector3f,
+    pub cos_theta_o: Float,
+    pub cos_theta_e: Float,
+    pub two_sided: bool,
+}
+
+impl LightBounds {
+    pub fn new(
+        bounds: &Bounds3f,
+        w: &Vector3f,
+        phi: Float,
+        cos_theta_o: Float,
+        cos_theta_e: Float,
+        two_sided: bool,
+    ) -> Self {
+        LightBounds {
+            bounds: *bounds,
+            phi,
+            w: w.normalize(),
+            cos_theta_o,
+            cos_theta_e,
+            two_sided,
+        }
+    }
+    /// Bounds of a light at a single point emitting in all directions.
+    pub fn point(p: &Point3f, phi: Float) -> Self {
+        LightBounds::new(
+            &Bounds3f::new(*p, *p),
+            &Vector3f {
+                x: 0.0,
+                y: 0.0,
+                z: 1.0,
+            },
+            phi,
+            -1.0 as Float,
+            0.0 as Float,
+            false,
+        )
+    }
+    pub fn centroid(&self) -> Point3f {
+        (self.bounds.p_min + self.bounds.p_max) * 0.5 as Float
+    }
+    /// Conservative estimate of the contribution of the bounded
+    /// lights to a reference point *p* with surface normal *n* (zero
+    /// for points in participating media).
+    pub fn importance(&self, p: &Point3f, n: &Normal3f) -> Float {
+        if self.phi <= 0.0 as Float {
+            return 0.0 as Float;
+        }
+        // compute clamped squared distance to reference point
+        let pc: Point3f = self.centroid();
+        let d2: Float =
+            pnt3_distance_squaredf(p, &pc).max(self.bounds.diagonal().length() * 0.5 as Float);
+        // compute sine and cosine of angle to vector w
+        let wi: Vector3f = (*p - pc).normalize();
+        let mut cos_theta_w: Float = vec3_dot_vec3f(&self.w, &wi);
+        if self.two_sided {
+            cos_theta_w = cos_theta_w.abs();
+        }
+        if cos_theta_w.is_nan() {
+            // p is the centroid of a point light
+            cos_theta_w = 1.0 as Float;
+        }
+        let sin_theta_w: Float = safe_sqrt(1.0 as Float - cos_theta_w * cos_theta_w);
+        // compute the cosine of the angle subtended by the bounds
+        let cos_theta_b: Float = bound_subtended_directions(&self.bounds, p);
+        let sin_theta_b: Float = safe_sqrt(1.0 as Float - cos_theta_b * cos_theta_b);
+        // compute cos(theta') and test against cos(theta_e)
+        let sin_theta_o: Float = safe_sqrt(1.0 as Float - self.cos_theta_o * self.cos_theta_o);
+        let cos_theta_x: Float =
+            cos_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let sin_theta_x: Float =
+            sin_sub_clamped(sin_theta_w, cos_theta_w, sin_theta_o, self.cos_theta_o);
+        let cos_theta_p: Float =
+            cos_sub_clamped(sin_theta_x, cos_theta_x, sin_theta_b, cos_theta_b);
+        if cos_theta_p <= self.cos_theta_e {
+            return 0.0 as Float;
+        }
+        let mut importance: Float = self.phi * cos_theta_p / d2;
+        // account for cos(theta_i) at surfaces
+        if n.x != 0.0 as Float || n.y != 0.0 as Float || n.z != 0.0 as Float {
+            let cos_theta_i: Float = vec3_abs_dot_nrmf(&wi, n).min(1.0 as Float);
+            let sin_theta_i: Float = safe_sqrt(1.0 as Float - cos_theta_i * cos_theta_i);
+            importance *= cos_sub_clamped(sin_theta_i, cos_theta_i, sin_theta_b, cos_theta_b);
+        }
+        importance.max(0.0 as Float)
+    }
+    pub fn union(a: &LightBounds, b: &LightBounds) -> LightBounds {
+        if a.phi <= 0.0 as Float {
+            return *b;
+        }
+        if b.phi <= 0.0 as Float {
+            return *a;
+        }
+        let (w, cos_theta_o) = cone_union(&a.w, a.cos_theta_o, &b.w, b.cos_theta_o);
+        LightBounds {
+            bounds: bnd3_union_bnd3f(&a.bounds, &b.bounds),
+            phi: a.phi + b.phi,
+            w,
+            cos_theta_o,
+            cos_theta_e: a.cos_theta_e.min(b.cos_theta_e),
+            two_sided: a.two_sided || b.two_sided,
+        }
+    }
+}
+
+fn safe_sqrt(x: Float) -> Float {
+    x.max(0.0 as Float).sqrt()
+}
+
+fn safe_acos(x: Float) -> Float {
+    x.max(-1.0 as Float).min(1.0 as Float).acos()
+}
+
+/// cos(max(0, theta_a - theta_b))
+fn cos_sub_clamped(
+    sin_theta_a: Float,
+    cos_theta_a: Float,
+    sin_theta_b: Float,
+    cos_theta_b: Float,
+) -> Float {
+    if cos_theta_a > cos_theta_b {
+        return 1.0 as Float;
+    }
+    cos_theta_a * cos_theta_b + sin_theta_a * sin_theta_b
+}
+
+/// sin(max(0, theta_a - theta_b))
+fn sin_sub_clamped(
+    sin_theta_a: Float,
+    cos_theta_a: Float,
+    sin_theta_b: Float,
+    cos_theta_b: Float,
+) -> Float {
+    if cos_theta_a > cos_theta_b {
+        return 0.0 as Float;
+    }
+    sin_theta_a * cos_theta_b - cos_theta_a * sin_theta_b
+}
+
+/// Cosine of the half angle of the cone of directions from *p* to
+/// the bounding sphere of *b* (-1 if *p* is inside).
+fn bound_subtended_directions(b: &Bounds3f, p: &Point3f) -> Float {
+    let mut center: Point3f = Point3f::default();
+    let mut radius: Float = 0.0 as Float;
+    Bounds3f::bounding_sphere(b, &mut center, &mut radius);
+    let dist2: Float = pnt3_distance_squaredf(p, &center);
+    if dist2 < radius * radius {
+        return -1.0 as Float;
+    }
+    let sin2_theta_max: Float = radius * radius / dist2;
+    safe_sqrt(1.0 as Float - sin2_theta_max)
+}
+
+/// Smallest cone containing the two cones given by their central
+/// directions and the cosines of their spread angles.
+fn cone_union(
+    w_a: &Vector3f,
+    cos_theta_a: Float,
+    w_b: &Vector3f,
+    cos_theta_b: Float,
+) -> (Vector3f, Float) {
+    let entire_sphere: (Vector3f, Float) = (*w_a, -1.0 as Float);
+    // handle the cases where one cone is inside the other
+    let theta_a: Float = safe_acos(cos_theta_a);
+    let theta_b: Float = safe_acos(cos_theta_b);
+    let theta_d: Float = safe_acos(vec3_dot_vec3f(w_a, w_b));
+    if (theta_d + theta_b).min(PI) <= theta_a {
+        return (*w_a, cos_theta_a);
+    }
+    if (theta_d + theta_a).min(PI) <= theta_b {
+        return (*w_b, cos_theta_b);
+    }
+    // compute the spread angle of the merged cone
+    let theta_o: Float = (theta_a + theta_d + theta_b) * 0.5 as Float;
+    if theta_o >= PI {
+        return entire_sphere;
+    }
+    // rotate w_a towards w_b to get the merged cone's direction
+    let theta_r: Float = theta_o - theta_a;
+    let wr: Vector3f = vec3_cross_vec3(w_a, w_b);
+    if wr.length_squared() == 0.0 as Float {
+        return entire_sphere;
+    }
+    let w: Vector3f = Transform::rotate(degrees(theta_r), &wr).transform_vector(w_a);
+    (w.normalize(), theta_o.cos())
+}
+
 /// VisibilityTesters are created by providing two Interaction
 /// objects, one for each end point of the shadow ray to be traced.
 #[derive(Default, Clone)]
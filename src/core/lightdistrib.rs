@@ -1,6 +1,7 @@
 //! Various probability distributions for sampling light sources.
 
 // std
+use std::f32::consts::PI;
 use std::rc::Rc;
 use std::sync::Arc;
 // others
@@ -8,13 +9,15 @@ use atom::AtomSetOnce;
 use atomic::{Atomic, Ordering};
 use strum::IntoEnumIterator;
 // pbrt
+use crate::core::geometry::{bnd3_union_bnd3f, bnd3_union_pnt3f, vec3_max_componentf};
 use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Point3i, Vector3f, XYZEnum};
 use crate::core::integrator::compute_light_power_distribution;
 use crate::core::interaction::InteractionCommon;
-use crate::core::light::VisibilityTester;
+use crate::core::light::{LightBounds, VisibilityTester};
 use crate::core::lowdiscrepancy::radical_inverse;
 use crate::core::pbrt::clamp_t;
 use crate::core::pbrt::{Float, Spectrum};
+use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
 use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
 
@@ -27,6 +30,7 @@ pub enum LightDistribution {
     Uniform(UniformLightDistribution),
     Power(PowerLightDistribution),
     Spatial(SpatialLightDistribution),
+    Bvh(BvhLightDistribution),
 }
 
 impl LightDistribution {
@@ -35,6 +39,43 @@ impl LightDistribution {
             LightDistribution::Uniform(distribution) => distribution.lookup(p),
             LightDistribution::Power(distribution) => distribution.lookup(p),
             LightDistribution::Spatial(distribution) => distribution.lookup(p),
+            LightDistribution::Bvh(distribution) => distribution.lookup(p),
+        }
+    }
+    /// Chooses a light for the reference point |p| with surface
+    /// normal |n| (zero for points in participating media) and
+    /// returns its index together with the probability of having
+    /// chosen it.
+    pub fn sample(&self, p: &Point3f, n: &Normal3f, u: Float, pmf: &mut Float) -> Option<usize> {
+        match self {
+            LightDistribution::Bvh(distribution) => distribution.sample(p, n, u, pmf),
+            _ => {
+                let distrib: Arc<Distribution1D> = self.lookup(p);
+                if distrib.count() == 0 {
+                    *pmf = 0.0 as Float;
+                    return None;
+                }
+                let light_num: usize = distrib.sample_discrete(u, Some(pmf));
+                if *pmf == 0.0 as Float {
+                    return None;
+                }
+                Some(light_num)
+            }
+        }
+    }
+    /// Returns the probability that sample() chooses the light with
+    /// index |light_num| for the reference point |p| with surface
+    /// normal |n|.
+    pub fn pmf(&self, p: &Point3f, n: &Normal3f, light_num: usize) -> Float {
+        match self {
+            LightDistribution::Bvh(distribution) => distribution.pmf(p, n, light_num),
+            _ => {
+                let distrib: Arc<Distribution1D> = self.lookup(p);
+                if light_num >= distrib.count() {
+                    return 0.0 as Float;
+                }
+                distrib.discrete_pdf(light_num)
+            }
         }
     }
 }
@@ -384,6 +425,297 @@ impl SpatialLightDistribution {
     }
 }
 
+/// A node of the light BVH. Interior nodes store the index of their
+/// second child, the first one directly follows them; leaves store
+/// the index of their light in the scene.
+struct LightBvhNode {
+    light_bounds: LightBounds,
+    child_or_light_index: usize,
+    parent: usize,
+    is_leaf: bool,
+}
+
+/// Samples lights by traversing a bounding volume hierarchy over the
+/// lights' bounds (see pbrt-v4's BVHLightSampler). At each interior
+/// node a child is chosen proportional to a conservative estimate of
+/// its importance for the reference point, which accounts for
+/// distance, emitted power and the cone of emitting normals. Lights
+/// at infinity are sampled separately, each with the same
+/// probability as the whole tree.
+pub struct BvhLightDistribution {
+    nodes: Vec<LightBvhNode>,
+    infinite_lights: Vec<usize>,
+    // leaf node of each light, if it's in the tree
+    light_nodes: Vec<Option<usize>>,
+    n_lights: usize,
+}
+
+impl BvhLightDistribution {
+    pub fn new(scene: &Scene) -> Self {
+        let mut infinite_lights: Vec<usize> = Vec::new();
+        let mut bvh_lights: Vec<(usize, LightBounds)> = Vec::new();
+        for (i, light) in scene.lights.iter().enumerate() {
+            if let Some(light_bounds) = light.bounds() {
+                // lights without emission are never sampled
+                if light_bounds.phi > 0.0 as Float {
+                    bvh_lights.push((i, light_bounds));
+                }
+            } else {
+                infinite_lights.push(i);
+            }
+        }
+        let mut distribution: BvhLightDistribution = BvhLightDistribution {
+            nodes: Vec::with_capacity(2 * bvh_lights.len()),
+            infinite_lights,
+            light_nodes: vec![None; scene.lights.len()],
+            n_lights: scene.lights.len(),
+        };
+        if !bvh_lights.is_empty() {
+            distribution.build(&mut bvh_lights, 0);
+        }
+        distribution
+    }
+    fn build(&mut self, bvh_lights: &mut [(usize, LightBounds)], parent: usize) -> LightBounds {
+        if bvh_lights.len() == 1 {
+            let (light_index, light_bounds) = bvh_lights[0];
+            self.light_nodes[light_index] = Some(self.nodes.len());
+            self.nodes.push(LightBvhNode {
+                light_bounds,
+                child_or_light_index: light_index,
+                parent,
+                is_leaf: true,
+            });
+            return light_bounds;
+        }
+        // choose the split minimizing the cost over 12 buckets per
+        // dimension of the centroid bounds
+        let mut bounds: Bounds3f = Bounds3f::default();
+        let mut centroid_bounds: Bounds3f = Bounds3f::default();
+        for (_i, light_bounds) in bvh_lights.iter() {
+            bounds = bnd3_union_bnd3f(&bounds, &light_bounds.bounds);
+            centroid_bounds = bnd3_union_pnt3f(&centroid_bounds, &light_bounds.centroid());
+        }
+        let mut min_cost: Float = Float::INFINITY;
+        let mut min_split: Option<(XYZEnum, usize)> = None;
+        for dim in XYZEnum::iter() {
+            let extent: Float = centroid_bounds.p_max[dim] - centroid_bounds.p_min[dim];
+            if extent <= 0.0 as Float {
+                continue;
+            }
+            let mut buckets: [LightBounds; N_BUCKETS] = [LightBounds::default(); N_BUCKETS];
+            for (_i, light_bounds) in bvh_lights.iter() {
+                let b: usize = bucket(&light_bounds.centroid(), &centroid_bounds, dim);
+                buckets[b] = LightBounds::union(&buckets[b], light_bounds);
+            }
+            for split in 0..N_BUCKETS - 1 {
+                let mut below: LightBounds = LightBounds::default();
+                let mut above: LightBounds = LightBounds::default();
+                for b in buckets.iter().take(split + 1) {
+                    below = LightBounds::union(&below, b);
+                }
+                for b in buckets.iter().skip(split + 1) {
+                    above = LightBounds::union(&above, b);
+                }
+                let cost: Float =
+                    evaluate_cost(&below, &bounds, dim) + evaluate_cost(&above, &bounds, dim);
+                if cost > 0.0 as Float && cost < min_cost {
+                    min_cost = cost;
+                    min_split = Some((dim, split));
+                }
+            }
+        }
+        let mut mid: usize = bvh_lights.len() / 2;
+        if let Some((dim, split)) = min_split {
+            let (below, above): (Vec<_>, Vec<_>) = bvh_lights
+                .iter()
+                .partition(|(_i, lb)| bucket(&lb.centroid(), &centroid_bounds, dim) <= split);
+            if !below.is_empty() && !above.is_empty() {
+                mid = below.len();
+                bvh_lights[..mid].copy_from_slice(&below);
+                bvh_lights[mid..].copy_from_slice(&above);
+            }
+        }
+        // the first child directly follows its parent
+        let node_index: usize = self.nodes.len();
+        self.nodes.push(LightBvhNode {
+            light_bounds: LightBounds::default(),
+            child_or_light_index: 0,
+            parent,
+            is_leaf: false,
+        });
+        let (lights0, lights1) = bvh_lights.split_at_mut(mid);
+        let bounds0: LightBounds = self.build(lights0, node_index);
+        let child1: usize = self.nodes.len();
+        let bounds1: LightBounds = self.build(lights1, node_index);
+        let light_bounds: LightBounds = LightBounds::union(&bounds0, &bounds1);
+        self.nodes[node_index].light_bounds = light_bounds;
+        self.nodes[node_index].child_or_light_index = child1;
+        light_bounds
+    }
+    fn infinite_probability(&self) -> Float {
+        let n_infinite: usize = self.infinite_lights.len();
+        let n_bvh: usize = if self.nodes.is_empty() { 0 } else { 1 };
+        if n_infinite + n_bvh == 0 {
+            return 0.0 as Float;
+        }
+        n_infinite as Float / (n_infinite + n_bvh) as Float
+    }
+    pub fn sample(&self, p: &Point3f, n: &Normal3f, u: Float, pmf: &mut Float) -> Option<usize> {
+        *pmf = 0.0 as Float;
+        // sample infinite lights with uniform probability
+        let p_infinite: Float = self.infinite_probability();
+        if u < p_infinite {
+            let n_infinite: usize = self.infinite_lights.len();
+            let index: usize =
+                ((u / p_infinite * n_infinite as Float) as usize).min(n_infinite - 1);
+            *pmf = p_infinite / n_infinite as Float;
+            return Some(self.infinite_lights[index]);
+        }
+        if self.nodes.is_empty() {
+            return None;
+        }
+        // traverse the light BVH to sample a light
+        let mut u: Float =
+            ((u - p_infinite) / (1.0 as Float - p_infinite)).min(FLOAT_ONE_MINUS_EPSILON);
+        let mut node_index: usize = 0;
+        let mut node_pmf: Float = 1.0 as Float - p_infinite;
+        loop {
+            let node: &LightBvhNode = &self.nodes[node_index];
+            if node.is_leaf {
+                if node_index > 0 || node.light_bounds.importance(p, n) > 0.0 as Float {
+                    *pmf = node_pmf;
+                    return Some(node.child_or_light_index);
+                }
+                return None;
+            }
+            let children: [usize; 2] = [node_index + 1, node.child_or_light_index];
+            let ci0: Float = self.nodes[children[0]].light_bounds.importance(p, n);
+            let ci1: Float = self.nodes[children[1]].light_bounds.importance(p, n);
+            if ci0 == 0.0 as Float && ci1 == 0.0 as Float {
+                return None;
+            }
+            let p0: Float = ci0 / (ci0 + ci1);
+            if u < p0 {
+                node_index = children[0];
+                u = (u / p0).min(FLOAT_ONE_MINUS_EPSILON);
+                node_pmf *= p0;
+            } else {
+                node_index = children[1];
+                u = ((u - p0) / (1.0 as Float - p0)).min(FLOAT_ONE_MINUS_EPSILON);
+                node_pmf *= 1.0 as Float - p0;
+            }
+        }
+    }
+    pub fn pmf(&self, p: &Point3f, n: &Normal3f, light_num: usize) -> Float {
+        let p_infinite: Float = self.infinite_probability();
+        if self.infinite_lights.contains(&light_num) {
+            return p_infinite / self.infinite_lights.len() as Float;
+        }
+        let mut node_index: usize = match self.light_nodes.get(light_num) {
+            Some(Some(node_index)) => *node_index,
+            _ => return 0.0 as Float,
+        };
+        if node_index == 0 {
+            // a single light in the tree
+            if self.nodes[0].light_bounds.importance(p, n) > 0.0 as Float {
+                return 1.0 as Float - p_infinite;
+            }
+            return 0.0 as Float;
+        }
+        // multiply the probabilities of choosing the nodes on the path
+        // from the root to the light's leaf
+        let mut pmf: Float = 1.0 as Float - p_infinite;
+        while node_index > 0 {
+            let parent: usize = self.nodes[node_index].parent;
+            let ci0: Float = self.nodes[parent + 1].light_bounds.importance(p, n);
+            let ci1: Float = self.nodes[self.nodes[parent].child_or_light_index]
+                .light_bounds
+                .importance(p, n);
+            if ci0 + ci1 == 0.0 as Float {
+                return 0.0 as Float;
+            }
+            let ci: Float = if node_index == parent + 1 { ci0 } else { ci1 };
+            pmf *= ci / (ci0 + ci1);
+            node_index = parent;
+        }
+        pmf
+    }
+
+    // LightDistribution
+
+    /// Returns the probabilities with which sample() would choose
+    /// each light at |p| (ignoring surface normals), raised to a
+    /// small minimum so that every light can be chosen. This visits
+    /// the whole tree and allocates a distribution over all lights,
+    /// so integrators should use sample() and pmf() instead.
+    pub fn lookup(&self, p: &Point3f) -> Arc<Distribution1D> {
+        let mut light_pmf: Vec<Float> = vec![0.0 as Float; self.n_lights];
+        let p_infinite: Float = self.infinite_probability();
+        for light_index in &self.infinite_lights {
+            light_pmf[*light_index] = p_infinite / self.infinite_lights.len() as Float;
+        }
+        let n: Normal3f = Normal3f::default();
+        let mut todo: Vec<(usize, Float)> = Vec::with_capacity(64);
+        if !self.nodes.is_empty() {
+            todo.push((0_usize, 1.0 as Float - p_infinite));
+        }
+        while let Some((node_index, node_pmf)) = todo.pop() {
+            let node: &LightBvhNode = &self.nodes[node_index];
+            if node.is_leaf {
+                if node_index > 0 || node.light_bounds.importance(p, &n) > 0.0 as Float {
+                    light_pmf[node.child_or_light_index] = node_pmf;
+                }
+                continue;
+            }
+            let children: [usize; 2] = [node_index + 1, node.child_or_light_index];
+            let ci0: Float = self.nodes[children[0]].light_bounds.importance(p, &n);
+            let ci1: Float = self.nodes[children[1]].light_bounds.importance(p, &n);
+            if ci0 + ci1 > 0.0 as Float {
+                todo.push((children[0], node_pmf * ci0 / (ci0 + ci1)));
+                todo.push((children[1], node_pmf * ci1 / (ci0 + ci1)));
+            }
+        }
+        let min_pmf: Float = 0.001 as Float / self.n_lights.max(1) as Float;
+        for pmf in &mut light_pmf {
+            *pmf = pmf.max(min_pmf);
+        }
+        Arc::new(Distribution1D::new(light_pmf))
+    }
+}
+
+const N_BUCKETS: usize = 12;
+
+fn bucket(p: &Point3f, centroid_bounds: &Bounds3f, dim: XYZEnum) -> usize {
+    let offset: Float = (p[dim] - centroid_bounds.p_min[dim])
+        / (centroid_bounds.p_max[dim] - centroid_bounds.p_min[dim]);
+    ((offset * N_BUCKETS as Float) as usize).min(N_BUCKETS - 1)
+}
+
+/// Cost of a light BVH node: its power times a measure of its solid
+/// angle of emission and its surface area, where long and thin
+/// bounds are penalized when split along their short axes.
+fn evaluate_cost(b: &LightBounds, bounds: &Bounds3f, dim: XYZEnum) -> Float {
+    let theta_o: Float = b.cos_theta_o.max(-1.0 as Float).min(1.0 as Float).acos();
+    let theta_e: Float = b.cos_theta_e.max(-1.0 as Float).min(1.0 as Float).acos();
+    let theta_w: Float = (theta_o + theta_e).min(PI);
+    let sin_theta_o: Float = (1.0 as Float - b.cos_theta_o * b.cos_theta_o)
+        .max(0.0 as Float)
+        .sqrt();
+    let m_omega: Float = 2.0 as Float * PI * (1.0 as Float - b.cos_theta_o)
+        + PI / 2.0 as Float
+            * (2.0 as Float * theta_w * sin_theta_o
+                - (theta_o - 2.0 as Float * theta_w).cos()
+                - 2.0 as Float * theta_o * sin_theta_o
+                + b.cos_theta_o);
+    let diagonal: Vector3f = bounds.diagonal();
+    let kr: Float = if diagonal[dim] > 0.0 as Float {
+        vec3_max_componentf(&diagonal) / diagonal[dim]
+    } else {
+        1.0 as Float
+    };
+    b.phi * m_omega * kr * b.bounds.surface_area()
+}
+
 // see lightdistrib.cpp
 
 const INVALID_PACKED_POS: u64 = 0xffff_ffff_ffff_ffff;
@@ -406,6 +738,10 @@ pub fn create_light_sample_distribution(
         Some(Arc::new(LightDistribution::Spatial(
             SpatialLightDistribution::new(scene, 64),
         )))
+    } else if name == "bvh" {
+        Some(Arc::new(LightDistribution::Bvh(BvhLightDistribution::new(
+            scene,
+        ))))
     } else {
         println!(
             "Light sample distribution type \"{:?}\" unknown. Using \"spatial\".",
@@ -7,7 +7,7 @@ use crate::core::geometry::{Point2f, Point2i};
 use crate::core::pbrt::Float;
 use crate::core::rng::Rng;
 use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
-use crate::core::sampling::shuffle;
+use crate::core::sampling::{shuffle, shuffle_nested};
 use crate::core::sobolmatrices::{
     NUM_SOBOL_DIMENSIONS, SOBOL_MATRICES_32, SOBOL_MATRIX_SIZE, VD_C_SOBOL_MATRICES,
     VD_C_SOBOL_MATRICES_INV,
@@ -852,17 +852,46 @@ pub fn gray_code_sample_2d(c0: &[u32], c1: &[u32], n: u32, scramble: Point2i, p:
     }
 }
 
-/// Generates a number of scrambled 1D sample values using the Gray
-/// code-based sampling machinery.
-pub fn van_der_corput(
-    n_samples_per_pixel_sample: i32,
-    n_pixel_samples: i32,
-    samples: &mut [Float],
-    rng: &mut Rng,
-) {
-    let scramble: u32 = rng.uniform_uint32();
-    let c_van_der_corput: [u32; 32] = [
-        0x8000_0000,
+/// Generator matrix of the van der Corput sequence.
+pub const C_VAN_DER_CORPUT: [u32; 32] = [
+    0x8000_0000,
+    0x4000_0000,
+    0x2000_0000,
+    0x1000_0000,
+    0x0800_0000,
+    0x0400_0000,
+    0x0200_0000,
+    0x0100_0000,
+    0x0080_0000,
+    0x0040_0000,
+    0x0020_0000,
+    0x0010_0000,
+    0x80000,
+    0x40000,
+    0x20000,
+    0x10000,
+    0x8000,
+    0x4000,
+    0x2000,
+    0x1000,
+    0x800,
+    0x400,
+    0x200,
+    0x100,
+    0x80,
+    0x40,
+    0x20,
+    0x10,
+    0x8,
+    0x4,
+    0x2,
+    0x1,
+];
+
+/// 2D Sobol$'$ generator matrices.
+pub const C_SOBOL: [[u32; 32]; 2] = [
+    [
+        0x8000_0000_u32,
         0x4000_0000,
         0x2000_0000,
         0x1000_0000,
@@ -894,9 +923,54 @@ pub fn van_der_corput(
         0x4,
         0x2,
         0x1,
-    ];
+    ],
+    [
+        0x8000_0000_u32,
+        0xc000_0000,
+        0xa000_0000,
+        0xf000_0000,
+        0x8800_0000,
+        0xcc00_0000,
+        0xaa00_0000,
+        0xff00_0000,
+        0x8080_0000,
+        0xc0c0_0000,
+        0xa0a0_0000,
+        0xf0f0_0000,
+        0x8888_0000,
+        0xcccc_0000,
+        0xaaaa_0000,
+        0xffff_0000,
+        0x8000_8000,
+        0xc000_c000,
+        0xa000_a000,
+        0xf000_f000,
+        0x8800_8800,
+        0xcc00_cc00,
+        0xaa00_aa00,
+        0xff00_ff00,
+        0x8080_8080,
+        0xc0c0_c0c0,
+        0xa0a0_a0a0,
+        0xf0f0_f0f0,
+        0x8888_8888,
+        0xcccc_cccc,
+        0xaaaa_aaaa,
+        0xffff_ffff,
+    ],
+];
+
+/// Generates a number of scrambled 1D sample values using the Gray
+/// code-based sampling machinery.
+pub fn van_der_corput(
+    n_samples_per_pixel_sample: i32,
+    n_pixel_samples: i32,
+    samples: &mut [Float],
+    rng: &mut Rng,
+) {
+    let scramble: u32 = rng.uniform_uint32();
     let total_samples: i32 = n_samples_per_pixel_sample * n_pixel_samples;
-    gray_code_sample_1d(c_van_der_corput, total_samples as u32, scramble, samples);
+    gray_code_sample_1d(C_VAN_DER_CORPUT, total_samples as u32, scramble, samples);
     // randomly shuffle 1D sample points
     for i in 0..n_pixel_samples as usize {
         shuffle(
@@ -925,80 +999,9 @@ pub fn sobol_2d(
     let x: i32 = rng.uniform_uint32() as i32;
     let y: i32 = rng.uniform_uint32() as i32;
     let scramble: Point2i = Point2i { x, y };
-    // define 2D Sobol$'$ generator matrices _c_sobol[2]_
-    let c_sobol: [[u32; 32]; 2] = [
-        [
-            0x8000_0000_u32,
-            0x4000_0000,
-            0x2000_0000,
-            0x1000_0000,
-            0x0800_0000,
-            0x0400_0000,
-            0x0200_0000,
-            0x0100_0000,
-            0x0080_0000,
-            0x0040_0000,
-            0x0020_0000,
-            0x0010_0000,
-            0x80000,
-            0x40000,
-            0x20000,
-            0x10000,
-            0x8000,
-            0x4000,
-            0x2000,
-            0x1000,
-            0x800,
-            0x400,
-            0x200,
-            0x100,
-            0x80,
-            0x40,
-            0x20,
-            0x10,
-            0x8,
-            0x4,
-            0x2,
-            0x1,
-        ],
-        [
-            0x8000_0000_u32,
-            0xc000_0000,
-            0xa000_0000,
-            0xf000_0000,
-            0x8800_0000,
-            0xcc00_0000,
-            0xaa00_0000,
-            0xff00_0000,
-            0x8080_0000,
-            0xc0c0_0000,
-            0xa0a0_0000,
-            0xf0f0_0000,
-            0x8888_0000,
-            0xcccc_0000,
-            0xaaaa_0000,
-            0xffff_0000,
-            0x8000_8000,
-            0xc000_c000,
-            0xa000_a000,
-            0xf000_f000,
-            0x8800_8800,
-            0xcc00_cc00,
-            0xaa00_aa00,
-            0xff00_ff00,
-            0x8080_8080,
-            0xc0c0_c0c0,
-            0xa0a0_a0a0,
-            0xf0f0_f0f0,
-            0x8888_8888,
-            0xcccc_cccc,
-            0xaaaa_aaaa,
-            0xffff_ffff,
-        ],
-    ];
     gray_code_sample_2d(
-        &c_sobol[0],
-        &c_sobol[1],
+        &C_SOBOL[0],
+        &C_SOBOL[1],
         (n_samples_per_pixel_sample * n_pixel_samples) as u32,
         scramble,
         samples,
@@ -1009,6 +1012,58 @@ pub fn sobol_2d(
     shuffle(samples, n_pixel_samples, n_samples_per_pixel_sample, rng);
 }
 
+/// Same as *van_der_corput()*, but the pixel samples are only
+/// shuffled within power-of-two blocks (see *shuffle_nested()*), so
+/// the first 2^k pixel samples stay stratified.
+pub fn van_der_corput_nested(
+    n_samples_per_pixel_sample: i32,
+    n_pixel_samples: i32,
+    samples: &mut [Float],
+    rng: &mut Rng,
+) {
+    let scramble: u32 = rng.uniform_uint32();
+    let total_samples: i32 = n_samples_per_pixel_sample * n_pixel_samples;
+    gray_code_sample_1d(C_VAN_DER_CORPUT, total_samples as u32, scramble, samples);
+    for i in 0..n_pixel_samples as usize {
+        shuffle(
+            &mut samples[(i * n_samples_per_pixel_sample as usize)..],
+            n_samples_per_pixel_sample,
+            1,
+            rng,
+        );
+    }
+    shuffle_nested(samples, n_pixel_samples, n_samples_per_pixel_sample, rng);
+}
+
+/// Same as *sobol_2d()*, but the pixel samples are only shuffled
+/// within power-of-two blocks (see *shuffle_nested()*).
+pub fn sobol_2d_nested(
+    n_samples_per_pixel_sample: i32,
+    n_pixel_samples: i32,
+    samples: &mut [Point2f],
+    rng: &mut Rng,
+) {
+    let x: i32 = rng.uniform_uint32() as i32;
+    let y: i32 = rng.uniform_uint32() as i32;
+    let scramble: Point2i = Point2i { x, y };
+    gray_code_sample_2d(
+        &C_SOBOL[0],
+        &C_SOBOL[1],
+        (n_samples_per_pixel_sample * n_pixel_samples) as u32,
+        scramble,
+        samples,
+    );
+    for i in 0..n_pixel_samples as usize {
+        shuffle(
+            &mut samples[(i * n_samples_per_pixel_sample as usize)..],
+            n_samples_per_pixel_sample,
+            1,
+            rng,
+        );
+    }
+    shuffle_nested(samples, n_pixel_samples, n_samples_per_pixel_sample, rng);
+}
+
 /// Returns the index of the _frame_th sample in the pixel p, if the
 /// sampling domain has be scaled to cover the pixel sampling area.
 pub fn sobol_interval_to_index(m: u32, frame: u64, p: Point2i) -> u64 {
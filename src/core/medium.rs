@@ -6,13 +6,15 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::{spherical_direction_vec3, vec3_coordinate_system, vec3_dot_vec3f};
-use crate::core::geometry::{Point2f, Ray, Vector3f, XYEnum};
+use crate::core::geometry::{Point2f, Point3f, Ray, Vector3f, XYEnum};
 use crate::core::interaction::MediumInteraction;
 use crate::core::pbrt::INV_4_PI;
 use crate::core::pbrt::{Float, Spectrum};
+use crate::core::rng::Rng;
 use crate::core::sampler::Sampler;
 use crate::media::grid::GridDensityMedium;
 use crate::media::homogeneous::HomogeneousMedium;
+use crate::media::nanovdb::NanoVdbMedium;
 
 pub const SUBSURFACE_PARAMETER_TABLE: [MeasuredSS; 47] = [
     // From "A Practical Model for Subsurface Light Transport"
@@ -271,6 +273,7 @@ pub enum Medium {
     Empty(NoMedium),
     GridDensity(GridDensityMedium),
     Homogeneous(HomogeneousMedium),
+    NanoVdb(NanoVdbMedium),
 }
 
 impl Medium {
@@ -279,21 +282,174 @@ impl Medium {
             Medium::Empty(_medium) => Spectrum::default(),
             Medium::GridDensity(medium) => medium.tr(r_world, sampler),
             Medium::Homogeneous(medium) => medium.tr(r_world, sampler),
+            Medium::NanoVdb(medium) => medium.tr(r_world, sampler),
         }
     }
     pub fn sample(
         &self,
         r_world: &Ray,
         sampler: &mut Sampler,
+    ) -> (Spectrum, Option<MediumInteraction>) {
+        let mut le: Spectrum = Spectrum::default();
+        self.sample_le(r_world, sampler, &mut le)
+    }
+    /// Like sample(), but adds an estimate of the radiance the medium
+    /// emits along the ray to |le|. It has to be multiplied with the
+    /// path throughput *before* the returned weight is applied.
+    pub fn sample_le(
+        &self,
+        r_world: &Ray,
+        sampler: &mut Sampler,
+        le: &mut Spectrum,
     ) -> (Spectrum, Option<MediumInteraction>) {
         match self {
             Medium::Empty(_medium) => (Spectrum::default(), None),
-            Medium::GridDensity(medium) => medium.sample(r_world, sampler),
-            Medium::Homogeneous(medium) => medium.sample(r_world, sampler),
+            Medium::GridDensity(medium) => medium.sample(r_world, sampler, le),
+            Medium::Homogeneous(medium) => medium.sample(r_world, sampler, le),
+            Medium::NanoVdb(medium) => medium.sample(r_world, sampler, le),
+        }
+    }
+    /// Samples tentative collisions along |r_world| up to |t_max|
+    /// (see SampleT_maj() in pbrt-v4) proportional to the majorant of
+    /// spectral |channel|, using |u| and then |rng| for the
+    /// distances. For each of them |callback| gets the point (in
+    /// world space), the medium properties there, the majorant, the
+    /// majorant transmittance since the previous one and |rng|;
+    /// sampling stops when it returns false. Returns the majorant
+    /// transmittance from the last collision to |t_max|, or one if
+    /// stopped.
+    pub fn sample_t_maj<F>(
+        &self,
+        r_world: &Ray,
+        t_max: Float,
+        u: Float,
+        rng: &mut Rng,
+        channel: usize,
+        callback: F,
+    ) -> Spectrum
+    where
+        F: FnMut(&Point3f, &MediumProperties, &Spectrum, &Spectrum, &mut Rng) -> bool,
+    {
+        // normalize ray direction and update _t_max_ accordingly
+        let t_max: Float = t_max * r_world.d.length();
+        let ray: Ray = Ray {
+            o: r_world.o,
+            d: r_world.d.normalize(),
+            time: r_world.time,
+            ..Default::default()
+        };
+        match self {
+            Medium::Empty(_medium) => Spectrum::new(1.0 as Float),
+            Medium::GridDensity(medium) => match medium.sample_ray(&ray, t_max) {
+                Some((medium_ray, iter)) => sample_majorant_segments(
+                    &ray,
+                    &medium_ray,
+                    iter.map(|s| (s.t_min, s.t_max, Spectrum::new(s.sigma_maj))),
+                    u,
+                    rng,
+                    channel,
+                    |p| medium.sample_point(p),
+                    callback,
+                ),
+                None => Spectrum::new(1.0 as Float),
+            },
+            Medium::Homogeneous(medium) => {
+                let mp: MediumProperties = MediumProperties {
+                    sigma_a: medium.sigma_a,
+                    sigma_s: medium.sigma_s,
+                    le: medium.le,
+                    g: medium.g,
+                };
+                sample_majorant_segments(
+                    &ray,
+                    &ray,
+                    std::iter::once((0.0 as Float, t_max, medium.sigma_t)),
+                    u,
+                    rng,
+                    channel,
+                    |_p| mp,
+                    callback,
+                )
+            }
+            Medium::NanoVdb(medium) => match medium.sample_ray(&ray, t_max) {
+                Some((medium_ray, iter)) => sample_majorant_segments(
+                    &ray,
+                    &medium_ray,
+                    iter.map(|s| (s.t_min, s.t_max, Spectrum::new(s.sigma_maj))),
+                    u,
+                    rng,
+                    channel,
+                    |p| medium.sample_point(p),
+                    callback,
+                ),
+                None => Spectrum::new(1.0 as Float),
+            },
         }
     }
 }
 
+/// Delta tracking over |segments| of constant majorants, see
+/// Medium::sample_t_maj(). Both rays share their parametrization,
+/// the medium properties are looked up along |medium_ray|.
+fn sample_majorant_segments<I, P, F>(
+    ray: &Ray,
+    medium_ray: &Ray,
+    segments: I,
+    mut u: Float,
+    rng: &mut Rng,
+    channel: usize,
+    sample_point: P,
+    mut callback: F,
+) -> Spectrum
+where
+    I: Iterator<Item = (Float, Float, Spectrum)>,
+    P: Fn(&Point3f) -> MediumProperties,
+    F: FnMut(&Point3f, &MediumProperties, &Spectrum, &Spectrum, &mut Rng) -> bool,
+{
+    let mut t_maj: Spectrum = Spectrum::new(1.0 as Float);
+    for (seg_t_min, seg_t_max, sigma_maj) in segments {
+        // handle zero-valued majorant for current segment
+        if sigma_maj[channel] == 0.0 as Float {
+            let dt: Float = (seg_t_max - seg_t_min).min(Float::MAX);
+            t_maj *= (-sigma_maj * dt).exp();
+            continue;
+        }
+        // generate samples along current majorant segment
+        let mut t_min: Float = seg_t_min;
+        loop {
+            // try to generate sample along current majorant segment
+            let t: Float = t_min - (1.0 as Float - u).ln() / sigma_maj[channel];
+            u = rng.uniform_float();
+            if t < seg_t_max {
+                t_maj *= (-sigma_maj * (t - t_min)).exp();
+                let mp: MediumProperties = sample_point(&medium_ray.position(t));
+                if !callback(&ray.position(t), &mp, &sigma_maj, &t_maj, rng) {
+                    return Spectrum::new(1.0 as Float);
+                }
+                t_maj = Spectrum::new(1.0 as Float);
+                t_min = t;
+            } else {
+                // handle sample past end of majorant segment
+                let dt: Float = (seg_t_max - t_min).min(Float::MAX);
+                t_maj *= (-sigma_maj * dt).exp();
+                break;
+            }
+        }
+    }
+    t_maj
+}
+
+/// Scattering properties at a point in a medium (see pbrt-v4).
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MediumProperties {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    /// emitted radiance, to be scaled by *sigma_a*
+    pub le: Spectrum,
+    /// asymmetry parameter of the Henyey-Greenstein phase function
+    pub g: Float,
+}
+
 pub struct HenyeyGreenstein {
     pub g: Float,
 }
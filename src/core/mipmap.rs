@@ -407,10 +407,6 @@ impl Clampable for Float {
 
 impl Clampable for Spectrum {
     fn clamp(self, min: Float, max: Float) -> Spectrum {
-        Spectrum::rgb(
-            clamp_t(self.c[0], min, max),
-            clamp_t(self.c[1], min, max),
-            clamp_t(self.c[2], min, max),
-        )
+        Spectrum::clamp(&self, min, max)
     }
 }
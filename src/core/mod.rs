@@ -3,11 +3,15 @@
 pub mod api;
 pub mod bssrdf;
 pub mod camera;
+pub mod checkpoint;
+pub mod denoise;
+pub mod distributed;
 pub mod efloat;
 pub mod film;
 pub mod filter;
 pub mod floatfile;
 pub mod geometry;
+pub mod imageio;
 pub mod integrator;
 pub mod interaction;
 pub mod interpolation;
@@ -19,11 +23,13 @@ pub mod medium;
 pub mod memory;
 pub mod microfacet;
 pub mod mipmap;
+pub mod nanovdb;
 pub mod parallel;
 pub mod paramset;
 pub mod pbrt;
 pub mod primitive;
 pub mod quaternion;
+pub mod rawfilm;
 pub mod reflection;
 pub mod rng;
 pub mod sampler;
@@ -0,0 +1,521 @@
+//! A reader for sparse volumes stored as NanoVDB files (*.nvdb*), the
+//! flat, pointer-free variant of OpenVDB. Converting an OpenVDB file
+//! is a one-liner with the *nanovdb_convert* tool which comes with
+//! OpenVDB.
+//!
+//! Only float grids (e.g. density or temperature of a fog volume) of
+//! the NanoVDB 32.x file format are supported, uncompressed or with
+//! ZIP compression. The grid data is kept as it is stored in the file
+//! and the tree is traversed directly on the bytes, so the memory
+//! footprint is the size of the (uncompressed) grid.
+
+// std
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+// others
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+// pbrt
+use crate::core::geometry::{Bounds3f, Point3f, Point3i};
+use crate::core::pbrt::{lerp, Float};
+
+// "NanoVDB0", "NanoVDB1", and "NanoVDB2" (since 32.6)
+const NANOVDB_MAGIC: [u64; 3] = [
+    0x3042_4456_6f6e_614e,
+    0x3142_4456_6f6e_614e,
+    0x3242_4456_6f6e_614e,
+];
+const NANOVDB_MAJOR_VERSION: u32 = 32;
+const CODEC_NONE: u16 = 0;
+const CODEC_ZIP: u16 = 1;
+const GRID_TYPE_FLOAT: u32 = 1;
+
+// byte layout of float grids (see NanoVDB.h)
+const GRID_DATA_SIZE: usize = 672;
+const GRID_MAP_OFFSET: usize = 296;
+const GRID_WORLD_BBOX_OFFSET: usize = 560;
+const TREE_DATA_SIZE: usize = 64;
+const ROOT_DATA_SIZE: usize = 64;
+const ROOT_TILE_SIZE: usize = 32;
+const UPPER_TABLE_OFFSET: usize = 8256;
+const UPPER_NODE_SIZE: usize = UPPER_TABLE_OFFSET + 8 * 32768;
+const LOWER_TABLE_OFFSET: usize = 1088;
+const LOWER_NODE_SIZE: usize = LOWER_TABLE_OFFSET + 8 * 4096;
+const LEAF_VALUES_OFFSET: usize = 96;
+const LEAF_NODE_SIZE: usize = LEAF_VALUES_OFFSET + 4 * 512;
+// offsets of the child masks of internal nodes
+const UPPER_CHILD_MASK_OFFSET: usize = 32 + 4096;
+const LOWER_CHILD_MASK_OFFSET: usize = 32 + 512;
+
+/// A float grid of a NanoVDB file. Voxel *ijk* is centered at the
+/// index space position *ijk*.
+pub struct NanoVdbGrid {
+    pub name: String,
+    /// Bounding box of the active voxels in world space
+    pub world_bbox: Bounds3f,
+    /// Bounding box of the active voxels in index space (inclusive)
+    pub index_bbox: [Point3i; 2],
+    pub background: Float,
+    data: Vec<u8>,
+    // index to world: world = mat * index + vec
+    mat: [f64; 9],
+    inv_mat: [f64; 9],
+    vec: [f64; 3],
+    root: usize,
+    root_tiles: HashMap<u64, usize>,
+    // first node and node count of leaves, lower and upper internal nodes
+    nodes: [(usize, usize); 3],
+}
+
+impl NanoVdbGrid {
+    fn new(name: String, data: Vec<u8>) -> std::io::Result<Self> {
+        if data.len() < GRID_DATA_SIZE + TREE_DATA_SIZE {
+            return Err(invalid_data("truncated NanoVDB grid"));
+        }
+        if !NANOVDB_MAGIC.contains(&read_u64(&data, 0)) {
+            return Err(invalid_data("no NanoVDB grid"));
+        }
+        let version: u32 = read_u32(&data, 16);
+        if version >> 21 != NANOVDB_MAJOR_VERSION {
+            return Err(invalid_data("unsupported NanoVDB version"));
+        }
+        if read_u32(&data, GRID_WORLD_BBOX_OFFSET + 76) != GRID_TYPE_FLOAT {
+            return Err(invalid_data("not a float grid"));
+        }
+        let mut mat: [f64; 9] = [0.0; 9];
+        let mut inv_mat: [f64; 9] = [0.0; 9];
+        let mut vec: [f64; 3] = [0.0; 3];
+        // skip the single precision copy of the map
+        let map_d: usize = GRID_MAP_OFFSET + 88;
+        for (i, m) in mat.iter_mut().enumerate() {
+            *m = read_f64(&data, map_d + 8 * i);
+        }
+        for (i, m) in inv_mat.iter_mut().enumerate() {
+            *m = read_f64(&data, map_d + 72 + 8 * i);
+        }
+        for (i, v) in vec.iter_mut().enumerate() {
+            *v = read_f64(&data, map_d + 144 + 8 * i);
+        }
+        let b: usize = GRID_WORLD_BBOX_OFFSET;
+        let world_bbox: Bounds3f = Bounds3f::new(
+            Point3f {
+                x: read_f64(&data, b) as Float,
+                y: read_f64(&data, b + 8) as Float,
+                z: read_f64(&data, b + 16) as Float,
+            },
+            Point3f {
+                x: read_f64(&data, b + 24) as Float,
+                y: read_f64(&data, b + 32) as Float,
+                z: read_f64(&data, b + 40) as Float,
+            },
+        );
+        // the tree follows the grid, node offsets are relative to it
+        let tree: usize = GRID_DATA_SIZE;
+        let node_offset = |level: usize| -> Option<usize> {
+            tree.checked_add(read_u64(&data, tree + 8 * level) as usize)
+        };
+        let nodes_end = |first: usize, count: usize, node_size: usize| -> Option<usize> {
+            first.checked_add(count.checked_mul(node_size)?)
+        };
+        let mut nodes: [(usize, usize); 3] = [(0, 0); 3];
+        for (level, node) in nodes.iter_mut().enumerate() {
+            let first: usize =
+                node_offset(level).ok_or_else(|| invalid_data("NanoVDB nodes out of bounds"))?;
+            *node = (first, read_u32(&data, tree + 32 + 4 * level) as usize);
+        }
+        let in_bounds = |end: Option<usize>| -> bool {
+            match end {
+                Some(end) => end <= data.len(),
+                None => false,
+            }
+        };
+        let root: Option<usize> = node_offset(3);
+        if !in_bounds(root.and_then(|root| root.checked_add(ROOT_DATA_SIZE)))
+            || !in_bounds(nodes_end(nodes[0].0, nodes[0].1, LEAF_NODE_SIZE))
+            || !in_bounds(nodes_end(nodes[1].0, nodes[1].1, LOWER_NODE_SIZE))
+            || !in_bounds(nodes_end(nodes[2].0, nodes[2].1, UPPER_NODE_SIZE))
+        {
+            return Err(invalid_data("NanoVDB nodes out of bounds"));
+        }
+        let root: usize = root.unwrap();
+        let index_bbox: [Point3i; 2] = [read_coord(&data, root), read_coord(&data, root + 12)];
+        let table_size: usize = read_u32(&data, root + 24) as usize;
+        let background: Float = read_f32(&data, root + 28);
+        if !in_bounds(nodes_end(root + ROOT_DATA_SIZE, table_size, ROOT_TILE_SIZE)) {
+            return Err(invalid_data("NanoVDB root table out of bounds"));
+        }
+        let mut root_tiles: HashMap<u64, usize> = HashMap::with_capacity(table_size);
+        for i in 0..table_size {
+            let tile: usize = root + ROOT_DATA_SIZE + i * ROOT_TILE_SIZE;
+            root_tiles.insert(read_u64(&data, tile), tile);
+        }
+        // value() follows the child offsets without further checks
+        let is_child = |parent: usize, offset: i64, level: usize, node_size: usize| -> bool {
+            let (first, count) = nodes[level];
+            match (parent as i64).checked_add(offset) {
+                Some(child) if child >= first as i64 => {
+                    let i: usize = child as usize - first;
+                    i % node_size == 0 && i / node_size < count
+                }
+                _ => false,
+            }
+        };
+        for tile in root_tiles.values() {
+            let child: i64 = read_i64(&data, tile + 8);
+            if child != 0 && !is_child(root, child, 2, UPPER_NODE_SIZE) {
+                return Err(invalid_data("NanoVDB root tile child out of bounds"));
+            }
+        }
+        // level, node size, table offset, child mask offset, table size,
+        // and node size of the children
+        let levels: [(usize, usize, usize, usize, usize, usize); 2] = [
+            (
+                2,
+                UPPER_NODE_SIZE,
+                UPPER_TABLE_OFFSET,
+                UPPER_CHILD_MASK_OFFSET,
+                32768,
+                LOWER_NODE_SIZE,
+            ),
+            (
+                1,
+                LOWER_NODE_SIZE,
+                LOWER_TABLE_OFFSET,
+                LOWER_CHILD_MASK_OFFSET,
+                4096,
+                LEAF_NODE_SIZE,
+            ),
+        ];
+        for (level, node_size, table_offset, mask_offset, n_entries, child_size) in levels.iter() {
+            let (first, count) = nodes[*level];
+            for i in 0..count {
+                let node: usize = first + i * node_size;
+                for n in 0..*n_entries {
+                    if !is_on(&data, node + mask_offset, n) {
+                        continue;
+                    }
+                    let child: i64 = read_i64(&data, node + table_offset + 8 * n);
+                    if !is_child(node, child, level - 1, *child_size) {
+                        return Err(invalid_data("NanoVDB node child out of bounds"));
+                    }
+                }
+            }
+        }
+        Ok(NanoVdbGrid {
+            name,
+            world_bbox,
+            index_bbox,
+            background,
+            data,
+            mat,
+            inv_mat,
+            vec,
+            root,
+            root_tiles,
+            nodes,
+        })
+    }
+    /// Returns the value of voxel |ijk|, the background value for
+    /// voxels outside of the tree.
+    pub fn value(&self, ijk: &Point3i) -> Float {
+        let tile: usize = match self.root_tiles.get(&root_key(ijk)) {
+            Some(tile) => *tile,
+            None => return self.background,
+        };
+        let child: i64 = read_i64(&self.data, tile + 8);
+        if child == 0 {
+            return read_f32(&self.data, tile + 20);
+        }
+        let upper: usize = (self.root as i64 + child) as usize;
+        let n: usize = ((((ijk.x & 4095) >> 7) << 10)
+            | (((ijk.y & 4095) >> 7) << 5)
+            | ((ijk.z & 4095) >> 7)) as usize;
+        let entry: usize = upper + UPPER_TABLE_OFFSET + 8 * n;
+        if !is_on(&self.data, upper + UPPER_CHILD_MASK_OFFSET, n) {
+            return read_f32(&self.data, entry);
+        }
+        let lower: usize = (upper as i64 + read_i64(&self.data, entry)) as usize;
+        let n: usize = ((((ijk.x & 127) >> 3) << 8)
+            | (((ijk.y & 127) >> 3) << 4)
+            | ((ijk.z & 127) >> 3)) as usize;
+        let entry: usize = lower + LOWER_TABLE_OFFSET + 8 * n;
+        if !is_on(&self.data, lower + LOWER_CHILD_MASK_OFFSET, n) {
+            return read_f32(&self.data, entry);
+        }
+        let leaf: usize = (lower as i64 + read_i64(&self.data, entry)) as usize;
+        let n: usize = (((ijk.x & 7) << 6) | ((ijk.y & 7) << 3) | (ijk.z & 7)) as usize;
+        read_f32(&self.data, leaf + LEAF_VALUES_OFFSET + 4 * n)
+    }
+    /// Trilinearly interpolated value at |p| in world space.
+    pub fn sample(&self, p: &Point3f) -> Float {
+        let p_index: Point3f = self.world_to_index(p);
+        let pi: Point3i = Point3i {
+            x: p_index.x.floor() as i32,
+            y: p_index.y.floor() as i32,
+            z: p_index.z.floor() as i32,
+        };
+        let dx: Float = p_index.x - pi.x as Float;
+        let dy: Float = p_index.y - pi.y as Float;
+        let dz: Float = p_index.z - pi.z as Float;
+        let v = |x: i32, y: i32, z: i32| -> Float {
+            self.value(&Point3i {
+                x: pi.x + x,
+                y: pi.y + y,
+                z: pi.z + z,
+            })
+        };
+        let d00: Float = lerp(dx, v(0, 0, 0), v(1, 0, 0));
+        let d10: Float = lerp(dx, v(0, 1, 0), v(1, 1, 0));
+        let d01: Float = lerp(dx, v(0, 0, 1), v(1, 0, 1));
+        let d11: Float = lerp(dx, v(0, 1, 1), v(1, 1, 1));
+        lerp(dz, lerp(dy, d00, d10), lerp(dy, d01, d11))
+    }
+    pub fn index_to_world(&self, p: &Point3f) -> Point3f {
+        let m: &[f64; 9] = &self.mat;
+        let (x, y, z) = (p.x as f64, p.y as f64, p.z as f64);
+        Point3f {
+            x: (m[0] * x + m[1] * y + m[2] * z + self.vec[0]) as Float,
+            y: (m[3] * x + m[4] * y + m[5] * z + self.vec[1]) as Float,
+            z: (m[6] * x + m[7] * y + m[8] * z + self.vec[2]) as Float,
+        }
+    }
+    pub fn world_to_index(&self, p: &Point3f) -> Point3f {
+        let m: &[f64; 9] = &self.inv_mat;
+        let x: f64 = p.x as f64 - self.vec[0];
+        let y: f64 = p.y as f64 - self.vec[1];
+        let z: f64 = p.z as f64 - self.vec[2];
+        Point3f {
+            x: (m[0] * x + m[1] * y + m[2] * z) as Float,
+            y: (m[3] * x + m[4] * y + m[5] * z) as Float,
+            z: (m[6] * x + m[7] * y + m[8] * z) as Float,
+        }
+    }
+    /// Calls |f| with the (inclusive) index space bounds and the
+    /// maximum value of every leaf and every tile of the tree. All
+    /// voxels not covered by them have the background value.
+    pub fn for_each_block<F>(&self, mut f: F)
+    where
+        F: FnMut(&Point3i, &Point3i, Float),
+    {
+        // leaves
+        let (first, count) = self.nodes[0];
+        for i in 0..count {
+            let leaf: usize = first + i * LEAF_NODE_SIZE;
+            let origin: Point3i = read_coord(&self.data, leaf);
+            let origin: Point3i = Point3i {
+                x: origin.x & !7,
+                y: origin.y & !7,
+                z: origin.z & !7,
+            };
+            let mut max_value: Float = Float::NEG_INFINITY;
+            for n in 0..512 {
+                max_value = max_value.max(read_f32(&self.data, leaf + LEAF_VALUES_OFFSET + 4 * n));
+            }
+            f(&origin, &offset_coord(&origin, 7), max_value);
+        }
+        // tiles of internal nodes
+        let levels: [(usize, usize, usize, i32, i32); 2] = [
+            (1, LOWER_NODE_SIZE, LOWER_TABLE_OFFSET, 4, 3),
+            (2, UPPER_NODE_SIZE, UPPER_TABLE_OFFSET, 5, 7),
+        ];
+        for (level, node_size, table_offset, log2_dim, child_log2_dim) in levels.iter() {
+            let (first, count) = self.nodes[*level];
+            let mask_offset: usize = if *level == 1 {
+                LOWER_CHILD_MASK_OFFSET
+            } else {
+                UPPER_CHILD_MASK_OFFSET
+            };
+            let dim: usize = 1 << log2_dim;
+            for i in 0..count {
+                let node: usize = first + i * node_size;
+                let node_origin: Point3i = read_coord(&self.data, node);
+                let node_size_voxels: i32 = 1 << (log2_dim + child_log2_dim);
+                let node_origin: Point3i = Point3i {
+                    x: node_origin.x & !(node_size_voxels - 1),
+                    y: node_origin.y & !(node_size_voxels - 1),
+                    z: node_origin.z & !(node_size_voxels - 1),
+                };
+                for n in 0..dim * dim * dim {
+                    if is_on(&self.data, node + mask_offset, n) {
+                        continue;
+                    }
+                    let value: Float = read_f32(&self.data, node + table_offset + 8 * n);
+                    if value == self.background {
+                        continue;
+                    }
+                    let x: i32 = (n >> (2 * log2_dim)) as i32;
+                    let y: i32 = ((n >> log2_dim) & (dim - 1)) as i32;
+                    let z: i32 = (n & (dim - 1)) as i32;
+                    let origin: Point3i = Point3i {
+                        x: node_origin.x + (x << child_log2_dim),
+                        y: node_origin.y + (y << child_log2_dim),
+                        z: node_origin.z + (z << child_log2_dim),
+                    };
+                    f(
+                        &origin,
+                        &offset_coord(&origin, (1 << child_log2_dim) - 1),
+                        value,
+                    );
+                }
+            }
+        }
+        // tiles of the root
+        for tile in self.root_tiles.values() {
+            if read_i64(&self.data, tile + 8) != 0 {
+                continue;
+            }
+            let value: Float = read_f32(&self.data, tile + 20);
+            if value == self.background {
+                continue;
+            }
+            // the root key holds the origin of the tile
+            let key: u64 = read_u64(&self.data, *tile);
+            let origin: Point3i = Point3i {
+                x: (((key >> 42) & 0x1f_ffff) << 12) as u32 as i32,
+                y: (((key >> 21) & 0x1f_ffff) << 12) as u32 as i32,
+                z: ((key & 0x1f_ffff) << 12) as u32 as i32,
+            };
+            f(&origin, &offset_coord(&origin, 4095), value);
+        }
+    }
+}
+
+/// Reads all float grids of a NanoVDB file.
+pub fn read_nanovdb_grids(filename: &str) -> std::io::Result<Vec<NanoVdbGrid>> {
+    let mut r = BufReader::new(File::open(filename)?);
+    let mut grids: Vec<NanoVdbGrid> = Vec::new();
+    // a file consists of segments, each a header and the metadata of
+    // its grids followed by the grids
+    loop {
+        let magic: u64 = match r.read_u64::<LittleEndian>() {
+            Ok(magic) => magic,
+            Err(ref e) if e.kind() == std::io::ErrorKind::UnexpectedEof && !grids.is_empty() => {
+                break;
+            }
+            Err(e) => return Err(e),
+        };
+        if !NANOVDB_MAGIC.contains(&magic) {
+            return Err(invalid_data("not a NanoVDB file"));
+        }
+        let version: u32 = r.read_u32::<LittleEndian>()?;
+        if version >> 21 != NANOVDB_MAJOR_VERSION {
+            return Err(invalid_data("unsupported NanoVDB version"));
+        }
+        let grid_count: u16 = r.read_u16::<LittleEndian>()?;
+        let codec: u16 = r.read_u16::<LittleEndian>()?;
+        if codec != CODEC_NONE && codec != CODEC_ZIP {
+            return Err(invalid_data("unsupported NanoVDB codec (use NONE or ZIP)"));
+        }
+        let mut meta_data: Vec<(u64, u64, u32, String)> = Vec::with_capacity(grid_count as usize);
+        for _ in 0..grid_count {
+            let mut meta: [u8; 176] = [0_u8; 176];
+            r.read_exact(&mut meta)?;
+            let grid_size: u64 = read_u64(&meta, 0);
+            let file_size: u64 = read_u64(&meta, 8);
+            let grid_type: u32 = read_u32(&meta, 32);
+            let name_size: u32 = read_u32(&meta, 136);
+            let name: Vec<u8> = read_bytes(&mut r, name_size as u64)?;
+            let name: String = String::from_utf8_lossy(&name)
+                .trim_end_matches('\0')
+                .to_string();
+            meta_data.push((grid_size, file_size, grid_type, name));
+        }
+        for (grid_size, file_size, grid_type, name) in meta_data {
+            let data: Vec<u8>;
+            if codec == CODEC_ZIP {
+                let compressed_size: u64 = r.read_u64::<LittleEndian>()?;
+                let mut decoder = ZlibDecoder::new((&mut r).take(compressed_size));
+                data = read_bytes(&mut decoder, grid_size)?;
+                // skip what the decoder didn't consume
+                std::io::copy(&mut decoder.into_inner(), &mut std::io::sink())?;
+            } else {
+                data = read_bytes(&mut r, grid_size)?;
+                if file_size > grid_size {
+                    std::io::copy(
+                        &mut (&mut r).take(file_size - grid_size),
+                        &mut std::io::sink(),
+                    )?;
+                }
+            }
+            if grid_type != GRID_TYPE_FLOAT {
+                println!(
+                    "WARNING: Skipping NanoVDB grid \"{}\" in \"{}\" (only float grids are supported)",
+                    name, filename
+                );
+                continue;
+            }
+            grids.push(NanoVdbGrid::new(name, data)?);
+        }
+    }
+    Ok(grids)
+}
+
+fn root_key(ijk: &Point3i) -> u64 {
+    ((ijk.z as u32 >> 12) as u64)
+        | (((ijk.y as u32 >> 12) as u64) << 21)
+        | (((ijk.x as u32 >> 12) as u64) << 42)
+}
+
+fn offset_coord(p: &Point3i, offset: i32) -> Point3i {
+    Point3i {
+        x: p.x + offset,
+        y: p.y + offset,
+        z: p.z + offset,
+    }
+}
+
+fn is_on(data: &[u8], mask: usize, n: usize) -> bool {
+    read_u64(data, mask + 8 * (n >> 6)) & (1_u64 << (n & 63)) != 0
+}
+
+fn read_coord(data: &[u8], offset: usize) -> Point3i {
+    Point3i {
+        x: read_i32(data, offset),
+        y: read_i32(data, offset + 4),
+        z: read_i32(data, offset + 8),
+    }
+}
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    let mut b: [u8; 4] = [0_u8; 4];
+    b.copy_from_slice(&data[offset..offset + 4]);
+    u32::from_le_bytes(b)
+}
+
+fn read_i32(data: &[u8], offset: usize) -> i32 {
+    read_u32(data, offset) as i32
+}
+
+fn read_f32(data: &[u8], offset: usize) -> Float {
+    f32::from_bits(read_u32(data, offset)) as Float
+}
+
+fn read_u64(data: &[u8], offset: usize) -> u64 {
+    let mut b: [u8; 8] = [0_u8; 8];
+    b.copy_from_slice(&data[offset..offset + 8]);
+    u64::from_le_bytes(b)
+}
+
+fn read_i64(data: &[u8], offset: usize) -> i64 {
+    read_u64(data, offset) as i64
+}
+
+fn read_f64(data: &[u8], offset: usize) -> f64 {
+    f64::from_bits(read_u64(data, offset))
+}
+
+/// Reads |size| bytes, the buffer only grows with the data actually
+/// read, so a corrupt size can't trigger a huge allocation.
+fn read_bytes<R: Read>(r: R, size: u64) -> std::io::Result<Vec<u8>> {
+    let mut bytes: Vec<u8> = Vec::new();
+    r.take(size).read_to_end(&mut bytes)?;
+    if (bytes.len() as u64) < size {
+        return Err(invalid_data("truncated NanoVDB file"));
+    }
+    Ok(bytes)
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
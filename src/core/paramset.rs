@@ -731,6 +731,14 @@ impl TextureParams {
         self.geom_params
             .find_one_float(name, self.material_params.find_one_float(name, d))
     }
+    pub fn find_floats(&mut self, name: &str) -> Vec<Float> {
+        let floats: Vec<Float> = self.geom_params.find_float(name);
+        if floats.is_empty() {
+            self.material_params.find_float(name)
+        } else {
+            floats
+        }
+    }
     pub fn find_string(&mut self, name: &str, d: String) -> String {
         self.geom_params
             .find_one_string(name, self.material_params.find_one_string(name, d))
@@ -5,11 +5,19 @@
 use std::f32::consts::PI;
 use std::ops::{Add, BitAnd, Div, Mul, Sub};
 // pbrt
+#[cfg(not(feature = "sampled_spectrum"))]
 use crate::core::spectrum::RGBSpectrum;
+#[cfg(feature = "sampled_spectrum")]
+use crate::core::spectrum::SampledSpectrum;
 
 // see pbrt.h
 
+/// RGB by default, sampled wavelengths with the *sampled_spectrum*
+/// feature (see PBRT_SAMPLED_SPECTRUM in pbrt.h).
+#[cfg(not(feature = "sampled_spectrum"))]
 pub type Spectrum = RGBSpectrum;
+#[cfg(feature = "sampled_spectrum")]
+pub type Spectrum = SampledSpectrum;
 
 pub type Float = f32;
 
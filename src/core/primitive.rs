@@ -6,7 +6,7 @@ use std::sync::Arc;
 // pbrt
 use crate::accelerators::bvh::BVHAccel;
 use crate::accelerators::kdtreeaccel::KdTreeAccel;
-use crate::core::geometry::nrm_dot_nrmf;
+use crate::core::geometry::{bnd3_intersect_bnd3f, bnd3_split_slabs, nrm_dot_nrmf};
 use crate::core::geometry::{Bounds3f, Ray};
 use crate::core::interaction::SurfaceInteraction;
 use crate::core::light::Light;
@@ -26,6 +26,36 @@ pub enum Primitive {
 }
 
 impl Primitive {
+    /// Bounds of the part of the primitive inside |clip|. For
+    /// triangles the clipped geometry is bounded, for all other
+    /// primitives the bounds are just intersected.
+    pub fn clipped_world_bound(&self, clip: &Bounds3f) -> Bounds3f {
+        if let Primitive::Geometric(primitive) = self {
+            if let Shape::Trngl(triangle) = &*primitive.shape {
+                return triangle.clipped_world_bound(clip);
+            }
+        }
+        bnd3_intersect_bnd3f(&self.world_bound(), clip)
+    }
+    /// Like **clipped_world_bound()**, but for the parts between
+    /// consecutive |planes| (in increasing order) along |axis|, which
+    /// are stored in |bounds| (one more than planes).
+    pub fn split_world_bounds(
+        &self,
+        clip: &Bounds3f,
+        axis: usize,
+        planes: &[Float],
+        bounds: &mut [Bounds3f],
+    ) {
+        if let Primitive::Geometric(primitive) = self {
+            if let Shape::Trngl(triangle) = &*primitive.shape {
+                triangle.split_world_bounds(clip, axis, planes, bounds);
+                return;
+            }
+        }
+        let clipped: Bounds3f = bnd3_intersect_bnd3f(&self.world_bound(), clip);
+        bnd3_split_slabs(&clipped, axis, planes, bounds);
+    }
     pub fn world_bound(&self) -> Bounds3f {
         match self {
             Primitive::Geometric(primitive) => primitive.world_bound(),
@@ -34,6 +64,38 @@ impl Primitive {
             Primitive::KdTree(primitive) => primitive.world_bound(),
         }
     }
+    /// The time range in which the primitive moves, *None* for static
+    /// primitives.
+    pub fn time_range(&self) -> Option<(Float, Float)> {
+        match self {
+            Primitive::Geometric(primitive) => {
+                if let Shape::Trngl(triangle) = &*primitive.shape {
+                    if triangle.is_moving() {
+                        return Some(triangle.time_range());
+                    }
+                }
+                None
+            }
+            Primitive::Transformed(primitive) => primitive.time_range(),
+            Primitive::BVH(primitive) => primitive.time_range(),
+            Primitive::KdTree(_primitive) => None,
+        }
+    }
+    /// Bounds of the primitive while it moves from |time0| to
+    /// |time1|.
+    pub fn motion_bound(&self, time0: Float, time1: Float) -> Bounds3f {
+        match self {
+            Primitive::Geometric(primitive) => {
+                if let Shape::Trngl(triangle) = &*primitive.shape {
+                    return triangle.motion_bound(time0, time1);
+                }
+                primitive.world_bound()
+            }
+            Primitive::Transformed(primitive) => primitive.motion_bound(time0, time1),
+            Primitive::BVH(primitive) => primitive.motion_bound(time0, time1),
+            Primitive::KdTree(primitive) => primitive.world_bound(),
+        }
+    }
     pub fn intersect(&self, ray: &Ray, isect: &mut SurfaceInteraction) -> bool {
         match self {
             Primitive::Geometric(primitive) => {
@@ -72,6 +134,13 @@ impl Primitive {
             Primitive::KdTree(primitive) => primitive.get_material(),
         }
     }
+    /// Returns the object and material id used for AOVs.
+    pub fn get_ids(&self) -> (u32, u32) {
+        match self {
+            Primitive::Geometric(primitive) => (primitive.object_id, primitive.material_id),
+            _ => (0_u32, 0_u32),
+        }
+    }
     pub fn compute_scattering_functions(
         &self,
         isect: &mut SurfaceInteraction,
@@ -102,6 +171,10 @@ pub struct GeometricPrimitive {
     pub material: Option<Arc<Material>>,
     pub area_light: Option<Arc<Light>>,
     pub medium_interface: Option<Arc<MediumInterface>>,
+    /// Identifies the **Shape** statement (0 if unknown), see **Aov**
+    pub object_id: u32,
+    /// Identifies the material (0 if unknown), see **Aov**
+    pub material_id: u32,
 }
 
 impl GeometricPrimitive {
@@ -118,6 +191,8 @@ impl GeometricPrimitive {
                     material,
                     area_light: Some(area_light),
                     medium_interface: Some(medium_interface),
+                    object_id: 0,
+                    material_id: 0,
                 }
             } else {
                 GeometricPrimitive {
@@ -125,6 +200,8 @@ impl GeometricPrimitive {
                     material,
                     area_light: Some(area_light),
                     medium_interface: None,
+                    object_id: 0,
+                    material_id: 0,
                 }
             }
         } else if let Some(medium_interface) = medium_interface {
@@ -133,6 +210,8 @@ impl GeometricPrimitive {
                 material,
                 area_light: None,
                 medium_interface: Some(medium_interface),
+                object_id: 0,
+                material_id: 0,
             }
         } else {
             GeometricPrimitive {
@@ -140,6 +219,8 @@ impl GeometricPrimitive {
                 material,
                 area_light: None,
                 medium_interface: None,
+                object_id: 0,
+                material_id: 0,
             }
         }
     }
@@ -212,13 +293,31 @@ impl TransformedPrimitive {
         self.primitive_to_world
             .motion_bounds(&self.primitive.world_bound())
     }
+    pub fn time_range(&self) -> Option<(Float, Float)> {
+        let mut time_range: Option<(Float, Float)> = self.primitive.time_range();
+        if self.primitive_to_world.is_animated() {
+            let (start_time, end_time) = self.primitive_to_world.time_range();
+            time_range = Some(match time_range {
+                Some((t0, t1)) => (t0.min(start_time), t1.max(end_time)),
+                None => (start_time, end_time),
+            });
+        }
+        time_range
+    }
+    pub fn motion_bound(&self, time0: Float, time1: Float) -> Bounds3f {
+        self.primitive_to_world.motion_bounds_between(
+            &self.primitive.motion_bound(time0, time1),
+            time0,
+            time1,
+        )
+    }
     pub fn intersect(&self, r: &Ray, isect: &mut SurfaceInteraction) -> bool {
         // compute _ray_ after transformation by _self.primitive_to_world_
         let mut interpolated_prim_to_world: Transform = Transform::default();
         self.primitive_to_world
             .interpolate(r.time, &mut interpolated_prim_to_world);
         let ray: Ray = Transform::inverse(&interpolated_prim_to_world).transform_ray(r);
-        if self.primitive.intersect(& ray, isect) {
+        if self.primitive.intersect(&ray, isect) {
             r.t_max.set(ray.t_max.get());
             // transform instance's intersection data to world space
             if !interpolated_prim_to_world.is_identity() {
@@ -248,9 +347,8 @@ impl TransformedPrimitive {
                 // is.shading.dpdv = new_isect.shading.dpdv;
                 // is.shading.dndu = new_isect.shading.dndu;
                 // is.shading.dndv = new_isect.shading.dndv;
-                return true;
             }
-            false
+            true
         } else {
             false
         }
@@ -0,0 +1,186 @@
+//! Unnormalized film dumps which can be combined later. A raw film
+//! holds the pixels of a (cropped) **Film** as they are before the
+//! image gets written: the filtered XYZ sums, the filter weight sums
+//! and the splats. It is written next to the image if the **Film**
+//! has a *rawfilename* parameter.
+//!
+//! Several raw films of the same scene (e.g. rendered with different
+//! crop windows, sample counts, or samplers) are merged by adding
+//! their filtered samples, so each render is weighted by its filter
+//! weight sum. Splats are normalized by the sum of the inverse splat
+//! scales of the renders contributing to a pixel, which is the total
+//! number of samples per pixel for **BDPT**.
+
+// std
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Read, Write};
+// others
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+// pbrt
+use crate::core::film::{Film, Pixel};
+use crate::core::geometry::{bnd2_union_bnd2i, pnt2_inside_exclusivei};
+use crate::core::geometry::{Bounds2i, Point2i};
+use crate::core::pbrt::Float;
+
+const RAW_FILM_MAGIC: &[u8; 8] = b"RSPBRTFM";
+const RAW_FILM_VERSION: u32 = 1;
+
+pub struct RawFilm {
+    /// The overall resolution of the image in pixels
+    pub full_resolution: Point2i,
+    /// The pixels stored (the crop window of the film)
+    pub pixel_bounds: Bounds2i,
+    /// The scale the splats were written with
+    pub splat_scale: Float,
+    /// The **Film**'s *scale* parameter
+    pub scale: Float,
+    pub pixels: Vec<Pixel>,
+}
+
+impl RawFilm {
+    pub fn from_film(film: &Film, splat_scale: Float) -> Self {
+        RawFilm {
+            full_resolution: film.full_resolution,
+            pixel_bounds: film.cropped_pixel_bounds,
+            splat_scale,
+            scale: film.get_scale(),
+            pixels: film.pixels.read().unwrap().clone(),
+        }
+    }
+    pub fn write(&self, filename: &str) -> std::io::Result<()> {
+        let mut w = BufWriter::new(File::create(filename)?);
+        w.write_all(RAW_FILM_MAGIC)?;
+        w.write_u32::<LittleEndian>(RAW_FILM_VERSION)?;
+        w.write_i32::<LittleEndian>(self.full_resolution.x)?;
+        w.write_i32::<LittleEndian>(self.full_resolution.y)?;
+        w.write_i32::<LittleEndian>(self.pixel_bounds.p_min.x)?;
+        w.write_i32::<LittleEndian>(self.pixel_bounds.p_min.y)?;
+        w.write_i32::<LittleEndian>(self.pixel_bounds.p_max.x)?;
+        w.write_i32::<LittleEndian>(self.pixel_bounds.p_max.y)?;
+        w.write_f32::<LittleEndian>(self.splat_scale)?;
+        w.write_f32::<LittleEndian>(self.scale)?;
+        for pixel in self.pixels.iter() {
+            pixel.write_state(&mut w)?;
+        }
+        w.flush()
+    }
+    pub fn read(filename: &str) -> std::io::Result<Self> {
+        let mut r = BufReader::new(File::open(filename)?);
+        let mut magic: [u8; 8] = [0_u8; 8];
+        r.read_exact(&mut magic)?;
+        if &magic != RAW_FILM_MAGIC {
+            return Err(invalid_data("not a raw film"));
+        }
+        if r.read_u32::<LittleEndian>()? != RAW_FILM_VERSION {
+            return Err(invalid_data("unsupported raw film version"));
+        }
+        let full_resolution: Point2i = Point2i {
+            x: r.read_i32::<LittleEndian>()?,
+            y: r.read_i32::<LittleEndian>()?,
+        };
+        let p_min: Point2i = Point2i {
+            x: r.read_i32::<LittleEndian>()?,
+            y: r.read_i32::<LittleEndian>()?,
+        };
+        let p_max: Point2i = Point2i {
+            x: r.read_i32::<LittleEndian>()?,
+            y: r.read_i32::<LittleEndian>()?,
+        };
+        let pixel_bounds: Bounds2i = Bounds2i { p_min, p_max };
+        let splat_scale: Float = r.read_f32::<LittleEndian>()?;
+        let scale: Float = r.read_f32::<LittleEndian>()?;
+        if p_min.x < 0
+            || p_min.y < 0
+            || p_min.x > p_max.x
+            || p_min.y > p_max.y
+            || p_max.x > full_resolution.x
+            || p_max.y > full_resolution.y
+        {
+            return Err(invalid_data("invalid pixel bounds"));
+        }
+        let n_pixels: i32 = (p_max.x - p_min.x)
+            .checked_mul(p_max.y - p_min.y)
+            .ok_or_else(|| invalid_data("invalid pixel bounds"))?;
+        // don't trust _n_pixels_ for pre-allocation, a corrupt file
+        // runs out of data first
+        let mut pixels: Vec<Pixel> = Vec::new();
+        for _ in 0..n_pixels {
+            pixels.push(Pixel::read_state(&mut r)?);
+        }
+        Ok(RawFilm {
+            full_resolution,
+            pixel_bounds,
+            splat_scale,
+            scale,
+            pixels,
+        })
+    }
+    fn get_pixel(&self, p: Point2i) -> Option<&Pixel> {
+        if !pnt2_inside_exclusivei(p, &self.pixel_bounds) {
+            return None;
+        }
+        let width: i32 = self.pixel_bounds.p_max.x - self.pixel_bounds.p_min.x;
+        let offset: i32 =
+            (p.x - self.pixel_bounds.p_min.x) + (p.y - self.pixel_bounds.p_min.y) * width;
+        Some(&self.pixels[offset as usize])
+    }
+}
+
+/// Merges raw films of the same resolution into RGB values (three
+/// per pixel, scaled by the *scale* of the first film) covering the
+/// union of their pixel bounds. Returns None if no films are given or
+/// the resolutions differ.
+pub fn merge_raw_films(films: &[RawFilm]) -> Option<(Bounds2i, Vec<Float>)> {
+    let first: &RawFilm = films.first()?;
+    let mut bounds: Bounds2i = first.pixel_bounds;
+    for film in films.iter() {
+        if film.full_resolution.x != first.full_resolution.x
+            || film.full_resolution.y != first.full_resolution.y
+        {
+            println!(
+                "ERROR: raw films with different resolutions ({:?} vs. {:?})",
+                first.full_resolution, film.full_resolution
+            );
+            return None;
+        }
+        if film.scale != first.scale {
+            println!(
+                "WARNING: raw films with different scales, using {}",
+                first.scale
+            );
+        }
+        bounds = bnd2_union_bnd2i(&bounds, &film.pixel_bounds);
+    }
+    // only films with splats contribute to the splat normalization
+    let has_splats: Vec<bool> = films
+        .iter()
+        .map(|film| film.pixels.iter().any(|pixel| pixel.has_splats()))
+        .collect();
+    let mut rgb: Vec<Float> = Vec::with_capacity(3 * bounds.area() as usize);
+    for p in &bounds {
+        let mut pixel: Pixel = Pixel::default();
+        let mut inv_splat_scale_sum: Float = 0.0 as Float;
+        for (film, splats) in films.iter().zip(has_splats.iter()) {
+            if let Some(film_pixel) = film.get_pixel(p) {
+                pixel.add(film_pixel);
+                if *splats && film.splat_scale > 0.0 as Float {
+                    inv_splat_scale_sum += 1.0 as Float / film.splat_scale;
+                }
+            }
+        }
+        let splat_scale: Float = if inv_splat_scale_sum > 0.0 as Float {
+            1.0 as Float / inv_splat_scale_sum
+        } else {
+            0.0 as Float
+        };
+        let pixel_rgb: [Float; 3] = pixel.to_rgb(splat_scale);
+        rgb.push(pixel_rgb[0] * first.scale);
+        rgb.push(pixel_rgb[1] * first.scale);
+        rgb.push(pixel_rgb[2] * first.scale);
+    }
+    Some((bounds, rgb))
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}
@@ -247,6 +247,37 @@ impl Bsdf {
         assert!(self.bxdfs.len() < MAX_BXDFS as usize);
         self.bxdfs.push(b);
     }
+    /// Let dispersive BxDFs refract the wavelength a path already
+    /// carries: if only one sample of the path throughput *beta* is
+    /// non-zero (because of an earlier dispersive refraction), all
+    /// other wavelengths would be discarded anyway.
+    pub fn select_wavelength(&mut self, beta: &Spectrum) {
+        let dispersive: bool = self.bxdfs.iter().any(|bxdf| match bxdf {
+            Bxdf::SpecTrans(bxdf) => bxdf.dispersion.is_some(),
+            Bxdf::FresnelSpec(bxdf) => bxdf.dispersion.is_some(),
+            _ => false,
+        });
+        if !dispersive {
+            return;
+        }
+        let mut hero_wavelength: Option<usize> = None;
+        for i in 0..Spectrum::N_SAMPLES {
+            if beta[i] != 0.0 as Float {
+                if hero_wavelength.is_some() {
+                    // more than one wavelength left
+                    return;
+                }
+                hero_wavelength = Some(i);
+            }
+        }
+        for bxdf in &mut self.bxdfs {
+            match bxdf {
+                Bxdf::SpecTrans(bxdf) => bxdf.hero_wavelength = hero_wavelength,
+                Bxdf::FresnelSpec(bxdf) => bxdf.hero_wavelength = hero_wavelength,
+                _ => {}
+            }
+        }
+    }
     pub fn num_components(&self, flags: u8) -> u8 {
         let mut num: u8 = 0;
         let n_bxdfs: usize = self.bxdfs.len();
@@ -294,6 +325,33 @@ impl Bsdf {
         }
         f
     }
+    /// Estimate the hemispherical-directional reflectance for the
+    /// outgoing direction *wo_world* by Monte Carlo integration over
+    /// the given *samples* (see BxDF::rho(...) in reflection.cpp).
+    pub fn rho_hd(&self, wo_world: &Vector3f, samples: &[Point2f], flags: u8) -> Spectrum {
+        let wo: Vector3f = self.world_to_local(wo_world);
+        let mut r: Spectrum = Spectrum::default();
+        if wo.z == 0.0 as Float || samples.is_empty() {
+            return r;
+        }
+        for bxdf in &self.bxdfs {
+            if !bxdf.matches_flags(flags) {
+                continue;
+            }
+            let mut rho: Spectrum = Spectrum::default();
+            for u in samples {
+                let mut wi: Vector3f = Vector3f::default();
+                let mut pdf: Float = 0.0 as Float;
+                let mut sampled_type: u8 = 0_u8;
+                let f: Spectrum = bxdf.sample_f(&wo, &mut wi, u, &mut pdf, &mut sampled_type);
+                if pdf > 0.0 as Float {
+                    rho += f * abs_cos_theta(&wi) / pdf;
+                }
+            }
+            r += rho / samples.len() as Float;
+        }
+        r
+    }
     /// Calls the individual Bxdf::sample_f() methods to generate samples.
     pub fn sample_f(
         &self,
@@ -707,6 +765,52 @@ impl FresnelNoOp {
     }
 }
 
+/// Wavelength (in nm) of the helium d-line, used for the (single)
+/// index of refraction of dispersive materials.
+pub const ETA_REFERENCE_WAVELENGTH: Float = 587.6;
+
+/// Wavelength-dependent index of refraction of a dielectric.
+#[derive(Debug, Copy, Clone)]
+pub enum Dispersion {
+    /// Cauchy's equation: eta = a + b / lambda^2 (lambda in micrometers)
+    Cauchy { a: Float, b: Float },
+    /// Sellmeier's equation: eta^2 = 1 + sum(b_i * lambda^2 / (lambda^2 - c_i))
+    /// (lambda in micrometers, c_i in square micrometers)
+    Sellmeier { b: [Float; 3], c: [Float; 3] },
+}
+
+impl Dispersion {
+    /// Index of refraction for the given wavelength *lambda* (in nm).
+    pub fn eta(&self, lambda: Float) -> Float {
+        let l: Float = lambda * 0.001 as Float;
+        let l2: Float = l * l;
+        match self {
+            Dispersion::Cauchy { a, b } => a + b / l2,
+            Dispersion::Sellmeier { b, c } => {
+                let mut eta2: Float = 1.0 as Float;
+                for i in 0..3 {
+                    eta2 += b[i] * l2 / (l2 - c[i]);
+                }
+                eta2.max(0.0 as Float).sqrt()
+            }
+        }
+    }
+}
+
+/// Choose the spectral sample a dispersive BxDF refracts. If the path
+/// already carries a single wavelength (*hero_wavelength*) only that
+/// one is used, otherwise a sample is picked uniformly by *u* and the
+/// returned weight compensates for dropping all other samples.
+fn sample_wavelength(hero_wavelength: Option<usize>, u: Float) -> (usize, Float) {
+    if let Some(hero) = hero_wavelength {
+        (hero, 1.0 as Float)
+    } else {
+        let n_samples: usize = Spectrum::N_SAMPLES;
+        let i: usize = ((u * n_samples as Float) as usize).min(n_samples - 1);
+        (i, n_samples as Float)
+    }
+}
+
 #[derive(Copy, Clone)]
 pub struct SpecularReflection {
     pub r: Spectrum,
@@ -759,6 +863,9 @@ pub struct SpecularTransmission {
     pub fresnel: FresnelDielectric,
     pub mode: TransportMode,
     pub sc_opt: Option<Spectrum>,
+    /// replaces *eta_b* per wavelength
+    pub dispersion: Option<Dispersion>,
+    pub hero_wavelength: Option<usize>,
 }
 
 impl SpecularTransmission {
@@ -768,6 +875,7 @@ impl SpecularTransmission {
         eta_b: Float,
         mode: TransportMode,
         sc_opt: Option<Spectrum>,
+        dispersion: Option<Dispersion>,
     ) -> Self {
         SpecularTransmission {
             t,
@@ -779,6 +887,8 @@ impl SpecularTransmission {
             },
             mode,
             sc_opt,
+            dispersion,
+            hero_wavelength: None,
         }
     }
     pub fn f(&self, _wo: &Vector3f, _wi: &Vector3f) -> Spectrum {
@@ -788,10 +898,13 @@ impl SpecularTransmission {
         &self,
         wo: &Vector3f,
         wi: &mut Vector3f,
-        _sample: &Point2f,
+        sample: &Point2f,
         pdf: &mut Float,
-        _sampled_type: &mut u8,
+        sampled_type: &mut u8,
     ) -> Spectrum {
+        if let Some(dispersion) = self.dispersion {
+            return self.sample_f_dispersive(&dispersion, wo, wi, sample, pdf, sampled_type);
+        }
         // figure out which $\eta$ is incident and which is transmitted
         let entering: bool = cos_theta(wo) > 0.0;
         let eta_i = if entering { self.eta_a } else { self.eta_b };
@@ -825,6 +938,55 @@ impl SpecularTransmission {
             ft / abs_cos_theta(&*wi)
         }
     }
+    /// Refracts a single wavelength, all other spectral samples are
+    /// set to zero.
+    fn sample_f_dispersive(
+        &self,
+        dispersion: &Dispersion,
+        wo: &Vector3f,
+        wi: &mut Vector3f,
+        sample: &Point2f,
+        pdf: &mut Float,
+        _sampled_type: &mut u8,
+    ) -> Spectrum {
+        let (wavelength, weight) = sample_wavelength(self.hero_wavelength, sample[XYEnum::X]);
+        let eta_b: Float = dispersion.eta(Spectrum::wavelength(wavelength));
+        // figure out which $\eta$ is incident and which is transmitted
+        let entering: bool = cos_theta(wo) > 0.0;
+        let eta_i = if entering { self.eta_a } else { eta_b };
+        let eta_t = if entering { eta_b } else { self.eta_a };
+        // compute ray direction for specular transmission
+        if !refract(
+            wo,
+            &nrm_faceforward_vec3(
+                &Normal3f {
+                    x: 0.0,
+                    y: 0.0,
+                    z: 1.0,
+                },
+                wo,
+            ),
+            eta_i / eta_t,
+            wi,
+        ) {
+            return Spectrum::default();
+        }
+        *pdf = 1.0;
+        let mut ft: Float = self.t[wavelength]
+            * (1.0 as Float - fr_dielectric(cos_theta(&*wi), self.eta_a, eta_b))
+            * weight;
+        // account for non-symmetry with transmission to different medium
+        if self.mode == TransportMode::Radiance {
+            ft *= (eta_i * eta_i) / (eta_t * eta_t);
+        }
+        let mut f: Spectrum = Spectrum::default();
+        f[wavelength] = ft / abs_cos_theta(&*wi);
+        if let Some(sc) = self.sc_opt {
+            sc * f
+        } else {
+            f
+        }
+    }
     pub fn pdf(&self, wo: &Vector3f, wi: &Vector3f) -> Float {
         if vec3_same_hemisphere_vec3(wo, wi) {
             abs_cos_theta(wi) * INV_PI
@@ -845,6 +1007,9 @@ pub struct FresnelSpecular {
     pub eta_b: Float,
     pub mode: TransportMode,
     pub sc_opt: Option<Spectrum>,
+    /// replaces *eta_b* per wavelength
+    pub dispersion: Option<Dispersion>,
+    pub hero_wavelength: Option<usize>,
 }
 
 impl FresnelSpecular {
@@ -855,6 +1020,7 @@ impl FresnelSpecular {
         eta_b: Float,
         mode: TransportMode,
         sc_opt: Option<Spectrum>,
+        dispersion: Option<Dispersion>,
     ) -> Self {
         FresnelSpecular {
             r,
@@ -863,6 +1029,8 @@ impl FresnelSpecular {
             eta_b,
             mode,
             sc_opt,
+            dispersion,
+            hero_wavelength: None,
         }
     }
     pub fn f(&self, _wo: &Vector3f, _wi: &Vector3f) -> Spectrum {
@@ -876,6 +1044,9 @@ impl FresnelSpecular {
         pdf: &mut Float,
         sampled_type: &mut u8,
     ) -> Spectrum {
+        if let Some(dispersion) = self.dispersion {
+            return self.sample_f_dispersive(&dispersion, wo, wi, sample, pdf, sampled_type);
+        }
         let ct: Float = cos_theta(wo);
         let f: Float = fr_dielectric(ct, self.eta_a, self.eta_b);
         if sample[XYEnum::X] < f {
@@ -935,6 +1106,87 @@ impl FresnelSpecular {
             }
         }
     }
+    /// Reflection vs. transmission is chosen with the Fresnel term at
+    /// the reference wavelength (clamped, because other wavelengths
+    /// might not be totally internally reflected); reflection keeps
+    /// all wavelengths, transmission refracts a single one.
+    fn sample_f_dispersive(
+        &self,
+        dispersion: &Dispersion,
+        wo: &Vector3f,
+        wi: &mut Vector3f,
+        sample: &Point2f,
+        pdf: &mut Float,
+        sampled_type: &mut u8,
+    ) -> Spectrum {
+        let ct: Float = cos_theta(wo);
+        let pr: Float = clamp_t(
+            fr_dielectric(ct, self.eta_a, self.eta_b),
+            0.01 as Float,
+            0.99 as Float,
+        );
+        if sample[XYEnum::X] < pr {
+            // compute specular reflection with per-wavelength Fresnel terms
+            *wi = Vector3f {
+                x: -wo.x,
+                y: -wo.y,
+                z: wo.z,
+            };
+            if *sampled_type != 0_u8 {
+                *sampled_type = BxdfType::BsdfReflection as u8 | BxdfType::BsdfSpecular as u8
+            }
+            *pdf = pr;
+            let mut fr: Spectrum = Spectrum::default();
+            for i in 0..Spectrum::N_SAMPLES {
+                let eta_b: Float = dispersion.eta(Spectrum::wavelength(i));
+                fr[i] = fr_dielectric(ct, self.eta_a, eta_b);
+            }
+            if let Some(sc) = self.sc_opt {
+                sc * self.r * fr / abs_cos_theta(&*wi)
+            } else {
+                self.r * fr / abs_cos_theta(&*wi)
+            }
+        } else {
+            // compute specular transmission for a single wavelength
+            let (wavelength, weight) = sample_wavelength(self.hero_wavelength, sample[XYEnum::Y]);
+            let eta_b: Float = dispersion.eta(Spectrum::wavelength(wavelength));
+            let entering: bool = ct > 0.0 as Float;
+            let eta_i = if entering { self.eta_a } else { eta_b };
+            let eta_t = if entering { eta_b } else { self.eta_a };
+            if !refract(
+                wo,
+                &nrm_faceforward_vec3(
+                    &Normal3f {
+                        x: 0.0,
+                        y: 0.0,
+                        z: 1.0,
+                    },
+                    wo,
+                ),
+                eta_i / eta_t,
+                wi,
+            ) {
+                return Spectrum::default();
+            }
+            let mut ft: Float =
+                self.t[wavelength] * (1.0 as Float - fr_dielectric(ct, self.eta_a, eta_b)) * weight;
+            // account for non-symmetry with transmission to different medium
+            if self.mode == TransportMode::Radiance {
+                ft *= (eta_i * eta_i) / (eta_t * eta_t);
+            }
+            if *sampled_type != 0_u8 {
+                *sampled_type = BxdfType::BsdfTransmission as u8 | BxdfType::BsdfSpecular as u8
+            }
+            *pdf = 1.0 as Float - pr;
+            let mut ft_spectrum: Spectrum = Spectrum::default();
+            ft_spectrum[wavelength] = ft / abs_cos_theta(&*wi);
+            if let Some(sc) = self.sc_opt {
+                sc * ft_spectrum
+            } else {
+                ft_spectrum
+            }
+        }
+    }
     pub fn pdf(&self, wo: &Vector3f, wi: &Vector3f) -> Float {
         if vec3_same_hemisphere_vec3(wo, wi) {
             abs_cos_theta(wi) * INV_PI
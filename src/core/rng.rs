@@ -30,6 +30,15 @@ impl Rng {
             inc: PCG32_DEFAULT_STREAM,
         }
     }
+    /// Returns the internal state (e.g. to store it in a checkpoint).
+    pub fn get_state(&self) -> (u64, u64) {
+        (self.state, self.inc)
+    }
+    /// Restores an internal state returned by *get_state()*.
+    pub fn set_state(&mut self, state: u64, inc: u64) {
+        self.state = state;
+        self.inc = inc;
+    }
     pub fn set_sequence(&mut self, initseq: u64) {
         self.state = 0_u64;
         let (shl, _overflow) = initseq.overflowing_shl(1);
@@ -192,12 +192,24 @@ impl Sampler {
             Sampler::ZeroTwoSequence(sampler) => sampler.get_samples_per_pixel(),
         }
     }
-    // GlobalSampler
+    /// Asks for pixel sample sets where every power-of-two prefix is
+    /// stratified. The global samplers (**Halton**, **Sobol**)
+    /// already behave like that, the **ZeroTwoSequence** sampler
+    /// restricts its shuffling accordingly.
+    pub fn set_nested(&mut self, nested: bool) {
+        if let Sampler::ZeroTwoSequence(sampler) = self {
+            sampler.nested = nested;
+        }
+    }
     pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
         match self {
             Sampler::Halton(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::MaxMinDist(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::MLT(_sampler) => false,
+            Sampler::Random(sampler) => sampler.set_sample_number(sample_num),
             Sampler::Sobol(sampler) => sampler.set_sample_number(sample_num),
-            _ => false,
+            Sampler::Stratified(sampler) => sampler.set_sample_number(sample_num),
+            Sampler::ZeroTwoSequence(sampler) => sampler.set_sample_number(sample_num),
         }
     }
 }
@@ -4,7 +4,8 @@
 use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
-use crate::core::geometry::{Point2f, Vector2f, Vector3f, XYEnum};
+use crate::core::geometry::{vec3_cross_vec3, vec3_dot_vec3f};
+use crate::core::geometry::{Point2f, Point3f, Vector2f, Vector3f, XYEnum};
 use crate::core::pbrt::clamp_t;
 use crate::core::pbrt::Float;
 use crate::core::pbrt::{INV_2_PI, INV_4_PI, INV_PI, PI_OVER_2, PI_OVER_4};
@@ -211,6 +212,25 @@ pub fn shuffle<T>(samp: &mut [T], count: i32, n_dimensions: i32, rng: &mut Rng)
     }
 }
 
+/// Like *shuffle()*, but only permutes within the blocks [0, 1), [1,
+/// 2), [2, 4), [4, 8), ... so every power-of-two prefix of the
+/// samples keeps the same set of values.
+pub fn shuffle_nested<T>(samp: &mut [T], count: i32, n_dimensions: i32, rng: &mut Rng) {
+    let mut start: i32 = 0;
+    let mut end: i32 = 1;
+    while start < count {
+        let block_end: i32 = end.min(count);
+        shuffle(
+            &mut samp[(n_dimensions * start) as usize..],
+            block_end - start,
+            n_dimensions,
+            rng,
+        );
+        start = block_end;
+        end *= 2;
+    }
+}
+
 /// Cosine-weighted hemisphere sampling using Malley's method.
 pub fn cosine_sample_hemisphere(u: &Point2f) -> Vector3f {
     let d: Point2f = concentric_sample_disk(u);
@@ -382,6 +402,163 @@ pub fn uniform_sample_cone(u: Point2f, cos_theta_max: Float) -> Vector3f {
     }
 }
 
+/// A rectangle as seen from a reference point, sampled uniformly by
+/// solid angle (see "An Area-Preserving Parametrization for Spherical
+/// Rectangles", Urena et al. 2013). Rectangles covering a tiny solid
+/// angle are sampled uniformly by area instead.
+pub struct SphericalRectangle {
+    o: Point3f,
+    s: Point3f,
+    ex: Vector3f,
+    ey: Vector3f,
+    // local reference system, _z_ points away from the rectangle
+    x: Vector3f,
+    y: Vector3f,
+    z: Vector3f,
+    z0: Float,
+    x0: Float,
+    y0: Float,
+    x1: Float,
+    y1: Float,
+    b0: Float,
+    b1: Float,
+    k: Float,
+    solid_angle: Float,
+}
+
+impl SphericalRectangle {
+    /// The rectangle has the corner |s| and the edges |ex| and |ey|,
+    /// which have to be perpendicular.
+    pub fn new(p_ref: &Point3f, s: &Point3f, ex: &Vector3f, ey: &Vector3f) -> Self {
+        let exl: Float = ex.length();
+        let eyl: Float = ey.length();
+        let x: Vector3f = *ex / exl;
+        let y: Vector3f = *ey / eyl;
+        let mut z: Vector3f = vec3_cross_vec3(&x, &y);
+        let d: Vector3f = *s - *p_ref;
+        let mut z0: Float = vec3_dot_vec3f(&d, &z);
+        // flip _z_ to make it point against the rectangle
+        if z0 > 0.0 as Float {
+            z0 = -z0;
+            z = -z;
+        }
+        let x0: Float = vec3_dot_vec3f(&d, &x);
+        let y0: Float = vec3_dot_vec3f(&d, &y);
+        let x1: Float = x0 + exl;
+        let y1: Float = y0 + eyl;
+        // compute normals of the planes through the edges
+        let v00: Vector3f = Vector3f {
+            x: x0,
+            y: y0,
+            z: z0,
+        };
+        let v01: Vector3f = Vector3f {
+            x: x0,
+            y: y1,
+            z: z0,
+        };
+        let v10: Vector3f = Vector3f {
+            x: x1,
+            y: y0,
+            z: z0,
+        };
+        let v11: Vector3f = Vector3f {
+            x: x1,
+            y: y1,
+            z: z0,
+        };
+        let n0: Vector3f = vec3_cross_vec3(&v00, &v10).normalize();
+        let n1: Vector3f = vec3_cross_vec3(&v10, &v11).normalize();
+        let n2: Vector3f = vec3_cross_vec3(&v11, &v01).normalize();
+        let n3: Vector3f = vec3_cross_vec3(&v01, &v00).normalize();
+        // internal angles and solid angle of the spherical rectangle
+        let angle = |a: &Vector3f, b: &Vector3f| -> f64 {
+            clamp_t(-vec3_dot_vec3f(a, b), -1.0 as Float, 1.0 as Float).acos() as f64
+        };
+        let g0: f64 = angle(&n0, &n1);
+        let g1: f64 = angle(&n1, &n2);
+        let g2: f64 = angle(&n2, &n3);
+        let g3: f64 = angle(&n3, &n0);
+        let mut solid_angle: Float = (g0 + g1 + g2 + g3 - 2.0 * std::f64::consts::PI) as Float;
+        if z0 == 0.0 as Float || solid_angle.is_nan() {
+            solid_angle = 0.0 as Float;
+        }
+        SphericalRectangle {
+            o: *p_ref,
+            s: *s,
+            ex: *ex,
+            ey: *ey,
+            x,
+            y,
+            z,
+            z0,
+            x0,
+            y0,
+            x1,
+            y1,
+            b0: n0.z,
+            b1: n2.z,
+            k: (2.0 * std::f64::consts::PI - g2 - g3) as Float,
+            solid_angle: solid_angle.max(0.0 as Float),
+        }
+    }
+    pub fn solid_angle(&self) -> Float {
+        self.solid_angle
+    }
+    /// Returns a point on the rectangle.
+    pub fn sample(&self, u: &Point2f) -> Point3f {
+        if self.solid_angle < MIN_SPHERICAL_RECTANGLE_SOLID_ANGLE {
+            return self.s + self.ex * u.x + self.ey * u.y;
+        }
+        // compute _xu_ along the $x$ edge
+        let au: Float = u.x * self.solid_angle + self.k;
+        let fu: Float = (au.cos() * self.b0 - self.b1) / au.sin();
+        let mut cu: Float = 1.0 as Float / (fu * fu + self.b0 * self.b0).sqrt();
+        if fu < 0.0 as Float {
+            cu = -cu;
+        }
+        cu = clamp_t(cu, -FLOAT_ONE_MINUS_EPSILON, FLOAT_ONE_MINUS_EPSILON);
+        let xu: Float = clamp_t(
+            -(cu * self.z0) / (1.0 as Float - cu * cu).sqrt(),
+            self.x0,
+            self.x1,
+        );
+        // compute _yv_ along the $y$ edge
+        let d: Float = (xu * xu + self.z0 * self.z0).sqrt();
+        let h0: Float = self.y0 / (d * d + self.y0 * self.y0).sqrt();
+        let h1: Float = self.y1 / (d * d + self.y1 * self.y1).sqrt();
+        let hv: Float = h0 + u.y * (h1 - h0);
+        let hv2: Float = hv * hv;
+        let yv: Float = if hv2 < 1.0 as Float - 1e-6 as Float {
+            (hv * d) / (1.0 as Float - hv2).sqrt()
+        } else {
+            self.y1
+        };
+        self.o + self.x * xu + self.y * yv + self.z * self.z0
+    }
+    /// Returns the PDF (with respect to solid angle) of sampling the
+    /// point |p| on the rectangle.
+    pub fn pdf(&self, p: &Point3f) -> Float {
+        if self.solid_angle == 0.0 as Float {
+            return 0.0 as Float;
+        }
+        if self.solid_angle >= MIN_SPHERICAL_RECTANGLE_SOLID_ANGLE {
+            return 1.0 as Float / self.solid_angle;
+        }
+        // convert the PDF of area sampling
+        let w: Vector3f = *p - self.o;
+        let dist_sq: Float = w.length_squared();
+        let cos_theta: Float = vec3_dot_vec3f(&w, &self.z).abs() / dist_sq.sqrt();
+        let area: Float = vec3_cross_vec3(&self.ex, &self.ey).length();
+        if cos_theta == 0.0 as Float || area == 0.0 as Float {
+            return 0.0 as Float;
+        }
+        dist_sq / (cos_theta * area)
+    }
+}
+
+const MIN_SPHERICAL_RECTANGLE_SOLID_ANGLE: Float = 1e-3;
+
 // Uniformly distributing samples over isosceles right triangles
 // actually works for any triangle.
 
@@ -4,23 +4,43 @@
 //!
 
 // std
+use std::collections::HashMap;
 use std::sync::Arc;
 // pbrt
+use crate::accelerators::bvh::BVHAccel;
 use crate::core::geometry::{Bounds3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, SurfaceInteraction};
 use crate::core::light::{Light, LightFlags};
-use crate::core::pbrt::Spectrum;
-use crate::core::primitive::Primitive;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::primitive::{Primitive, TransformedPrimitive};
 use crate::core::sampler::Sampler;
+use crate::core::transform::AnimatedTransform;
 
 // see scene.h
 
+/// The top-level BVH is rebuilt instead of refit once refitting made
+/// its SAH cost this much higher than after the last build.
+const TLAS_REBUILD_COST_RATIO: Float = 1.5;
+
+/// How **update_instance_transforms()** updated the top-level BVH.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum TlasUpdate {
+    Refit,
+    Rebuild,
+}
+
 #[derive(Clone)]
 pub struct Scene {
     pub lights: Vec<Arc<Light>>,
     pub infinite_lights: Vec<Arc<Light>>,
     pub aggregate: Arc<Primitive>,
     pub world_bound: Bounds3f,
+    /// object instances in the order of the **ObjectInstance** calls,
+    /// if the aggregate is a top-level BVH over them
+    pub instances: Vec<Arc<Primitive>>,
+    // where each instance is in the primitives of the top-level BVH
+    instance_slots: Vec<Vec<usize>>,
+    tlas_build_cost: Float,
 }
 
 impl Scene {
@@ -31,6 +51,9 @@ impl Scene {
             infinite_lights: Vec::new(),
             aggregate: aggregate.clone(),
             world_bound,
+            instances: Vec::new(),
+            instance_slots: Vec::new(),
+            tlas_build_cost: 0.0 as Float,
         };
         let mut changed_lights = Vec::new();
         let mut infinite_lights = Vec::new();
@@ -47,7 +70,75 @@ impl Scene {
             infinite_lights,
             aggregate,
             world_bound,
+            instances: Vec::new(),
+            instance_slots: Vec::new(),
+            tlas_build_cost: 0.0 as Float,
+        }
+    }
+    /// Marks the aggregate as a top-level BVH over the object
+    /// |instances| (and other primitives), whose transformations can
+    /// be updated with **update_instance_transforms()**.
+    pub fn set_instances(&mut self, instances: Vec<Arc<Primitive>>) {
+        self.instances = instances;
+        if let Primitive::BVH(ref bvh) = *self.aggregate {
+            self.instance_slots = find_instance_slots(bvh, &self.instances);
+            self.tlas_build_cost = bvh.sah_cost();
+        }
+    }
+    /// Sets new transformations for object instances (by their index
+    /// in *instances*), e.g. between the frames of an animation. The
+    /// bottom-level BVHs of the instanced objects are kept, only the
+    /// top-level BVH is refit, or rebuilt if refitting made it too
+    /// slow. Returns *None* if the scene has no top-level BVH that
+    /// can be updated.
+    pub fn update_instance_transforms(
+        &mut self,
+        updates: &[(usize, AnimatedTransform)],
+    ) -> Option<TlasUpdate> {
+        if self.instances.is_empty() {
+            println!("ERROR: Scene has no top-level BVH over instances to update.");
+            return None;
+        }
+        let bvh: &mut BVHAccel = match Arc::get_mut(&mut self.aggregate) {
+            Some(Primitive::BVH(bvh)) => bvh,
+            _ => {
+                // e.g. by a clone of the scene
+                println!("ERROR: Top-level BVH is shared and can't be updated.");
+                return None;
+            }
+        };
+        for (instance, primitive_to_world) in updates {
+            let primitive: Arc<Primitive> = match self.instances.get(*instance).map(|p| &**p) {
+                Some(Primitive::Transformed(transformed)) => {
+                    Arc::new(Primitive::Transformed(Box::new(TransformedPrimitive::new(
+                        transformed.primitive.clone(),
+                        *primitive_to_world,
+                    ))))
+                }
+                _ => {
+                    println!("ERROR: No object instance {} to update.", instance);
+                    continue;
+                }
+            };
+            for slot in &self.instance_slots[*instance] {
+                bvh.replace_primitive(*slot, primitive.clone());
+            }
+            self.instances[*instance] = primitive;
+        }
+        bvh.refit();
+        let mut update: TlasUpdate = TlasUpdate::Refit;
+        if bvh.sah_cost() > TLAS_REBUILD_COST_RATIO * self.tlas_build_cost {
+            bvh.rebuild();
+            self.instance_slots = find_instance_slots(bvh, &self.instances);
+            self.tlas_build_cost = bvh.sah_cost();
+            update = TlasUpdate::Rebuild;
+        }
+        // lights depend on the scene bounds
+        self.world_bound = self.aggregate.world_bound();
+        for light in &self.lights {
+            light.preprocess(self);
         }
+        Some(update)
     }
     pub fn world_bound(&self) -> &Bounds3f {
         &self.world_bound
@@ -105,3 +196,17 @@ impl Scene {
         }
     }
 }
+
+fn find_instance_slots(bvh: &BVHAccel, instances: &[Arc<Primitive>]) -> Vec<Vec<usize>> {
+    let mut instance_indices: HashMap<*const Primitive, usize> = HashMap::new();
+    for (i, instance) in instances.iter().enumerate() {
+        instance_indices.insert(Arc::as_ptr(instance), i);
+    }
+    let mut instance_slots: Vec<Vec<usize>> = vec![Vec::new(); instances.len()];
+    for (slot, primitive) in bvh.primitives.iter().enumerate() {
+        if let Some(i) = instance_indices.get(&Arc::as_ptr(primitive)) {
+            instance_slots[*i].push(slot);
+        }
+    }
+    instance_slots
+}
@@ -1480,6 +1480,505 @@ pub const CIE_LAMBDA: [Float; N_CIE_SAMPLES as usize] = [
 ];
 pub const CIE_Y_INTEGRAL: Float = 106.856_895;
 
+// RGB to spectrum conversion tables (see spectrum.cpp)
+pub const N_RGB_2_SPECT_SAMPLES: usize = 32;
+pub const RGB2SPECT_LAMBDA: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    380.0, 390.967_74, 401.935_5, 412.903_23, 423.870_97, 434.838_7, 445.806_46, 456.774_2,
+    467.741_94, 478.709_7, 489.677_43, 500.645_17, 511.612_9, 522.580_6, 533.548_34, 544.516_05,
+    555.483_76, 566.451_5, 577.419_2, 588.386_9, 599.354_6, 610.322_3, 621.290_04, 632.257_75,
+    643.225_46, 654.193_2, 665.160_9, 676.128_6, 687.096_3, 698.064, 709.031_74, 720.0,
+];
+
+pub const RGB_REFL2SPECT_WHITE: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.061_895_8,
+    1.061_502,
+    1.061_433_6,
+    1.062_271_1,
+    1.062_203_6,
+    1.062_506,
+    1.062_393_9,
+    1.062_470_7,
+    1.062_504_8,
+    1.062_436_6,
+    1.062_069_4,
+    1.061_316_7,
+    1.061_033_4,
+    1.061_386_8,
+    1.061_421_5,
+    1.062_033_7,
+    1.062_549_7,
+    1.062_431_7,
+    1.062_524_9,
+    1.062_427_8,
+    1.062_475,
+    1.062_553_9,
+    1.062_532_7,
+    1.062_392_2,
+    1.062_365,
+    1.062_525_6,
+    1.061_227_8,
+    1.059_426_3,
+    1.059_981_1,
+    1.060_254_7,
+    1.060_126_3,
+    1.060_656_5,
+];
+
+pub const RGB_REFL2SPECT_CYAN: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.041_462_8,
+    1.032_866_1,
+    1.012_614_6,
+    1.035_046_1,
+    1.007_866_1,
+    1.042_228,
+    1.044_259_7,
+    1.053_523_8,
+    1.018_077_6,
+    1.044_273,
+    1.052_936_2,
+    1.053_703_4,
+    1.053_390_1,
+    1.053_778_3,
+    1.052_709_3,
+    1.053_044_9,
+    1.055_055_5,
+    1.055_367_4,
+    1.045_430_7,
+    0.623_489_5,
+    0.180_380_72,
+    -0.007_630_376,
+    -0.000_152_178_47,
+    -0.007_510_225_8,
+    -0.002_170_864,
+    0.000_659_194_66,
+    0.012_278_816,
+    -0.004_466_977_4,
+    0.017_119_799,
+    0.004_921_109,
+    0.005_876_292_5,
+    0.025_259_4,
+];
+
+pub const RGB_REFL2SPECT_MAGENTA: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.994_221_4,
+    0.989_869_36,
+    0.982_936_56,
+    0.996_278_7,
+    1.019_895_6,
+    1.016_639_6,
+    1.022_091_3,
+    0.996_516_64,
+    1.009_776_6,
+    1.021_542_2,
+    0.640_319_5,
+    0.002_501_238,
+    0.006_533_994,
+    0.002_833_408,
+    -0.000_000_000_051_209_675,
+    -0.009_059_229,
+    0.003_393_672,
+    -0.003_063_874,
+    0.222_039_36,
+    0.631_411_4,
+    0.974_809_9,
+    0.972_095_6,
+    1.017_377,
+    0.998_751_94,
+    0.947_017_25,
+    0.852_586_2,
+    0.948_978,
+    0.947_518_77,
+    0.995_989_44,
+    0.863_013_5,
+    0.891_509_9,
+    0.848_664_94,
+];
+
+pub const RGB_REFL2SPECT_YELLOW: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.005_574_062_5,
+    -0.004_798_283_3,
+    -0.005_253_656_3,
+    -0.006_457_148,
+    -0.005_969_351_6,
+    -0.002_183_671_6,
+    0.016_781_121,
+    0.096_096_35,
+    0.212_173_57,
+    0.361_691_33,
+    0.539_610_1,
+    0.744_088_1,
+    0.922_095_7,
+    1.046_030_4,
+    1.051_382_5,
+    1.051_199_2,
+    1.051_053,
+    1.051_739_7,
+    1.051_604_3,
+    1.051_194_4,
+    1.051_159,
+    1.051_661_3,
+    1.051_403_9,
+    1.051_594_1,
+    1.051_146,
+    1.051_512_4,
+    1.050_887_1,
+    1.050_892_4,
+    1.047_749_3,
+    1.049_327_3,
+    1.043_596_4,
+    1.039_228_1,
+];
+
+pub const RGB_REFL2SPECT_RED: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.165_756_05,
+    0.118_464_425,
+    0.124_082_93,
+    0.113_712_72,
+    0.078_992_434,
+    0.032_205_604,
+    -0.010_798_366,
+    0.018_051_976,
+    0.005_340_719_6,
+    0.013_654_918,
+    -0.005_956_421,
+    -0.001_844_436_5,
+    -0.010_571_884,
+    -0.002_937_552,
+    -0.010_790_477,
+    -0.008_022_43,
+    -0.002_266_916_7,
+    0.007_020_024,
+    -0.008_152_847,
+    0.607_728_66,
+    0.988_315_6,
+    0.993_916_9,
+    1.003_933_9,
+    0.992_345,
+    0.999_265_3,
+    1.008_462_2,
+    0.983_583,
+    1.008_502_4,
+    0.974_511_4,
+    0.985_432_7,
+    0.934_957_6,
+    0.987_139_1,
+];
+
+pub const RGB_REFL2SPECT_GREEN: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.002_649_415_3,
+    -0.005_017_501_3,
+    -0.012_547_236,
+    -0.009_455_496,
+    -0.012_526_086_5,
+    -0.007_917_07,
+    -0.007_995_574,
+    -0.009_355_944,
+    0.065_468_61,
+    0.395_728_77,
+    0.752_440_2,
+    0.963_764_8,
+    0.998_544_34,
+    0.999_929_8,
+    0.999_390_84,
+    0.999_943_73,
+    0.999_391_2,
+    0.999_112_37,
+    0.960_195_84,
+    0.631_862_8,
+    0.257_974,
+    0.009_401_489,
+    -0.003_079_834_6,
+    -0.004_523_036_5,
+    -0.006_893_341,
+    -0.009_035_219,
+    -0.008_591_367,
+    -0.008_369_087,
+    -0.007_868_583,
+    -0.000_008_365_758,
+    0.005_430_122_4,
+    -0.002_774_559,
+];
+
+pub const RGB_REFL2SPECT_BLUE: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.992_097_74,
+    0.988_764_3,
+    0.995_390_4,
+    0.995_293_2,
+    0.991_814_5,
+    1.000_258_4,
+    0.999_684_8,
+    0.999_881_2,
+    0.985_040_1,
+    0.790_298_46,
+    0.560_822,
+    0.331_334_6,
+    0.136_924_1,
+    0.018_914_906,
+    -0.000_005_112_977_3,
+    -0.000_423_954_95,
+    -0.000_419_345_92,
+    0.001_747_302_8,
+    0.003_799_916,
+    -0.000_551_014_73,
+    -0.000_043_716_664,
+    0.007_587_45,
+    0.025_795_652,
+    0.038_168_38,
+    0.049_489_588,
+    0.049_595_993,
+    0.049_814_82,
+    0.039_840_91,
+    0.030_501_025,
+    0.021_243_054,
+    0.006_959_653,
+    0.004_173_365,
+];
+
+pub const RGB_ILLUM2SPECT_WHITE: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.156_523_2,
+    1.156_722_5,
+    1.156_620_3,
+    1.155_578_3,
+    1.156_217_6,
+    1.156_767_4,
+    1.156_802_3,
+    1.156_767_7,
+    1.156_356_3,
+    1.156_705_5,
+    1.156_513_5,
+    1.156_433_6,
+    1.156_802_3,
+    1.147_314_8,
+    1.133_931_8,
+    1.129_387_6,
+    1.129_051_6,
+    1.050_486_4,
+    1.045_969_6,
+    0.993_666_9,
+    0.956_016_7,
+    0.924_674_8,
+    0.914_999_4,
+    0.899_394_7,
+    0.895_425_2,
+    0.888_705_7,
+    0.882_228_43,
+    0.879_983_1,
+    0.876_352_4,
+    0.880_003_7,
+    0.880_656_66,
+    0.883_047_04,
+];
+
+pub const RGB_ILLUM2SPECT_CYAN: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.133_448,
+    1.126_676_2,
+    1.134_682_8,
+    1.135_739_6,
+    1.135_637_2,
+    1.136_115_3,
+    1.136_218,
+    1.136_482,
+    1.135_510_7,
+    1.136_406_1,
+    1.136_036_4,
+    1.136_012_3,
+    1.135_426_6,
+    1.136_31,
+    1.135_545,
+    1.135_373_2,
+    1.134_949_7,
+    1.111_111_4,
+    0.905_399,
+    0.610_002_04,
+    0.335_499_35,
+    0.016_040_264,
+    -0.000_960_405_74,
+    -0.000_424_586_82,
+    -0.000_224_670_6,
+    -0.000_054_221_055,
+    0.000_040_151_44,
+    0.000_034_106_84,
+    0.000_058_125_213,
+    0.000_054_316_923,
+    0.000_053_998_658,
+    0.000_040_225_94,
+];
+
+pub const RGB_ILLUM2SPECT_MAGENTA: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.037_189_2,
+    1.058_754_3,
+    1.076_727_2,
+    1.076_270_7,
+    1.079_528_9,
+    1.074_364_4,
+    1.072_702_9,
+    1.073_244_7,
+    1.082_376_1,
+    1.084_054_6,
+    0.956_075_67,
+    0.551_978_95,
+    0.084_191_09,
+    0.000_087_940_07,
+    -0.002_308_640_9,
+    -0.001_124_813_7,
+    -0.000_000_000_077_297_61,
+    -0.000_272_707_7,
+    0.014_466_473,
+    0.258_831_17,
+    0.529_08,
+    0.909_666_24,
+    1.069_057_1,
+    1.088_732_6,
+    1.063_762_2,
+    1.020_181_3,
+    1.026_219_7,
+    1.078_308_6,
+    0.983_338_5,
+    1.070_724_6,
+    1.063_424_8,
+    1.015_087_6,
+];
+
+pub const RGB_ILLUM2SPECT_YELLOW: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.002_775_695_9,
+    0.003_967_382,
+    -0.000_146_069_37,
+    0.000_361_983_96,
+    -0.000_258_192_6,
+    -0.000_050_133_192,
+    -0.000_244_372_44,
+    -0.000_078_061_42,
+    0.049_690_302,
+    0.485_159_72,
+    1.029_572_6,
+    1.033_321_1,
+    1.036_810_3,
+    1.036_488_4,
+    1.036_542_8,
+    1.036_859_5,
+    1.036_564_6,
+    1.036_393_9,
+    1.036_720_5,
+    1.036_523_9,
+    1.036_153_1,
+    1.034_878_5,
+    1.004_272_9,
+    0.842_184_84,
+    0.737_593_95,
+    0.658_531_55,
+    0.605_316_8,
+    0.595_497_97,
+    0.594_192_6,
+    0.565_176_84,
+    0.560_611_84,
+    0.582_286_1,
+];
+
+pub const RGB_ILLUM2SPECT_RED: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.054_711_185,
+    0.055_609_066,
+    0.060_755_875,
+    0.056_232_948,
+    0.046_169_94,
+    0.038_012_806,
+    0.024_424_225,
+    0.003_898_358,
+    -0.000_560_822_55,
+    0.000_964_938_7,
+    0.000_373_411_98,
+    -0.000_433_673_88,
+    -0.000_093_533_96,
+    -0.000_123_549_67,
+    -0.000_145_245_47,
+    -0.000_200_476_92,
+    -0.000_499_385_9,
+    0.027_255_084,
+    0.160_674_07,
+    0.350_697_88,
+    0.573_574_66,
+    0.763_920_9,
+    0.891_444_7,
+    0.963_946_1,
+    0.988_794_6,
+    0.998_974_5,
+    0.986_051_4,
+    0.995_325,
+    0.974_334_8,
+    0.991_343_6,
+    0.988_662_9,
+    0.997_138_56,
+];
+
+pub const RGB_ILLUM2SPECT_GREEN: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    0.025_168_39,
+    0.039_427_437,
+    0.006_205_957_4,
+    0.007_112_086,
+    0.000_217_600_45,
+    0.000_000_000_007_327_184,
+    -0.021_623_066,
+    0.015_670_21,
+    0.002_801_960_3,
+    0.324_947_74,
+    1.016_491_8,
+    1.032_947_7,
+    1.032_158_7,
+    1.035_866_7,
+    1.015_123_6,
+    1.033_807_6,
+    1.037_137_3,
+    1.036_137_7,
+    1.022_982_2,
+    0.969_103_3,
+    -0.005_178_592,
+    0.001_113_126_2,
+    0.006_667_55,
+    0.000_740_243_2,
+    0.021_591_568,
+    0.005_148_162,
+    0.001_456_192_8,
+    0.000_164_145_11,
+    -0.006_463_076_5,
+    0.010_250_854,
+    0.042_387_396,
+    0.021_252_718,
+];
+
+pub const RGB_ILLUM2SPECT_BLUE: [Float; N_RGB_2_SPECT_SAMPLES] = [
+    1.057_049,
+    1.053_846_7,
+    1.055_049_4,
+    1.053_040_7,
+    1.057_993,
+    1.057_843_9,
+    1.058_313_3,
+    1.057_971_2,
+    1.056_188_5,
+    1.057_139_9,
+    1.042_579_5,
+    0.326_030_85,
+    -0.001_925_562_9,
+    -0.001_295_922_1,
+    -0.001_435_735_6,
+    -0.001_296_369_7,
+    -0.001_922_708_1,
+    0.001_262_115_2,
+    -0.001_609_525,
+    -0.001_302_998_4,
+    -0.001_766_660_1,
+    -0.001_232_528_1,
+    0.010_316_81,
+    0.031_284_51,
+    0.088_773_88,
+    0.138_736_22,
+    0.155_350_67,
+    0.148_784_77,
+    0.166_242_55,
+    0.169_976_15,
+    0.157_697_44,
+    0.190_690_9,
+];
+
 pub fn blackbody(lambda: &[Float], n: usize, t: Float, le: &mut Vec<Float>) {
     if t <= 0.0 as Float {
         for _i in 0..n {
@@ -1532,6 +2031,12 @@ pub struct RGBSpectrum {
 }
 
 impl RGBSpectrum {
+    /// Number of spectral samples (one per RGB channel)
+    pub const N_SAMPLES: usize = 3;
+    /// Dominant wavelength (in nm) of the red, green and blue channel.
+    pub fn wavelength(i: usize) -> Float {
+        [610.0 as Float, 550.0 as Float, 465.0 as Float][i]
+    }
     pub fn new(v: Float) -> Self {
         // let n_spectrum_samples = 3; // RGB
         RGBSpectrum { c: [v, v, v] }
@@ -1565,6 +2070,11 @@ impl RGBSpectrum {
         // TODO: DCHECK(!s.HasNaNs());
         s
     }
+    /// RGB values are stored as they are, regardless of the
+    /// spectrum type (see **SampledSpectrum::from_rgb_type()**).
+    pub fn from_rgb_type(rgb: &[Float; 3], _spectrum_type: SpectrumType) -> RGBSpectrum {
+        RGBSpectrum::from_rgb(rgb)
+    }
     pub fn to_rgb(&self, rgb: &mut [Float; 3]) {
         rgb[0] = self.c[0];
         rgb[1] = self.c[1];
@@ -1812,12 +2322,434 @@ impl IndexMut<RGBEnum> for RGBSpectrum {
     }
 }
 
+impl Index<usize> for RGBSpectrum {
+    type Output = Float;
+    fn index(&self, index: usize) -> &Float {
+        &self.c[index]
+    }
+}
+
+impl IndexMut<usize> for RGBSpectrum {
+    fn index_mut(&mut self, index: usize) -> &mut Float {
+        &mut self.c[index]
+    }
+}
+
 impl From<Float> for RGBSpectrum {
     fn from(f: Float) -> Self {
         RGBSpectrum::new(f)
     }
 }
 
+pub const SAMPLED_LAMBDA_START: Float = 400.0;
+pub const SAMPLED_LAMBDA_END: Float = 700.0;
+pub const N_SPECTRAL_SAMPLES: usize = 60;
+
+/// Color matching functions and RGB conversion spectra, averaged over the
+/// wavelength ranges of the **SampledSpectrum** samples (see
+/// SampledSpectrum::Init() in spectrum.h).
+struct SampledSpectrumTables {
+    x: [Float; N_SPECTRAL_SAMPLES],
+    y: [Float; N_SPECTRAL_SAMPLES],
+    z: [Float; N_SPECTRAL_SAMPLES],
+    rgb_refl_2_spect_white: SampledSpectrum,
+    rgb_refl_2_spect_cyan: SampledSpectrum,
+    rgb_refl_2_spect_magenta: SampledSpectrum,
+    rgb_refl_2_spect_yellow: SampledSpectrum,
+    rgb_refl_2_spect_red: SampledSpectrum,
+    rgb_refl_2_spect_green: SampledSpectrum,
+    rgb_refl_2_spect_blue: SampledSpectrum,
+    rgb_illum_2_spect_white: SampledSpectrum,
+    rgb_illum_2_spect_cyan: SampledSpectrum,
+    rgb_illum_2_spect_magenta: SampledSpectrum,
+    rgb_illum_2_spect_yellow: SampledSpectrum,
+    rgb_illum_2_spect_red: SampledSpectrum,
+    rgb_illum_2_spect_green: SampledSpectrum,
+    rgb_illum_2_spect_blue: SampledSpectrum,
+}
+
+impl SampledSpectrumTables {
+    fn new() -> Self {
+        let mut x: [Float; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
+        let mut y: [Float; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
+        let mut z: [Float; N_SPECTRAL_SAMPLES] = [0.0; N_SPECTRAL_SAMPLES];
+        let n: i32 = N_CIE_SAMPLES as i32;
+        for i in 0..N_SPECTRAL_SAMPLES {
+            let (wl0, wl1) = SampledSpectrum::wavelength_range(i);
+            x[i] = average_spectrum_samples(&CIE_LAMBDA, &CIE_X, n, wl0, wl1);
+            y[i] = average_spectrum_samples(&CIE_LAMBDA, &CIE_Y, n, wl0, wl1);
+            z[i] = average_spectrum_samples(&CIE_LAMBDA, &CIE_Z, n, wl0, wl1);
+        }
+        // compute RGB to spectrum functions for _SampledSpectrum_
+        let from_table = |table: &[Float]| -> SampledSpectrum {
+            SampledSpectrum::from_sampled(&RGB2SPECT_LAMBDA, table, N_RGB_2_SPECT_SAMPLES as i32)
+        };
+        SampledSpectrumTables {
+            x,
+            y,
+            z,
+            rgb_refl_2_spect_white: from_table(&RGB_REFL2SPECT_WHITE),
+            rgb_refl_2_spect_cyan: from_table(&RGB_REFL2SPECT_CYAN),
+            rgb_refl_2_spect_magenta: from_table(&RGB_REFL2SPECT_MAGENTA),
+            rgb_refl_2_spect_yellow: from_table(&RGB_REFL2SPECT_YELLOW),
+            rgb_refl_2_spect_red: from_table(&RGB_REFL2SPECT_RED),
+            rgb_refl_2_spect_green: from_table(&RGB_REFL2SPECT_GREEN),
+            rgb_refl_2_spect_blue: from_table(&RGB_REFL2SPECT_BLUE),
+            rgb_illum_2_spect_white: from_table(&RGB_ILLUM2SPECT_WHITE),
+            rgb_illum_2_spect_cyan: from_table(&RGB_ILLUM2SPECT_CYAN),
+            rgb_illum_2_spect_magenta: from_table(&RGB_ILLUM2SPECT_MAGENTA),
+            rgb_illum_2_spect_yellow: from_table(&RGB_ILLUM2SPECT_YELLOW),
+            rgb_illum_2_spect_red: from_table(&RGB_ILLUM2SPECT_RED),
+            rgb_illum_2_spect_green: from_table(&RGB_ILLUM2SPECT_GREEN),
+            rgb_illum_2_spect_blue: from_table(&RGB_ILLUM2SPECT_BLUE),
+        }
+    }
+    fn to_xyz(&self, c: &[Float; N_SPECTRAL_SAMPLES], xyz: &mut [Float; 3]) {
+        xyz[0] = 0.0 as Float;
+        xyz[1] = 0.0 as Float;
+        xyz[2] = 0.0 as Float;
+        for (i, v) in c.iter().enumerate() {
+            xyz[0] += self.x[i] * v;
+            xyz[1] += self.y[i] * v;
+            xyz[2] += self.z[i] * v;
+        }
+        let scale: Float = (SAMPLED_LAMBDA_END - SAMPLED_LAMBDA_START)
+            / (CIE_Y_INTEGRAL * N_SPECTRAL_SAMPLES as Float);
+        xyz[0] *= scale;
+        xyz[1] *= scale;
+        xyz[2] *= scale;
+    }
+}
+
+lazy_static::lazy_static! {
+    static ref SAMPLED_SPECTRUM_TABLES: SampledSpectrumTables = SampledSpectrumTables::new();
+}
+
+/// Represents a spectrum by *N_SPECTRAL_SAMPLES* values, uniformly
+/// spaced between *SAMPLED_LAMBDA_START* and *SAMPLED_LAMBDA_END*
+/// nanometers. Used as **Spectrum** if the *sampled_spectrum* feature
+/// is enabled.
+#[derive(Debug, Copy, Clone)]
+pub struct SampledSpectrum {
+    pub c: [Float; N_SPECTRAL_SAMPLES],
+}
+
+impl Default for SampledSpectrum {
+    fn default() -> Self {
+        SampledSpectrum::new(0.0 as Float)
+    }
+}
+
+impl SampledSpectrum {
+    /// Number of spectral samples
+    pub const N_SAMPLES: usize = N_SPECTRAL_SAMPLES;
+    pub fn new(v: Float) -> Self {
+        SampledSpectrum {
+            c: [v; N_SPECTRAL_SAMPLES],
+        }
+    }
+    /// The wavelength range (in nm) covered by sample *i*.
+    pub fn wavelength_range(i: usize) -> (Float, Float) {
+        let wl0: Float = lerp(
+            i as Float / N_SPECTRAL_SAMPLES as Float,
+            SAMPLED_LAMBDA_START,
+            SAMPLED_LAMBDA_END,
+        );
+        let wl1: Float = lerp(
+            (i + 1) as Float / N_SPECTRAL_SAMPLES as Float,
+            SAMPLED_LAMBDA_START,
+            SAMPLED_LAMBDA_END,
+        );
+        (wl0, wl1)
+    }
+    /// The wavelength (in nm) at the center of sample *i*.
+    pub fn wavelength(i: usize) -> Float {
+        let (wl0, wl1) = SampledSpectrum::wavelength_range(i);
+        0.5 as Float * (wl0 + wl1)
+    }
+    pub fn rgb(r: Float, g: Float, b: Float) -> SampledSpectrum {
+        SampledSpectrum::from_rgb(&[r, g, b])
+    }
+    pub fn from_srgb(rgb: [u8; 3]) -> SampledSpectrum {
+        fn as_float(v: u8) -> Float {
+            v as Float / 255.0
+        }
+        SampledSpectrum::rgb(
+            inverse_gamma_convert_float(as_float(rgb[0])),
+            inverse_gamma_convert_float(as_float(rgb[1])),
+            inverse_gamma_convert_float(as_float(rgb[2])),
+        )
+    }
+    pub fn inverse_gamma_correct(&self) -> SampledSpectrum {
+        let mut rgb: [Float; 3] = [0.0 as Float; 3];
+        self.to_rgb(&mut rgb);
+        SampledSpectrum::rgb(
+            inverse_gamma_convert_float(rgb[0]),
+            inverse_gamma_convert_float(rgb[1]),
+            inverse_gamma_convert_float(rgb[2]),
+        )
+    }
+    pub fn from_rgb(rgb: &[Float; 3]) -> SampledSpectrum {
+        SampledSpectrum::from_rgb_type(rgb, SpectrumType::Reflectance)
+    }
+    /// Smits' decomposition of an RGB value into white, cyan, magenta,
+    /// yellow, red, green and blue spectra (see SampledSpectrum::FromRGB()
+    /// in spectrum.cpp).
+    pub fn from_rgb_type(rgb: &[Float; 3], spectrum_type: SpectrumType) -> SampledSpectrum {
+        let tables: &SampledSpectrumTables = &SAMPLED_SPECTRUM_TABLES;
+        let (white, cyan, magenta, yellow, red, green, blue, scale) = match spectrum_type {
+            SpectrumType::Reflectance => (
+                &tables.rgb_refl_2_spect_white,
+                &tables.rgb_refl_2_spect_cyan,
+                &tables.rgb_refl_2_spect_magenta,
+                &tables.rgb_refl_2_spect_yellow,
+                &tables.rgb_refl_2_spect_red,
+                &tables.rgb_refl_2_spect_green,
+                &tables.rgb_refl_2_spect_blue,
+                0.94 as Float,
+            ),
+            SpectrumType::Illuminant => (
+                &tables.rgb_illum_2_spect_white,
+                &tables.rgb_illum_2_spect_cyan,
+                &tables.rgb_illum_2_spect_magenta,
+                &tables.rgb_illum_2_spect_yellow,
+                &tables.rgb_illum_2_spect_red,
+                &tables.rgb_illum_2_spect_green,
+                &tables.rgb_illum_2_spect_blue,
+                0.86445 as Float,
+            ),
+        };
+        let mut r: SampledSpectrum = SampledSpectrum::new(0.0 as Float);
+        if rgb[0] <= rgb[1] && rgb[0] <= rgb[2] {
+            // compute _SampledSpectrum_ with _rgb[0]_ as minimum
+            r += *white * rgb[0];
+            if rgb[1] <= rgb[2] {
+                r += *cyan * (rgb[1] - rgb[0]);
+                r += *blue * (rgb[2] - rgb[1]);
+            } else {
+                r += *cyan * (rgb[2] - rgb[0]);
+                r += *green * (rgb[1] - rgb[2]);
+            }
+        } else if rgb[1] <= rgb[0] && rgb[1] <= rgb[2] {
+            // compute _SampledSpectrum_ with _rgb[1]_ as minimum
+            r += *white * rgb[1];
+            if rgb[0] <= rgb[2] {
+                r += *magenta * (rgb[0] - rgb[1]);
+                r += *blue * (rgb[2] - rgb[0]);
+            } else {
+                r += *magenta * (rgb[2] - rgb[1]);
+                r += *red * (rgb[0] - rgb[2]);
+            }
+        } else {
+            // compute _SampledSpectrum_ with _rgb[2]_ as minimum
+            r += *white * rgb[2];
+            if rgb[0] <= rgb[1] {
+                r += *yellow * (rgb[0] - rgb[2]);
+                r += *green * (rgb[1] - rgb[0]);
+            } else {
+                r += *yellow * (rgb[1] - rgb[2]);
+                r += *red * (rgb[0] - rgb[1]);
+            }
+        }
+        (r * scale).clamp(0.0 as Float, Float::INFINITY)
+    }
+    pub fn to_rgb(&self, rgb: &mut [Float; 3]) {
+        let mut xyz: [Float; 3] = [0.0 as Float; 3];
+        self.to_xyz(&mut xyz);
+        xyz_to_rgb(&xyz, rgb);
+    }
+    pub fn to_xyz(&self, xyz: &mut [Float; 3]) {
+        SAMPLED_SPECTRUM_TABLES.to_xyz(&self.c, xyz);
+    }
+    pub fn from_xyz(xyz: &[Float; 3], spectrum_type: SpectrumType) -> SampledSpectrum {
+        let mut rgb: [Float; 3] = [0.0 as Float; 3];
+        xyz_to_rgb(xyz, &mut rgb);
+        SampledSpectrum::from_rgb_type(&rgb, spectrum_type)
+    }
+    pub fn y(&self) -> Float {
+        let tables: &SampledSpectrumTables = &SAMPLED_SPECTRUM_TABLES;
+        let mut yy: Float = 0.0 as Float;
+        for i in 0..N_SPECTRAL_SAMPLES {
+            yy += tables.y[i] * self.c[i];
+        }
+        yy * (SAMPLED_LAMBDA_END - SAMPLED_LAMBDA_START)
+            / (CIE_Y_INTEGRAL * N_SPECTRAL_SAMPLES as Float)
+    }
+    pub fn from_sampled(lambda: &[Float], v: &[Float], n: i32) -> SampledSpectrum {
+        let mut r: SampledSpectrum = SampledSpectrum::new(0.0 as Float);
+        for i in 0..N_SPECTRAL_SAMPLES {
+            // compute average value of given SPD over $i$th sample's range
+            let (wl0, wl1) = SampledSpectrum::wavelength_range(i);
+            r.c[i] = average_spectrum_samples(lambda, v, n, wl0, wl1);
+        }
+        r
+    }
+    // from CoefficientSpectrum
+    pub fn is_black(&self) -> bool {
+        self.c.iter().all(|v| *v == 0.0 as Float)
+    }
+    pub fn sqrt(&self) -> SampledSpectrum {
+        let mut ret: SampledSpectrum = *self;
+        for v in ret.c.iter_mut() {
+            *v = v.sqrt();
+        }
+        ret
+    }
+    pub fn exp(&self) -> SampledSpectrum {
+        let mut ret: SampledSpectrum = *self;
+        for v in ret.c.iter_mut() {
+            *v = v.exp();
+        }
+        ret
+    }
+    /// Clamp spectrum to lie between the values low and high. Use
+    /// (0.0 as Float, std::f32::INFINITY as Float) if there are no
+    /// specific values.
+    pub fn clamp(&self, low: Float, high: Float) -> SampledSpectrum {
+        let mut ret: SampledSpectrum = *self;
+        for v in ret.c.iter_mut() {
+            *v = clamp_t(*v, low, high);
+        }
+        assert!(!ret.has_nans());
+        ret
+    }
+    pub fn max_component_value(&self) -> Float {
+        self.c.iter().fold(self.c[0], |m, v| m.max(*v))
+    }
+    pub fn has_nans(&self) -> bool {
+        self.c.iter().any(|v| v.is_nan())
+    }
+}
+
+impl PartialEq for SampledSpectrum {
+    fn eq(&self, rhs: &SampledSpectrum) -> bool {
+        self.c == rhs.c
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn add(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        self += rhs;
+        self
+    }
+}
+
+impl AddAssign for SampledSpectrum {
+    fn add_assign(&mut self, rhs: SampledSpectrum) {
+        for (a, b) in self.c.iter_mut().zip(rhs.c.iter()) {
+            *a += *b;
+        }
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        self *= rhs;
+        self
+    }
+}
+
+impl Mul<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn mul(mut self, rhs: Float) -> SampledSpectrum {
+        for a in self.c.iter_mut() {
+            *a *= rhs;
+        }
+        self
+    }
+}
+
+impl Mul<SampledSpectrum> for Float {
+    type Output = SampledSpectrum;
+    fn mul(self, rhs: SampledSpectrum) -> SampledSpectrum {
+        rhs * self
+    }
+}
+
+impl MulAssign for SampledSpectrum {
+    fn mul_assign(&mut self, rhs: SampledSpectrum) {
+        for (a, b) in self.c.iter_mut().zip(rhs.c.iter()) {
+            *a *= *b;
+        }
+    }
+}
+
+impl Sub for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn sub(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        for (a, b) in self.c.iter_mut().zip(rhs.c.iter()) {
+            *a -= *b;
+        }
+        self
+    }
+}
+
+impl Div for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn div(mut self, rhs: SampledSpectrum) -> SampledSpectrum {
+        for (a, b) in self.c.iter_mut().zip(rhs.c.iter()) {
+            *a /= *b;
+        }
+        self
+    }
+}
+
+impl Div<Float> for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn div(mut self, rhs: Float) -> SampledSpectrum {
+        self /= rhs;
+        self
+    }
+}
+
+impl DivAssign<Float> for SampledSpectrum {
+    fn div_assign(&mut self, rhs: Float) {
+        assert_ne!(rhs, 0.0 as Float);
+        assert!(!rhs.is_nan());
+        for a in self.c.iter_mut() {
+            *a /= rhs;
+        }
+    }
+}
+
+impl Neg for SampledSpectrum {
+    type Output = SampledSpectrum;
+    fn neg(self) -> SampledSpectrum {
+        self * -1.0 as Float
+    }
+}
+
+impl Zero for SampledSpectrum {
+    fn zero() -> SampledSpectrum {
+        SampledSpectrum::new(0.0 as Float)
+    }
+
+    fn is_zero(&self) -> bool {
+        self.is_black()
+    }
+}
+
+impl Index<usize> for SampledSpectrum {
+    type Output = Float;
+    fn index(&self, index: usize) -> &Float {
+        &self.c[index]
+    }
+}
+
+impl IndexMut<usize> for SampledSpectrum {
+    fn index_mut(&mut self, index: usize) -> &mut Float {
+        &mut self.c[index]
+    }
+}
+
+impl From<Float> for SampledSpectrum {
+    fn from(f: Float) -> Self {
+        SampledSpectrum::new(f)
+    }
+}
+
 /// Calculate RGB coefficients from a XYZ representation.
 pub fn xyz_to_rgb(xyz: &[Float; 3], rgb: &mut [Float; 3]) {
     rgb[0] = 3.240_479 * xyz[0] - 1.537_150 * xyz[1] - 0.498_535 * xyz[2];
@@ -1862,6 +2794,58 @@ pub fn interpolate_spectrum_samples(lambda: &[Float], vals: &[Float], n: i32, l:
     lerp(t, vals[offset], vals[offset + 1])
 }
 
+/// Average of the piecewise linear function given by the samples
+/// over the wavelength range [*lambda_start*, *lambda_end*].
+pub fn average_spectrum_samples(
+    lambda: &[Float],
+    vals: &[Float],
+    n: i32,
+    lambda_start: Float,
+    lambda_end: Float,
+) -> Float {
+    let n: usize = n as usize;
+    // handle cases with out-of-bounds range or single sample only
+    if lambda_end <= lambda[0] {
+        return vals[0];
+    }
+    if lambda_start >= lambda[n - 1] {
+        return vals[n - 1];
+    }
+    if n == 1 {
+        return vals[0];
+    }
+    let mut sum: Float = 0.0 as Float;
+    // add contributions of constant segments before/after samples
+    if lambda_start < lambda[0] {
+        sum += vals[0] * (lambda[0] - lambda_start);
+    }
+    if lambda_end > lambda[n - 1] {
+        sum += vals[n - 1] * (lambda_end - lambda[n - 1]);
+    }
+    // advance to first relevant wavelength segment
+    let mut i: usize = 0;
+    while lambda_start > lambda[i + 1] {
+        i += 1;
+    }
+    // loop over wavelength sample segments and add contributions
+    let interp = |w: Float, i: usize| -> Float {
+        lerp(
+            (w - lambda[i]) / (lambda[i + 1] - lambda[i]),
+            vals[i],
+            vals[i + 1],
+        )
+    };
+    while i + 1 < n && lambda_end >= lambda[i] {
+        let seg_lambda_start: Float = lambda_start.max(lambda[i]);
+        let seg_lambda_end: Float = lambda_end.min(lambda[i + 1]);
+        sum += 0.5 as Float
+            * (interp(seg_lambda_start, i) + interp(seg_lambda_end, i))
+            * (seg_lambda_end - seg_lambda_start);
+        i += 1;
+    }
+    sum / (lambda_end - lambda_start)
+}
+
 pub fn inverse_gamma_convert_float(v: Float) -> Float {
     if v <= 0.04045 {
         v / 12.92
@@ -855,7 +855,7 @@ impl Transform {
         // ret.bssrdf = si.bssrdf.clone();
         ret.primitive = None; // TODO? si.primitive;
         ret.shading.n = nrm_faceforward_nrm(&ret.shading.n, &ret.common.n);
-        // TODO: ret.faceIndex = si.faceIndex;
+        ret.face_index = si.face_index;
         *si = ret;
     }
 }
@@ -2078,6 +2078,12 @@ impl AnimatedTransform {
         // compute scale _S_ using rotation and original matrix
         *s = mtx_mul(&Matrix4x4::inverse(&r), m);
     }
+    pub fn is_animated(&self) -> bool {
+        self.actually_animated
+    }
+    pub fn time_range(&self) -> (Float, Float) {
+        (self.start_time, self.end_time)
+    }
     pub fn interpolate(&self, time: Float, t: &mut Transform) {
         // handle boundary conditions for matrix interpolation
         if !self.actually_animated || time <= self.start_time {
@@ -2201,6 +2207,72 @@ impl AnimatedTransform {
         }
         bounds
     }
+    /// Like **motion_bounds()**, but only for the motion between
+    /// |time0| and |time1|.
+    pub fn motion_bounds_between(&self, b: &Bounds3f, time0: Float, time1: Float) -> Bounds3f {
+        if !self.actually_animated || self.end_time <= self.start_time {
+            return self.motion_bounds(b);
+        }
+        if !self.has_rotation {
+            let mut t0: Transform = Transform::default();
+            let mut t1: Transform = Transform::default();
+            self.interpolate(time0, &mut t0);
+            self.interpolate(time1, &mut t1);
+            return bnd3_union_bnd3f(&t0.transform_bounds(b), &t1.transform_bounds(b));
+        }
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for corner in 0..8 {
+            bounds = bnd3_union_bnd3f(
+                &bounds,
+                &self.bound_point_motion_between(&b.corner(corner), time0, time1),
+            );
+        }
+        bounds
+    }
+    fn bound_point_motion_between(&self, p: &Point3f, time0: Float, time1: Float) -> Bounds3f {
+        let mut bounds: Bounds3f = Bounds3f::new(
+            self.transform_point(time0, p),
+            self.transform_point(time1, p),
+        );
+        let cos_theta: Float = quat_dot_quat(&self.r[0], &self.r[1]);
+        let theta: Float = clamp_t(cos_theta, -1.0 as Float, 1.0 as Float).acos();
+        // the zeros are found for the interpolation parameter
+        let duration: Float = self.end_time - self.start_time;
+        let u0: Float = clamp_t(
+            (time0 - self.start_time) / duration,
+            0.0 as Float,
+            1.0 as Float,
+        );
+        let u1: Float = clamp_t(
+            (time1 - self.start_time) / duration,
+            0.0 as Float,
+            1.0 as Float,
+        );
+        for c in 0..3 {
+            // find any motion derivative zeros for the component _c_
+            let mut zeros: [Float; 8] = [0.0 as Float; 8];
+            let mut n_zeros: u8 = 0;
+            interval_find_zeros(
+                self.c1[c].eval(p),
+                self.c2[c].eval(p),
+                self.c3[c].eval(p),
+                self.c4[c].eval(p),
+                self.c5[c].eval(p),
+                theta,
+                Interval::new(u0, u1),
+                &mut zeros,
+                &mut n_zeros,
+                8_usize,
+            );
+            // expand bounding box for any motion derivative zeros found
+            for item in zeros.iter().take(n_zeros as usize) {
+                let pz: Point3f =
+                    self.transform_point(lerp(*item, self.start_time, self.end_time), p);
+                bounds = bnd3_union_pnt3f(&bounds, &pz);
+            }
+        }
+        bounds
+    }
 }
 
 #[derive(Debug, Default, Copy, Clone)]
@@ -2343,7 +2415,9 @@ pub fn interval_find_zeros(
             }
             t_newton -= f_newton / f_prime_newton;
         }
-        if t_newton >= t_interval.low - 1e-3 as Float && t_newton < t_interval.high + 1e-3 as Float
+        if t_newton >= t_interval.low - 1e-3 as Float
+            && t_newton < t_interval.high + 1e-3 as Float
+            && (*zero_count as usize) < zeros.len()
         {
             zeros[*zero_count as usize] = t_newton;
             *zero_count += 1;
@@ -4,6 +4,7 @@ use std::sync::Arc;
 use crate::core::camera::Camera;
 use crate::core::geometry::{nrm_cross_vec3, nrm_faceforward_vec3, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Point2f, Ray, Vector3f};
+use crate::core::integrator::AovSample;
 use crate::core::interaction::{Interaction, SurfaceInteraction};
 use crate::core::material::TransportMode;
 use crate::core::pbrt::{Float, Spectrum};
@@ -54,6 +55,7 @@ impl AOIntegrator {
         sampler: &mut Sampler,
         // arena: &mut Arena,
         _depth: i32,
+        aov: &mut Option<AovSample>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
@@ -61,6 +63,9 @@ impl AOIntegrator {
         if scene.intersect(ray, &mut isect) {
             let mode: TransportMode = TransportMode::Radiance;
             isect.compute_scattering_functions(ray, true, mode);
+            if let Some(aov) = aov {
+                aov.record(&isect);
+            }
             // if (!isect.bsdf) {
             //     VLOG(2) << "Skipping intersection due to null bsdf";
             //     ray = isect.SpawnRay(ray.d);
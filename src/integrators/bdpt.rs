@@ -5,6 +5,9 @@ use std::sync::Arc;
 // pbrt
 use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
+use crate::core::checkpoint::{load_completed_tiles, remove_checkpoint};
+use crate::core::checkpoint::{CheckpointSettings, TileCheckpointer};
+use crate::core::film::FilmTile;
 use crate::core::geometry::{
     nrm_abs_dot_vec3f, pnt2_inside_exclusivei, pnt3_offset_ray_origin, vec3_abs_dot_nrmf,
     vec3_dot_nrmf,
@@ -18,6 +21,7 @@ use crate::core::interaction::{
 use crate::core::light::is_delta_light;
 use crate::core::light::{Light, LightFlags, VisibilityTester};
 use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::LightDistribution;
 use crate::core::material::TransportMode;
 use crate::core::medium::{HenyeyGreenstein, Medium, MediumInterface};
 use crate::core::pbrt::{Float, Spectrum};
@@ -846,7 +850,7 @@ impl BDPTIntegrator {
     pub fn get_light_sample_strategy(&self) -> String {
         self.light_sample_strategy.clone()
     }
-    pub fn render(&self, scene: &Scene, num_threads: u8) {
+    pub fn render(&self, scene: &Scene, num_threads: u8, checkpoint: &CheckpointSettings) {
         // TODO
         // Compute a reverse mapping from light pointers to offsets into
         // the scene lights vector (and, equivalently, offsets into
@@ -877,7 +881,7 @@ impl BDPTIntegrator {
             };
             println!("Rendering with {:?} thread(s) ...", num_cores);
             {
-                let block_queue = BlockQueue::new(
+                let mut block_queue = BlockQueue::new(
                     (
                         (n_x_tiles * tile_size) as u32,
                         (n_y_tiles * tile_size) as u32,
@@ -885,192 +889,220 @@ impl BDPTIntegrator {
                     (tile_size as u32, tile_size as u32),
                     (0, 0),
                 );
-                let bq = &block_queue;
-                let integrator = &self;
-                let sampler = &self.get_sampler();
-                let camera = &self.get_camera();
+                let completed: Vec<(u32, u32)> = load_completed_tiles(&film, checkpoint);
+                block_queue.remove_blocks(&completed);
                 let film = &film;
-                // let pixel_bounds = integrator.get_pixel_bounds().clone();
-                crossbeam::scope(|scope| {
-                    let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
-                    // spawn worker threads
-                    for _ in 0..num_cores {
-                        let pixel_tx = pixel_tx.clone();
-                        scope.spawn(move |_| {
-                            while let Some((x, y)) = bq.next() {
-                                let tile: Point2i = Point2i {
-                                    x: x as i32,
-                                    y: y as i32,
-                                };
-                                let seed: i32 = tile.y * n_x_tiles + tile.x;
-                                let mut tile_sampler: Box<Sampler> =
-                                    sampler.clone_with_seed(seed as u64);
-                                let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
-                                let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
-                                let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
-                                let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
-                                let tile_bounds: Bounds2i = Bounds2i::new(
-                                    Point2i { x: x0, y: y0 },
-                                    Point2i { x: x1, y: y1 },
-                                );
-                                // println!("Starting image tile {:?}", tile_bounds);
-                                let mut film_tile = film.get_film_tile(&tile_bounds);
-                                for p_pixel in &tile_bounds {
-                                    tile_sampler.start_pixel(p_pixel);
-                                    if !pnt2_inside_exclusivei(p_pixel, &integrator.pixel_bounds) {
-                                        continue;
-                                    }
-                                    let mut done: bool = false;
-                                    while !done {
-                                        // Get a distribution for sampling
-                                        // the light at the start of the
-                                        // light subpath. Because the
-                                        // light path follows multiple
-                                        // bounces, basing the sampling
-                                        // distribution on any of the
-                                        // vertices of the camera path is
-                                        // unlikely to be a good
-                                        // strategy. We use the
-                                        // PowerLightDistribution by
-                                        // default here, which doesn't use
-                                        // the point passed to it. Now
-                                        // trace the light subpath
-                                        if let Some(light_distribution) =
-                                            create_light_sample_distribution(
-                                                integrator.get_light_sample_strategy(),
-                                                scene,
-                                            )
+                let mut checkpointer = TileCheckpointer::new(film, checkpoint, completed);
+                self.render_tiles(
+                    scene,
+                    num_threads,
+                    &block_queue,
+                    |block, film_tile, splats| {
+                        // merge image tile into _Film_
+                        film.merge_film_tile(film_tile);
+                        for (p_film, lpath) in splats.iter() {
+                            film.add_splat(*p_film, lpath);
+                        }
+                        checkpointer.tile_done(block);
+                    },
+                );
+            }
+            film.write_image(1.0 as Float / samples_per_pixel as Float);
+            remove_checkpoint(&film, checkpoint);
+            // TODO: Write buffers for debug visualization
+        }
+    }
+    /// Renders the tiles of a [BlockQueue](../../blockqueue/struct.BlockQueue.html)
+    /// and hands every finished tile together with the splats of its
+    /// light paths (*t == 1*) to *collect*, which is called from a
+    /// single thread. The scene needs at least one light.
+    pub fn render_tiles<F>(&self, scene: &Scene, num_threads: u8, bq: &BlockQueue, collect: F)
+    where
+        F: FnMut((u32, u32), &FilmTile, &[(Point2f, Spectrum)]) + Send,
+    {
+        let film = self.get_camera().get_film();
+        let sample_bounds: Bounds2i = film.get_sample_bounds();
+        let sample_extent: Vector2i = sample_bounds.diagonal();
+        let tile_size: i32 = 16;
+        let n_x_tiles: i32 = (sample_extent.x + tile_size - 1) / tile_size;
+        let num_cores = if num_threads == 0_u8 {
+            num_cpus::get()
+        } else {
+            num_threads as usize
+        };
+        let mut collect = collect;
+        let integrator = &self;
+        let sampler = &self.get_sampler();
+        let camera = &self.get_camera();
+        let film = &film;
+        // let pixel_bounds = integrator.get_pixel_bounds().clone();
+        // Get a distribution for sampling the light at the start of
+        // the light subpath. Because the light path follows multiple
+        // bounces, basing the sampling distribution on any of the
+        // vertices of the camera path is unlikely to be a good
+        // strategy. We use the PowerLightDistribution by default
+        // here, which doesn't use the point passed to it. The light
+        // BVH would have to be walked over all lights for every
+        // sample to get a distribution, so the power distribution is
+        // used instead of it.
+        let mut light_sample_strategy: String = self.get_light_sample_strategy();
+        if light_sample_strategy == "bvh" {
+            light_sample_strategy = String::from("power");
+        }
+        let light_distribution: Arc<LightDistribution> =
+            create_light_sample_distribution(light_sample_strategy, scene)
+                .expect("light sample distribution");
+        let light_distribution = &light_distribution;
+        crossbeam::scope(|scope| {
+            let (pixel_tx, pixel_rx) = crossbeam_channel::bounded(num_cores);
+            // spawn worker threads
+            for _ in 0..num_cores {
+                let pixel_tx = pixel_tx.clone();
+                scope.spawn(move |_| {
+                    while let Some((x, y)) = bq.next() {
+                        let tile: Point2i = Point2i {
+                            x: x as i32,
+                            y: y as i32,
+                        };
+                        let seed: i32 = tile.y * n_x_tiles + tile.x;
+                        let mut tile_sampler: Box<Sampler> = sampler.clone_with_seed(seed as u64);
+                        let x0: i32 = sample_bounds.p_min.x + tile.x * tile_size;
+                        let x1: i32 = std::cmp::min(x0 + tile_size, sample_bounds.p_max.x);
+                        let y0: i32 = sample_bounds.p_min.y + tile.y * tile_size;
+                        let y1: i32 = std::cmp::min(y0 + tile_size, sample_bounds.p_max.y);
+                        let tile_bounds: Bounds2i =
+                            Bounds2i::new(Point2i { x: x0, y: y0 }, Point2i { x: x1, y: y1 });
+                        // println!("Starting image tile {:?}", tile_bounds);
+                        let mut film_tile = film.get_film_tile(&tile_bounds);
+                        // splats are added with the tile, so a
+                        // checkpoint never holds splats of
+                        // unfinished tiles
+                        let mut splats: Vec<(Point2f, Spectrum)> = Vec::new();
+                        for p_pixel in &tile_bounds {
+                            tile_sampler.start_pixel(p_pixel);
+                            if !pnt2_inside_exclusivei(p_pixel, &integrator.pixel_bounds) {
+                                continue;
+                            }
+                            let mut done: bool = false;
+                            while !done {
+                                // generate a single sample using BDPT
+                                let p_film: Point2f = Point2f {
+                                    x: p_pixel.x as Float,
+                                    y: p_pixel.y as Float,
+                                } + tile_sampler.get_2d();
+                                // trace the camera subpath
+                                let mut camera_vertices: Vec<Vertex> =
+                                    Vec::with_capacity((integrator.max_depth + 2) as usize);
+                                let n_camera;
+                                let p;
+                                let time;
+                                {
+                                    let (n_camera_new, p_new, time_new) = generate_camera_subpath(
+                                        scene,
+                                        &mut tile_sampler,
+                                        integrator.max_depth + 2,
+                                        camera,
+                                        p_film,
+                                        &mut camera_vertices,
+                                    );
+                                    n_camera = n_camera_new;
+                                    p = p_new;
+                                    time = time_new;
+                                }
+                                let light_distr: Arc<Distribution1D> =
+                                    light_distribution.lookup(&p);
+                                let mut light_vertices: Vec<Vertex> =
+                                    Vec::with_capacity((integrator.max_depth + 1) as usize);
+                                let n_light;
+                                {
+                                    n_light = generate_light_subpath(
+                                        scene,
+                                        &mut tile_sampler,
+                                        integrator.max_depth + 1,
+                                        time,
+                                        light_distr.clone(),
+                                        // light_to_index,
+                                        &mut light_vertices,
+                                    );
+                                }
+                                // Execute all BDPT connection strategies
+                                let mut l: Spectrum = Spectrum::new(0.0 as Float);
+                                // println!("n_camera = {:?}", n_camera);
+                                // println!("n_light = {:?}", n_light);
+                                for t in 1..=n_camera {
+                                    for s in 0..=n_light {
+                                        // int depth = t + s - 2;
+                                        let depth: isize = (t + s) as isize - 2;
+                                        if (s == 1 && t == 1)
+                                            || depth < 0
+                                            || depth > integrator.max_depth as isize
                                         {
-                                            // generate a single sample using BDPT
-                                            let p_film: Point2f = Point2f {
-                                                x: p_pixel.x as Float,
-                                                y: p_pixel.y as Float,
-                                            } + tile_sampler.get_2d();
-                                            // trace the camera subpath
-                                            let mut camera_vertices: Vec<Vertex> =
-                                                Vec::with_capacity(
-                                                    (integrator.max_depth + 2) as usize,
-                                                );
-                                            let n_camera;
-                                            let p;
-                                            let time;
-                                            {
-                                                let (n_camera_new, p_new, time_new) =
-                                                    generate_camera_subpath(
-                                                        scene,
-                                                        &mut tile_sampler,
-                                                        integrator.max_depth + 2,
-                                                        camera,
-                                                        p_film,
-                                                        &mut camera_vertices,
-                                                    );
-                                                n_camera = n_camera_new;
-                                                p = p_new;
-                                                time = time_new;
-                                            }
-                                            let light_distr: Arc<Distribution1D> =
-                                                light_distribution.lookup(&p);
-                                            let mut light_vertices: Vec<Vertex> =
-                                                Vec::with_capacity(
-                                                    (integrator.max_depth + 1) as usize,
-                                                );
-                                            let n_light;
-                                            {
-                                                n_light = generate_light_subpath(
-                                                    scene,
-                                                    &mut tile_sampler,
-                                                    integrator.max_depth + 1,
-                                                    time,
-                                                    light_distr.clone(),
-                                                    // light_to_index,
-                                                    &mut light_vertices,
-                                                );
-                                            }
-                                            // Execute all BDPT connection strategies
-                                            let mut l: Spectrum = Spectrum::new(0.0 as Float);
-                                            // println!("n_camera = {:?}", n_camera);
-                                            // println!("n_light = {:?}", n_light);
-                                            for t in 1..=n_camera {
-                                                for s in 0..=n_light {
-                                                    // int depth = t + s - 2;
-                                                    let depth: isize = (t + s) as isize - 2;
-                                                    if (s == 1 && t == 1)
-                                                        || depth < 0
-                                                        || depth > integrator.max_depth as isize
-                                                    {
-                                                        continue;
-                                                    }
-                                                    // execute the $(s, t)$ connection strategy and update _L_
-                                                    let mut p_film_new: Point2f = Point2f {
-                                                        x: p_film.x,
-                                                        y: p_film.y,
-                                                    };
-                                                    let mut mis_weight: Option<Float> =
-                                                        Some(0.0 as Float);
-                                                    let lpath: Spectrum = connect_bdpt(
-                                                        scene,
-                                                        &light_vertices,
-                                                        &camera_vertices,
-                                                        s,
-                                                        t,
-                                                        light_distr.clone(),
-                                                        camera,
-                                                        &mut tile_sampler,
-                                                        &mut p_film_new,
-                                                        mis_weight.as_mut(),
-                                                    );
-                                                    // if let Some(mis_weight_flt) = mis_weight {
-                                                    //     println!("Connect bdpt s: {:?}, t: {:?}, lpath: {:?}, mis_weight: {:?}",
-                                                    //              s, t, lpath, mis_weight_flt);
-                                                    // }
-                                                    // if (visualizeStrategies || visualizeWeights) {
-                                                    //     Spectrum value;
-                                                    //     if (visualizeStrategies)
-                                                    //         value =
-                                                    //             mis_weight == 0 ? 0 : lpath / mis_weight;
-                                                    //     if (visualizeWeights) value = lpath;
-                                                    //     weightFilms[BufferIndex(s, t)]->AddSplat(
-                                                    //         pFilmNew, value);
-                                                    // }
-                                                    if t != 1 {
-                                                        l += lpath;
-                                                    } else if !lpath.is_black() {
-                                                        film.add_splat(p_film_new, &lpath);
-                                                    }
-                                                }
-                                            }
-                                            // println!(
-                                            //     "Add film sample pFilm: {:?}, L: {:?}, (y: {:?})",
-                                            //     p_film,
-                                            //     l,
-                                            //     l.y()
-                                            // );
-                                            film_tile.add_sample(p_film, &mut l, 1.0 as Float);
-                                            done = !tile_sampler.start_next_sample();
+                                            continue;
+                                        }
+                                        // execute the $(s, t)$ connection strategy and update _L_
+                                        let mut p_film_new: Point2f = Point2f {
+                                            x: p_film.x,
+                                            y: p_film.y,
+                                        };
+                                        let mut mis_weight: Option<Float> = Some(0.0 as Float);
+                                        let lpath: Spectrum = connect_bdpt(
+                                            scene,
+                                            &light_vertices,
+                                            &camera_vertices,
+                                            s,
+                                            t,
+                                            light_distr.clone(),
+                                            camera,
+                                            &mut tile_sampler,
+                                            &mut p_film_new,
+                                            mis_weight.as_mut(),
+                                        );
+                                        // if let Some(mis_weight_flt) = mis_weight {
+                                        //     println!("Connect bdpt s: {:?}, t: {:?}, lpath: {:?}, mis_weight: {:?}",
+                                        //              s, t, lpath, mis_weight_flt);
+                                        // }
+                                        // if (visualizeStrategies || visualizeWeights) {
+                                        //     Spectrum value;
+                                        //     if (visualizeStrategies)
+                                        //         value =
+                                        //             mis_weight == 0 ? 0 : lpath / mis_weight;
+                                        //     if (visualizeWeights) value = lpath;
+                                        //     weightFilms[BufferIndex(s, t)]->AddSplat(
+                                        //         pFilmNew, value);
+                                        // }
+                                        if t != 1 {
+                                            l += lpath;
+                                        } else if !lpath.is_black() {
+                                            splats.push((p_film_new, lpath));
                                         }
                                     }
                                 }
-                                // send the tile through the channel to main thread
-                                pixel_tx
-                                    .send(film_tile)
-                                    .unwrap_or_else(|_| panic!("Failed to send tile"));
+                                // println!(
+                                //     "Add film sample pFilm: {:?}, L: {:?}, (y: {:?})",
+                                //     p_film,
+                                //     l,
+                                //     l.y()
+                                // );
+                                film_tile.add_sample(p_film, &mut l, 1.0 as Float);
+                                done = !tile_sampler.start_next_sample();
                             }
-                        });
-                    }
-                    // spawn thread to collect pixels and render image to file
-                    scope.spawn(move |_| {
-                        for _ in pbr::PbIter::new(0..bq.len()) {
-                            let film_tile = pixel_rx.recv().unwrap();
-                            // merge image tile into _Film_
-                            film.merge_film_tile(&film_tile);
                         }
-                    });
-                })
-                .unwrap();
+                        // send the tile through the channel to main thread
+                        pixel_tx
+                            .send(((x, y), film_tile, splats))
+                            .unwrap_or_else(|_| panic!("Failed to send tile"));
+                    }
+                });
             }
-            film.write_image(1.0 as Float / samples_per_pixel as Float);
-            // TODO: Write buffers for debug visualization
-        }
+            // spawn thread to collect pixels and render image to file
+            scope.spawn(move |_| {
+                for _ in pbr::PbIter::new(0..bq.len()) {
+                    let (block, film_tile, splats) = pixel_rx.recv().unwrap();
+                    collect(block, &film_tile, &splats);
+                }
+            });
+        })
+        .unwrap();
     }
     pub fn get_camera(&self) -> Arc<Camera> {
         self.camera.clone()
@@ -2329,6 +2361,9 @@ pub fn connect_bdpt<'a>(
                         iref.p_error = si.common.p_error;
                         iref.wo = si.common.wo;
                         iref.n = si.common.n;
+                        if let Some(ref medium_interface_arc) = si.common.medium_interface {
+                            iref.medium_interface = Some(medium_interface_arc.clone())
+                        }
                     }
                 }
                 _ => {
@@ -2338,6 +2373,9 @@ pub fn connect_bdpt<'a>(
                         iref.p_error = ei.common.p_error;
                         iref.wo = ei.common.wo;
                         iref.n = ei.common.n;
+                        if let Some(ref medium_interface_arc) = ei.common.medium_interface {
+                            iref.medium_interface = Some(medium_interface_arc.clone())
+                        }
                     }
                 }
             }
@@ -4,7 +4,7 @@ use std::sync::Arc;
 use crate::core::camera::Camera;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Ray, RayDifferential, Vector3f};
-use crate::core::integrator::{uniform_sample_all_lights, uniform_sample_one_light};
+use crate::core::integrator::{uniform_sample_all_lights, uniform_sample_one_light, AovSample};
 use crate::core::interaction::{Interaction, SurfaceInteraction};
 use crate::core::material::TransportMode;
 use crate::core::pbrt::{Float, Spectrum};
@@ -75,6 +75,7 @@ impl DirectLightingIntegrator {
         sampler: &mut Sampler,
         // arena: &mut Arena,
         depth: i32,
+        aov: &mut Option<AovSample>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::new(0.0 as Float);
@@ -84,8 +85,11 @@ impl DirectLightingIntegrator {
             // compute scattering functions for surface interaction
             let mode: TransportMode = TransportMode::Radiance;
             isect.compute_scattering_functions(ray, false, mode);
+            if let Some(aov) = aov {
+                aov.record(&isect);
+            }
             if isect.bsdf.is_none() {
-                return self.li(&isect.spawn_ray(&ray.d), scene, sampler, depth);
+                return self.li(&isect.spawn_ray(&ray.d), scene, sampler, depth, aov);
             }
             let wo: Vector3f = isect.common.wo;
             l += isect.le(&wo);
@@ -181,7 +185,7 @@ impl DirectLightingIntegrator {
                     };
                     rd.differential = Some(diff);
                 }
-                f * self.li(&rd, scene, sampler, depth + 1)
+                f * self.li(&rd, scene, sampler, depth + 1, &mut None)
                     * Spectrum::new(vec3_abs_dot_nrmf(&wi, &ns) / pdf)
             } else {
                 Spectrum::new(0.0)
@@ -248,7 +252,7 @@ impl DirectLightingIntegrator {
                     };
                     rd.differential = Some(diff);
                 }
-                f * self.li(&rd, scene, sampler, depth + 1)
+                f * self.li(&rd, scene, sampler, depth + 1, &mut None)
                     * Spectrum::new(vec3_abs_dot_nrmf(&wi, &ns) / pdf)
             } else {
                 Spectrum::new(0.0)
@@ -0,0 +1,714 @@
+//! Path tracing with path guiding, following Müller et al.,
+//! "Practical Path Guiding for Efficient Light-Transport Simulation"
+//! (2017). A spatial binary tree over the scene bounds stores a
+//! directional distribution of the incident radiance in each leaf
+//! (SD-tree). Instead of adaptive quadtrees the leaves hold histograms
+//! over the sphere, sampled with a **Distribution2D**, and surfaces
+//! facing different directions use separate trees. The distributions
+//! are learned in training passes of 1, 2, 4, ... samples per pixel
+//! before the image is rendered, and are combined with BSDF sampling
+//! by one-sample multiple importance sampling.
+
+// std
+use std::cell::Cell;
+use std::f32::consts::PI;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+// others
+use rayon::prelude::*;
+use smallvec::SmallVec;
+// pbrt
+use crate::core::camera::{Camera, CameraSample};
+use crate::core::geometry::{nrm_faceforward_vec3, spherical_direction, spherical_phi};
+use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
+use crate::core::geometry::{Bounds2i, Bounds3f, Normal3f, Point2f, Point2i, Point3f, Ray};
+use crate::core::geometry::{Vector3f, XYZEnum};
+use crate::core::integrator::{sample_one_light, AovSample};
+use crate::core::interaction::{Interaction, SurfaceInteraction};
+use crate::core::lightdistrib::create_light_sample_distribution;
+use crate::core::lightdistrib::LightDistribution;
+use crate::core::material::TransportMode;
+use crate::core::parallel::AtomicFloat;
+use crate::core::pbrt::{clamp_t, Float, Spectrum};
+use crate::core::reflection::{Bsdf, BxdfType};
+use crate::core::sampler::Sampler;
+use crate::core::sampling::Distribution2D;
+use crate::core::scene::Scene;
+use crate::samplers::random::RandomSampler;
+
+/// Resolution of the directional histograms (per dimension)
+const DIRECTIONAL_RESOLUTION: usize = 16;
+/// A leaf is split once it received more than c * sqrt(spp) samples
+/// in a training pass of spp samples per pixel
+const SPATIAL_SPLIT_FACTOR: Float = 2000.0;
+/// Leaves with fewer samples (per bin) are not guided
+const MIN_SAMPLES_PER_BIN: u64 = 4;
+const MAX_SPATIAL_DEPTH: u32 = 32;
+
+/// Maps a direction to the unit square, using the cosine of the
+/// polar angle and the azimuth (which preserves areas).
+fn direction_to_canonical(d: &Vector3f) -> Point2f {
+    let cos_theta: Float = clamp_t(d.z, -1.0 as Float, 1.0 as Float);
+    Point2f {
+        x: (cos_theta + 1.0 as Float) * 0.5 as Float,
+        y: spherical_phi(d) / (2.0 as Float * PI),
+    }
+}
+
+fn canonical_to_direction(p: Point2f) -> Vector3f {
+    let cos_theta: Float = 2.0 as Float * p.x - 1.0 as Float;
+    let sin_theta: Float = (1.0 as Float - cos_theta * cos_theta)
+        .max(0.0 as Float)
+        .sqrt();
+    spherical_direction(sin_theta, cos_theta, 2.0 as Float * PI * p.y)
+}
+
+/// The directional part of an SD-tree leaf: a piecewise constant
+/// distribution over the sphere used for sampling, and the radiance
+/// recorded during the current training pass.
+pub struct DirectionalDistribution {
+    sampling: Option<Distribution2D>,
+    flux: Vec<AtomicFloat>,
+    n_samples: AtomicU64,
+}
+
+impl Default for DirectionalDistribution {
+    fn default() -> Self {
+        DirectionalDistribution {
+            sampling: None,
+            flux: (0..DIRECTIONAL_RESOLUTION * DIRECTIONAL_RESOLUTION)
+                .map(|_| AtomicFloat::default())
+                .collect(),
+            n_samples: AtomicU64::new(0),
+        }
+    }
+}
+
+impl DirectionalDistribution {
+    pub fn sample(&self, u: Point2f, pdf: &mut Float) -> Option<Vector3f> {
+        let sampling: &Distribution2D = self.sampling.as_ref()?;
+        let mut map_pdf: Float = 0.0 as Float;
+        let p: Point2f = sampling.sample_continuous(u, &mut map_pdf);
+        // the mapping covers 4 * PI steradians
+        *pdf = map_pdf / (4.0 as Float * PI);
+        Some(canonical_to_direction(p))
+    }
+    pub fn pdf(&self, w: &Vector3f) -> Float {
+        match self.sampling {
+            Some(ref sampling) => sampling.pdf(direction_to_canonical(w)) / (4.0 as Float * PI),
+            None => 0.0 as Float,
+        }
+    }
+    pub fn record(&self, w: &Vector3f, value: Float) {
+        let p: Point2f = direction_to_canonical(w);
+        let res: usize = DIRECTIONAL_RESOLUTION;
+        let iu: usize = std::cmp::min((p.x * res as Float) as usize, res - 1);
+        let iv: usize = std::cmp::min((p.y * res as Float) as usize, res - 1);
+        if value > 0.0 as Float && value.is_finite() {
+            self.flux[iv * res + iu].add(value);
+        }
+        self.n_samples.fetch_add(1, Ordering::Relaxed);
+    }
+    /// Replaces the sampling distribution by the radiance recorded in
+    /// the last pass. The previous distribution is kept if too few
+    /// samples were recorded to learn a reliable one.
+    fn build(&mut self) {
+        let func: Vec<Float> = self.flux.iter().map(Float::from).collect();
+        let min_samples: u64 = MIN_SAMPLES_PER_BIN * func.len() as u64;
+        if self.n_samples.load(Ordering::Relaxed) >= min_samples
+            && func.iter().any(|f| *f > 0.0 as Float)
+        {
+            let res: i32 = DIRECTIONAL_RESOLUTION as i32;
+            self.sampling = Some(Distribution2D::new(func, res, res));
+        }
+    }
+    fn reset(&mut self) {
+        for flux in self.flux.iter_mut() {
+            *flux = AtomicFloat::default();
+        }
+        self.n_samples.store(0, Ordering::Relaxed);
+    }
+    fn split(&self) -> DirectionalDistribution {
+        let n_samples: u64 = self.n_samples.load(Ordering::Relaxed) / 2;
+        self.n_samples.store(n_samples, Ordering::Relaxed);
+        DirectionalDistribution {
+            sampling: self.sampling.clone(),
+            n_samples: AtomicU64::new(n_samples),
+            ..Default::default()
+        }
+    }
+}
+
+fn split_axis(bounds: &Bounds3f) -> XYZEnum {
+    match bounds.maximum_extent() {
+        0 => XYZEnum::X,
+        1 => XYZEnum::Y,
+        _ => XYZEnum::Z,
+    }
+}
+
+/// Number of separate spatial trees, one per dominant axis (and sign)
+/// of the surface normal.
+const N_ORIENTATIONS: usize = 6;
+
+/// Surfaces facing different directions see different hemispheres
+/// of incident radiance, so they don't share a distribution.
+fn orientation(n: &Normal3f) -> usize {
+    let (axis, value): (usize, Float) = if n.x.abs() > n.y.abs() && n.x.abs() > n.z.abs() {
+        (0, n.x)
+    } else if n.y.abs() > n.z.abs() {
+        (1, n.y)
+    } else {
+        (2, n.z)
+    };
+    2 * axis + (value < 0.0 as Float) as usize
+}
+
+struct SpatialNode {
+    bounds: Bounds3f,
+    depth: u32,
+    /// Index of the first child (the second child follows), or None
+    /// for leaves
+    children: Option<usize>,
+    axis: XYZEnum,
+    /// Index into the directional distributions (leaves only)
+    leaf: usize,
+}
+
+/// A binary tree over the scene bounds with a
+/// [DirectionalDistribution](struct.DirectionalDistribution.html) in
+/// each leaf. Nodes are split at the midpoint of their longest axis.
+pub struct SDTree {
+    nodes: Vec<SpatialNode>,
+    leaves: Vec<DirectionalDistribution>,
+}
+
+impl SDTree {
+    pub fn new(bounds: &Bounds3f) -> Self {
+        SDTree {
+            nodes: (0..N_ORIENTATIONS)
+                .map(|i| SpatialNode {
+                    bounds: *bounds,
+                    depth: 0,
+                    children: None,
+                    axis: split_axis(bounds),
+                    leaf: i,
+                })
+                .collect(),
+            leaves: (0..N_ORIENTATIONS)
+                .map(|_| DirectionalDistribution::default())
+                .collect(),
+        }
+    }
+    /// Returns the distribution for a point *p* on a surface, *n* is
+    /// the surface normal facing the side the path is on.
+    pub fn lookup(&self, p: &Point3f, n: &Normal3f) -> &DirectionalDistribution {
+        let mut node: &SpatialNode = &self.nodes[orientation(n)];
+        while let Some(first_child) = node.children {
+            let axis: XYZEnum = node.axis;
+            let split: Float = (node.bounds.p_min[axis] + node.bounds.p_max[axis]) * 0.5 as Float;
+            node = if p[axis] < split {
+                &self.nodes[first_child]
+            } else {
+                &self.nodes[first_child + 1]
+            };
+        }
+        &self.leaves[node.leaf]
+    }
+    /// Called after each training pass of *spp* samples per pixel:
+    /// builds the sampling distributions from the recorded radiance
+    /// and splits leaves which received many samples.
+    pub fn update(&mut self, spp: i64) {
+        for leaf in self.leaves.iter_mut() {
+            leaf.build();
+        }
+        let threshold: u64 = (SPATIAL_SPLIT_FACTOR * (spp as Float).sqrt()) as u64;
+        // new children are appended and checked again
+        let mut i: usize = 0;
+        while i < self.nodes.len() {
+            let leaf: usize = self.nodes[i].leaf;
+            if self.nodes[i].children.is_none()
+                && self.nodes[i].depth < MAX_SPATIAL_DEPTH
+                && self.leaves[leaf].n_samples.load(Ordering::Relaxed) > threshold
+            {
+                let bounds: Bounds3f = self.nodes[i].bounds;
+                let axis: XYZEnum = self.nodes[i].axis;
+                let split: Float = (bounds.p_min[axis] + bounds.p_max[axis]) * 0.5 as Float;
+                let mut lower: Bounds3f = bounds;
+                lower.p_max[axis] = split;
+                let mut upper: Bounds3f = bounds;
+                upper.p_min[axis] = split;
+                let new_leaf: DirectionalDistribution = self.leaves[leaf].split();
+                self.leaves.push(new_leaf);
+                let first_child: usize = self.nodes.len();
+                let depth: u32 = self.nodes[i].depth + 1;
+                self.nodes.push(SpatialNode {
+                    bounds: lower,
+                    depth,
+                    children: None,
+                    axis: split_axis(&lower),
+                    leaf,
+                });
+                self.nodes.push(SpatialNode {
+                    bounds: upper,
+                    depth,
+                    children: None,
+                    axis: split_axis(&upper),
+                    leaf: self.leaves.len() - 1,
+                });
+                self.nodes[i].children = Some(first_child);
+            }
+            i += 1;
+        }
+        for leaf in self.leaves.iter_mut() {
+            leaf.reset();
+        }
+    }
+    pub fn num_leaves(&self) -> usize {
+        self.leaves.len()
+    }
+}
+
+/// A path vertex whose direction was sampled with guiding; collects
+/// the radiance arriving from that direction during training.
+struct GuidingVertex<'a> {
+    distribution: &'a DirectionalDistribution,
+    wi: Vector3f,
+    throughput: Spectrum,
+    radiance: Spectrum,
+    wi_pdf: Float,
+}
+
+impl<'a> GuidingVertex<'a> {
+    fn add(&mut self, l: &Spectrum) {
+        for i in 0..Spectrum::N_SAMPLES {
+            if self.throughput[i] > 0.0 as Float {
+                self.radiance[i] += l[i] / self.throughput[i];
+            }
+        }
+    }
+    fn record(&self) {
+        if self.wi_pdf > 0.0 as Float {
+            self.distribution
+                .record(&self.wi, self.radiance.y().max(0.0 as Float) / self.wi_pdf);
+        }
+    }
+}
+
+fn add_radiance(vertices: &mut [GuidingVertex], l: &Spectrum) {
+    for vertex in vertices.iter_mut() {
+        vertex.add(l);
+    }
+}
+
+// see path.h
+
+/// Guided Path Tracing - uses the render loop of a
+/// [SamplerIntegrator](../../core/integrator/enum.SamplerIntegrator.html)
+pub struct GuidedPathIntegrator {
+    // inherited from SamplerIntegrator (see integrator.h)
+    pub camera: Arc<Camera>,
+    pub sampler: Box<Sampler>,
+    pixel_bounds: Bounds2i,
+    // see path.h
+    max_depth: u32,
+    rr_threshold: Float,           // 1.0
+    light_sample_strategy: String, // "spatial"
+    light_distribution: Option<Arc<LightDistribution>>,
+    // path guiding
+    training_passes: u32,          // 5
+    bsdf_sampling_fraction: Float, // 0.5
+    sd_tree: Option<SDTree>,
+}
+
+impl GuidedPathIntegrator {
+    pub fn new(
+        max_depth: u32,
+        camera: Arc<Camera>,
+        sampler: Box<Sampler>,
+        pixel_bounds: Bounds2i,
+        rr_threshold: Float,
+        light_sample_strategy: String,
+        training_passes: u32,
+        bsdf_sampling_fraction: Float,
+    ) -> Self {
+        GuidedPathIntegrator {
+            camera,
+            sampler,
+            pixel_bounds,
+            max_depth,
+            rr_threshold,
+            light_sample_strategy,
+            light_distribution: None,
+            training_passes,
+            bsdf_sampling_fraction: clamp_t(bsdf_sampling_fraction, 0.0 as Float, 1.0 as Float),
+            sd_tree: None,
+        }
+    }
+    /// Learns the SD-tree in training passes which double the number
+    /// of samples per pixel each time. The images of the training
+    /// passes are discarded.
+    pub fn preprocess(&mut self, scene: &Scene) {
+        self.light_distribution =
+            create_light_sample_distribution(self.light_sample_strategy.clone(), scene);
+        self.sd_tree = Some(SDTree::new(scene.world_bound()));
+        for pass in 0..self.training_passes {
+            let spp: i64 = 1_i64 << pass;
+            println!(
+                "Training pass {}/{} with {} sample(s) per pixel ...",
+                pass + 1,
+                self.training_passes,
+                spp
+            );
+            self.train(scene, spp, pass);
+            if let Some(ref mut sd_tree) = self.sd_tree {
+                sd_tree.update(spp);
+            }
+        }
+        if let Some(ref sd_tree) = self.sd_tree {
+            println!("SD-tree with {} leaves", sd_tree.num_leaves());
+        }
+    }
+    fn train(&self, scene: &Scene, spp: i64, pass: u32) {
+        let bounds: Bounds2i = self.pixel_bounds;
+        let height: i64 = (bounds.p_max.y - bounds.p_min.y) as i64;
+        let sampler: Sampler = Sampler::Random(RandomSampler::new(spp));
+        let sampler = &sampler;
+        let rows: Vec<i32> = (bounds.p_min.y..bounds.p_max.y).collect();
+        rows.par_iter().for_each(|y| {
+            let seed: i64 = pass as i64 * height + (*y - bounds.p_min.y) as i64;
+            let mut row_sampler: Box<Sampler> = sampler.clone_with_seed(seed as u64);
+            for x in bounds.p_min.x..bounds.p_max.x {
+                let pixel: Point2i = Point2i { x, y: *y };
+                row_sampler.start_pixel(pixel);
+                loop {
+                    let camera_sample: CameraSample = row_sampler.get_camera_sample(pixel);
+                    let mut ray: Ray = Ray::default();
+                    let ray_weight: Float = self
+                        .camera
+                        .generate_ray_differential(&camera_sample, &mut ray);
+                    if ray_weight > 0.0 as Float {
+                        if self.camera.get_clipping_start() > 0.0 as Float {
+                            self.camera
+                                .adjust_to_clipping_start(&camera_sample, &mut ray);
+                        }
+                        self.trace(&ray, scene, &mut row_sampler, true, &mut None);
+                    }
+                    if !row_sampler.start_next_sample() {
+                        break;
+                    }
+                }
+            }
+        });
+    }
+    pub fn li(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        // arena: &mut Arena,
+        _depth: i32,
+        aov: &mut Option<AovSample>,
+    ) -> Spectrum {
+        self.trace(r, scene, sampler, false, aov)
+    }
+    /// Samples a direction from the BSDF or the guiding distribution
+    /// and returns the BSDF value and the combined pdf.
+    fn sample_direction(
+        &self,
+        bsdf: &Bsdf,
+        distribution: &DirectionalDistribution,
+        wo: &Vector3f,
+        wi: &mut Vector3f,
+        u: Point2f,
+        pdf: &mut Float,
+        sampled_type: &mut u8,
+    ) -> Spectrum {
+        let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+        let alpha: Float = if distribution.sampling.is_some() {
+            self.bsdf_sampling_fraction
+        } else {
+            1.0 as Float
+        };
+        let mut bsdf_pdf: Float = 0.0 as Float;
+        if u.x < alpha {
+            // sample the BSDF (reusing the first sample dimension)
+            let u_bsdf: Point2f = Point2f {
+                x: u.x / alpha,
+                y: u.y,
+            };
+            let f: Spectrum =
+                bsdf.sample_f(wo, wi, &u_bsdf, &mut bsdf_pdf, bsdf_flags, sampled_type);
+            if f.is_black() || bsdf_pdf == 0.0 as Float {
+                *pdf = 0.0 as Float;
+                return f;
+            }
+            *pdf = alpha * bsdf_pdf + (1.0 as Float - alpha) * distribution.pdf(wi);
+            f
+        } else {
+            // sample the guiding distribution
+            let u_guide: Point2f = Point2f {
+                x: (u.x - alpha) / (1.0 as Float - alpha),
+                y: u.y,
+            };
+            let mut guide_pdf: Float = 0.0 as Float;
+            match distribution.sample(u_guide, &mut guide_pdf) {
+                Some(w) => *wi = w,
+                None => {
+                    *pdf = 0.0 as Float;
+                    return Spectrum::default();
+                }
+            }
+            bsdf_pdf = bsdf.pdf(wo, wi, bsdf_flags);
+            *pdf = alpha * bsdf_pdf + (1.0 as Float - alpha) * guide_pdf;
+            let reflect: bool =
+                vec3_dot_nrmf(wo, &bsdf.ng) * vec3_dot_nrmf(wi, &bsdf.ng) > 0.0 as Float;
+            *sampled_type = if reflect {
+                BxdfType::BsdfReflection as u8
+            } else {
+                BxdfType::BsdfTransmission as u8
+            };
+            bsdf.f(wo, wi, bsdf_flags)
+        }
+    }
+    fn trace(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        record: bool,
+        aov: &mut Option<AovSample>,
+    ) -> Spectrum {
+        let mut l: Spectrum = Spectrum::default();
+        let mut beta: Spectrum = Spectrum::new(1.0 as Float);
+        let mut ray: Ray = Ray {
+            o: r.o,
+            d: r.d,
+            t_max: Cell::new(r.t_max.get()),
+            time: r.time,
+            differential: r.differential,
+            medium: r.medium.clone(),
+        };
+        let mut specular_bounce: bool = false;
+        let mut bounces: u32 = 0_u32;
+        let mut eta_scale: Float = 1.0;
+        // vertices recording the radiance they receive (training
+        // only), which is what gets added to _l_ afterwards: emitters
+        // hit after a non-specular bounce are left to light sampling
+        let mut vertices: SmallVec<[GuidingVertex; 8]> = SmallVec::new();
+        loop {
+            // find next path vertex and accumulate contribution
+            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+            if scene.intersect(&ray, &mut isect) {
+                // possibly add emitted light at intersection
+                if bounces == 0 || specular_bounce {
+                    // add emitted light at path vertex
+                    let le: Spectrum = beta * isect.le(&-ray.d);
+                    l += le;
+                    add_radiance(&mut vertices, &le);
+                }
+                // terminate path if _maxDepth_ was reached
+                if bounces >= self.max_depth {
+                    break;
+                }
+                // compute scattering functions and skip over medium boundaries
+                let mode: TransportMode = TransportMode::Radiance;
+                isect.compute_scattering_functions(&ray, true, mode);
+                if bounces == 0 {
+                    if let Some(aov) = aov {
+                        aov.record(&isect);
+                    }
+                }
+                if let Some(ref mut bsdf) = isect.bsdf {
+                    bsdf.select_wavelength(&beta);
+                }
+                if isect.bsdf.is_none() {
+                    ray = isect.spawn_ray(&ray.d);
+                    continue;
+                }
+                if let Some(ref light_distribution) = self.light_distribution {
+                    // Sample illumination from lights to find path contribution.
+                    // (But skip this for perfectly specular BSDFs.)
+                    let bsdf_flags: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
+                    if let Some(ref bsdf) = isect.bsdf {
+                        if bsdf.num_components(bsdf_flags) > 0 {
+                            let it: &SurfaceInteraction = &isect;
+                            let ld: Spectrum = beta
+                                * sample_one_light(it, scene, sampler, false, light_distribution);
+                            assert!(ld.y() >= 0.0 as Float, "ld = {:?}", ld);
+                            l += ld;
+                            add_radiance(&mut vertices, &ld);
+                        }
+                        // Sample BSDF (or guiding distribution) to get new path direction
+                        let wo: Vector3f = -ray.d;
+                        let mut wi: Vector3f = Vector3f::default();
+                        let mut pdf: Float = 0.0 as Float;
+                        let mut sampled_type: u8 = u8::MAX; // != 0
+
+                        // guide only BSDFs without specular components
+                        let distribution: Option<&DirectionalDistribution> = match self.sd_tree {
+                            Some(ref sd_tree)
+                                if bsdf.num_components(BxdfType::BsdfSpecular as u8) == 0 =>
+                            {
+                                let n: Normal3f = nrm_faceforward_vec3(&isect.shading.n, &wo);
+                                Some(sd_tree.lookup(&isect.common.p, &n))
+                            }
+                            _ => None,
+                        };
+                        let f: Spectrum = match distribution {
+                            Some(distribution) => self.sample_direction(
+                                bsdf,
+                                distribution,
+                                &wo,
+                                &mut wi,
+                                sampler.get_2d(),
+                                &mut pdf,
+                                &mut sampled_type,
+                            ),
+                            None => bsdf.sample_f(
+                                &wo,
+                                &mut wi,
+                                &sampler.get_2d(),
+                                &mut pdf,
+                                BxdfType::BsdfAll as u8,
+                                &mut sampled_type,
+                            ),
+                        };
+                        if f.is_black() || pdf == 0.0 as Float {
+                            break;
+                        }
+                        beta *= (f * vec3_abs_dot_nrmf(&wi, &isect.shading.n)) / pdf;
+                        assert!(beta.y() >= 0.0 as Float);
+                        assert!(!(beta.y().is_infinite()));
+                        if let Some(distribution) = distribution {
+                            if record {
+                                vertices.push(GuidingVertex {
+                                    distribution,
+                                    wi,
+                                    throughput: beta,
+                                    radiance: Spectrum::default(),
+                                    wi_pdf: pdf,
+                                });
+                            }
+                        }
+                        specular_bounce = (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+                        if ((sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8)
+                            && ((sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8)
+                        {
+                            let eta: Float = bsdf.eta;
+                            // Update the term that tracks radiance
+                            // scaling for refraction depending on
+                            // whether the ray is entering or leaving
+                            // the medium.
+                            if vec3_dot_nrmf(&wo, &isect.common.n) > 0.0 as Float {
+                                eta_scale *= eta * eta;
+                            } else {
+                                eta_scale *= 1.0 as Float / (eta * eta);
+                            }
+                        }
+                        ray = isect.spawn_ray(&wi);
+
+                        // account for subsurface scattering, if applicable
+                        if let Some(ref bssrdf) = isect.bssrdf {
+                            if (sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8 {
+                                // importance sample the BSSRDF
+                                let s2: Point2f = sampler.get_2d();
+                                let s1: Float = sampler.get_1d();
+                                let (s, pi_opt) = bssrdf.sample_s(
+                                    // the next three (extra) parameters are used for SeparableBssrdfAdapter
+                                    bssrdf.clone(),
+                                    bssrdf.mode,
+                                    bssrdf.eta,
+                                    // done
+                                    scene,
+                                    s1,
+                                    s2,
+                                    &mut pdf,
+                                );
+                                if s.is_black() || pdf == 0.0 as Float {
+                                    break;
+                                }
+                                assert!(!(beta.y().is_infinite()));
+                                beta *= s / pdf;
+                                if let Some(pi) = pi_opt {
+                                    // account for the direct subsurface scattering component
+                                    let ld: Spectrum = beta
+                                        * sample_one_light(
+                                            &pi,
+                                            scene,
+                                            sampler,
+                                            false,
+                                            light_distribution,
+                                        );
+                                    l += ld;
+                                    add_radiance(&mut vertices, &ld);
+                                    // account for the indirect subsurface scattering component
+                                    let mut wi: Vector3f = Vector3f::default();
+                                    let mut pdf: Float = 0.0 as Float;
+                                    let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+                                    let mut sampled_type: u8 = u8::MAX; // != 0
+                                    if let Some(ref bsdf) = pi.bsdf {
+                                        let f: Spectrum = bsdf.sample_f(
+                                            &pi.common.wo,
+                                            &mut wi,
+                                            &sampler.get_2d(),
+                                            &mut pdf,
+                                            bsdf_flags,
+                                            &mut sampled_type,
+                                        );
+                                        if f.is_black() || pdf == 0.0 as Float {
+                                            break;
+                                        }
+                                        beta *= f * vec3_abs_dot_nrmf(&wi, &pi.shading.n) / pdf;
+                                        assert!(!(beta.y().is_infinite()));
+                                        specular_bounce =
+                                            (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+                                        ray = pi.spawn_ray(&wi);
+                                    }
+                                }
+                            }
+                        }
+
+                        // Possibly terminate the path with Russian roulette.
+                        // Factor out radiance scaling due to refraction in rr_beta.
+                        let rr_beta: Spectrum = beta * eta_scale;
+                        if rr_beta.max_component_value() < self.rr_threshold && bounces > 3 {
+                            let q: Float =
+                                (0.05 as Float).max(1.0 as Float - rr_beta.max_component_value());
+                            if sampler.get_1d() < q {
+                                break;
+                            }
+                            beta /= 1.0 as Float - q;
+                            assert!(!(beta.y().is_infinite()));
+                        }
+                    }
+                }
+            } else {
+                // add emitted light from the environment
+                if bounces == 0 || specular_bounce {
+                    for light in &scene.infinite_lights {
+                        let le: Spectrum = beta * light.le(&ray);
+                        l += le;
+                        add_radiance(&mut vertices, &le);
+                    }
+                }
+                // terminate path if ray escaped
+                break;
+            }
+            bounces += 1_u32;
+        }
+        for vertex in vertices.iter() {
+            vertex.record();
+        }
+        l
+    }
+    pub fn get_camera(&self) -> Arc<Camera> {
+        self.camera.clone()
+    }
+    pub fn get_sampler(&self) -> &Sampler {
+        &self.sampler
+    }
+    pub fn get_pixel_bounds(&self) -> Bounds2i {
+        self.pixel_bounds
+    }
+}
@@ -1,9 +1,14 @@
 // std
+use std::collections::HashMap;
 use std::ops::DerefMut;
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::{Duration, Instant};
 // pbrt
 use crate::core::camera::Camera;
+use crate::core::checkpoint::{load_checkpoint, remove_checkpoint, save_checkpoint};
+use crate::core::checkpoint::{CheckpointGate, CheckpointSettings, IntegratorState, MLTChainState};
 use crate::core::film::Film;
 use crate::core::geometry::{Bounds2f, Bounds2i, Point2f, Point2i};
 use crate::core::integrator::compute_light_power_distribution;
@@ -404,7 +409,7 @@ impl MLTIntegrator {
             None,
         ) * (n_strategies as Float)
     }
-    pub fn render(&self, scene: &Scene, num_threads: u8) {
+    pub fn render(&self, scene: &Scene, num_threads: u8, checkpoint: &CheckpointSettings) {
         let mut num_cores: usize;
         let num_cores_init = if num_threads == 0_u8 {
             num_cpus::get()
@@ -483,98 +488,197 @@ impl MLTIntegrator {
                 // TODO: let progress_frequency = 32768;
                 // TODO: ProgressReporter progress(nTotalMutations / progressFrequency,
                 //                           "Rendering");
+                let n_chains = self.n_chains;
+                // continue finished and interrupted chains of a checkpoint
+                let mut completed: Vec<u32> = Vec::new();
+                let mut chains: HashMap<u32, MLTChainState> = HashMap::new();
+                if let Some(IntegratorState::MLT {
+                    completed: completed_chains,
+                    chains: chain_states,
+                }) = load_checkpoint(&film, checkpoint, "mlt")
+                {
+                    completed = completed_chains;
+                    for state in chain_states.into_iter() {
+                        chains.insert(state.chain, state);
+                    }
+                }
+                let ivec: Vec<u32> = (0..n_chains).filter(|i| !completed.contains(i)).collect();
+                let completed: Mutex<Vec<u32>> = Mutex::new(completed);
+                let chains: Mutex<HashMap<u32, MLTChainState>> = Mutex::new(chains);
+                // chains leave the gate between two mutations to let
+                // a checkpoint be written
+                let gate: CheckpointGate = CheckpointGate::default();
+                let finished: AtomicBool = AtomicBool::new(false);
                 // use parallel iterator (par_iter_with) from rayon crate
                 let (sender, receiver) = crossbeam_channel::bounded(num_cores);
-                let n_chains = self.n_chains;
+                let n_started: usize = ivec.len();
                 // spawn thread to report progress
                 let finish = thread::spawn(move || {
-                    for _ in pbr::PbIter::new(0..n_chains) {
+                    for _ in pbr::PbIter::new(0..n_started) {
                         receiver.recv().unwrap();
                     }
                 });
-                // for i in 0..n_chains {
-                let ivec: Vec<u32> = (0..n_chains).collect();
-                ivec.par_iter().for_each_with(sender, |s, &i| {
-                    s.send(i).unwrap_or_else(|_| panic!("Failed to send chain"));
-                    let n_chain_mutations: u64 = ((i as u64 + 1) * n_total_mutations
-                        / n_chains as u64)
-                        .min(n_total_mutations)
-                        - i as u64 * n_total_mutations / n_chains as u64;
-                    // select initial state from the set of bootstrap samples
-                    let mut rng: Rng = Rng::default();
-                    rng.set_sequence(i as u64);
-                    let bootstrap_index: usize =
-                        bootstrap.sample_discrete(rng.uniform_float(), None);
-                    let depth: u32 = bootstrap_index as u32 % (self.max_depth + 1);
-                    // initialize local variables for selected state
-                    let mut sampler: Box<Sampler> = Box::new(Sampler::MLT(MLTSampler::new(
-                        self.mutations_per_pixel as i64,
-                        bootstrap_index as u64,
-                        self.sigma,
-                        self.large_step_probability,
-                        N_SAMPLE_STREAMS as i32,
-                    )));
-                    let mut p_current: Point2f = Point2f::default();
-                    let mut l_current: Spectrum = self.l(
-                        scene,
-                        light_distr.clone(),
-                        &mut sampler,
-                        depth,
-                        &mut p_current,
-                    );
-                    // run the Markov chain for _n_chain_mutations_ steps
-                    for _j in 0..n_chain_mutations {
-                        match sampler.deref_mut() {
-                            Sampler::MLT(mlt_sampler) => mlt_sampler.start_iteration(),
-                            _ => panic!("MLTSampler needed."),
-                        }
-                        let mut p_proposed: Point2f = Point2f::default();
-                        let l_proposed: Spectrum = self.l(
-                            scene,
-                            light_distr.clone(),
-                            &mut sampler,
-                            depth,
-                            &mut p_proposed,
-                        );
-                        // compute acceptance probability for proposed sample
-                        let accept: Float = (1.0 as Float).min(l_proposed.y() / l_current.y());
-                        // splat both current and proposed samples to _film_
-                        if accept > 0.0 as Float {
-                            film.add_splat(p_proposed, &(l_proposed * accept / l_proposed.y()));
-                        }
-                        film.add_splat(
-                            p_current,
-                            &(l_current * (1.0 as Float - accept) / l_current.y()),
-                        );
-                        // accept or reject the proposal
-                        if rng.uniform_float() < accept {
-                            p_current = p_proposed;
-                            l_current = l_proposed;
-                            match sampler.deref_mut() {
-                                Sampler::MLT(mlt_sampler) => mlt_sampler.accept(),
-                                _ => panic!("MLTSampler needed."),
+                crossbeam::scope(|scope| {
+                    if checkpoint.is_enabled() {
+                        // spawn thread to write checkpoints
+                        scope.spawn(|_| {
+                            let mut last_checkpoint: Instant = Instant::now();
+                            while !finished.load(Ordering::SeqCst) {
+                                thread::sleep(Duration::from_millis(10));
+                                if last_checkpoint.elapsed().as_secs_f32() >= checkpoint.seconds {
+                                    gate.pause();
+                                    save_checkpoint(
+                                        &film,
+                                        checkpoint,
+                                        IntegratorState::MLT {
+                                            completed: completed.lock().unwrap().clone(),
+                                            chains: chains
+                                                .lock()
+                                                .unwrap()
+                                                .values()
+                                                .cloned()
+                                                .collect(),
+                                        },
+                                    );
+                                    gate.resume();
+                                    last_checkpoint = Instant::now();
+                                }
                             }
-                        // TODO: ++acceptedMutations;
+                        });
+                    }
+                    ivec.par_iter().for_each_with(sender, |s, &i| {
+                        s.send(i).unwrap_or_else(|_| panic!("Failed to send chain"));
+                        let n_chain_mutations: u64 = ((i as u64 + 1) * n_total_mutations
+                            / n_chains as u64)
+                            .min(n_total_mutations)
+                            - i as u64 * n_total_mutations / n_chains as u64;
+                        gate.enter();
+                        let resumed: Option<MLTChainState> =
+                            chains.lock().unwrap().get(&i).cloned();
+                        let state: MLTChainState = if let Some(state) = resumed {
+                            state
                         } else {
+                            // select initial state from the set of bootstrap samples
+                            let mut rng: Rng = Rng::default();
+                            rng.set_sequence(i as u64);
+                            let bootstrap_index: usize =
+                                bootstrap.sample_discrete(rng.uniform_float(), None);
+                            let depth: u32 = bootstrap_index as u32 % (self.max_depth + 1);
+                            // initialize local variables for selected state
+                            let mut sampler: Box<Sampler> =
+                                Box::new(Sampler::MLT(MLTSampler::new(
+                                    self.mutations_per_pixel as i64,
+                                    bootstrap_index as u64,
+                                    self.sigma,
+                                    self.large_step_probability,
+                                    N_SAMPLE_STREAMS as i32,
+                                )));
+                            let mut p_current: Point2f = Point2f::default();
+                            let l_current: Spectrum = self.l(
+                                scene,
+                                light_distr.clone(),
+                                &mut sampler,
+                                depth,
+                                &mut p_current,
+                            );
+                            MLTChainState {
+                                chain: i,
+                                mutation: 0,
+                                depth,
+                                p_current,
+                                l_current,
+                                rng,
+                                sampler: get_mlt_sampler(&sampler).clone(),
+                            }
+                        };
+                        let depth: u32 = state.depth;
+                        let mut rng: Rng = state.rng;
+                        let mut p_current: Point2f = state.p_current;
+                        let mut l_current: Spectrum = state.l_current;
+                        let mut sampler: Box<Sampler> = Box::new(Sampler::MLT(state.sampler));
+                        // run the Markov chain for _n_chain_mutations_ steps
+                        for j in state.mutation..n_chain_mutations {
+                            if gate.is_paused() {
+                                chains.lock().unwrap().insert(
+                                    i,
+                                    MLTChainState {
+                                        chain: i,
+                                        mutation: j,
+                                        depth,
+                                        p_current,
+                                        l_current,
+                                        rng,
+                                        sampler: get_mlt_sampler(&sampler).clone(),
+                                    },
+                                );
+                                gate.leave();
+                                gate.enter();
+                            }
                             match sampler.deref_mut() {
-                                Sampler::MLT(mlt_sampler) => mlt_sampler.reject(),
+                                Sampler::MLT(mlt_sampler) => mlt_sampler.start_iteration(),
                                 _ => panic!("MLTSampler needed."),
                             }
+                            let mut p_proposed: Point2f = Point2f::default();
+                            let l_proposed: Spectrum = self.l(
+                                scene,
+                                light_distr.clone(),
+                                &mut sampler,
+                                depth,
+                                &mut p_proposed,
+                            );
+                            // compute acceptance probability for proposed sample
+                            let accept: Float = (1.0 as Float).min(l_proposed.y() / l_current.y());
+                            // splat both current and proposed samples to _film_
+                            if accept > 0.0 as Float {
+                                film.add_splat(p_proposed, &(l_proposed * accept / l_proposed.y()));
+                            }
+                            film.add_splat(
+                                p_current,
+                                &(l_current * (1.0 as Float - accept) / l_current.y()),
+                            );
+                            // accept or reject the proposal
+                            if rng.uniform_float() < accept {
+                                p_current = p_proposed;
+                                l_current = l_proposed;
+                                match sampler.deref_mut() {
+                                    Sampler::MLT(mlt_sampler) => mlt_sampler.accept(),
+                                    _ => panic!("MLTSampler needed."),
+                                }
+                            // TODO: ++acceptedMutations;
+                            } else {
+                                match sampler.deref_mut() {
+                                    Sampler::MLT(mlt_sampler) => mlt_sampler.reject(),
+                                    _ => panic!("MLTSampler needed."),
+                                }
+                            }
+                            // TODO: ++totalMutations;
+                            // if (i * n_total_mutations / n_chains + j) % progress_frequency == 0 {
+                            //     progress.update();
+                            // }
+                            // TODO: arena.Reset();
                         }
-                        // TODO: ++totalMutations;
-                        // if (i * n_total_mutations / n_chains + j) % progress_frequency == 0 {
-                        //     progress.update();
-                        // }
-                        // TODO: arena.Reset();
-                    }
-                });
+                        chains.lock().unwrap().remove(&i);
+                        completed.lock().unwrap().push(i);
+                        gate.leave();
+                    });
+                    finished.store(true, Ordering::SeqCst);
+                })
+                .unwrap();
                 finish.join().unwrap();
             }
             // Store final image computed with MLT
             film.write_image(b / self.mutations_per_pixel as Float);
+            remove_checkpoint(&film, checkpoint);
         }
     }
     pub fn get_camera(&self) -> Arc<Camera> {
         self.camera.clone()
     }
 }
+
+fn get_mlt_sampler(sampler: &Sampler) -> &MLTSampler {
+    match sampler {
+        Sampler::MLT(mlt_sampler) => mlt_sampler,
+        _ => panic!("MLTSampler needed."),
+    }
+}
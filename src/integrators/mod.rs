@@ -4,6 +4,7 @@
 //! - AOIntegrator
 //! - BDPTIntegrator
 //! - DirectLightingIntegrator
+//! - GuidedPathIntegrator
 //! - MLTIntegrator
 //! - PathIntegrator
 //! - SPPMIntegrator
@@ -39,6 +40,13 @@
 //!
 //! ![Path Tracing](/doc/img/cornell_box_pbrt_rust_path.png)
 //!
+//! ## Guided Path Tracing
+//!
+//! A path tracer which learns the distribution of the incident
+//! radiance in the scene (stored in an SD-tree) during a few training
+//! passes, and samples directions from it in addition to the BSDF.
+//! This helps most in scenes dominated by indirect illumination.
+//!
 //! ## Bidirectional Path Tracing (BDPT)
 //!
 //! Bidirectional path tracing is a generalization of the standard
@@ -61,6 +69,7 @@
 pub mod ao;
 pub mod bdpt;
 pub mod directlighting;
+pub mod guidedpath;
 pub mod mlt;
 pub mod path;
 pub mod sppm;
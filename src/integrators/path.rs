@@ -6,7 +6,7 @@ use std::sync::Arc;
 use crate::core::camera::Camera;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Point2f, Ray, Vector3f};
-use crate::core::integrator::uniform_sample_one_light;
+use crate::core::integrator::{sample_one_light, AovSample};
 use crate::core::interaction::{Interaction, SurfaceInteraction};
 use crate::core::lightdistrib::create_light_sample_distribution;
 use crate::core::lightdistrib::LightDistribution;
@@ -14,7 +14,6 @@ use crate::core::material::TransportMode;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::BxdfType;
 use crate::core::sampler::Sampler;
-use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
 
 // see path.h
@@ -63,6 +62,7 @@ impl PathIntegrator {
         sampler: &mut Sampler,
         // arena: &mut Arena,
         _depth: i32,
+        aov: &mut Option<AovSample>,
     ) -> Spectrum {
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
@@ -106,6 +106,14 @@ impl PathIntegrator {
                 // compute scattering functions and skip over medium boundaries
                 let mode: TransportMode = TransportMode::Radiance;
                 isect.compute_scattering_functions(&ray, true, mode);
+                if bounces == 0 {
+                    if let Some(aov) = aov {
+                        aov.record(&isect);
+                    }
+                }
+                if let Some(ref mut bsdf) = isect.bsdf {
+                    bsdf.select_wavelength(&beta);
+                }
                 if let Some(ref _bsdf) = isect.bsdf {
                     // we are fine (for below)
                 } else {
@@ -115,7 +123,6 @@ impl PathIntegrator {
                     continue;
                 }
                 if let Some(ref light_distribution) = self.light_distribution {
-                    let distrib: Arc<Distribution1D> = light_distribution.lookup(&isect.common.p);
                     // Sample illumination from lights to find path contribution.
                     // (But skip this for perfectly specular BSDFs.)
                     let bsdf_flags: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
@@ -124,13 +131,7 @@ impl PathIntegrator {
                             // TODO: ++total_paths;
                             let it: &SurfaceInteraction = &isect;
                             let ld: Spectrum = beta
-                                * uniform_sample_one_light(
-                                    it,
-                                    scene,
-                                    sampler,
-                                    false,
-                                    Some(&distrib),
-                                );
+                                * sample_one_light(it, scene, sampler, false, light_distribution);
                             // TODO: println!("Sampled direct lighting Ld = {:?}", ld);
                             // TODO: if ld.is_black() {
                             //     ++zero_radiance_paths;
@@ -211,15 +212,13 @@ impl PathIntegrator {
                                 beta *= s / pdf;
                                 if let Some(pi) = pi_opt {
                                     // account for the direct subsurface scattering component
-                                    let distrib: Arc<Distribution1D> =
-                                        light_distribution.lookup(&pi.common.p);
                                     l += beta
-                                        * uniform_sample_one_light(
+                                        * sample_one_light(
                                             &pi,
                                             scene,
                                             sampler,
                                             false,
-                                            Some(&distrib),
+                                            light_distribution,
                                         );
                                     // account for the indirect subsurface scattering component
                                     let mut wi: Vector3f = Vector3f::default();
@@ -1,6 +1,7 @@
 // std
 use std::f32::consts::PI;
 use std::sync::Arc;
+use std::time::Instant;
 // others
 use atom::*;
 use atomic::{Atomic, Ordering};
@@ -8,6 +9,8 @@ use strum::IntoEnumIterator;
 // pbrt
 use crate::blockqueue::BlockQueue;
 use crate::core::camera::{Camera, CameraSample};
+use crate::core::checkpoint::{load_checkpoint, remove_checkpoint, save_checkpoint};
+use crate::core::checkpoint::{CheckpointSettings, IntegratorState, SPPMPixelState};
 use crate::core::film::Film;
 use crate::core::geometry::{
     bnd3_expand, bnd3_union_bnd3f, nrm_abs_dot_vec3f, pnt3_distance_squaredf, vec3_abs_dot_nrmf,
@@ -26,7 +29,6 @@ use crate::core::pbrt::{clamp_t, lerp};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::{Bsdf, BxdfType};
 use crate::core::scene::Scene;
-use crate::core::spectrum::RGBEnum;
 use crate::samplers::halton::HaltonSampler;
 
 /// Stochastic Progressive Photon Mapping
@@ -63,7 +65,7 @@ impl SPPMIntegrator {
             write_frequency,
         }
     }
-    pub fn render(&self, scene: &Scene, num_threads: u8) {
+    pub fn render(&self, scene: &Scene, num_threads: u8, checkpoint: &CheckpointSettings) {
         let num_cores = if num_threads == 0_u8 {
             num_cpus::get()
         } else {
@@ -84,6 +86,26 @@ impl SPPMIntegrator {
             };
             pixels.push(pixel);
         }
+        // continue with the pixel statistics of a checkpoint
+        let mut first_iteration: i32 = 0;
+        if let Some(IntegratorState::SPPM {
+            iteration,
+            pixels: states,
+        }) = load_checkpoint(&film, checkpoint, "sppm")
+        {
+            if states.len() == pixels.len() {
+                for (pixel, state) in pixels.iter_mut().zip(states.iter()) {
+                    pixel.radius = state.radius;
+                    pixel.ld = state.ld;
+                    pixel.n = state.n;
+                    pixel.tau = state.tau;
+                }
+                first_iteration = iteration;
+            } else {
+                println!("WARNING: SPPM checkpoint does not match the film, starting from scratch");
+            }
+        }
+        let mut last_checkpoint: Instant = Instant::now();
         let inv_sqrt_spp: Float = 1.0 as Float / (self.n_iterations as Float).sqrt();
         // TODO: let pixel_memory_bytes: usize = n_pixels as usize * std::mem::size_of::<SPPMPixel>();
 
@@ -103,7 +125,7 @@ impl SPPMIntegrator {
                 y: (pixel_extent.y + tile_size - 1) / tile_size,
             };
             // TODO: ProgressReporter progress(2 * nIterations, "Rendering");
-            for iteration in pbr::PbIter::new(0..self.n_iterations) {
+            for iteration in pbr::PbIter::new(first_iteration..self.n_iterations) {
                 // generate SPPM visible points
                 {
                     // TODO: ProfilePhase _(Prof::SPPMCameraPass);
@@ -612,19 +634,9 @@ impl SPPMIntegrator {
                                                                                     &wi,
                                                                                     bsdf_flags,
                                                                                 );
-                                                                            for i in 0..3 {
-                                                                                let rgb_i: RGBEnum =
-                                                                                    match i {
-                                                                                        0 =>
-                                                                                            RGBEnum::Red,
-                                                                                        1 =>
-                                                                                            RGBEnum::Green,
-                                                                                        _ =>
-                                                                                            RGBEnum::Blue,
-                                                                                    };
-                                                                                let phi_i: Float = phi[rgb_i];
+                                                                            for i in 0..Spectrum::N_SAMPLES {
                                                                                 pixel.phi[i]
-                                                                                    .add(phi_i);
+                                                                                    .add(phi[i]);
                                                                             }
                                                                             pixel.m.fetch_add(
                                                                                 1_i32,
@@ -741,26 +753,15 @@ impl SPPMIntegrator {
                                             let r_new: Float =
                                                 p.radius * (n_new / (p.n + p_m as Float)).sqrt();
                                             let mut phi: Spectrum = Spectrum::default();
-                                            for j in 0..3 {
-                                                match j {
-                                                    0 => {
-                                                        phi[RGBEnum::Red] = Float::from(&p.phi[j]);
-                                                    }
-                                                    1 => {
-                                                        phi[RGBEnum::Green] =
-                                                            Float::from(&p.phi[j]);
-                                                    }
-                                                    _ => {
-                                                        phi[RGBEnum::Blue] = Float::from(&p.phi[j]);
-                                                    }
-                                                }
+                                            for j in 0..Spectrum::N_SAMPLES {
+                                                phi[j] = Float::from(&p.phi[j]);
                                             }
                                             p.tau = (p.tau + p.vp.beta * phi) * (r_new * r_new)
                                                 / (p.radius * p.radius);
                                             p.n = n_new;
                                             p.radius = r_new;
                                             p.m.store(0, atomic::Ordering::Relaxed);
-                                            for j in 0..3 {
+                                            for j in 0..Spectrum::N_SAMPLES {
                                                 p.phi[j] = AtomicFloat::new(0.0 as Float);
                                             }
                                         }
@@ -839,7 +840,32 @@ impl SPPMIntegrator {
                     //     WriteImage("sppm_radius.png", rimg.get(), pixel_bounds, res);
                     // }
                 }
+                // only the statistics persist across iterations
+                if checkpoint.is_enabled()
+                    && iteration + 1 < self.n_iterations
+                    && last_checkpoint.elapsed().as_secs_f32() >= checkpoint.seconds
+                {
+                    let states: Vec<SPPMPixelState> = pixels
+                        .iter()
+                        .map(|p| SPPMPixelState {
+                            radius: p.radius,
+                            ld: p.ld,
+                            n: p.n,
+                            tau: p.tau,
+                        })
+                        .collect();
+                    save_checkpoint(
+                        &film,
+                        checkpoint,
+                        IntegratorState::SPPM {
+                            iteration: iteration + 1,
+                            pixels: states,
+                        },
+                    );
+                    last_checkpoint = Instant::now();
+                }
             }
+            remove_checkpoint(&film, checkpoint);
             // TODO: progress.Done();
         }
     }
@@ -856,17 +882,30 @@ pub struct VisiblePoint {
     pub beta: Spectrum,
 }
 
-#[derive(Default)]
 pub struct SPPMPixel {
     pub radius: Float,
     pub ld: Spectrum,
     pub vp: VisiblePoint,
-    pub phi: [AtomicFloat; 3],
+    pub phi: [AtomicFloat; Spectrum::N_SAMPLES],
     pub m: Atomic<i32>,
     pub n: Float,
     pub tau: Spectrum,
 }
 
+impl Default for SPPMPixel {
+    fn default() -> Self {
+        SPPMPixel {
+            radius: 0.0 as Float,
+            ld: Spectrum::default(),
+            vp: VisiblePoint::default(),
+            phi: std::array::from_fn(|_| AtomicFloat::default()),
+            m: Atomic::default(),
+            n: 0.0 as Float,
+            tau: Spectrum::default(),
+        }
+    }
+}
+
 pub struct SPPMPixelListNode<'p> {
     pub pixel: &'p SPPMPixel,
     pub next: AtomSetOnce<Arc<SPPMPixelListNode<'p>>>,
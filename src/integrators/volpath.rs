@@ -1,27 +1,41 @@
 // std
 use std::cell::Cell;
+use std::collections::HashMap;
 use std::sync::Arc;
 // pbrt
 // use crate::core::bssrdf::Bssrdf;
 use crate::core::camera::Camera;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
-use crate::core::geometry::{Bounds2i, Point2f, Ray, Vector3f};
-use crate::core::integrator::uniform_sample_one_light;
-use crate::core::interaction::{Interaction, MediumInteraction, SurfaceInteraction};
+use crate::core::geometry::{Bounds2i, Normal3f, Point2f, Ray, Vector3f};
+use crate::core::integrator::{sample_one_light, AovSample};
+use crate::core::interaction::{Interaction, InteractionCommon};
+use crate::core::interaction::{MediumInteraction, SurfaceInteraction};
+use crate::core::light::{is_delta_light, Light, VisibilityTester};
 use crate::core::lightdistrib::create_light_sample_distribution;
 use crate::core::lightdistrib::LightDistribution;
 use crate::core::material::TransportMode;
+use crate::core::medium::{HenyeyGreenstein, MediumProperties};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::BxdfType;
+use crate::core::rng::Rng;
 use crate::core::sampler::Sampler;
-use crate::core::sampling::Distribution1D;
 use crate::core::scene::Scene;
+use crate::media::majorant::average;
 
 // see volpath.h
 
 /// Accounts for scattering and attenuation from participating media
 /// as well as scattering from surfaces - uses the render loop of a
 /// [SamplerIntegrator](../../core/integrator/enum.SamplerIntegrator.html)
+///
+/// With *null_scattering* it uses the volumetric path tracer of
+/// pbrt-v4 instead, which is based on the null-scattering path
+/// integral formulation (Miller et al. 2019): tentative collisions
+/// are sampled against the majorants of the media and the
+/// probabilities of sampling a path unidirectionally and by light
+/// sampling are tracked (relative to the path throughput) for all
+/// spectral channels, which gives unbiased MIS between distance
+/// sampling and light sampling in chromatic and emissive media.
 pub struct VolPathIntegrator {
     // inherited from SamplerIntegrator (see integrator.h)
     pub camera: Arc<Camera>,
@@ -31,7 +45,10 @@ pub struct VolPathIntegrator {
     pub max_depth: u32,
     pub rr_threshold: Float,           // 1.0
     pub light_sample_strategy: String, // "spatial"
+    pub null_scattering: bool,         // false
     pub light_distribution: Option<Arc<LightDistribution>>,
+    // index in scene.lights by light address
+    light_indices: HashMap<usize, usize>,
 }
 
 impl VolPathIntegrator {
@@ -42,6 +59,7 @@ impl VolPathIntegrator {
         pixel_bounds: Bounds2i,
         rr_threshold: Float,
         light_sample_strategy: String,
+        null_scattering: bool,
     ) -> Self {
         VolPathIntegrator {
             camera,
@@ -50,12 +68,18 @@ impl VolPathIntegrator {
             max_depth,
             rr_threshold,
             light_sample_strategy,
+            null_scattering,
             light_distribution: None,
+            light_indices: HashMap::new(),
         }
     }
     pub fn preprocess(&mut self, scene: &Scene) {
         self.light_distribution =
             create_light_sample_distribution(self.light_sample_strategy.clone(), scene);
+        self.light_indices.clear();
+        for (i, light) in scene.lights.iter().enumerate() {
+            self.light_indices.insert(light_address(light), i);
+        }
     }
     pub fn li(
         &self,
@@ -64,7 +88,11 @@ impl VolPathIntegrator {
         sampler: &mut Sampler,
         // arena: &mut Arena,
         _depth: i32,
+        aov: &mut Option<AovSample>,
     ) -> Spectrum {
+        if self.null_scattering {
+            return self.li_null_scattering(r, scene, sampler, aov);
+        }
         // TODO: ProfilePhase p(Prof::SamplerIntegratorLi);
         let mut l: Spectrum = Spectrum::default();
         let mut beta: Spectrum = Spectrum::new(1.0 as Float);
@@ -94,7 +122,10 @@ impl VolPathIntegrator {
             if scene.intersect(&ray, &mut isect) {
                 // sample the participating medium, if present
                 if let Some(ref medium) = ray.medium {
-                    let (spectrum, option) = medium.sample(&ray, sampler);
+                    // add the radiance emitted by the medium along the ray
+                    let mut le: Spectrum = Spectrum::default();
+                    let (spectrum, option) = medium.sample_le(&ray, sampler, &mut le);
+                    l += beta * le;
                     beta *= spectrum;
                     if let Some(mi) = option {
                         mi_opt = Some(mi);
@@ -109,20 +140,18 @@ impl VolPathIntegrator {
                     if bounces >= self.max_depth {
                         break;
                     }
-                    let mi_p = mi.common.p;
                     // if mi.is_valid() {...}
                     if let Some(phase) = mi.clone().phase {
                         // TODO: ++volumeInteractions;
                         // handle scattering at point in medium for volumetric path tracer
                         if let Some(ref light_distribution) = self.light_distribution {
-                            let distrib: Arc<Distribution1D> = light_distribution.lookup(&mi_p);
                             l += beta
-                                * uniform_sample_one_light(
+                                * sample_one_light(
                                     &mi as &dyn Interaction,
                                     scene,
                                     sampler,
                                     true,
-                                    Some(&distrib),
+                                    light_distribution,
                                 );
                             let mut wi: Vector3f = Vector3f::default();
                             phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
@@ -144,6 +173,14 @@ impl VolPathIntegrator {
                     // compute scattering functions and skip over medium boundaries
                     let mode: TransportMode = TransportMode::Radiance;
                     isect.compute_scattering_functions(&ray, true, mode);
+                    if bounces == 0 {
+                        if let Some(aov) = aov {
+                            aov.record(&isect);
+                        }
+                    }
+                    if let Some(ref mut bsdf) = isect.bsdf {
+                        bsdf.select_wavelength(&beta);
+                    }
                     if let Some(ref _bsdf) = isect.bsdf {
                         // we are fine (for below)
                     } else {
@@ -152,19 +189,10 @@ impl VolPathIntegrator {
                         continue;
                     }
                     if let Some(ref light_distribution) = self.light_distribution {
-                        let light_distrib: Arc<Distribution1D> =
-                            light_distribution.lookup(&isect.common.p);
                         // Sample illumination from lights to find
                         // attenuated path contribution.
                         let it: &SurfaceInteraction = &isect;
-                        l += beta
-                            * uniform_sample_one_light(
-                                it,
-                                scene,
-                                sampler,
-                                true,
-                                Some(&light_distrib),
-                            );
+                        l += beta * sample_one_light(it, scene, sampler, true, light_distribution);
                         if let Some(ref bsdf) = isect.bsdf {
                             // Sample BSDF to get new path direction
                             let wo: Vector3f = -ray.d;
@@ -234,15 +262,13 @@ impl VolPathIntegrator {
                                     beta *= s / pdf;
                                     if let Some(pi) = pi_opt {
                                         // account for the direct subsurface scattering component
-                                        let distrib: Arc<Distribution1D> =
-                                            light_distribution.lookup(&pi.common.p);
                                         l += beta
-                                            * uniform_sample_one_light(
+                                            * sample_one_light(
                                                 &pi,
                                                 scene,
                                                 sampler,
                                                 true,
-                                                Some(&distrib),
+                                                light_distribution,
                                             );
                                         // account for the indirect subsurface scattering component
                                         let mut wi: Vector3f = Vector3f::default();
@@ -295,7 +321,10 @@ impl VolPathIntegrator {
             } else {
                 // sample the participating medium, if present
                 if let Some(ref medium) = ray.medium {
-                    let (spectrum, option) = medium.sample(&ray, sampler);
+                    // add the radiance emitted by the medium along the ray
+                    let mut le: Spectrum = Spectrum::default();
+                    let (spectrum, option) = medium.sample_le(&ray, sampler, &mut le);
+                    l += beta * le;
                     beta *= spectrum;
                     if let Some(mi) = option {
                         mi_opt = Some(mi);
@@ -310,20 +339,18 @@ impl VolPathIntegrator {
                     if bounces >= self.max_depth {
                         break;
                     }
-                    let mi_p = mi.common.p;
                     // if mi.is_valid() {...}
                     if let Some(phase) = mi.clone().phase {
                         // TODO: ++volumeInteractions;
                         // handle scattering at point in medium for volumetric path tracer
                         if let Some(ref light_distribution) = self.light_distribution {
-                            let distrib: Arc<Distribution1D> = light_distribution.lookup(&mi_p);
                             l += beta
-                                * uniform_sample_one_light(
+                                * sample_one_light(
                                     &mi as &dyn Interaction,
                                     scene,
                                     sampler,
                                     true,
-                                    Some(&distrib),
+                                    light_distribution,
                                 );
                             let mut wi: Vector3f = Vector3f::default();
                             phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
@@ -345,6 +372,518 @@ impl VolPathIntegrator {
         }
         l
     }
+    /// Radiance along |r| with the null-scattering formulation (see
+    /// VolPathIntegrator::Li() in pbrt-v4). *r_u* and *r_l* are the
+    /// probabilities of sampling the path unidirectionally and of
+    /// sampling its last vertex from a light, divided by the
+    /// probability of the spectral channel which drives the sampling
+    /// decisions.
+    fn li_null_scattering(
+        &self,
+        r: &Ray,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        aov: &mut Option<AovSample>,
+    ) -> Spectrum {
+        let light_distribution: &LightDistribution = match self.light_distribution {
+            Some(ref light_distribution) => light_distribution,
+            None => return Spectrum::default(),
+        };
+        let mut l: Spectrum = Spectrum::default();
+        let mut beta: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_u: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_l: Spectrum = Spectrum::new(1.0 as Float);
+        let mut ray: Ray = Ray {
+            o: r.o,
+            d: r.d,
+            t_max: Cell::new(r.t_max.get()),
+            time: r.time,
+            differential: r.differential,
+            medium: r.medium.clone(),
+        };
+        let mut specular_bounce: bool = false;
+        let mut depth: u32 = 0_u32;
+        let mut eta_scale: Float = 1.0;
+        // choose the channel which drives the sampling decisions
+        let channel: usize = ((sampler.get_1d() * Spectrum::N_SAMPLES as Float) as usize)
+            .min(Spectrum::N_SAMPLES - 1_usize);
+        let mut rng: Rng = Rng::new();
+        rng.set_sequence(
+            ((sampler.get_1d().to_bits() as u64) << 32) | sampler.get_1d().to_bits() as u64,
+        );
+        // previous scattering vertex (for MIS of emission found by
+        // unidirectional sampling) and its normal for light sampling
+        let mut prev_intr: MediumInteraction = MediumInteraction::default();
+        let mut prev_n: Normal3f = Normal3f::default();
+        loop {
+            // intersect _ray_ with scene
+            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+            let hit_surface: bool = scene.intersect(&ray, &mut isect);
+            // sample the participating medium, if present
+            if let Some(medium) = ray.medium.clone() {
+                let mut scattered: bool = false;
+                let mut terminated: bool = false;
+                let mut scattered_ray: Option<Ray> = None;
+                let u: Float = sampler.get_1d();
+                let mut u_mode: Float = sampler.get_1d();
+                let t_maj: Spectrum = medium.sample_t_maj(
+                    &ray,
+                    ray.t_max.get(),
+                    u,
+                    &mut rng,
+                    channel,
+                    |p, mp: &MediumProperties, sigma_maj, t_maj, rng| {
+                        // add emission from medium scattering event
+                        if depth < self.max_depth && !mp.le.is_black() {
+                            let pdf: Float = sigma_maj[channel] * t_maj[channel];
+                            let betap: Spectrum = beta * *t_maj / pdf;
+                            let r_e: Spectrum = r_u * *sigma_maj * *t_maj / pdf;
+                            if !r_e.is_black() {
+                                l += betap * mp.sigma_a * mp.le / average(&r_e);
+                            }
+                        }
+                        // compute medium event probabilities for interaction
+                        let p_absorb: Float = mp.sigma_a[channel] / sigma_maj[channel];
+                        let p_scatter: Float = mp.sigma_s[channel] / sigma_maj[channel];
+                        let p_null: Float = (1.0 as Float - p_absorb - p_scatter).max(0.0 as Float);
+                        // sample medium scattering event type and update path
+                        let u_event: Float = u_mode * (p_absorb + p_scatter + p_null);
+                        u_mode = rng.uniform_float();
+                        if u_event < p_absorb {
+                            // handle absorption along ray path
+                            terminated = true;
+                            return false;
+                        }
+                        if u_event < p_absorb + p_scatter {
+                            // handle scattering along ray path, stop
+                            // path sampling if maximum depth has been
+                            // reached
+                            if depth >= self.max_depth {
+                                terminated = true;
+                                return false;
+                            }
+                            depth += 1;
+                            let pdf: Float = t_maj[channel] * mp.sigma_s[channel];
+                            beta *= *t_maj * mp.sigma_s / pdf;
+                            r_u *= *t_maj * mp.sigma_s / pdf;
+                            if !beta.is_black() && !r_u.is_black() {
+                                // sample direct lighting at volume scattering event
+                                let phase: Arc<HenyeyGreenstein> =
+                                    Arc::new(HenyeyGreenstein { g: mp.g });
+                                let mi: MediumInteraction = MediumInteraction::new(
+                                    p,
+                                    &(-ray.d),
+                                    ray.time,
+                                    Some(medium.clone()),
+                                    Some(phase.clone()),
+                                );
+                                l += self.sample_ld(
+                                    &mi,
+                                    scene,
+                                    sampler,
+                                    light_distribution,
+                                    channel,
+                                    &beta,
+                                    &r_u,
+                                );
+                                // sample new direction at real scattering event
+                                let mut wi: Vector3f = Vector3f::default();
+                                let phase_pdf: Float =
+                                    phase.sample_p(&(-ray.d), &mut wi, sampler.get_2d());
+                                if phase_pdf == 0.0 as Float {
+                                    terminated = true;
+                                } else {
+                                    // the phase function is sampled exactly
+                                    r_l = r_u / phase_pdf;
+                                    scattered_ray = Some(mi.spawn_ray(&wi));
+                                    prev_intr = mi;
+                                    prev_n = Normal3f::default();
+                                    scattered = true;
+                                    specular_bounce = false;
+                                }
+                            }
+                            return false;
+                        }
+                        // handle null scattering along ray path
+                        let sigma_n: Spectrum = (*sigma_maj - mp.sigma_a - mp.sigma_s)
+                            .clamp(0.0 as Float, Float::INFINITY);
+                        let pdf: Float = t_maj[channel] * sigma_n[channel];
+                        if pdf == 0.0 as Float {
+                            beta = Spectrum::default();
+                            return false;
+                        }
+                        beta *= *t_maj * sigma_n / pdf;
+                        r_u *= *t_maj * sigma_n / pdf;
+                        r_l *= *t_maj * *sigma_maj / pdf;
+                        !beta.is_black() && !r_u.is_black()
+                    },
+                );
+                // handle terminated, scattered, and unscattered medium rays
+                if terminated || beta.is_black() || r_u.is_black() {
+                    return l;
+                }
+                if scattered {
+                    if let Some(scattered_ray) = scattered_ray {
+                        ray = scattered_ray;
+                    }
+                    continue;
+                }
+                if t_maj[channel] == 0.0 as Float {
+                    return l;
+                }
+                beta *= t_maj / t_maj[channel];
+                r_u *= t_maj / t_maj[channel];
+                r_l *= t_maj / t_maj[channel];
+            }
+            // handle surviving unscattered rays
+            if !hit_surface {
+                // accumulate contributions from infinite light sources
+                for light in &scene.infinite_lights {
+                    let le: Spectrum = light.le(&ray);
+                    if le.is_black() {
+                        continue;
+                    }
+                    if depth == 0 || specular_bounce {
+                        l += beta * le / average(&r_u);
+                    } else {
+                        // add infinite light contribution using both PDFs with MIS
+                        let r_l: Spectrum = r_l
+                            * self.light_pdf(
+                                light,
+                                light_distribution,
+                                &prev_intr,
+                                &prev_n,
+                                &ray.d,
+                            );
+                        l += beta * le / average(&(r_u + r_l));
+                    }
+                }
+                break;
+            }
+            // add emitted light at intersection point
+            let le: Spectrum = isect.le(&-ray.d);
+            if !le.is_black() {
+                if depth == 0 || specular_bounce {
+                    l += beta * le / average(&r_u);
+                } else if let Some(primitive_raw) = isect.primitive {
+                    let primitive = unsafe { &*primitive_raw };
+                    if let Some(area_light) = primitive.get_area_light() {
+                        // add contribution of emission from intersected
+                        // surface using both PDFs with MIS
+                        let r_l: Spectrum = r_l
+                            * self.light_pdf(
+                                &area_light,
+                                light_distribution,
+                                &prev_intr,
+                                &prev_n,
+                                &ray.d,
+                            );
+                        l += beta * le / average(&(r_u + r_l));
+                    }
+                }
+            }
+            // get BSDF and skip over medium boundaries
+            isect.compute_scattering_functions(&ray, true, TransportMode::Radiance);
+            if depth == 0 {
+                if let Some(aov) = aov {
+                    aov.record(&isect);
+                }
+            }
+            if let Some(ref mut bsdf) = isect.bsdf {
+                bsdf.select_wavelength(&beta);
+            }
+            if isect.bsdf.is_none() {
+                ray = isect.spawn_ray(&ray.d);
+                continue;
+            }
+            // terminate path if maximum depth reached
+            if depth >= self.max_depth {
+                return l;
+            }
+            depth += 1;
+            if let Some(ref bsdf) = isect.bsdf {
+                // sample illumination from lights to find attenuated
+                // path contribution
+                let non_specular: u8 = BxdfType::BsdfAll as u8 & !(BxdfType::BsdfSpecular as u8);
+                if bsdf.num_components(non_specular) > 0 {
+                    l += self.sample_ld(
+                        &isect,
+                        scene,
+                        sampler,
+                        light_distribution,
+                        channel,
+                        &beta,
+                        &r_u,
+                    );
+                }
+                prev_intr = MediumInteraction {
+                    common: isect.common.clone(),
+                    phase: None,
+                };
+                prev_n = isect.shading.n;
+                // sample BSDF to get new volumetric path direction
+                let wo: Vector3f = -ray.d;
+                let mut wi: Vector3f = Vector3f::default();
+                let mut pdf: Float = 0.0 as Float;
+                let mut sampled_type: u8 = u8::MAX; // != 0
+                let f: Spectrum = bsdf.sample_f(
+                    &wo,
+                    &mut wi,
+                    &sampler.get_2d(),
+                    &mut pdf,
+                    BxdfType::BsdfAll as u8,
+                    &mut sampled_type,
+                );
+                if f.is_black() || pdf == 0.0 as Float {
+                    break;
+                }
+                // update volumetric integrator path state after surface scattering
+                beta *= f * vec3_abs_dot_nrmf(&wi, &isect.shading.n) / pdf;
+                r_l = r_u / pdf;
+                specular_bounce = (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+                if ((sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8)
+                    && ((sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8)
+                {
+                    let eta: Float = bsdf.eta;
+                    if vec3_dot_nrmf(&wo, &isect.common.n) > 0.0 as Float {
+                        eta_scale *= eta * eta;
+                    } else {
+                        eta_scale *= 1.0 as Float / (eta * eta);
+                    }
+                }
+                ray = isect.spawn_ray(&wi);
+                // account for attenuated subsurface scattering, if applicable
+                if let Some(ref bssrdf) = isect.bssrdf {
+                    if (sampled_type & BxdfType::BsdfTransmission as u8) != 0_u8 {
+                        // sample BSSRDF probe segment to find exit point
+                        let s2: Point2f = sampler.get_2d();
+                        let s1: Float = sampler.get_1d();
+                        let (s, pi_opt) = bssrdf.sample_s(
+                            bssrdf.clone(),
+                            bssrdf.mode,
+                            bssrdf.eta,
+                            scene,
+                            s1,
+                            s2,
+                            &mut pdf,
+                        );
+                        if s.is_black() || pdf == 0.0 as Float {
+                            break;
+                        }
+                        beta *= s / pdf;
+                        if let Some(pi) = pi_opt {
+                            // possibly regularize subsurface BSDF and
+                            // account for attenuated direct illumination
+                            l += self.sample_ld(
+                                &pi,
+                                scene,
+                                sampler,
+                                light_distribution,
+                                channel,
+                                &beta,
+                                &r_u,
+                            );
+                            // sample ray for indirect subsurface scattering
+                            if let Some(ref bsdf) = pi.bsdf {
+                                let f: Spectrum = bsdf.sample_f(
+                                    &pi.common.wo,
+                                    &mut wi,
+                                    &sampler.get_2d(),
+                                    &mut pdf,
+                                    BxdfType::BsdfAll as u8,
+                                    &mut sampled_type,
+                                );
+                                if f.is_black() || pdf == 0.0 as Float {
+                                    break;
+                                }
+                                beta *= f * vec3_abs_dot_nrmf(&wi, &pi.shading.n) / pdf;
+                                r_l = r_u / pdf;
+                                specular_bounce =
+                                    (sampled_type & BxdfType::BsdfSpecular as u8) != 0_u8;
+                                prev_intr = MediumInteraction {
+                                    common: pi.common.clone(),
+                                    phase: None,
+                                };
+                                prev_n = pi.shading.n;
+                                ray = pi.spawn_ray(&wi);
+                            }
+                        }
+                    }
+                }
+            }
+            if beta.is_black() {
+                break;
+            }
+            // possibly terminate volumetric path with Russian roulette
+            let rr_beta: Spectrum = beta * eta_scale / average(&r_u);
+            if rr_beta.max_component_value() < self.rr_threshold && depth > 3 {
+                let q: Float = (0.05 as Float).max(1.0 as Float - rr_beta.max_component_value());
+                if sampler.get_1d() < q {
+                    break;
+                }
+                beta /= 1.0 as Float - q;
+            }
+        }
+        l
+    }
+    /// Samples direct lighting at |it| for the null-scattering
+    /// formulation (see SampleLd() in pbrt-v4). The transmittance
+    /// towards the light is estimated by ratio tracking, MIS between
+    /// light and BSDF or phase function sampling uses |r_p|, the
+    /// rescaled probability of the path up to |it|.
+    fn sample_ld(
+        &self,
+        it: &dyn Interaction,
+        scene: &Scene,
+        sampler: &mut Sampler,
+        light_distribution: &LightDistribution,
+        channel: usize,
+        beta: &Spectrum,
+        r_p: &Spectrum,
+    ) -> Spectrum {
+        // choose a light source for the direct lighting calculation
+        let n: Normal3f = if let Some(shading_n) = it.get_shading_n() {
+            *shading_n
+        } else {
+            Normal3f::default()
+        };
+        let mut light_pmf: Float = 0.0 as Float;
+        let light_num: usize =
+            match light_distribution.sample(it.get_p(), &n, sampler.get_1d(), &mut light_pmf) {
+                Some(light_num) => light_num,
+                None => return Spectrum::default(),
+            };
+        let u_light: Point2f = sampler.get_2d();
+        let mut rng: Rng = Rng::new();
+        rng.set_sequence(sampler.get_1d().to_bits() as u64);
+        // sample a point on the light source
+        let light: &Light = &scene.lights[light_num];
+        let mut wi: Vector3f = Vector3f::default();
+        let mut light_pdf: Float = 0.0 as Float;
+        let mut visibility: VisibilityTester = VisibilityTester::default();
+        let mut light_intr: InteractionCommon = InteractionCommon::default();
+        let li: Spectrum = light.sample_li(
+            it.get_common(),
+            &mut light_intr,
+            u_light,
+            &mut wi,
+            &mut light_pdf,
+            &mut visibility,
+        );
+        if light_pdf == 0.0 as Float || li.is_black() {
+            return Spectrum::default();
+        }
+        let p_l: Float = light_pmf * light_pdf;
+        // evaluate BSDF or phase function for light sample direction
+        let mut f_hat: Spectrum = Spectrum::default();
+        let mut scatter_pdf: Float = 0.0 as Float;
+        if it.is_surface_interaction() {
+            if let Some(bsdf) = it.get_bsdf() {
+                let bsdf_flags: u8 = BxdfType::BsdfAll as u8;
+                f_hat = bsdf.f(it.get_wo(), &wi, bsdf_flags) * vec3_abs_dot_nrmf(&wi, &n);
+                scatter_pdf = bsdf.pdf(it.get_wo(), &wi, bsdf_flags);
+            }
+        } else if let Some(ref phase) = it.get_phase() {
+            let p: Float = phase.p(it.get_wo(), &wi);
+            f_hat = Spectrum::new(p);
+            scatter_pdf = p;
+        }
+        if f_hat.is_black() {
+            return Spectrum::default();
+        }
+        // declare path state variables for ray to light source
+        let mut light_ray: Ray = it.get_common().spawn_ray_to(&light_intr);
+        let mut t_ray: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_l: Spectrum = Spectrum::new(1.0 as Float);
+        let mut r_u: Spectrum = Spectrum::new(1.0 as Float);
+        loop {
+            // trace ray through media to estimate transmittance
+            let mut isect: SurfaceInteraction = SurfaceInteraction::default();
+            let hit_surface: bool = scene.intersect(&light_ray, &mut isect);
+            // handle opaque surface along ray's path
+            if hit_surface {
+                if let Some(primitive_raw) = isect.primitive {
+                    let primitive = unsafe { &*primitive_raw };
+                    if primitive.get_material().is_some() {
+                        return Spectrum::default();
+                    }
+                }
+            }
+            // update transmittance for current ray segment
+            if let Some(ref medium) = light_ray.medium {
+                let u: Float = rng.uniform_float();
+                let t_maj: Spectrum = medium.sample_t_maj(
+                    &light_ray,
+                    light_ray.t_max.get(),
+                    u,
+                    &mut rng,
+                    channel,
+                    |_p, mp: &MediumProperties, sigma_maj, t_maj, rng| {
+                        // update ray transmittance estimate at sampled point,
+                        // first compute _t_ray_ and PDFs for ratio tracking
+                        let sigma_n: Spectrum = (*sigma_maj - mp.sigma_a - mp.sigma_s)
+                            .clamp(0.0 as Float, Float::INFINITY);
+                        let pdf: Float = t_maj[channel] * sigma_maj[channel];
+                        t_ray *= *t_maj * sigma_n / pdf;
+                        r_l *= *t_maj * *sigma_maj / pdf;
+                        r_u *= *t_maj * sigma_n / pdf;
+                        // possibly terminate transmittance computation
+                        // using Russian roulette
+                        let tr: Spectrum = t_ray / average(&(r_l + r_u));
+                        if tr.max_component_value() < 0.05 as Float {
+                            if rng.uniform_float() < 0.75 as Float {
+                                t_ray = Spectrum::default();
+                            } else {
+                                t_ray /= 0.25 as Float;
+                            }
+                        }
+                        !t_ray.is_black()
+                    },
+                );
+                if t_maj[channel] == 0.0 as Float {
+                    return Spectrum::default();
+                }
+                // update transmittance estimate for final segment
+                t_ray *= t_maj / t_maj[channel];
+                r_l *= t_maj / t_maj[channel];
+                r_u *= t_maj / t_maj[channel];
+            }
+            // generate next ray segment or return final transmittance
+            if t_ray.is_black() {
+                return Spectrum::default();
+            }
+            if !hit_surface {
+                break;
+            }
+            light_ray = isect.common.spawn_ray_to(&light_intr);
+        }
+        // return path contribution function estimate for direct lighting
+        r_l *= *r_p * p_l;
+        r_u *= *r_p * scatter_pdf;
+        if is_delta_light(light.get_flags()) {
+            *beta * f_hat * t_ray * li / average(&r_l)
+        } else {
+            *beta * f_hat * t_ray * li / average(&(r_l + r_u))
+        }
+    }
+    /// Probability of sampling the direction |wi| from |prev_intr| by
+    /// light sampling, including the probability of choosing |light|.
+    fn light_pdf(
+        &self,
+        light: &Light,
+        light_distribution: &LightDistribution,
+        prev_intr: &MediumInteraction,
+        prev_n: &Normal3f,
+        wi: &Vector3f,
+    ) -> Float {
+        match self.light_indices.get(&light_address(light)) {
+            Some(light_num) => {
+                light_distribution.pmf(&prev_intr.common.p, prev_n, *light_num)
+                    * light.pdf_li(prev_intr, wi)
+            }
+            None => 0.0 as Float,
+        }
+    }
     pub fn get_camera(&self) -> Arc<Camera> {
         self.camera.clone()
     }
@@ -355,3 +894,7 @@ impl VolPathIntegrator {
         self.pixel_bounds
     }
 }
+
+fn light_address(light: &Light) -> usize {
+    light as *const Light as usize
+}
@@ -4,6 +4,7 @@ use std::sync::Arc;
 use crate::core::camera::Camera;
 use crate::core::geometry::{vec3_abs_dot_nrmf, vec3_dot_nrmf};
 use crate::core::geometry::{Bounds2i, Normal3f, Ray, RayDifferential, Vector3f};
+use crate::core::integrator::AovSample;
 use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
 use crate::core::light::VisibilityTester;
 use crate::core::material::TransportMode;
@@ -47,6 +48,7 @@ impl WhittedIntegrator {
         sampler: &mut Sampler,
         // arena: &mut Arena,
         depth: i32,
+        aov: &mut Option<AovSample>,
     ) -> Spectrum {
         let mut l: Spectrum = Spectrum::default();
         // find closest ray intersection or return background radiance
@@ -61,10 +63,13 @@ impl WhittedIntegrator {
             // compute scattering functions for surface interaction
             let mode: TransportMode = TransportMode::Radiance;
             isect.compute_scattering_functions(ray, false, mode);
+            if let Some(aov) = aov {
+                aov.record(&isect);
+            }
             // if (!isect.bsdf)
             if let Some(ref _bsdf) = isect.bsdf {
             } else {
-                return self.li(&mut isect.spawn_ray(&ray.d), scene, sampler, depth);
+                return self.li(&mut isect.spawn_ray(&ray.d), scene, sampler, depth, aov);
             }
             // compute emitted light if ray hit an area light source
             l += isect.le(&wo);
@@ -175,7 +180,7 @@ impl WhittedIntegrator {
                     };
                     rd.differential = Some(diff);
                 }
-                f * self.li(&mut rd, scene, sampler, depth + 1)
+                f * self.li(&mut rd, scene, sampler, depth + 1, &mut None)
                     * Spectrum::new(vec3_abs_dot_nrmf(&wi, &ns) / pdf)
             } else {
                 Spectrum::new(0.0)
@@ -242,7 +247,7 @@ impl WhittedIntegrator {
                     };
                     rd.differential = Some(diff);
                 }
-                f * self.li(&mut rd, scene, sampler, depth + 1)
+                f * self.li(&mut rd, scene, sampler, depth + 1, &mut None)
                     * Spectrum::new(vec3_abs_dot_nrmf(&wi, &ns) / pdf)
             } else {
                 Spectrum::new(0.0)
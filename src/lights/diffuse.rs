@@ -3,9 +3,9 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::{nrm_abs_dot_vec3f, nrm_dot_vec3f, vec3_coordinate_system};
-use crate::core::geometry::{Normal3f, Point2f, Ray, Vector3f, XYEnum};
+use crate::core::geometry::{Normal3f, Point2f, Point3f, Ray, Vector3f, XYEnum};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
@@ -91,6 +91,28 @@ impl DiffuseAreaLight {
         };
         self.l_emit * factor * self.area * PI
     }
+    pub fn bounds(&self) -> Option<LightBounds> {
+        let phi: Float = self.power().y().max(0.0 as Float);
+        // only flat shapes emit into a fixed hemisphere
+        let normal: Option<Normal3f> = match *self.shape {
+            Shape::Trngl(ref triangle) => triangle.get_normal(),
+            _ => None,
+        };
+        if let Some(n) = normal {
+            Some(LightBounds::new(
+                &self.shape.world_bound(),
+                &Vector3f::from(n),
+                phi,
+                1.0 as Float,
+                0.0 as Float,
+                self.two_sided,
+            ))
+        } else {
+            let mut light_bounds: LightBounds = LightBounds::point(&Point3f::default(), phi);
+            light_bounds.bounds = self.shape.world_bound();
+            Some(light_bounds)
+        }
+    }
     pub fn preprocess(&self, _scene: &Scene) {
         // TODO?
     }
@@ -6,7 +6,7 @@ use std::sync::RwLock;
 use crate::core::geometry::vec3_coordinate_system;
 use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::MediumInterface;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampling::concentric_sample_disk;
@@ -75,6 +75,9 @@ impl DistantLight {
     /// **DistanceLight** implements the optional *preprocess()*
     /// method to get the bound. This method is called at the end of
     /// the **Scene** constructor.
+    pub fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
     pub fn preprocess(&self, scene: &Scene) {
         let mut world_center_ref = self.world_center.write().unwrap();
         let mut world_radius_ref = self.world_radius.write().unwrap();
@@ -10,16 +10,19 @@ use half::f16;
 use openexr::{FrameBufferMut, InputFile, PixelType};
 // pbrt
 use crate::core::geometry::{spherical_phi, spherical_theta, vec3_coordinate_system};
+use crate::core::geometry::{vec3_cross_vec3, vec3_dot_vec3f};
 use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point2i, Point3f, Ray, Vector3f, XYEnum};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::MediumInterface;
 use crate::core::mipmap::{ImageWrap, MipMap};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::pbrt::{INV_2_PI, INV_PI};
+use crate::core::rng::FLOAT_ONE_MINUS_EPSILON;
 use crate::core::sampling::concentric_sample_disk;
-use crate::core::sampling::Distribution2D;
+use crate::core::sampling::{Distribution2D, SphericalRectangle};
 use crate::core::scene::Scene;
+use crate::core::spectrum::SpectrumType;
 use crate::core::transform::Transform;
 
 // see https://stackoverflow.com/questions/36008434/how-can-i-decode-f16-to-f32-using-only-the-stable-standard-library
@@ -43,6 +46,71 @@ fn decode_f16(half: u16) -> f32 {
     }
 }
 
+/// Fraction of the light samples taken through the portals (if any
+/// is visible), the rest importance samples the environment map.
+const PORTAL_SAMPLING_FRACTION: Float = 0.5;
+
+/// A rectangle (e.g. a window) through which an
+/// **InfiniteAreaLight** illuminates an interior. Portals only guide
+/// the sampling, the emitted radiance doesn't change.
+#[derive(Debug, Copy, Clone)]
+pub struct Portal {
+    pub p: Point3f,
+    pub ex: Vector3f,
+    pub ey: Vector3f,
+}
+
+impl Portal {
+    /// Creates a portal from four corners (in world space) in order
+    /// around the rectangle, returns *None* if they don't form one.
+    pub fn new(corners: &[Point3f]) -> Option<Portal> {
+        if corners.len() != 4 {
+            return None;
+        }
+        let ex: Vector3f = corners[1] - corners[0];
+        let ey: Vector3f = corners[3] - corners[0];
+        let ex_len: Float = ex.length();
+        let ey_len: Float = ey.length();
+        if ex_len == 0.0 as Float || ey_len == 0.0 as Float {
+            return None;
+        }
+        let tolerance: Float = 1e-3 as Float;
+        if vec3_dot_vec3f(&ex, &ey).abs() > tolerance * ex_len * ey_len
+            || (corners[0] + ex + ey - corners[2]).length() > tolerance * (ex_len + ey_len)
+        {
+            return None;
+        }
+        Some(Portal {
+            p: corners[0],
+            ex,
+            ey,
+        })
+    }
+    /// Returns the point where the half-line from |o| in direction
+    /// |d| passes through the portal.
+    pub fn intersect(&self, o: &Point3f, d: &Vector3f) -> Option<Point3f> {
+        let n: Vector3f = vec3_cross_vec3(&self.ex, &self.ey);
+        let denom: Float = vec3_dot_vec3f(d, &n);
+        if denom == 0.0 as Float {
+            return None;
+        }
+        let t: Float = vec3_dot_vec3f(&(self.p - *o), &n) / denom;
+        if t <= 0.0 as Float || t.is_infinite() {
+            return None;
+        }
+        let p_hit: Point3f = *o + *d * t;
+        let q: Vector3f = p_hit - self.p;
+        let a: Float = vec3_dot_vec3f(&q, &self.ex) / self.ex.length_squared();
+        let b: Float = vec3_dot_vec3f(&q, &self.ey) / self.ey.length_squared();
+        if !(0.0 as Float..=1.0 as Float).contains(&a)
+            || !(0.0 as Float..=1.0 as Float).contains(&b)
+        {
+            return None;
+        }
+        Some(p_hit)
+    }
+}
+
 // see infinte.h
 
 pub struct InfiniteAreaLight {
@@ -51,6 +119,7 @@ pub struct InfiniteAreaLight {
     pub world_center: RwLock<Point3f>,
     pub world_radius: RwLock<Float>,
     pub distribution: Arc<Distribution2D>,
+    pub portals: Vec<Portal>,
     // inherited from class Light (see light.h)
     pub flags: u8,
     pub n_samples: i32,
@@ -109,10 +178,13 @@ impl InfiniteAreaLight {
                     for i in 0..(resolution.x * resolution.y) {
                         let (r, g, b) = pixel_data[i as usize];
                         texels.push(
-                            Spectrum::rgb(
-                                decode_f16(r.to_bits()),
-                                decode_f16(g.to_bits()),
-                                decode_f16(b.to_bits()),
+                            Spectrum::from_rgb_type(
+                                &[
+                                    decode_f16(r.to_bits()),
+                                    decode_f16(g.to_bits()),
+                                    decode_f16(b.to_bits()),
+                                ],
+                                SpectrumType::Illuminant,
                             ) * *l,
                         );
                     }
@@ -153,6 +225,7 @@ impl InfiniteAreaLight {
                         world_center: RwLock::new(Point3f::default()),
                         world_radius: RwLock::new(0.0),
                         distribution,
+                        portals: Vec::new(),
                         flags: LightFlags::Infinite as u8,
                         n_samples: std::cmp::max(1_i32, n_samples),
                         medium_interface: MediumInterface::default(),
@@ -194,7 +267,10 @@ impl InfiniteAreaLight {
                     let img_result = hdr.read_image_transform(
                         |p| {
                             let rgb = p.to_hdr();
-                            Spectrum::rgb(rgb[0], rgb[1], rgb[2]) * *l
+                            Spectrum::from_rgb_type(
+                                &[rgb[0], rgb[1], rgb[2]],
+                                SpectrumType::Illuminant,
+                            ) * *l
                         },
                         &mut texels,
                     );
@@ -236,6 +312,7 @@ impl InfiniteAreaLight {
                             world_center: RwLock::new(Point3f::default()),
                             world_radius: RwLock::new(0.0),
                             distribution,
+                            portals: Vec::new(),
                             flags: LightFlags::Infinite as u8,
                             n_samples: std::cmp::max(1_i32, n_samples),
                             medium_interface: MediumInterface::default(),
@@ -287,6 +364,7 @@ impl InfiniteAreaLight {
             world_center: RwLock::new(Point3f::default()),
             world_radius: RwLock::new(0.0),
             distribution,
+            portals: Vec::new(),
             flags: LightFlags::Infinite as u8,
             n_samples: std::cmp::max(1_i32, n_samples),
             medium_interface: MediumInterface::default(),
@@ -305,38 +383,69 @@ impl InfiniteAreaLight {
         vis: &mut VisibilityTester<'a, 'b>,
     ) -> Spectrum {
         // TODO: ProfilePhase _(Prof::LightSample);
-        // find $(u,v)$ sample coordinates in infinite light texture
-        let mut map_pdf: Float = 0.0 as Float;
-        let uv: Point2f = self.distribution.sample_continuous(u, &mut map_pdf);
-        if map_pdf == 0 as Float {
-            return Spectrum::default();
-        }
-        // convert infinite light sample point to direction
-        let theta: Float = uv[XYEnum::Y] * PI;
-        let phi: Float = uv[XYEnum::X] * 2.0 as Float * PI;
-        let cos_theta: Float = theta.cos();
-        let sin_theta: Float = theta.sin();
-        let sin_phi: Float = phi.sin();
-        let cos_phi: Float = phi.cos();
-        let vec: Vector3f = Vector3f {
-            x: sin_theta * cos_phi,
-            y: sin_theta * sin_phi,
-            z: cos_theta,
+        let mut u: Point2f = u;
+        let rects: Vec<(usize, SphericalRectangle)> = self.visible_portals(&iref.p);
+        let portal_fraction: Float = if rects.is_empty() {
+            0.0 as Float
+        } else {
+            PORTAL_SAMPLING_FRACTION
         };
-        *wi = self.light_to_world.transform_vector(&vec);
+        let l: Spectrum;
+        if u.x < portal_fraction {
+            // pick a visible portal proportional to its solid angle
+            u.x = (u.x / portal_fraction).min(FLOAT_ONE_MINUS_EPSILON);
+            let total: Float = rects.iter().map(|(_, r)| r.solid_angle()).sum();
+            let mut up: Float = u.x * total;
+            let mut chosen: usize = rects.len() - 1;
+            for (i, (_, rect)) in rects.iter().enumerate() {
+                if up < rect.solid_angle() {
+                    chosen = i;
+                    break;
+                }
+                up -= rect.solid_angle();
+            }
+            let omega: Float = rects[chosen].1.solid_angle();
+            u.x = (up / omega).min(FLOAT_ONE_MINUS_EPSILON);
+            // sample a direction through the portal
+            let p_portal: Point3f = rects[chosen].1.sample(&u);
+            *wi = (p_portal - iref.p).normalize();
+            l = self.lookup_le(wi);
+        } else {
+            u.x = ((u.x - portal_fraction) / (1.0 as Float - portal_fraction))
+                .min(FLOAT_ONE_MINUS_EPSILON);
+            // find $(u,v)$ sample coordinates in infinite light texture
+            let mut map_pdf: Float = 0.0 as Float;
+            let uv: Point2f = self.distribution.sample_continuous(u, &mut map_pdf);
+            if map_pdf == 0 as Float {
+                return Spectrum::default();
+            }
+            // convert infinite light sample point to direction
+            let theta: Float = uv[XYEnum::Y] * PI;
+            let phi: Float = uv[XYEnum::X] * 2.0 as Float * PI;
+            let cos_theta: Float = theta.cos();
+            let sin_theta: Float = theta.sin();
+            let sin_phi: Float = phi.sin();
+            let cos_phi: Float = phi.cos();
+            let vec: Vector3f = Vector3f {
+                x: sin_theta * cos_phi,
+                y: sin_theta * sin_phi,
+                z: cos_theta,
+            };
+            *wi = self.light_to_world.transform_vector(&vec);
+            l = self.lmap.lookup_pnt_flt(uv, 0.0 as Float);
+        }
         // compute PDF for sampled infinite light direction
-        *pdf = map_pdf / (2.0 as Float * PI * PI * sin_theta);
-        if sin_theta == 0.0 as Float {
-            *pdf = 0.0 as Float;
+        *pdf = self.pdf_portals(&iref.p, wi, &rects, portal_fraction);
+        if *pdf == 0.0 as Float {
+            return Spectrum::default();
         }
         // return radiance value for infinite light direction
         let world_radius: Float = *self.world_radius.read().unwrap();
-        // TODO: SpectrumType::Illuminant
         light_intr.p = iref.p + *wi * (2.0 as Float * world_radius);
         light_intr.time = iref.time;
         vis.p0 = Some(iref);
         vis.p1 = Some(light_intr);
-        self.lmap.lookup_pnt_flt(uv, 0.0 as Float)
+        l
     }
     /// Like directional lights, the total power from the infinite
     /// area light is related to the surface area of the scene. Like
@@ -344,13 +453,15 @@ impl InfiniteAreaLight {
     pub fn power(&self) -> Spectrum {
         let p: Point2f = Point2f { x: 0.5, y: 0.5 };
         let world_radius: Float = *self.world_radius.read().unwrap();
-        // TODO: SpectrumType::Illuminant
         self.lmap.lookup_pnt_flt(p, 0.5 as Float) * Spectrum::new(PI * world_radius * world_radius)
     }
     /// Like **DistanceLights**, **InfiniteAreaLights** also need the
     /// scene bounds; here again, the **preprocess()** method finds
     /// the scene bounds after all of the scene geometry has been
     /// created.
+    pub fn bounds(&self) -> Option<LightBounds> {
+        None
+    }
     pub fn preprocess(&self, scene: &Scene) {
         let mut world_center_ref = self.world_center.write().unwrap();
         let mut world_radius_ref = self.world_radius.write().unwrap();
@@ -367,28 +478,18 @@ impl InfiniteAreaLight {
     /// the scene bounds. It's the responsibility of the integrators
     /// to call this method for these rays.
     pub fn le(&self, ray: &Ray) -> Spectrum {
-        let w: Vector3f = self.world_to_light.transform_vector(&ray.d).normalize();
-        let st: Point2f = Point2f {
-            x: spherical_phi(&w) * INV_2_PI,
-            y: spherical_theta(&w) * INV_PI,
-        };
-        // TODO: SpectrumType::Illuminant
-        self.lmap.lookup_pnt_flt(st, 0.0 as Float)
+        self.lookup_le(&ray.d)
     }
-    pub fn pdf_li(&self, _iref: &dyn Interaction, w: &Vector3f) -> Float {
+    pub fn pdf_li(&self, iref: &dyn Interaction, w: &Vector3f) -> Float {
         // TODO: ProfilePhase _(Prof::LightPdf);
-        let wi: Vector3f = self.world_to_light.transform_vector(w);
-        let theta: Float = spherical_theta(&wi);
-        let phi: Float = spherical_phi(&wi);
-        let sin_theta: Float = theta.sin();
-        if sin_theta == 0 as Float {
-            return 0 as Float;
-        }
-        let p: Point2f = Point2f {
-            x: phi * INV_2_PI,
-            y: theta * INV_PI,
+        let p: Point3f = *iref.get_p();
+        let rects: Vec<(usize, SphericalRectangle)> = self.visible_portals(&p);
+        let portal_fraction: Float = if rects.is_empty() {
+            0.0 as Float
+        } else {
+            PORTAL_SAMPLING_FRACTION
         };
-        self.distribution.pdf(p) / (2.0 as Float * PI * PI * sin_theta)
+        self.pdf_portals(&p, w, &rects, portal_fraction)
     }
     pub fn sample_le(
         &self,
@@ -443,7 +544,6 @@ impl InfiniteAreaLight {
             *pdf_dir = map_pdf / (2.0 as Float * PI * PI * sin_theta);
         }
         *pdf_pos = 1.0 as Float / (PI * world_radius * world_radius);
-        // TODO: return Spectrum(Lmap->Lookup(uv), SpectrumType::Illuminant);
         self.lmap.lookup_pnt_flt(uv, 0.0 as Float)
     }
     pub fn pdf_le(&self, ray: &Ray, _n_light: &Normal3f, pdf_pos: &mut Float, pdf_dir: &mut Float) {
@@ -465,4 +565,62 @@ impl InfiniteAreaLight {
     pub fn get_n_samples(&self) -> i32 {
         self.n_samples
     }
+    /// Radiance of the environment map in world space direction |w|.
+    fn lookup_le(&self, w: &Vector3f) -> Spectrum {
+        let wl: Vector3f = self.world_to_light.transform_vector(w).normalize();
+        let st: Point2f = Point2f {
+            x: spherical_phi(&wl) * INV_2_PI,
+            y: spherical_theta(&wl) * INV_PI,
+        };
+        self.lmap.lookup_pnt_flt(st, 0.0 as Float)
+    }
+    /// PDF of importance sampling world space direction |w| from the
+    /// environment map alone.
+    fn map_pdf(&self, w: &Vector3f) -> Float {
+        let wi: Vector3f = self.world_to_light.transform_vector(w);
+        let theta: Float = spherical_theta(&wi);
+        let phi: Float = spherical_phi(&wi);
+        let sin_theta: Float = theta.sin();
+        if sin_theta == 0 as Float {
+            return 0 as Float;
+        }
+        let p: Point2f = Point2f {
+            x: phi * INV_2_PI,
+            y: theta * INV_PI,
+        };
+        self.distribution.pdf(p) / (2.0 as Float * PI * PI * sin_theta)
+    }
+    /// The portals as seen from |p|, skipping those with no solid
+    /// angle (e.g. because |p| lies in their plane).
+    fn visible_portals(&self, p: &Point3f) -> Vec<(usize, SphericalRectangle)> {
+        let mut rects: Vec<(usize, SphericalRectangle)> = Vec::with_capacity(self.portals.len());
+        for (i, portal) in self.portals.iter().enumerate() {
+            let rect: SphericalRectangle =
+                SphericalRectangle::new(p, &portal.p, &portal.ex, &portal.ey);
+            if rect.solid_angle() > 0.0 as Float {
+                rects.push((i, rect));
+            }
+        }
+        rects
+    }
+    /// PDF of sampling world space direction |w| from |p|, mixing
+    /// portal and environment map sampling.
+    fn pdf_portals(
+        &self,
+        p: &Point3f,
+        w: &Vector3f,
+        rects: &[(usize, SphericalRectangle)],
+        portal_fraction: Float,
+    ) -> Float {
+        let mut pdf: Float = (1.0 as Float - portal_fraction) * self.map_pdf(w);
+        if portal_fraction > 0.0 as Float {
+            let total: Float = rects.iter().map(|(_, r)| r.solid_angle()).sum();
+            for (i, rect) in rects {
+                if let Some(p_hit) = self.portals[*i].intersect(p, w) {
+                    pdf += portal_fraction * (rect.solid_angle() / total) * rect.pdf(&p_hit);
+                }
+            }
+        }
+        pdf
+    }
 }
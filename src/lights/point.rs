@@ -6,7 +6,7 @@ use std::sync::Arc;
 use crate::core::geometry::pnt3_distance_squaredf;
 use crate::core::geometry::{Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampling::{uniform_sample_sphere, uniform_sphere_pdf};
@@ -70,6 +70,12 @@ impl PointLight {
     pub fn power(&self) -> Spectrum {
         self.i * (4.0 as Float * PI)
     }
+    pub fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::point(
+            &self.p_light,
+            4.0 as Float * PI * self.i.y().max(0.0 as Float),
+        ))
+    }
     pub fn preprocess(&self, _scene: &Scene) {}
     /// Default implementation returns no emitted radiance for a ray
     /// that escapes the scene bounds.
@@ -12,13 +12,14 @@ use openexr::{FrameBufferMut, InputFile, PixelType};
 use crate::core::geometry::{pnt2_inside_bnd2f, pnt3_distance_squaredf};
 use crate::core::geometry::{Bounds2f, Normal3f, Point2f, Point2i, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::mipmap::{ImageWrap, MipMap};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::cos_theta;
 use crate::core::sampling::{uniform_cone_pdf, uniform_sample_cone};
 use crate::core::scene::Scene;
+use crate::core::spectrum::SpectrumType;
 use crate::core::transform::Transform;
 
 // see https://stackoverflow.com/questions/36008434/how-can-i-decode-f16-to-f32-using-only-the-stable-standard-library
@@ -122,10 +123,13 @@ impl ProjectionLight {
                     let mut texels: Vec<Spectrum> = Vec::new();
                     for idx in 0..(resolution.x * resolution.y) {
                         let (r, g, b) = pixel_data[idx as usize];
-                        texels.push(Spectrum::rgb(
-                            decode_f16(r.to_bits()),
-                            decode_f16(g.to_bits()),
-                            decode_f16(b.to_bits()),
+                        texels.push(Spectrum::from_rgb_type(
+                            &[
+                                decode_f16(r.to_bits()),
+                                decode_f16(g.to_bits()),
+                                decode_f16(b.to_bits()),
+                            ],
+                            SpectrumType::Illuminant,
                         ));
                     }
                     // create _MipMap_ from converted texels (see above)
@@ -246,7 +250,10 @@ impl ProjectionLight {
                     let img_result = hdr.read_image_transform(
                         |p| {
                             let rgb = p.to_hdr();
-                            Spectrum::rgb(rgb[0], rgb[1], rgb[2])
+                            Spectrum::from_rgb_type(
+                                &[rgb[0], rgb[1], rgb[2]],
+                                SpectrumType::Illuminant,
+                            )
                         },
                         &mut texels,
                     );
@@ -396,6 +403,12 @@ impl ProjectionLight {
                 * (1.0 as Float - self.cos_total_width)
         }
     }
+    pub fn bounds(&self) -> Option<LightBounds> {
+        Some(LightBounds::point(
+            &self.p_light,
+            self.power().y().max(0.0 as Float),
+        ))
+    }
     pub fn preprocess(&self, _scene: &Scene) {}
     /// Default implementation returns no emitted radiance for a ray
     /// that escapes the scene bounds.
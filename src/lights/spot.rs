@@ -4,9 +4,9 @@ use std::f32::consts::PI;
 use std::sync::Arc;
 // pbrt
 use crate::core::geometry::pnt3_distance_squaredf;
-use crate::core::geometry::{Normal3f, Point2f, Point3f, Ray, Vector3f};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f};
 use crate::core::interaction::{Interaction, InteractionCommon};
-use crate::core::light::{LightFlags, VisibilityTester};
+use crate::core::light::{LightBounds, LightFlags, VisibilityTester};
 use crate::core::medium::{Medium, MediumInterface};
 use crate::core::pbrt::radians;
 use crate::core::pbrt::{Float, Spectrum};
@@ -110,6 +110,26 @@ impl SpotLight {
             * PI
             * (1.0 as Float - 0.5 as Float * (self.cos_falloff_start + self.cos_total_width))
     }
+    pub fn bounds(&self) -> Option<LightBounds> {
+        // as for point lights, phi is the power for emission into
+        // all directions, the cone limits it to the spot
+        let phi: Float = 4.0 as Float * PI * self.i.y().max(0.0 as Float);
+        let w: Vector3f = self.light_to_world.transform_vector(&Vector3f {
+            x: 0.0,
+            y: 0.0,
+            z: 1.0,
+        });
+        let cos_theta_e: Float =
+            (self.cos_total_width.acos() - self.cos_falloff_start.acos()).cos();
+        Some(LightBounds::new(
+            &Bounds3f::new(self.p_light, self.p_light),
+            &w,
+            phi,
+            self.cos_falloff_start,
+            cos_theta_e,
+            false,
+        ))
+    }
     pub fn preprocess(&self, _scene: &Scene) {}
     /// Default implementation returns no emitted radiance for a ray
     /// that escapes the scene bounds.
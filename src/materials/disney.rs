@@ -169,6 +169,7 @@ impl DisneyMaterial {
                             e,
                             mode,
                             Some(sc),
+                            None,
                         )));
                     } else {
                         bsdf.add(Bxdf::SpecTrans(SpecularTransmission::new(
@@ -177,6 +178,7 @@ impl DisneyMaterial {
                             e,
                             mode,
                             None,
+                            None,
                         )));
                     }
                     // TODO: BSSRDF
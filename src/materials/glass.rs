@@ -7,8 +7,8 @@ use crate::core::microfacet::{MicrofacetDistribution, TrowbridgeReitzDistributio
 use crate::core::paramset::TextureParams;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::reflection::{
-    Bsdf, Bxdf, Fresnel, FresnelDielectric, FresnelSpecular, MicrofacetReflection,
-    MicrofacetTransmission, SpecularReflection, SpecularTransmission,
+    Bsdf, Bxdf, Dispersion, Fresnel, FresnelDielectric, FresnelSpecular, MicrofacetReflection,
+    MicrofacetTransmission, SpecularReflection, SpecularTransmission, ETA_REFERENCE_WAVELENGTH,
 };
 use crate::core::texture::Texture;
 
@@ -16,6 +16,11 @@ use crate::core::texture::Texture;
 
 /// Perfect or glossy specular reflection and transmission, weighted
 /// by Fresnel terms for accurate angular-dependent variation.
+///
+/// Specular transmission can be made wavelength-dependent by
+/// providing Cauchy (`"float cauchy" [A B]`) or Sellmeier (`"float
+/// sellmeier" [B1 B2 B3 C1 C2 C3]`) coefficients (wavelengths in
+/// micrometers), which replace the index of refraction.
 pub struct GlassMaterial {
     pub kr: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 1.0
     pub kt: Arc<dyn Texture<Spectrum> + Sync + Send>, // default: 1.0
@@ -24,6 +29,7 @@ pub struct GlassMaterial {
     pub index: Arc<dyn Texture<Float> + Sync + Send>,
     pub bump_map: Option<Arc<dyn Texture<Float> + Send + Sync>>,
     pub remap_roughness: bool,
+    pub dispersion: Option<Dispersion>,
 }
 
 impl GlassMaterial {
@@ -35,6 +41,7 @@ impl GlassMaterial {
         index: Arc<dyn Texture<Float> + Send + Sync>,
         bump_map: Option<Arc<dyn Texture<Float> + Sync + Send>>,
         remap_roughness: bool,
+        dispersion: Option<Dispersion>,
     ) -> Self {
         GlassMaterial {
             kr,
@@ -44,6 +51,7 @@ impl GlassMaterial {
             index,
             bump_map,
             remap_roughness,
+            dispersion,
         }
     }
     pub fn create(mp: &mut TextureParams) -> Arc<Material> {
@@ -53,6 +61,7 @@ impl GlassMaterial {
         let roughv = mp.get_float_texture("vroughness", 0.0 as Float);
         let bump_map = mp.get_float_texture_or_null("bumpmap");
         let remap_roughness: bool = mp.find_bool("remaproughness", true);
+        let dispersion: Option<Dispersion> = GlassMaterial::find_dispersion(mp);
         let eta_option: Option<Arc<dyn Texture<Float> + Send + Sync>> =
             mp.get_float_texture_or_null("eta");
         if let Some(ref eta) = eta_option {
@@ -64,6 +73,7 @@ impl GlassMaterial {
                 eta.clone(),
                 bump_map,
                 remap_roughness,
+                dispersion,
             ))))
         } else {
             let eta: Arc<dyn Texture<Float> + Send + Sync> =
@@ -76,9 +86,39 @@ impl GlassMaterial {
                 eta,
                 bump_map,
                 remap_roughness,
+                dispersion,
             ))))
         }
     }
+    fn find_dispersion(mp: &mut TextureParams) -> Option<Dispersion> {
+        let cauchy: Vec<Float> = mp.find_floats("cauchy");
+        let sellmeier: Vec<Float> = mp.find_floats("sellmeier");
+        if !sellmeier.is_empty() {
+            if sellmeier.len() == 6 {
+                return Some(Dispersion::Sellmeier {
+                    b: [sellmeier[0], sellmeier[1], sellmeier[2]],
+                    c: [sellmeier[3], sellmeier[4], sellmeier[5]],
+                });
+            }
+            println!(
+                "ERROR: \"sellmeier\" needs 6 values (B1 B2 B3 C1 C2 C3), got {}",
+                sellmeier.len()
+            );
+        }
+        if !cauchy.is_empty() {
+            if cauchy.len() == 2 {
+                return Some(Dispersion::Cauchy {
+                    a: cauchy[0],
+                    b: cauchy[1],
+                });
+            }
+            println!(
+                "ERROR: \"cauchy\" needs 2 values (A B), got {}",
+                cauchy.len()
+            );
+        }
+        None
+    }
     // Material
     pub fn compute_scattering_functions(
         &self,
@@ -109,7 +149,11 @@ impl GlassMaterial {
             .evaluate(si)
             .clamp(0.0 as Float, std::f32::INFINITY as Float);
         let is_specular: bool = urough == 0.0 as Float && vrough == 0.0 as Float;
-        let eta: Float = self.index.evaluate(si);
+        let eta: Float = if let Some(dispersion) = self.dispersion {
+            dispersion.eta(ETA_REFERENCE_WAVELENGTH)
+        } else {
+            self.index.evaluate(si)
+        };
         si.bsdf = Some(Bsdf::new(si, eta));
         if let Some(bsdf) = &mut si.bsdf {
             if is_specular && allow_multiple_lobes {
@@ -121,6 +165,7 @@ impl GlassMaterial {
                         eta,
                         mode,
                         Some(sc),
+                        self.dispersion,
                     )));
                 } else {
                     bsdf.add(Bxdf::FresnelSpec(FresnelSpecular::new(
@@ -130,6 +175,7 @@ impl GlassMaterial {
                         eta,
                         mode,
                         None,
+                        self.dispersion,
                     )));
                 }
             } else {
@@ -179,10 +225,16 @@ impl GlassMaterial {
                                 eta,
                                 mode,
                                 Some(sc),
+                                self.dispersion,
                             )));
                         } else {
                             bsdf.add(Bxdf::SpecTrans(SpecularTransmission::new(
-                                t, 1.0, eta, mode, None,
+                                t,
+                                1.0,
+                                eta,
+                                mode,
+                                None,
+                                self.dispersion,
                             )));
                         }
                     } else {
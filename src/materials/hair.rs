@@ -307,7 +307,7 @@ impl HairBSDF {
     }
     pub fn sigma_a_from_reflectance(c: Spectrum, beta_n: Float) -> Spectrum {
         let mut sigma_a: Spectrum = Spectrum::default();
-        for i in 0..3 {
+        for i in 0..Spectrum::N_SAMPLES {
             let sqr: Float = beta_n * beta_n;
             let pow3: Float = sqr * beta_n;
             let pow4: Float = pow3 * beta_n;
@@ -107,6 +107,7 @@ impl MixMaterial {
                                 bxdf.eta_b,
                                 bxdf.mode,
                                 bxdf.sc_opt,
+                                bxdf.dispersion,
                             )))
                         }
                         Bxdf::FresnelSpec(bxdf) => {
@@ -117,6 +118,7 @@ impl MixMaterial {
                                 bxdf.eta_b,
                                 bxdf.mode,
                                 bxdf.sc_opt,
+                                bxdf.dispersion,
                             )))
                         }
                         Bxdf::LambertianRefl(bxdf) => bsdf1.add(Bxdf::LambertianRefl(
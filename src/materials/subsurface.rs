@@ -169,6 +169,7 @@ impl SubsurfaceMaterial {
                         self.eta,
                         mode,
                         Some(sc),
+                        None,
                     )));
                 } else {
                     bsdf.add(Bxdf::FresnelSpec(FresnelSpecular::new(
@@ -178,6 +179,7 @@ impl SubsurfaceMaterial {
                         self.eta,
                         mode,
                         None,
+                        None,
                     )));
                 }
             } else {
@@ -227,10 +229,11 @@ impl SubsurfaceMaterial {
                                 self.eta,
                                 mode,
                                 Some(sc),
+                                None,
                             )));
                         } else {
                             bsdf.add(Bxdf::SpecTrans(SpecularTransmission::new(
-                                t, 1.0, self.eta, mode, None,
+                                t, 1.0, self.eta, mode, None, None,
                             )));
                         }
                     } else {
@@ -182,10 +182,11 @@ impl UberMaterial {
                         1.0,
                         mode,
                         Some(sc),
+                        None,
                     )));
                 } else {
                     bsdf.add(Bxdf::SpecTrans(SpecularTransmission::new(
-                        t, 1.0, 1.0, mode, None,
+                        t, 1.0, 1.0, mode, None, None,
                     )));
                 }
             }
@@ -247,10 +248,11 @@ impl UberMaterial {
                         e,
                         mode,
                         Some(sc),
+                        None,
                     )));
                 } else {
                     bsdf.add(Bxdf::SpecTrans(SpecularTransmission::new(
-                        kt, 1.0, e, mode, None,
+                        kt, 1.0, e, mode, None, None,
                     )));
                 }
             }
@@ -4,15 +4,114 @@ use std::sync::Arc;
 use crate::core::geometry::pnt3i_inside_exclusive;
 use crate::core::geometry::{Bounds3f, Bounds3i, Point3f, Point3i, Ray, Vector3f, Vector3i};
 use crate::core::interaction::MediumInteraction;
-use crate::core::medium::{HenyeyGreenstein, Medium};
+use crate::core::medium::{HenyeyGreenstein, Medium, MediumProperties};
 use crate::core::pbrt::lerp;
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampler::Sampler;
-use crate::core::spectrum::RGBEnum;
+use crate::core::spectrum::{blackbody_normalized, CIE_LAMBDA, N_CIE_SAMPLES};
 use crate::core::transform::Transform;
+use crate::media::majorant::{ratio_tracking, spectral_tracking};
+use crate::media::majorant::{DdaMajorantIterator, MajorantGrid};
 
 // see grid.h
 
+const MAJORANT_GRID_RES: i32 = 16;
+const BLACKBODY_TABLE_SIZE: usize = 256;
+
+/// Normalized blackbody spectra (see blackbody_normalized()) for
+/// temperatures up to a maximum, interpolated linearly in between.
+pub struct BlackbodyTable {
+    pub max_temperature: Float,
+    pub spectra: Vec<Spectrum>,
+}
+
+impl BlackbodyTable {
+    pub fn new(max_temperature: Float) -> Self {
+        let mut spectra: Vec<Spectrum> = Vec::with_capacity(BLACKBODY_TABLE_SIZE);
+        for i in 0..BLACKBODY_TABLE_SIZE {
+            let t: Float = max_temperature * i as Float / (BLACKBODY_TABLE_SIZE - 1) as Float;
+            if t <= 0.0 as Float {
+                spectra.push(Spectrum::default());
+            } else {
+                let mut v: Vec<Float> = Vec::with_capacity(N_CIE_SAMPLES as usize);
+                blackbody_normalized(&CIE_LAMBDA, N_CIE_SAMPLES as usize, t, &mut v);
+                spectra.push(Spectrum::from_sampled(
+                    &CIE_LAMBDA,
+                    &v,
+                    N_CIE_SAMPLES as i32,
+                ));
+            }
+        }
+        BlackbodyTable {
+            max_temperature,
+            spectra,
+        }
+    }
+    pub fn le(&self, temperature: Float) -> Spectrum {
+        if temperature <= 0.0 as Float || self.max_temperature <= 0.0 as Float {
+            return Spectrum::default();
+        }
+        let x: Float = (temperature / self.max_temperature).min(1.0 as Float)
+            * (BLACKBODY_TABLE_SIZE - 1) as Float;
+        let i: usize = (x as usize).min(BLACKBODY_TABLE_SIZE - 2);
+        lerp(x - i as Float, self.spectra[i], self.spectra[i + 1])
+    }
+}
+
+/// Radiance emitted by a grid medium. It is scaled by the absorption
+/// coefficient at each point, so only absorbing media emit light.
+#[derive(Clone)]
+pub enum GridEmission {
+    None,
+    /// *Le*, optionally scaled per voxel
+    Scaled {
+        le: Spectrum,
+        scale: Option<Arc<Vec<Float>>>,
+    },
+    /// Blackbody emission of a temperature grid. The temperature in
+    /// Kelvin is *(temperature - offset) \* temperature_scale*, the
+    /// normalized blackbody spectrum is multiplied by *le_scale*.
+    Temperature {
+        temperature: Arc<Vec<Float>>,
+        offset: Float,
+        temperature_scale: Float,
+        le_scale: Float,
+        table: Arc<BlackbodyTable>,
+    },
+}
+
+impl GridEmission {
+    pub fn temperature(
+        temperature: Arc<Vec<Float>>,
+        offset: Float,
+        temperature_scale: Float,
+        le_scale: Float,
+    ) -> Self {
+        let mut max_temperature: Float = 0.0 as Float;
+        for t in temperature.iter() {
+            max_temperature = max_temperature.max((t - offset) * temperature_scale);
+        }
+        GridEmission::Temperature {
+            temperature,
+            offset,
+            temperature_scale,
+            le_scale,
+            table: Arc::new(BlackbodyTable::new(max_temperature)),
+        }
+    }
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            GridEmission::None => false,
+            GridEmission::Scaled { le, .. } => !le.is_black(),
+            GridEmission::Temperature { table, .. } => table.max_temperature > 0.0 as Float,
+        }
+    }
+}
+
+/// A heterogeneous medium defined by a regular grid of densities in
+/// [0,1]^3 (medium space). A coarse grid of maximum densities lets
+/// delta and ratio tracking skip empty space.
+#[derive(Clone)]
 pub struct GridDensityMedium {
     pub sigma_a: Spectrum,
     pub sigma_s: Spectrum,
@@ -22,8 +121,9 @@ pub struct GridDensityMedium {
     pub nz: i32,
     pub world_to_medium: Transform,
     pub density: Arc<Vec<Float>>,
-    pub sigma_t: Float,
-    pub inv_max_density: Float,
+    pub emission: GridEmission,
+    pub sigma_t: Spectrum,
+    pub majorant_grid: Arc<MajorantGrid>,
 }
 
 impl GridDensityMedium {
@@ -36,10 +136,35 @@ impl GridDensityMedium {
         nz: i32,
         medium_to_world: &Transform,
         d: Arc<Vec<Float>>,
+        emission: GridEmission,
     ) -> Self {
-        let mut max_density: Float = 0.0;
-        for i in 0..(nx * ny * nz) as usize {
-            max_density = max_density.max(d[i]);
+        // each voxel contributes to the trilinear interpolation up to
+        // the centers of its neighbors
+        let mut majorant_grid: MajorantGrid = MajorantGrid::new(
+            &medium_bounds(),
+            [MAJORANT_GRID_RES, MAJORANT_GRID_RES, MAJORANT_GRID_RES],
+        );
+        for z in 0..nz {
+            for y in 0..ny {
+                for x in 0..nx {
+                    let density: Float = d[((z * ny + y) * nx + x) as usize];
+                    if density > 0.0 as Float {
+                        let voxel_bounds: Bounds3f = Bounds3f::new(
+                            Point3f {
+                                x: (x as Float - 0.5 as Float) / nx as Float,
+                                y: (y as Float - 0.5 as Float) / ny as Float,
+                                z: (z as Float - 0.5 as Float) / nz as Float,
+                            },
+                            Point3f {
+                                x: (x as Float + 1.5 as Float) / nx as Float,
+                                y: (y as Float + 1.5 as Float) / ny as Float,
+                                z: (z as Float + 1.5 as Float) / nz as Float,
+                            },
+                        );
+                        majorant_grid.add_bounds(&voxel_bounds, density);
+                    }
+                }
+            }
         }
         GridDensityMedium {
             sigma_a: *sigma_a,
@@ -50,11 +175,46 @@ impl GridDensityMedium {
             nz,
             world_to_medium: Transform::inverse(medium_to_world),
             density: d,
-            sigma_t: (*sigma_s + *sigma_a)[RGBEnum::Red],
-            inv_max_density: 1.0 as Float / max_density,
+            emission,
+            sigma_t: *sigma_s + *sigma_a,
+            majorant_grid: Arc::new(majorant_grid),
         }
     }
     pub fn d(&self, p: &Point3i) -> Float {
+        self.voxel(&self.density, p)
+    }
+    pub fn density(&self, p: &Point3f) -> Float {
+        self.interpolate(&self.density, p)
+    }
+    /// Radiance emitted at |p| (in medium space) per unit distance.
+    pub fn emitted(&self, p: &Point3f, density: Float) -> Spectrum {
+        self.sigma_a * density * self.le(p)
+    }
+    /// Radiance emitted at |p| (in medium space), before the scaling
+    /// by the absorption coefficient.
+    pub fn le(&self, p: &Point3f) -> Spectrum {
+        match self.emission {
+            GridEmission::None => Spectrum::default(),
+            GridEmission::Scaled { le, ref scale } => {
+                if let Some(scale) = scale {
+                    le * self.interpolate(scale, p)
+                } else {
+                    le
+                }
+            }
+            GridEmission::Temperature {
+                ref temperature,
+                offset,
+                temperature_scale,
+                le_scale,
+                ref table,
+            } => {
+                let t: Float = (self.interpolate(temperature, p) - offset) * temperature_scale;
+                table.le(t) * le_scale
+            }
+        }
+    }
+    fn voxel(&self, grid: &[Float], p: &Point3i) -> Float {
         let sample_bounds: Bounds3i = Bounds3i {
             p_min: Point3i {
                 x: 0_i32,
@@ -70,10 +230,10 @@ impl GridDensityMedium {
         if !pnt3i_inside_exclusive(p, &sample_bounds) {
             0.0 as Float
         } else {
-            self.density[((p.z * self.ny + p.y) * self.nx + p.x) as usize]
+            grid[((p.z * self.ny + p.y) * self.nx + p.x) as usize]
         }
     }
-    pub fn density(&self, p: &Point3f) -> Float {
+    fn interpolate(&self, grid: &[Float], p: &Point3f) -> Float {
         // compute voxel coordinates and offsets for _p_
         let p_samples: Point3f = Point3f {
             x: p.x * self.nx as Float - 0.5 as Float,
@@ -91,61 +251,75 @@ impl GridDensityMedium {
             y: p_samples.y - pi.y as Float,
             z: p_samples.z - pi.z as Float,
         };
-        // trilinearly interpolate density values to compute local density
+        // trilinearly interpolate grid values to compute local value
         let d00: Float = lerp(
             d.x,
-            self.d(&pi),
-            self.d(&(pi
-                + Vector3i {
+            self.voxel(grid, &pi),
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 1_i32,
                     y: 0_i32,
                     z: 0_i32,
-                })),
+                }),
+            ),
         );
         let d10: Float = lerp(
             d.x,
-            self.d(&(pi
-                + Vector3i {
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 0_i32,
                     y: 1_i32,
                     z: 0_i32,
-                })),
-            self.d(&(pi
-                + Vector3i {
+                }),
+            ),
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 1_i32,
                     y: 1_i32,
                     z: 0_i32,
-                })),
+                }),
+            ),
         );
         let d01: Float = lerp(
             d.x,
-            self.d(&(pi
-                + Vector3i {
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 0_i32,
                     y: 0_i32,
                     z: 1_i32,
-                })),
-            self.d(&(pi
-                + Vector3i {
+                }),
+            ),
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 1_i32,
                     y: 0_i32,
                     z: 1_i32,
-                })),
+                }),
+            ),
         );
         let d11: Float = lerp(
             d.x,
-            self.d(&(pi
-                + Vector3i {
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 0_i32,
                     y: 1_i32,
                     z: 1_i32,
-                })),
-            self.d(&(pi
-                + Vector3i {
+                }),
+            ),
+            self.voxel(
+                grid,
+                &(pi + Vector3i {
                     x: 1_i32,
                     y: 1_i32,
                     z: 1_i32,
-                })),
+                }),
+            ),
         );
         let d0: Float = lerp(d.y, d00, d10);
         let d1: Float = lerp(d.y, d01, d11);
@@ -155,63 +329,100 @@ impl GridDensityMedium {
     pub fn tr(&self, r_world: &Ray, sampler: &mut Sampler) -> Spectrum {
         // TODO: ProfilePhase _(Prof::MediumTr);
         // TODO: ++nTrCalls;
-        let mut in_ray: Ray = Ray {
-            o: r_world.o,
-            d: r_world.d.normalize(),
-            ..Default::default()
+        let (ray, t_min, t_max) = match self.medium_ray(r_world) {
+            Some(overlap) => overlap,
+            None => return Spectrum::new(1.0 as Float),
         };
-        *in_ray.t_max.get_mut() = r_world.t_max.get() * r_world.d.length();
-        let ray: Ray = self.world_to_medium.transform_ray(&in_ray);
-        // compute $[\tmin, \tmax]$ interval of _ray_'s overlap with medium bounds
-        let b: Bounds3f = Bounds3f::new(
-            Point3f {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Point3f {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
-            },
-        );
-        let mut t_min: Float = 0.0;
-        let mut t_max: Float = 0.0;
-        // if (!b.IntersectP(ray, &tMin, &tMax)) return Spectrum(1.f);
-        if !b.intersect_b(&ray, &mut t_min, &mut t_max) {
-            return Spectrum::new(1.0 as Float);
-        }
         // perform ratio tracking to estimate the transmittance value
-        let mut tr: Float = 1.0;
-        let mut t: Float = t_min;
-        loop {
-            // TODO: ++nTrSteps;
-            t -= (1.0 as Float - sampler.get_1d()).ln() * self.inv_max_density / self.sigma_t;
-            if t >= t_max {
-                break;
-            }
-            let density: Float = self.density(&ray.position(t));
-            tr *= 1.0 as Float - (0.0 as Float).max(density * self.inv_max_density);
-            // added after book publication: when transmittance gets
-            // low, start applying Russian roulette to terminate
-            // sampling.
-            let rr_threshold: Float = 0.1;
-            if tr < rr_threshold {
-                let q: Float = (0.05 as Float).max(1.0 as Float - tr);
-                if sampler.get_1d() < q {
-                    return Spectrum::default();
-                }
-                tr /= 1.0 as Float - q;
-            }
-        }
-        Spectrum::new(tr)
+        ratio_tracking(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            &self.sigma_t,
+            sampler,
+            |p| self.density(p),
+        )
     }
     pub fn sample(
         &self,
         r_world: &Ray,
         sampler: &mut Sampler,
+        le: &mut Spectrum,
     ) -> (Spectrum, Option<MediumInteraction>) {
         // TODO: ProfilePhase _(Prof::MediumSample);
+        let (ray, t_min, t_max) = match self.medium_ray(r_world) {
+            Some(overlap) => overlap,
+            None => return (Spectrum::new(1.0 as Float), None),
+        };
+        let emitted = |p: &Point3f, density: Float| self.emitted(p, density);
+        let (w, t_opt) = spectral_tracking(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            &self.sigma_t,
+            &self.sigma_s,
+            sampler,
+            |p| self.density(p),
+            if self.emission.is_emissive() {
+                Some(&emitted)
+            } else {
+                None
+            },
+            le,
+        );
+        if let Some(t) = t_opt {
+            // populate _mi_ with medium interaction information and return
+            let mi: MediumInteraction = MediumInteraction::new(
+                &r_world.position(t),
+                &(-r_world.d),
+                r_world.time,
+                Some(Arc::new(Medium::GridDensity(self.clone()))),
+                Some(Arc::new(HenyeyGreenstein { g: self.g })),
+            );
+            return (w, Some(mi));
+        }
+        (w, None)
+    }
+    /// Returns the majorant segments along |ray| (in world space, with
+    /// a normalized direction) up to |t_max| and the ray in medium
+    /// space, which has the same parametrization.
+    pub fn sample_ray(&self, ray: &Ray, t_max: Float) -> Option<(Ray, DdaMajorantIterator<'_>)> {
+        let mut in_ray: Ray = Ray {
+            o: ray.o,
+            d: ray.d,
+            ..Default::default()
+        };
+        *in_ray.t_max.get_mut() = t_max;
+        let (ray, t_min, t_max) = self.medium_ray(&in_ray)?;
+        let iter = DdaMajorantIterator::new(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            self.sigma_t.max_component_value(),
+        );
+        Some((ray, iter))
+    }
+    /// Scattering properties at |p| (in medium space).
+    pub fn sample_point(&self, p: &Point3f) -> MediumProperties {
+        let density: Float = self.density(p);
+        MediumProperties {
+            sigma_a: self.sigma_a * density,
+            sigma_s: self.sigma_s * density,
+            le: if self.emission.is_emissive() {
+                self.le(p)
+            } else {
+                Spectrum::default()
+            },
+            g: self.g,
+        }
+    }
+    /// Transforms |r_world| into medium space with a normalized
+    /// direction and returns it with the parametric range overlapping
+    /// the medium bounds.
+    fn medium_ray(&self, r_world: &Ray) -> Option<(Ray, Float, Float)> {
         let mut in_ray: Ray = Ray {
             o: r_world.o,
             d: r_world.d.normalize(),
@@ -220,54 +431,26 @@ impl GridDensityMedium {
         *in_ray.t_max.get_mut() = r_world.t_max.get() * r_world.d.length();
         let ray: Ray = self.world_to_medium.transform_ray(&in_ray);
         // compute $[\tmin, \tmax]$ interval of _ray_'s overlap with medium bounds
-        let b: Bounds3f = Bounds3f::new(
-            Point3f {
-                x: 0.0,
-                y: 0.0,
-                z: 0.0,
-            },
-            Point3f {
-                x: 1.0,
-                y: 1.0,
-                z: 1.0,
-            },
-        );
         let mut t_min: Float = 0.0;
         let mut t_max: Float = 0.0;
-        if !b.intersect_b(&ray, &mut t_min, &mut t_max) {
-            return (Spectrum::new(1.0 as Float), None);
+        if !medium_bounds().intersect_b(&ray, &mut t_min, &mut t_max) {
+            return None;
         }
-        // run delta-tracking iterations to sample a medium interaction
-        let mut t: Float = t_min;
-        loop {
-            t -= (1.0 as Float - sampler.get_1d()).ln() * self.inv_max_density / self.sigma_t;
-            if t >= t_max {
-                break;
-            }
-            if self.density(&ray.position(t)) * self.inv_max_density > sampler.get_1d() {
-                // populate _mi_ with medium interaction information and return
-                let mi: MediumInteraction = MediumInteraction::new(
-                    &r_world.position(t),
-                    &(-r_world.d),
-                    r_world.time,
-                    Some(Arc::new(Medium::GridDensity(GridDensityMedium {
-                        sigma_a: self.sigma_a,
-                        sigma_s: self.sigma_s,
-                        g: self.g,
-                        nx: self.nx,
-                        ny: self.ny,
-                        nz: self.nz,
-                        world_to_medium: self.world_to_medium,
-                        density: self.density.clone(),
-                        sigma_t: self.sigma_t,
-                        inv_max_density: self.inv_max_density,
-                    }))),
-                    Some(Arc::new(HenyeyGreenstein { g: self.g })),
-                );
-                let mi_opt: Option<MediumInteraction> = Some(mi);
-                return (self.sigma_s / self.sigma_t, mi_opt);
-            }
-        }
-        (Spectrum::new(1.0 as Float), None)
+        Some((ray, t_min, t_max))
     }
 }
+
+fn medium_bounds() -> Bounds3f {
+    Bounds3f::new(
+        Point3f {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        },
+        Point3f {
+            x: 1.0,
+            y: 1.0,
+            z: 1.0,
+        },
+    )
+}
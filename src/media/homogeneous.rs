@@ -2,14 +2,12 @@
 use std::f32;
 use std::sync::Arc;
 // others
-use strum::IntoEnumIterator;
 // pbrt
 use crate::core::geometry::Ray;
 use crate::core::interaction::MediumInteraction;
 use crate::core::medium::{HenyeyGreenstein, Medium};
 use crate::core::pbrt::{Float, Spectrum};
 use crate::core::sampler::Sampler;
-use crate::core::spectrum::RGBEnum;
 
 // see homogeneous.h
 
@@ -18,15 +16,18 @@ pub struct HomogeneousMedium {
     pub sigma_s: Spectrum,
     pub sigma_t: Spectrum,
     pub g: Float,
+    // emitted radiance, scaled by sigma_a
+    pub le: Spectrum,
 }
 
 impl HomogeneousMedium {
-    pub fn new(sigma_a: &Spectrum, sigma_s: &Spectrum, g: Float) -> Self {
+    pub fn new(sigma_a: &Spectrum, sigma_s: &Spectrum, g: Float, le: &Spectrum) -> Self {
         HomogeneousMedium {
             sigma_a: *sigma_a,
             sigma_s: *sigma_s,
             sigma_t: *sigma_s + *sigma_a,
             g,
+            le: *le,
         }
     }
     // Medium
@@ -38,16 +39,13 @@ impl HomogeneousMedium {
         &self,
         ray: &Ray,
         sampler: &mut Sampler,
+        le: &mut Spectrum,
     ) -> (Spectrum, Option<MediumInteraction>) {
         // TODO: ProfilePhase _(Prof::MediumSample);
         // sample a channel and distance along the ray
-        let channel: usize = ((sampler.get_1d() * 3.0 as Float) as usize).min(2_usize);
-        let channel_rgb: RGBEnum = match channel {
-            0 => RGBEnum::Red,
-            1 => RGBEnum::Green,
-            _ => RGBEnum::Blue,
-        };
-        let dist: Float = -((1.0 as Float - sampler.get_1d()).ln()) / self.sigma_t[channel_rgb];
+        let channel: usize = ((sampler.get_1d() * Spectrum::N_SAMPLES as Float) as usize)
+            .min(Spectrum::N_SAMPLES - 1_usize);
+        let dist: Float = -((1.0 as Float - sampler.get_1d()).ln()) / self.sigma_t[channel];
         let t: Float = (dist / ray.d.length()).min(ray.t_max.get());
         let sampled_medium: bool = t < ray.t_max.get();
         let mi_opt = if sampled_medium {
@@ -59,6 +57,7 @@ impl HomogeneousMedium {
                     &self.sigma_a,
                     &self.sigma_s,
                     self.g,
+                    &self.le,
                 )))),
                 Some(Arc::new(HenyeyGreenstein { g: self.g })),
             );
@@ -74,16 +73,17 @@ impl HomogeneousMedium {
             tr
         };
         let mut pdf: Float = 0.0 as Float;
-        for i in RGBEnum::iter() {
-            // TODO: Spectrum::nSamples
+        for i in 0..Spectrum::N_SAMPLES {
             pdf += density[i];
         }
-        pdf *= 1.0 as Float / 3.0 as Float; // TODO: Spectrum::nSamples
+        pdf *= 1.0 as Float / Spectrum::N_SAMPLES as Float;
         if pdf == 0.0 as Float {
             assert!(tr.is_black());
             pdf = 1.0 as Float;
         }
         if sampled_medium {
+            // the sampled distance also estimates the emission along the ray
+            *le += tr * self.sigma_a * self.le / pdf;
             (tr * self.sigma_s / pdf, mi_opt)
         } else {
             (tr / pdf, mi_opt)
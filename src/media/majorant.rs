@@ -0,0 +1,301 @@
+//! Majorant grids bound the extinction coefficient of heterogeneous
+//! media per coarse cell. Delta and ratio tracking then step through
+//! the cells a ray passes with a 3D DDA and use a tight majorant for
+//! each segment, instead of a single one for the whole medium, which
+//! is far too conservative for mostly empty volumes.
+
+// pbrt
+use crate::core::geometry::{Bounds3f, Point3f, Ray, Vector3f};
+use crate::core::pbrt::{clamp_t, Float, Spectrum};
+use crate::core::sampler::Sampler;
+
+// see media.h (pbrt-v4)
+
+/// Maximum densities over a regular grid of cells covering the
+/// bounds of a medium (in medium space).
+pub struct MajorantGrid {
+    pub bounds: Bounds3f,
+    pub res: [i32; 3],
+    pub voxels: Vec<Float>,
+}
+
+impl MajorantGrid {
+    pub fn new(bounds: &Bounds3f, res: [i32; 3]) -> Self {
+        MajorantGrid {
+            bounds: *bounds,
+            res,
+            voxels: vec![0.0 as Float; (res[0] * res[1] * res[2]) as usize],
+        }
+    }
+    pub fn lookup(&self, x: i32, y: i32, z: i32) -> Float {
+        self.voxels[(x + self.res[0] * (y + self.res[1] * z)) as usize]
+    }
+    /// Raises the majorant of all cells overlapping |b| (in medium
+    /// space) to at least |value|.
+    pub fn add_bounds(&mut self, b: &Bounds3f, value: Float) {
+        let p_min: Vector3f = self.bounds.offset(&b.p_min);
+        let p_max: Vector3f = self.bounds.offset(&b.p_max);
+        let mut lo: [i32; 3] = [0; 3];
+        let mut hi: [i32; 3] = [0; 3];
+        for axis in 0..3 {
+            let res: Float = self.res[axis] as Float;
+            let (a, b) = match axis {
+                0 => (p_min.x, p_max.x),
+                1 => (p_min.y, p_max.y),
+                _ => (p_min.z, p_max.z),
+            };
+            if b < 0.0 as Float || a > 1.0 as Float {
+                return;
+            }
+            lo[axis] = clamp_t((a * res).floor() as i32, 0, self.res[axis] - 1);
+            hi[axis] = clamp_t((b * res).floor() as i32, 0, self.res[axis] - 1);
+        }
+        for z in lo[2]..=hi[2] {
+            for y in lo[1]..=hi[1] {
+                for x in lo[0]..=hi[0] {
+                    let i: usize = (x + self.res[0] * (y + self.res[1] * z)) as usize;
+                    self.voxels[i] = self.voxels[i].max(value);
+                }
+            }
+        }
+    }
+}
+
+/// The radiance emitted at a point (in medium space) for a density.
+pub type EmittedFn<'a> = &'a dyn Fn(&Point3f, Float) -> Spectrum;
+
+/// A part of a ray with a constant majorant.
+#[derive(Debug, Copy, Clone)]
+pub struct RayMajorantSegment {
+    pub t_min: Float,
+    pub t_max: Float,
+    pub sigma_maj: Float,
+}
+
+/// Iterates over the majorant grid cells a ray passes through
+/// between *t_min* and *t_max*, front to back.
+pub struct DdaMajorantIterator<'a> {
+    grid: &'a MajorantGrid,
+    sigma_t: Float,
+    t_min: Float,
+    t_max: Float,
+    next_crossing_t: [Float; 3],
+    delta_t: [Float; 3],
+    step: [i32; 3],
+    voxel_limit: [i32; 3],
+    voxel: [i32; 3],
+}
+
+impl<'a> DdaMajorantIterator<'a> {
+    /// The ray is given in medium space, |sigma_t| scales the
+    /// densities of the grid.
+    pub fn new(
+        ray: &Ray,
+        t_min: Float,
+        t_max: Float,
+        grid: &'a MajorantGrid,
+        sigma_t: Float,
+    ) -> Self {
+        // set up the ray in grid space
+        let diag: Vector3f = grid.bounds.diagonal();
+        let o: Vector3f = grid.bounds.offset(&ray.o);
+        let d: [Float; 3] = [ray.d.x / diag.x, ray.d.y / diag.y, ray.d.z / diag.z];
+        let grid_intersect: Vector3f = o + Vector3f {
+            x: d[0],
+            y: d[1],
+            z: d[2],
+        } * t_min;
+        let p: [Float; 3] = [grid_intersect.x, grid_intersect.y, grid_intersect.z];
+        let mut iter: DdaMajorantIterator = DdaMajorantIterator {
+            grid,
+            sigma_t,
+            t_min,
+            t_max,
+            next_crossing_t: [0.0 as Float; 3],
+            delta_t: [0.0 as Float; 3],
+            step: [0; 3],
+            voxel_limit: [0; 3],
+            voxel: [0; 3],
+        };
+        for axis in 0..3 {
+            let res: i32 = grid.res[axis];
+            // initialize ray stepping parameters for _axis_
+            iter.voxel[axis] = clamp_t((p[axis] * res as Float) as i32, 0, res - 1);
+            iter.delta_t[axis] = 1.0 as Float / (d[axis].abs() * res as Float);
+            if d[axis] >= 0.0 as Float {
+                let next_voxel_pos: Float = (iter.voxel[axis] + 1) as Float / res as Float;
+                iter.next_crossing_t[axis] = if d[axis] == 0.0 as Float {
+                    Float::INFINITY
+                } else {
+                    t_min + (next_voxel_pos - p[axis]) / d[axis]
+                };
+                iter.step[axis] = 1;
+                iter.voxel_limit[axis] = res;
+            } else {
+                let next_voxel_pos: Float = iter.voxel[axis] as Float / res as Float;
+                iter.next_crossing_t[axis] = t_min + (next_voxel_pos - p[axis]) / d[axis];
+                iter.step[axis] = -1;
+                iter.voxel_limit[axis] = -1;
+            }
+        }
+        iter
+    }
+}
+
+impl<'a> Iterator for DdaMajorantIterator<'a> {
+    type Item = RayMajorantSegment;
+
+    fn next(&mut self) -> Option<RayMajorantSegment> {
+        if self.t_min >= self.t_max {
+            return None;
+        }
+        // find _step_axis_ for stepping to next voxel and exit point _t_voxel_exit_
+        let mut step_axis: usize = 0;
+        for axis in 1..3 {
+            if self.next_crossing_t[axis] < self.next_crossing_t[step_axis] {
+                step_axis = axis;
+            }
+        }
+        let t_voxel_exit: Float = self.t_max.min(self.next_crossing_t[step_axis]);
+        // get _sigma_maj_ for current voxel and initialize segment
+        let sigma_maj: Float = self.sigma_t
+            * self
+                .grid
+                .lookup(self.voxel[0], self.voxel[1], self.voxel[2]);
+        let segment: RayMajorantSegment = RayMajorantSegment {
+            t_min: self.t_min,
+            t_max: t_voxel_exit,
+            sigma_maj,
+        };
+        // advance to next voxel in maximum density grid
+        self.t_min = t_voxel_exit;
+        if self.next_crossing_t[step_axis] > self.t_max {
+            self.t_min = self.t_max;
+        }
+        self.voxel[step_axis] += self.step[step_axis];
+        if self.voxel[step_axis] == self.voxel_limit[step_axis] {
+            self.t_min = self.t_max;
+        }
+        self.next_crossing_t[step_axis] += self.delta_t[step_axis];
+        Some(segment)
+    }
+}
+
+/// Estimates the transmittance along |ray| (in medium space, with a
+/// normalized direction) between |t_min| and |t_max| by ratio
+/// tracking against the majorants of |grid|.
+pub fn ratio_tracking<F>(
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+    grid: &MajorantGrid,
+    sigma_t: &Spectrum,
+    sampler: &mut Sampler,
+    density: F,
+) -> Spectrum
+where
+    F: Fn(&Point3f) -> Float,
+{
+    let mut tr: Spectrum = Spectrum::new(1.0 as Float);
+    let iter = DdaMajorantIterator::new(ray, t_min, t_max, grid, sigma_t.max_component_value());
+    for segment in iter {
+        // empty cells don't change the transmittance
+        if segment.sigma_maj <= 0.0 as Float {
+            continue;
+        }
+        let mut t: Float = segment.t_min;
+        loop {
+            // TODO: ++nTrSteps;
+            t -= (1.0 as Float - sampler.get_1d()).ln() / segment.sigma_maj;
+            if t >= segment.t_max {
+                break;
+            }
+            let sigma_n: Spectrum =
+                null_coefficient(segment.sigma_maj, sigma_t, density(&ray.position(t)));
+            tr *= sigma_n / segment.sigma_maj;
+            // when transmittance gets low, start applying Russian
+            // roulette to terminate sampling
+            let rr_threshold: Float = 0.1;
+            let tr_max: Float = tr.max_component_value();
+            if tr_max < rr_threshold {
+                let q: Float = (0.05 as Float).max(1.0 as Float - tr_max);
+                if sampler.get_1d() < q {
+                    return Spectrum::default();
+                }
+                tr /= 1.0 as Float - q;
+            }
+        }
+    }
+    tr
+}
+
+/// Samples a scattering event along |ray| (in medium space, with a
+/// normalized direction) between |t_min| and |t_max| with spectral
+/// tracking (Kutz et al. 2017) against the majorants of |grid|: real
+/// and null collisions are chosen proportional to the channel average
+/// of their coefficients times the path weight, which is updated to
+/// account for the difference between the channels. Returns the path
+/// weight and the distance of the scattering event, if any. If
+/// |emitted| is given, it returns the radiance emitted at a point for
+/// a density, and the emission along the ray is added to |le|.
+pub fn spectral_tracking<F>(
+    ray: &Ray,
+    t_min: Float,
+    t_max: Float,
+    grid: &MajorantGrid,
+    sigma_t: &Spectrum,
+    sigma_s: &Spectrum,
+    sampler: &mut Sampler,
+    density: F,
+    emitted: Option<EmittedFn>,
+    le: &mut Spectrum,
+) -> (Spectrum, Option<Float>)
+where
+    F: Fn(&Point3f) -> Float,
+{
+    let mut w: Spectrum = Spectrum::new(1.0 as Float);
+    let iter = DdaMajorantIterator::new(ray, t_min, t_max, grid, sigma_t.max_component_value());
+    for segment in iter {
+        if segment.sigma_maj <= 0.0 as Float {
+            continue;
+        }
+        let sigma_maj: Float = segment.sigma_maj;
+        let mut t: Float = segment.t_min;
+        loop {
+            t -= (1.0 as Float - sampler.get_1d()).ln() / sigma_maj;
+            if t >= segment.t_max {
+                break;
+            }
+            let p: Point3f = ray.position(t);
+            let d: Float = density(&p);
+            if let Some(emitted) = emitted {
+                *le += w * emitted(&p, d) / sigma_maj;
+            }
+            let sigma_n: Spectrum = null_coefficient(sigma_maj, sigma_t, d);
+            let p_real: Float = average(&(w * *sigma_t * d));
+            let p_null: Float = average(&(w * sigma_n));
+            if p_real + p_null <= 0.0 as Float {
+                return (Spectrum::default(), None);
+            }
+            if sampler.get_1d() * (p_real + p_null) < p_real {
+                w *= *sigma_s * d * ((p_real + p_null) / (p_real * sigma_maj));
+                return (w, Some(t));
+            }
+            w *= sigma_n * ((p_real + p_null) / (p_null * sigma_maj));
+        }
+    }
+    (w, None)
+}
+
+fn null_coefficient(sigma_maj: Float, sigma_t: &Spectrum, density: Float) -> Spectrum {
+    (Spectrum::new(sigma_maj) - *sigma_t * density).clamp(0.0 as Float, Float::INFINITY)
+}
+
+/// The average of the channels of |s|.
+pub fn average(s: &Spectrum) -> Float {
+    let mut sum: Float = 0.0 as Float;
+    for i in 0..Spectrum::N_SAMPLES {
+        sum += s[i];
+    }
+    sum / Spectrum::N_SAMPLES as Float
+}
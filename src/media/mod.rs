@@ -4,6 +4,7 @@
 //!
 //! - GridDensityMedium
 //! - HomogeneousMedium
+//! - NanoVdbMedium
 //!
 //! ## Grid Density Medium
 //!
@@ -12,6 +13,13 @@
 //! ## Homogeneous Medium
 //!
 //! ![A Volumetric Caustic](/doc/img/volume_caustic_pbrt_rust_mlt.png)
+//!
+//! ## NanoVDB Medium
+//!
+//! Sparse density grids (and optionally temperature or emission
+//! grids) read from a NanoVDB file, e.g. from a fluid simulation.
 
 pub mod grid;
 pub mod homogeneous;
+pub mod majorant;
+pub mod nanovdb;
@@ -0,0 +1,294 @@
+// std
+use std::sync::Arc;
+// pbrt
+use crate::core::geometry::bnd3_union_pnt3f;
+use crate::core::geometry::{Bounds3f, Point3f, Point3i, Ray};
+use crate::core::interaction::MediumInteraction;
+use crate::core::medium::{HenyeyGreenstein, Medium, MediumProperties};
+use crate::core::nanovdb::NanoVdbGrid;
+use crate::core::pbrt::{Float, Spectrum};
+use crate::core::sampler::Sampler;
+use crate::core::transform::Transform;
+use crate::media::grid::BlackbodyTable;
+use crate::media::majorant::{ratio_tracking, spectral_tracking};
+use crate::media::majorant::{DdaMajorantIterator, MajorantGrid};
+
+// see media.h (pbrt-v4)
+
+const MAJORANT_GRID_RES: i32 = 64;
+
+/// Radiance emitted by a NanoVDB medium. Like for the
+/// **GridDensityMedium** it is scaled by the absorption coefficient.
+#[derive(Clone)]
+pub enum VdbEmission {
+    None,
+    /// *Le*, optionally scaled by a grid
+    Scaled {
+        le: Spectrum,
+        scale: Option<Arc<NanoVdbGrid>>,
+    },
+    /// Blackbody emission of a temperature grid (see **GridEmission**)
+    Temperature {
+        temperature: Arc<NanoVdbGrid>,
+        offset: Float,
+        temperature_scale: Float,
+        le_scale: Float,
+        table: Arc<BlackbodyTable>,
+    },
+}
+
+impl VdbEmission {
+    pub fn temperature(
+        temperature: Arc<NanoVdbGrid>,
+        offset: Float,
+        temperature_scale: Float,
+        le_scale: Float,
+    ) -> Self {
+        let mut max_temperature: Float = 0.0 as Float;
+        temperature.for_each_block(|_p_min, _p_max, value| {
+            max_temperature = max_temperature.max((value - offset) * temperature_scale);
+        });
+        VdbEmission::Temperature {
+            temperature,
+            offset,
+            temperature_scale,
+            le_scale,
+            table: Arc::new(BlackbodyTable::new(max_temperature)),
+        }
+    }
+    pub fn is_emissive(&self) -> bool {
+        match self {
+            VdbEmission::None => false,
+            VdbEmission::Scaled { le, .. } => !le.is_black(),
+            VdbEmission::Temperature { table, .. } => table.max_temperature > 0.0 as Float,
+        }
+    }
+}
+
+/// A heterogeneous medium defined by sparse grids of a NanoVDB file.
+/// Medium space is the world space of the grids. A coarse grid of
+/// maximum densities lets delta and ratio tracking skip empty space.
+#[derive(Clone)]
+pub struct NanoVdbMedium {
+    pub sigma_a: Spectrum,
+    pub sigma_s: Spectrum,
+    pub sigma_t: Spectrum,
+    pub g: Float,
+    pub world_to_medium: Transform,
+    pub bounds: Bounds3f,
+    pub density: Arc<NanoVdbGrid>,
+    pub emission: VdbEmission,
+    pub majorant_grid: Arc<MajorantGrid>,
+}
+
+impl NanoVdbMedium {
+    pub fn new(
+        sigma_a: &Spectrum,
+        sigma_s: &Spectrum,
+        g: Float,
+        medium_to_world: &Transform,
+        density: Arc<NanoVdbGrid>,
+        emission: VdbEmission,
+    ) -> Self {
+        // bounds of the active voxels including the support of the
+        // trilinear interpolation
+        let bounds: Bounds3f =
+            index_bounds_to_world(&density, &density.index_bbox[0], &density.index_bbox[1]);
+        let mut majorant_grid: MajorantGrid = MajorantGrid::new(
+            &bounds,
+            [MAJORANT_GRID_RES, MAJORANT_GRID_RES, MAJORANT_GRID_RES],
+        );
+        density.for_each_block(|p_min, p_max, max_density| {
+            if max_density > 0.0 as Float {
+                let block_bounds: Bounds3f = index_bounds_to_world(&density, p_min, p_max);
+                majorant_grid.add_bounds(&block_bounds, max_density);
+            }
+        });
+        if density.background > 0.0 as Float {
+            println!(
+                "WARNING: Background density {} of NanoVDB grid \"{}\" is ignored outside of its active voxels.",
+                density.background, density.name
+            );
+        }
+        NanoVdbMedium {
+            sigma_a: *sigma_a,
+            sigma_s: *sigma_s,
+            sigma_t: *sigma_a + *sigma_s,
+            g,
+            world_to_medium: Transform::inverse(medium_to_world),
+            bounds,
+            density,
+            emission,
+            majorant_grid: Arc::new(majorant_grid),
+        }
+    }
+    pub fn density(&self, p: &Point3f) -> Float {
+        self.density.sample(p).max(0.0 as Float)
+    }
+    /// Radiance emitted at |p| (in medium space) per unit distance.
+    pub fn emitted(&self, p: &Point3f, density: Float) -> Spectrum {
+        self.sigma_a * density * self.le(p)
+    }
+    /// Radiance emitted at |p| (in medium space), before the scaling
+    /// by the absorption coefficient.
+    pub fn le(&self, p: &Point3f) -> Spectrum {
+        match self.emission {
+            VdbEmission::None => Spectrum::default(),
+            VdbEmission::Scaled { le, ref scale } => {
+                if let Some(scale) = scale {
+                    le * scale.sample(p).max(0.0 as Float)
+                } else {
+                    le
+                }
+            }
+            VdbEmission::Temperature {
+                ref temperature,
+                offset,
+                temperature_scale,
+                le_scale,
+                ref table,
+            } => {
+                let t: Float = (temperature.sample(p) - offset) * temperature_scale;
+                table.le(t) * le_scale
+            }
+        }
+    }
+    // Medium
+    pub fn tr(&self, r_world: &Ray, sampler: &mut Sampler) -> Spectrum {
+        // TODO: ProfilePhase _(Prof::MediumTr);
+        let (ray, t_min, t_max) = match self.medium_ray(r_world) {
+            Some(overlap) => overlap,
+            None => return Spectrum::new(1.0 as Float),
+        };
+        ratio_tracking(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            &self.sigma_t,
+            sampler,
+            |p| self.density(p),
+        )
+    }
+    pub fn sample(
+        &self,
+        r_world: &Ray,
+        sampler: &mut Sampler,
+        le: &mut Spectrum,
+    ) -> (Spectrum, Option<MediumInteraction>) {
+        // TODO: ProfilePhase _(Prof::MediumSample);
+        let (ray, t_min, t_max) = match self.medium_ray(r_world) {
+            Some(overlap) => overlap,
+            None => return (Spectrum::new(1.0 as Float), None),
+        };
+        let emitted = |p: &Point3f, density: Float| self.emitted(p, density);
+        let (w, t_opt) = spectral_tracking(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            &self.sigma_t,
+            &self.sigma_s,
+            sampler,
+            |p| self.density(p),
+            if self.emission.is_emissive() {
+                Some(&emitted)
+            } else {
+                None
+            },
+            le,
+        );
+        if let Some(t) = t_opt {
+            // populate _mi_ with medium interaction information and return
+            let mi: MediumInteraction = MediumInteraction::new(
+                &r_world.position(t),
+                &(-r_world.d),
+                r_world.time,
+                Some(Arc::new(Medium::NanoVdb(self.clone()))),
+                Some(Arc::new(HenyeyGreenstein { g: self.g })),
+            );
+            return (w, Some(mi));
+        }
+        (w, None)
+    }
+    /// Returns the majorant segments along |ray| (in world space, with
+    /// a normalized direction) up to |t_max| and the ray in medium
+    /// space, which has the same parametrization.
+    pub fn sample_ray(&self, ray: &Ray, t_max: Float) -> Option<(Ray, DdaMajorantIterator<'_>)> {
+        let mut in_ray: Ray = Ray {
+            o: ray.o,
+            d: ray.d,
+            ..Default::default()
+        };
+        *in_ray.t_max.get_mut() = t_max;
+        let (ray, t_min, t_max) = self.medium_ray(&in_ray)?;
+        let iter = DdaMajorantIterator::new(
+            &ray,
+            t_min,
+            t_max,
+            &self.majorant_grid,
+            self.sigma_t.max_component_value(),
+        );
+        Some((ray, iter))
+    }
+    /// Scattering properties at |p| (in medium space).
+    pub fn sample_point(&self, p: &Point3f) -> MediumProperties {
+        let density: Float = self.density(p);
+        MediumProperties {
+            sigma_a: self.sigma_a * density,
+            sigma_s: self.sigma_s * density,
+            le: if self.emission.is_emissive() {
+                self.le(p)
+            } else {
+                Spectrum::default()
+            },
+            g: self.g,
+        }
+    }
+    /// Transforms |r_world| into medium space with a normalized
+    /// direction and returns it with the parametric range overlapping
+    /// the medium bounds.
+    fn medium_ray(&self, r_world: &Ray) -> Option<(Ray, Float, Float)> {
+        let mut in_ray: Ray = Ray {
+            o: r_world.o,
+            d: r_world.d.normalize(),
+            ..Default::default()
+        };
+        *in_ray.t_max.get_mut() = r_world.t_max.get() * r_world.d.length();
+        let ray: Ray = self.world_to_medium.transform_ray(&in_ray);
+        let mut t_min: Float = 0.0;
+        let mut t_max: Float = 0.0;
+        if !self.bounds.intersect_b(&ray, &mut t_min, &mut t_max) {
+            return None;
+        }
+        Some((ray, t_min, t_max))
+    }
+}
+
+/// World space bounds of the (inclusive) index space bounds of
+/// voxels, grown by one voxel for the support of the trilinear
+/// interpolation.
+fn index_bounds_to_world(grid: &NanoVdbGrid, p_min: &Point3i, p_max: &Point3i) -> Bounds3f {
+    let mut bounds: Bounds3f = Bounds3f::default();
+    for corner in 0..8 {
+        let p: Point3f = Point3f {
+            x: if corner & 1 == 0 {
+                p_min.x - 1
+            } else {
+                p_max.x + 1
+            } as Float,
+            y: if corner & 2 == 0 {
+                p_min.y - 1
+            } else {
+                p_max.y + 1
+            } as Float,
+            z: if corner & 4 == 0 {
+                p_min.z - 1
+            } else {
+                p_max.z + 1
+            } as Float,
+        };
+        bounds = bnd3_union_pnt3f(&bounds, &grid.index_to_world(&p));
+    }
+    bounds
+}
@@ -1,6 +1,8 @@
 // pbrt
 use crate::core::geometry::{Point2f, Point2i};
-use crate::core::lowdiscrepancy::{sobol_2d, van_der_corput};
+use crate::core::lowdiscrepancy::{
+    sobol_2d, sobol_2d_nested, van_der_corput, van_der_corput_nested,
+};
 use crate::core::paramset::ParamSet;
 use crate::core::pbrt::round_up_pow2_32;
 use crate::core::pbrt::Float;
@@ -12,6 +14,8 @@ use crate::core::sampler::Sampler;
 pub struct ZeroTwoSequenceSampler {
     pub samples_per_pixel: i64,
     pub n_sampled_dimensions: i64,
+    /// keep power-of-two prefixes of the pixel samples stratified
+    pub nested: bool,
     // inherited from class PixelSampler (see sampler.h)
     samples_1d: Vec<Vec<Float>>,
     samples_2d: Vec<Vec<Point2f>>,
@@ -34,6 +38,7 @@ impl Default for ZeroTwoSequenceSampler {
         let mut lds: ZeroTwoSequenceSampler = ZeroTwoSequenceSampler {
             samples_per_pixel: 1_i64,
             n_sampled_dimensions: 4_i64,
+            nested: false,
             samples_1d: Vec::new(),
             samples_2d: Vec::new(),
             current_1d_dimension: 0_i32,
@@ -64,6 +69,7 @@ impl ZeroTwoSequenceSampler {
         let mut lds: ZeroTwoSequenceSampler = ZeroTwoSequenceSampler {
             samples_per_pixel,
             n_sampled_dimensions,
+            nested: false,
             samples_1d: Vec::new(),
             samples_2d: Vec::new(),
             current_1d_dimension: 0_i32,
@@ -91,6 +97,7 @@ impl ZeroTwoSequenceSampler {
         let mut zero_two_sampler = ZeroTwoSequenceSampler {
             samples_per_pixel: self.samples_per_pixel,
             n_sampled_dimensions: self.n_sampled_dimensions,
+            nested: self.nested,
             samples_1d: self.samples_1d.to_vec(),
             samples_2d: self.samples_2d.to_vec(),
             current_1d_dimension: self.current_1d_dimension,
@@ -121,17 +128,28 @@ impl ZeroTwoSequenceSampler {
     // Sampler
     pub fn start_pixel(&mut self, p: Point2i) {
         // TODO: ProfilePhase _(Prof::StartPixel);
+        // nested shuffling keeps power-of-two prefixes stratified
+        let gen_1d: fn(i32, i32, &mut [Float], &mut Rng) = if self.nested {
+            van_der_corput_nested
+        } else {
+            van_der_corput
+        };
+        let gen_2d: fn(i32, i32, &mut [Point2f], &mut Rng) = if self.nested {
+            sobol_2d_nested
+        } else {
+            sobol_2d
+        };
         // generate 1D and 2D pixel sample components using $(0,2)$-sequence
         for samples in &mut self.samples_1d {
-            van_der_corput(1, self.samples_per_pixel as i32, samples, &mut self.rng);
+            gen_1d(1, self.samples_per_pixel as i32, samples, &mut self.rng);
         }
         for samples in &mut self.samples_2d {
-            sobol_2d(1, self.samples_per_pixel as i32, samples, &mut self.rng);
+            gen_2d(1, self.samples_per_pixel as i32, samples, &mut self.rng);
         }
         // generate 1D and 2D array samples using $(0,2)$-sequence
         for i in 0..self.samples_1d_array_sizes.len() {
             let samples: &mut [Float] = self.sample_array_1d[i].as_mut_slice();
-            van_der_corput(
+            gen_1d(
                 self.samples_1d_array_sizes[i],
                 self.samples_per_pixel as i32,
                 samples,
@@ -140,7 +158,7 @@ impl ZeroTwoSequenceSampler {
         }
         for i in 0..self.samples_2d_array_sizes.len() {
             let samples: &mut [Point2f] = self.sample_array_2d[i].as_mut_slice();
-            sobol_2d(
+            gen_2d(
                 self.samples_2d_array_sizes[i],
                 self.samples_per_pixel as i32,
                 samples,
@@ -226,6 +244,16 @@ impl ZeroTwoSequenceSampler {
         self.current_pixel_sample_index += 1_i64;
         self.current_pixel_sample_index < self.samples_per_pixel
     }
+    pub fn set_sample_number(&mut self, sample_num: i64) -> bool {
+        self.current_1d_dimension = 0_i32;
+        self.current_2d_dimension = 0_i32;
+        // Sampler::SetSampleNumber(...)
+        // reset array offsets for next pixel sample
+        self.array_1d_offset = 0_usize;
+        self.array_2d_offset = 0_usize;
+        self.current_pixel_sample_index = sample_num;
+        self.current_pixel_sample_index < self.samples_per_pixel
+    }
     pub fn reseed(&mut self, seed: u64) {
         self.rng.set_sequence(seed);
     }
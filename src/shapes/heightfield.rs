@@ -0,0 +1,426 @@
+// std
+use std::sync::{Arc, OnceLock};
+// pbrt
+use crate::core::geometry::{
+    nrm_abs_dot_vec3f, pnt3_distance_squaredf, vec3_coordinate_system, vec3_cross_vec3,
+};
+use crate::core::geometry::{
+    Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector2f, Vector3f, XYEnum,
+};
+use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
+use crate::core::material::Material;
+use crate::core::pbrt::{clamp_t, gamma, Float};
+use crate::core::sampling::Distribution1D;
+use crate::core::transform::Transform;
+use crate::shapes::triangle::intersect_triangle;
+
+// see heightfield.cpp
+
+/// A regular grid of *nu* by *nv* heights (*Pz*) over $[0,1]^2$ in
+/// object space. Each grid cell is split into two triangles (the same
+/// triangulation pbrt-v3 uses when it converts a heightfield into a
+/// triangle mesh), but the triangles are generated on the fly while
+/// the ray walks through the grid cells, so only the heights are
+/// stored.
+pub struct Heightfield {
+    pub nu: usize,
+    pub nv: usize,
+    /// *nu* x *nv* heights, row by row
+    pub z: Vec<Float>,
+    pub z_min: Float,
+    pub z_max: Float,
+    /// Per triangle world space areas, built on first use (only
+    /// needed if the heightfield is used as an area light)
+    area_distrib: OnceLock<Distribution1D>,
+    // inherited from class Shape (see shape.h)
+    pub object_to_world: Transform,
+    pub world_to_object: Transform,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+    pub material: Option<Arc<Material>>,
+}
+
+impl Heightfield {
+    pub fn new(
+        object_to_world: Transform,
+        world_to_object: Transform,
+        reverse_orientation: bool,
+        nu: usize,
+        nv: usize,
+        z: Vec<Float>,
+    ) -> Self {
+        assert!(nu >= 2 && nv >= 2 && z.len() == nu * nv);
+        let z_min: Float = z.iter().fold(Float::INFINITY, |a, b| a.min(*b));
+        let z_max: Float = z.iter().fold(-Float::INFINITY, |a, b| a.max(*b));
+        Heightfield {
+            // Shape
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+            transform_swaps_handedness: object_to_world.swaps_handedness(),
+            // Heightfield
+            nu,
+            nv,
+            z,
+            z_min,
+            z_max,
+            area_distrib: OnceLock::new(),
+            material: None,
+        }
+    }
+    /// Object space position of grid vertex (*u*, *v*).
+    fn vertex(&self, u: usize, v: usize) -> Point3f {
+        Point3f {
+            x: u as Float / (self.nu - 1) as Float,
+            y: v as Float / (self.nv - 1) as Float,
+            z: self.z[v * self.nu + u],
+        }
+    }
+    /// The object space vertices of triangle *tri*. Triangles are
+    /// numbered two per cell, cells row by row.
+    fn triangle(&self, tri: usize) -> [Point3f; 3] {
+        let cell: usize = tri / 2;
+        let u: usize = cell % (self.nu - 1);
+        let v: usize = cell / (self.nu - 1);
+        if tri % 2 == 0 {
+            [
+                self.vertex(u, v),
+                self.vertex(u + 1, v),
+                self.vertex(u + 1, v + 1),
+            ]
+        } else {
+            [
+                self.vertex(u, v),
+                self.vertex(u + 1, v + 1),
+                self.vertex(u, v + 1),
+            ]
+        }
+    }
+    fn n_triangles(&self) -> usize {
+        2 * (self.nu - 1) * (self.nv - 1)
+    }
+    fn get_area_distrib(&self) -> &Distribution1D {
+        self.area_distrib.get_or_init(|| {
+            let areas: Vec<Float> = (0..self.n_triangles())
+                .map(|tri| {
+                    let p: [Point3f; 3] = self.triangle(tri);
+                    let p0: Point3f = self.object_to_world.transform_point(&p[0]);
+                    let p1: Point3f = self.object_to_world.transform_point(&p[1]);
+                    let p2: Point3f = self.object_to_world.transform_point(&p[2]);
+                    0.5 as Float * vec3_cross_vec3(&(p1 - p0), &(p2 - p0)).length()
+                })
+                .collect();
+            Distribution1D::new(areas)
+        })
+    }
+    /// Walk through the grid cells pierced by the (object space) ray
+    /// and test the two triangles of each cell. Cells are visited in
+    /// front to back order, so the first hit is the closest one.
+    /// Returns the parametric distance, the triangle vertices and the
+    /// barycentric coordinates of the hit point.
+    fn find_hit(&self, ray: &Ray) -> Option<(Float, [Point3f; 3], [Float; 3])> {
+        let mut t0: Float = 0.0;
+        let mut t1: Float = 0.0;
+        if !self.object_bound().intersect_b(ray, &mut t0, &mut t1) {
+            return None;
+        }
+        let nx: usize = self.nu - 1;
+        let ny: usize = self.nv - 1;
+        let p_entry: Point3f = ray.position(t0);
+        // set up 2D DDA for ray through the grid cells
+        let mut cell: [i64; 2] = [
+            clamp_t((p_entry.x * nx as Float) as i64, 0, nx as i64 - 1),
+            clamp_t((p_entry.y * ny as Float) as i64, 0, ny as i64 - 1),
+        ];
+        let n_cells: [i64; 2] = [nx as i64, ny as i64];
+        let origin: [Float; 2] = [p_entry.x, p_entry.y];
+        let dir: [Float; 2] = [ray.d.x, ray.d.y];
+        let mut next_crossing_t: [Float; 2] = [Float::INFINITY; 2];
+        let mut delta_t: [Float; 2] = [0.0; 2];
+        let mut step: [i64; 2] = [0; 2];
+        let mut out: [i64; 2] = [0; 2];
+        for axis in 0..2 {
+            let width: Float = 1.0 as Float / n_cells[axis] as Float;
+            if dir[axis] > 0.0 as Float {
+                let next_pos: Float = (cell[axis] + 1) as Float * width;
+                next_crossing_t[axis] = t0 + (next_pos - origin[axis]) / dir[axis];
+                delta_t[axis] = width / dir[axis];
+                step[axis] = 1;
+                out[axis] = n_cells[axis];
+            } else if dir[axis] < 0.0 as Float {
+                let next_pos: Float = cell[axis] as Float * width;
+                next_crossing_t[axis] = t0 + (next_pos - origin[axis]) / dir[axis];
+                delta_t[axis] = -width / dir[axis];
+                step[axis] = -1;
+                out[axis] = -1;
+            } else {
+                out[axis] = -1;
+            }
+        }
+        let mut t_enter: Float = t0;
+        loop {
+            let t_exit: Float = next_crossing_t[0].min(next_crossing_t[1]).min(t1);
+            // skip the cell if the ray passes above or below it
+            let u: usize = cell[0] as usize;
+            let v: usize = cell[1] as usize;
+            let z00: Float = self.z[v * self.nu + u];
+            let z10: Float = self.z[v * self.nu + u + 1];
+            let z01: Float = self.z[(v + 1) * self.nu + u];
+            let z11: Float = self.z[(v + 1) * self.nu + u + 1];
+            let cell_z_min: Float = z00.min(z10).min(z01.min(z11));
+            let cell_z_max: Float = z00.max(z10).max(z01.max(z11));
+            let ray_z0: Float = ray.o.z + t_enter * ray.d.z;
+            let ray_z1: Float = ray.o.z + t_exit * ray.d.z;
+            // be conservative, the DDA's $t$ values are not exact and
+            // the ray's $z$ values carry the rounding error of the
+            // origin's magnitude
+            let eps: Float = 1e-3 as Float * (ray_z1 - ray_z0).abs()
+                + gamma(7_i32)
+                    * (ray.o.z.abs()
+                        + (t_enter * ray.d.z).abs().max((t_exit * ray.d.z).abs())
+                        + cell_z_max.abs().max(cell_z_min.abs())
+                        + 1.0 as Float);
+            if ray_z0.min(ray_z1) <= cell_z_max + eps && ray_z0.max(ray_z1) >= cell_z_min - eps {
+                let cell_index: usize = v * nx + u;
+                let mut hit: Option<(Float, [Point3f; 3], [Float; 3])> = None;
+                for tri in [2 * cell_index, 2 * cell_index + 1] {
+                    let p: [Point3f; 3] = self.triangle(tri);
+                    if let Some((t, b)) = intersect_triangle(ray, &p) {
+                        let closer: bool = match hit {
+                            Some(h) => t < h.0,
+                            None => true,
+                        };
+                        if closer {
+                            hit = Some((t, p, b));
+                        }
+                    }
+                }
+                if hit.is_some() {
+                    return hit;
+                }
+            }
+            // advance to next grid cell
+            let axis: usize = if next_crossing_t[0] < next_crossing_t[1] {
+                0
+            } else {
+                1
+            };
+            if t1 < next_crossing_t[axis] {
+                return None;
+            }
+            cell[axis] += step[axis];
+            if cell[axis] == out[axis] {
+                return None;
+            }
+            t_enter = next_crossing_t[axis];
+            next_crossing_t[axis] += delta_t[axis];
+        }
+    }
+    // Shape
+    pub fn object_bound(&self) -> Bounds3f {
+        Bounds3f {
+            p_min: Point3f {
+                x: 0.0,
+                y: 0.0,
+                z: self.z_min,
+            },
+            p_max: Point3f {
+                x: 1.0,
+                y: 1.0,
+                z: self.z_max,
+            },
+        }
+    }
+    pub fn world_bound(&self) -> Bounds3f {
+        // in C++: Bounds3f Shape::WorldBound() const { return (*ObjectToWorld)(ObjectBound()); }
+        self.object_to_world.transform_bounds(&self.object_bound())
+    }
+    pub fn intersect(&self, r: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        let (t, p, b) = match self.find_hit(&ray) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        // the grid's $(x,y)$ coordinates are the $(u,v)$ parameterization
+        let uv: [Point2f; 3] = [
+            Point2f {
+                x: p[0].x,
+                y: p[0].y,
+            },
+            Point2f {
+                x: p[1].x,
+                y: p[1].y,
+            },
+            Point2f {
+                x: p[2].x,
+                y: p[2].y,
+            },
+        ];
+        // compute deltas for triangle partial derivatives
+        let duv02: Vector2f = uv[0] - uv[2];
+        let duv12: Vector2f = uv[1] - uv[2];
+        let dp02: Vector3f = p[0] - p[2];
+        let dp12: Vector3f = p[1] - p[2];
+        let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
+        let mut dpdu: Vector3f = Vector3f::default();
+        let mut dpdv: Vector3f = Vector3f::default();
+        if determinant.abs() < 1e-8 as Float {
+            vec3_coordinate_system(
+                &vec3_cross_vec3(&dp02, &dp12).normalize(),
+                &mut dpdu,
+                &mut dpdv,
+            );
+        } else {
+            let invdet: Float = 1.0 / determinant;
+            dpdu = (dp02 * duv12.y - dp12 * duv02.y) * invdet;
+            dpdv = (dp02 * -duv12.x + dp12 * duv02.x) * invdet;
+        }
+        // compute error bounds for triangle intersection
+        let x_abs_sum: Float =
+            (b[0] * p[0].x).abs() + (b[1] * p[1].x).abs() + (b[2] * p[2].x).abs();
+        let y_abs_sum: Float =
+            (b[0] * p[0].y).abs() + (b[1] * p[1].y).abs() + (b[2] * p[2].y).abs();
+        let z_abs_sum: Float =
+            (b[0] * p[0].z).abs() + (b[1] * p[1].z).abs() + (b[2] * p[2].z).abs();
+        let p_error: Vector3f = Vector3f {
+            x: x_abs_sum,
+            y: y_abs_sum,
+            z: z_abs_sum,
+        } * gamma(7);
+        // interpolate $(u,v)$ parametric coordinates and hit point
+        let p_hit: Point3f = p[0] * b[0] + p[1] * b[1] + p[2] * b[2];
+        let uv_hit: Point2f = uv[0] * b[0] + uv[1] * b[1] + uv[2] * b[2];
+        // initialize _SurfaceInteraction_ from parametric information
+        let wo: Vector3f = -ray.d;
+        *isect = SurfaceInteraction::new(
+            &p_hit,
+            &p_error,
+            uv_hit,
+            &wo,
+            &dpdu,
+            &dpdv,
+            &Normal3f::default(),
+            &Normal3f::default(),
+            ray.time,
+            None,
+        );
+        self.object_to_world.transform_surface_interaction(isect);
+        // override surface normal in _isect_ like for triangles
+        let mut surface_normal: Normal3f =
+            Normal3f::from(vec3_cross_vec3(&isect.dpdu, &isect.dpdv).normalize());
+        if self.reverse_orientation ^ self.transform_swaps_handedness {
+            surface_normal = -surface_normal;
+        }
+        isect.common.n = surface_normal;
+        isect.shading.n = surface_normal;
+        *t_hit = t;
+        true
+    }
+    pub fn intersect_p(&self, r: &Ray) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        self.find_hit(&ray).is_some()
+    }
+    pub fn get_reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+    pub fn get_transform_swaps_handedness(&self) -> bool {
+        self.transform_swaps_handedness
+    }
+    pub fn get_object_to_world(&self) -> Transform {
+        self.object_to_world
+    }
+    pub fn area(&self) -> Float {
+        let distrib: &Distribution1D = self.get_area_distrib();
+        distrib.func_int * distrib.count() as Float
+    }
+    /// Uniformly sample a point on the heightfield by first selecting
+    /// a triangle proportional to its area and then a point within it.
+    pub fn sample(&self, u: Point2f, pdf: &mut Float) -> InteractionCommon {
+        let distrib: &Distribution1D = self.get_area_distrib();
+        let tri: usize = distrib.sample_discrete(u[XYEnum::X], None);
+        // remap the sample to $[0,1)$ within the selected triangle
+        let cdf_width: Float = distrib.cdf[tri + 1] - distrib.cdf[tri];
+        let u0: Float = if cdf_width > 0.0 as Float {
+            ((u[XYEnum::X] - distrib.cdf[tri]) / cdf_width).min(1.0 as Float)
+        } else {
+            0.0 as Float
+        };
+        let su0: Float = u0.sqrt();
+        let bx: Float = 1.0 as Float - su0;
+        let by: Float = u[XYEnum::Y] * su0;
+        let p: [Point3f; 3] = self.triangle(tri);
+        let p_obj: Point3f = p[0] * bx + p[1] * by + p[2] * (1.0 as Float - bx - by);
+        let n_obj: Normal3f = Normal3f::from(vec3_cross_vec3(&(p[1] - p[0]), &(p[2] - p[0])));
+        let mut it: InteractionCommon = InteractionCommon {
+            n: self.object_to_world.transform_normal(&n_obj).normalize(),
+            ..Default::default()
+        };
+        if self.reverse_orientation {
+            it.n *= -1.0 as Float;
+        }
+        let p_obj_error: Vector3f = Vector3f {
+            x: p_obj.x,
+            y: p_obj.y,
+            z: p_obj.z,
+        }
+        .abs()
+            * gamma(6_i32);
+        it.p = self.object_to_world.transform_point_with_abs_error(
+            &p_obj,
+            &p_obj_error,
+            &mut it.p_error,
+        );
+        *pdf = 1.0 as Float / self.area();
+        it
+    }
+    pub fn sample_with_ref_point(
+        &self,
+        iref: &InteractionCommon,
+        u: Point2f,
+        pdf: &mut Float,
+    ) -> InteractionCommon {
+        let intr: InteractionCommon = self.sample(u, pdf);
+        let mut wi: Vector3f = intr.p - iref.p;
+        if wi.length_squared() == 0.0 as Float {
+            *pdf = 0.0 as Float;
+        } else {
+            wi = wi.normalize();
+            // convert from area measure, as returned by the Sample()
+            // call above, to solid angle measure.
+            *pdf *= pnt3_distance_squaredf(&iref.p, &intr.p) / nrm_abs_dot_vec3f(&intr.n, &-wi);
+            if (*pdf).is_infinite() {
+                *pdf = 0.0 as Float;
+            }
+        }
+        intr
+    }
+    pub fn pdf_with_ref_point(&self, iref: &dyn Interaction, wi: &Vector3f) -> Float {
+        // intersect sample ray with area light geometry
+        let ray: Ray = iref.spawn_ray(wi);
+        let mut t_hit: Float = 0.0;
+        let mut isect_light: SurfaceInteraction = SurfaceInteraction::default();
+        if self.intersect(&ray, &mut t_hit, &mut isect_light) {
+            // convert light sample weight to solid angle measure
+            let mut pdf: Float = pnt3_distance_squaredf(iref.get_p(), &isect_light.common.p)
+                / (nrm_abs_dot_vec3f(&isect_light.common.n, &-(*wi)) * self.area());
+            if pdf.is_infinite() {
+                pdf = 0.0 as Float;
+            }
+            pdf
+        } else {
+            0.0 as Float
+        }
+    }
+}
@@ -0,0 +1,432 @@
+// std
+use std::f32::consts::PI;
+use std::sync::Arc;
+// pbrt
+use crate::core::efloat::quadratic_efloat;
+use crate::core::efloat::EFloat;
+use crate::core::geometry::{
+    nrm_abs_dot_vec3f, pnt3_distance_squaredf, vec3_cross_vec3, vec3_dot_vec3f,
+};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f, XYEnum};
+use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
+use crate::core::material::Material;
+use crate::core::pbrt::Float;
+use crate::core::pbrt::{clamp_t, gamma, radians};
+use crate::core::sampling::Distribution1D;
+use crate::core::transform::Transform;
+
+// see hyperboloid.h
+
+#[derive(Clone)]
+pub struct Hyperboloid {
+    pub p1: Point3f,
+    pub p2: Point3f,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub phi_max: Float,
+    pub r_max: Float,
+    pub ah: Float,
+    pub ch: Float,
+    /// Tabulated surface area along $v$, used for area computation
+    /// and uniform sampling
+    pub area_distrib: Distribution1D,
+    // inherited from class Shape (see shape.h)
+    pub object_to_world: Transform,
+    pub world_to_object: Transform,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+    pub material: Option<Arc<Material>>,
+}
+
+impl Default for Hyperboloid {
+    fn default() -> Self {
+        Hyperboloid::new(
+            Transform::default(),
+            Transform::default(),
+            false,
+            Point3f {
+                x: 0.0,
+                y: 0.0,
+                z: 0.0,
+            },
+            Point3f {
+                x: 1.0,
+                y: 1.0,
+                z: 1.0,
+            },
+            360.0,
+        )
+    }
+}
+
+impl Hyperboloid {
+    /// The line from |point1| to |point2| is swept around the z axis,
+    /// they have to be at different heights.
+    pub fn new(
+        object_to_world: Transform,
+        world_to_object: Transform,
+        reverse_orientation: bool,
+        point1: Point3f,
+        point2: Point3f,
+        tm: Float,
+    ) -> Self {
+        let mut p1: Point3f = point1;
+        let mut p2: Point3f = point2;
+        let radius1: Float = (p1.x * p1.x + p1.y * p1.y).sqrt();
+        let radius2: Float = (p2.x * p2.x + p2.y * p2.y).sqrt();
+        let r_max: Float = radius1.max(radius2);
+        let z_min: Float = p1.z.min(p2.z);
+        let z_max: Float = p1.z.max(p2.z);
+        // compute implicit function coefficients for hyperboloid
+        if p2.z == 0.0 as Float {
+            std::mem::swap(&mut p1, &mut p2);
+        }
+        let mut pp: Point3f = p1;
+        let mut ah: Float;
+        let mut ch: Float;
+        loop {
+            pp += (p2 - p1) * 2.0 as Float;
+            let xy1: Float = pp.x * pp.x + pp.y * pp.y;
+            let xy2: Float = p2.x * p2.x + p2.y * p2.y;
+            ah = (1.0 as Float / xy1 - (pp.z * pp.z) / (xy1 * p2.z * p2.z))
+                / (1.0 as Float - (xy2 * pp.z * pp.z) / (xy1 * p2.z * p2.z));
+            ch = (ah * xy2 - 1.0 as Float) / (p2.z * p2.z);
+            if ah.is_finite() && ch.is_finite() {
+                break;
+            }
+        }
+        // tabulate $|\dpdu \times \dpdv|$ along $v$; for a surface
+        // of revolution it doesn't depend on $\phi$
+        let n_steps: usize = 256;
+        let d: Vector3f = p2 - p1;
+        let func: Vec<Float> = (0..n_steps)
+            .map(|i| {
+                let v: Float = (i as Float + 0.5 as Float) / n_steps as Float;
+                let pr: Point3f = p1 * (1.0 as Float - v) + p2 * v;
+                let a: Float = (pr.x * pr.x + pr.y * pr.y) * d.z * d.z;
+                let b: Float = pr.x * d.x + pr.y * d.y;
+                (a + b * b).sqrt()
+            })
+            .collect();
+        Hyperboloid {
+            // Shape
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+            transform_swaps_handedness: object_to_world.swaps_handedness(),
+            // Hyperboloid
+            p1,
+            p2,
+            z_min,
+            z_max,
+            phi_max: radians(clamp_t(tm, 0.0, 360.0)),
+            r_max,
+            ah,
+            ch,
+            area_distrib: Distribution1D::new(func),
+            material: None,
+        }
+    }
+    /// Point on the line from *p1* to *p2* which is rotated around
+    /// the z axis to form the hyperboloid.
+    fn profile(&self, v: Float) -> Point3f {
+        self.p1 * (1.0 as Float - v) + self.p2 * v
+    }
+    /// Compute $\phi$ and $v$ for a point on the hyperboloid.
+    fn inverse_mapping(&self, p_hit: &Point3f) -> (Float, Float) {
+        let v: Float = (p_hit.z - self.p1.z) / (self.p2.z - self.p1.z);
+        let pr: Point3f = self.profile(v);
+        let mut phi: Float =
+            (pr.x * p_hit.y - p_hit.x * pr.y).atan2(p_hit.x * pr.x + p_hit.y * pr.y);
+        if phi < 0.0 as Float {
+            phi += 2.0 as Float * PI;
+        }
+        (phi, v)
+    }
+    /// Find the nearest valid intersection of the (object space) ray
+    /// with the hyperboloid. Returns the parametric distance, the hit point
+    /// and $\phi$.
+    fn find_hit(
+        &self,
+        ray: &Ray,
+        o_err: &Vector3f,
+        d_err: &Vector3f,
+    ) -> Option<(EFloat, Point3f, Float)> {
+        // compute quadratic hyperboloid coefficients
+
+        // initialize _EFloat_ ray coordinate values
+        let ox = EFloat::new(ray.o.x, o_err.x);
+        let oy = EFloat::new(ray.o.y, o_err.y);
+        let oz = EFloat::new(ray.o.z, o_err.z);
+        let dx = EFloat::new(ray.d.x, d_err.x);
+        let dy = EFloat::new(ray.d.y, d_err.y);
+        let dz = EFloat::new(ray.d.z, d_err.z);
+        let ah: EFloat = EFloat::new(self.ah, 0.0);
+        let ch: EFloat = EFloat::new(self.ch, 0.0);
+        let a: EFloat = ah * dx * dx + ah * dy * dy - ch * dz * dz;
+        let b: EFloat = (ah * dx * ox + ah * dy * oy - ch * dz * oz) * 2.0f32;
+        let c: EFloat = ah * ox * ox + ah * oy * oy - ch * oz * oz - EFloat::new(1.0, 0.0);
+
+        // solve quadratic equation for _t_ values
+        let mut t0: EFloat = EFloat::default();
+        let mut t1: EFloat = EFloat::default();
+        if !quadratic_efloat(a, b, c, &mut t0, &mut t1) {
+            return None;
+        }
+        // check quadric shape _t0_ and _t1_ for nearest intersection
+        if t0.upper_bound() > ray.t_max.get() || t1.lower_bound() <= 0.0f32 {
+            return None;
+        }
+        let mut t_shape_hit: EFloat = t0;
+        if t_shape_hit.lower_bound() <= 0.0f32 {
+            t_shape_hit = t1;
+            if t_shape_hit.upper_bound() > ray.t_max.get() {
+                return None;
+            }
+        }
+        // compute hyperboloid inverse mapping
+        let mut p_hit: Point3f = ray.position(t_shape_hit.v);
+        let (mut phi, _v) = self.inverse_mapping(&p_hit);
+        // test hyperboloid intersection against clipping parameters
+        if p_hit.z < self.z_min || p_hit.z > self.z_max || phi > self.phi_max {
+            if t_shape_hit == t1 {
+                return None;
+            }
+            t_shape_hit = t1;
+            if t1.upper_bound() > ray.t_max.get() {
+                return None;
+            }
+            // compute hyperboloid inverse mapping
+            p_hit = ray.position(t_shape_hit.v);
+            phi = self.inverse_mapping(&p_hit).0;
+            if p_hit.z < self.z_min || p_hit.z > self.z_max || phi > self.phi_max {
+                return None;
+            }
+        }
+        Some((t_shape_hit, p_hit, phi))
+    }
+    // Shape
+    pub fn object_bound(&self) -> Bounds3f {
+        Bounds3f {
+            p_min: Point3f {
+                x: -self.r_max,
+                y: -self.r_max,
+                z: self.z_min,
+            },
+            p_max: Point3f {
+                x: self.r_max,
+                y: self.r_max,
+                z: self.z_max,
+            },
+        }
+    }
+    pub fn world_bound(&self) -> Bounds3f {
+        // in C++: Bounds3f Shape::WorldBound() const { return (*ObjectToWorld)(ObjectBound()); }
+        self.object_to_world.transform_bounds(&self.object_bound())
+    }
+    pub fn intersect(&self, r: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        let (t_shape_hit, p_hit, _phi) = match self.find_hit(&ray, &o_err, &d_err) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        // find parametric representation of hyperboloid hit
+        let (phi, v) = self.inverse_mapping(&p_hit);
+        let u: Float = phi / self.phi_max;
+        // compute hyperboloid $\dpdu$ and $\dpdv$
+        let cos_phi: Float = phi.cos();
+        let sin_phi: Float = phi.sin();
+        let dpdu: Vector3f = Vector3f {
+            x: -self.phi_max * p_hit.y,
+            y: self.phi_max * p_hit.x,
+            z: 0.0,
+        };
+        let dpdv: Vector3f = Vector3f {
+            x: (self.p2.x - self.p1.x) * cos_phi - (self.p2.y - self.p1.y) * sin_phi,
+            y: (self.p2.x - self.p1.x) * sin_phi + (self.p2.y - self.p1.y) * cos_phi,
+            z: self.p2.z - self.p1.z,
+        };
+        // compute hyperboloid $\dndu$ and $\dndv$
+        let d2_p_duu: Vector3f = Vector3f {
+            x: p_hit.x,
+            y: p_hit.y,
+            z: 0.0,
+        } * -self.phi_max
+            * self.phi_max;
+        let d2_p_duv: Vector3f = Vector3f {
+            x: -dpdv.y,
+            y: dpdv.x,
+            z: 0.0,
+        } * self.phi_max;
+        let d2_p_dvv: Vector3f = Vector3f {
+            x: 0.0,
+            y: 0.0,
+            z: 0.0,
+        };
+        // compute coefficients for fundamental forms
+        let ec: Float = vec3_dot_vec3f(&dpdu, &dpdu);
+        let fc: Float = vec3_dot_vec3f(&dpdu, &dpdv);
+        let gc: Float = vec3_dot_vec3f(&dpdv, &dpdv);
+        let nc: Vector3f = vec3_cross_vec3(&dpdu, &dpdv).normalize();
+        let el: Float = vec3_dot_vec3f(&nc, &d2_p_duu);
+        let fl: Float = vec3_dot_vec3f(&nc, &d2_p_duv);
+        let gl: Float = vec3_dot_vec3f(&nc, &d2_p_dvv);
+        // compute $\dndu$ and $\dndv$ from fundamental form coefficients
+        let inv_egf2: Float = 1.0 / (ec * gc - fc * fc);
+        let dndu = dpdu * (fl * fc - el * gc) * inv_egf2 + dpdv * (el * fc - fl * ec) * inv_egf2;
+        let dndu = Normal3f {
+            x: dndu.x,
+            y: dndu.y,
+            z: dndu.z,
+        };
+        let dndv = dpdu * (gl * fc - fl * gc) * inv_egf2 + dpdv * (fl * fc - gl * ec) * inv_egf2;
+        let dndv = Normal3f {
+            x: dndv.x,
+            y: dndv.y,
+            z: dndv.z,
+        };
+        // compute error bounds for hyperboloid intersection
+        let ox = EFloat::new(ray.o.x, o_err.x);
+        let oy = EFloat::new(ray.o.y, o_err.y);
+        let oz = EFloat::new(ray.o.z, o_err.z);
+        let dx = EFloat::new(ray.d.x, d_err.x);
+        let dy = EFloat::new(ray.d.y, d_err.y);
+        let dz = EFloat::new(ray.d.z, d_err.z);
+        let px: EFloat = ox + t_shape_hit * dx;
+        let py: EFloat = oy + t_shape_hit * dy;
+        let pz: EFloat = oz + t_shape_hit * dz;
+        let p_error: Vector3f = Vector3f {
+            x: px.get_absolute_error(),
+            y: py.get_absolute_error(),
+            z: pz.get_absolute_error(),
+        };
+        // initialize _SurfaceInteraction_ from parametric information
+        let uv_hit: Point2f = Point2f { x: u, y: v };
+        let wo: Vector3f = -ray.d;
+        *isect = SurfaceInteraction::new(
+            &p_hit, &p_error, uv_hit, &wo, &dpdu, &dpdv, &dndu, &dndv, ray.time, None,
+        );
+        self.object_to_world.transform_surface_interaction(isect);
+        *t_hit = t_shape_hit.v;
+        true
+    }
+    pub fn intersect_p(&self, r: &Ray) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        self.find_hit(&ray, &o_err, &d_err).is_some()
+    }
+    pub fn get_reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+    pub fn get_transform_swaps_handedness(&self) -> bool {
+        self.transform_swaps_handedness
+    }
+    pub fn get_object_to_world(&self) -> Transform {
+        self.object_to_world
+    }
+    pub fn area(&self) -> Float {
+        self.phi_max * self.area_distrib.func_int
+    }
+    /// Sample a point on the hyperboloid's surface. The position along
+    /// the profile line is drawn from the tabulated area distribution.
+    pub fn sample(&self, u: Point2f, pdf: &mut Float) -> InteractionCommon {
+        let v: Float = self
+            .area_distrib
+            .sample_continuous(u[XYEnum::X], None, None);
+        let phi: Float = u[XYEnum::Y] * self.phi_max;
+        let cos_phi: Float = phi.cos();
+        let sin_phi: Float = phi.sin();
+        let pr: Point3f = self.profile(v);
+        let p_obj: Point3f = Point3f {
+            x: pr.x * cos_phi - pr.y * sin_phi,
+            y: pr.x * sin_phi + pr.y * cos_phi,
+            z: pr.z,
+        };
+        let dpdu: Vector3f = Vector3f {
+            x: -p_obj.y,
+            y: p_obj.x,
+            z: 0.0,
+        };
+        let dpdv: Vector3f = Vector3f {
+            x: (self.p2.x - self.p1.x) * cos_phi - (self.p2.y - self.p1.y) * sin_phi,
+            y: (self.p2.x - self.p1.x) * sin_phi + (self.p2.y - self.p1.y) * cos_phi,
+            z: self.p2.z - self.p1.z,
+        };
+        let n: Vector3f = vec3_cross_vec3(&dpdu, &dpdv);
+        let n_obj: Normal3f = Normal3f {
+            x: n.x,
+            y: n.y,
+            z: n.z,
+        };
+        let mut it: InteractionCommon = InteractionCommon {
+            n: self.object_to_world.transform_normal(&n_obj).normalize(),
+            ..Default::default()
+        };
+        if self.reverse_orientation {
+            it.n *= -1.0 as Float;
+        }
+        let p_obj_error: Vector3f = Vector3f {
+            x: p_obj.x,
+            y: p_obj.y,
+            z: p_obj.z,
+        }
+        .abs()
+            * gamma(5_i32);
+        it.p = self.object_to_world.transform_point_with_abs_error(
+            &p_obj,
+            &p_obj_error,
+            &mut it.p_error,
+        );
+        *pdf = 1.0 as Float / self.area();
+        it
+    }
+    pub fn sample_with_ref_point(
+        &self,
+        iref: &InteractionCommon,
+        u: Point2f,
+        pdf: &mut Float,
+    ) -> InteractionCommon {
+        let intr: InteractionCommon = self.sample(u, pdf);
+        let mut wi: Vector3f = intr.p - iref.p;
+        if wi.length_squared() == 0.0 as Float {
+            *pdf = 0.0 as Float;
+        } else {
+            wi = wi.normalize();
+            // convert from area measure, as returned by the Sample()
+            // call above, to solid angle measure.
+            *pdf *= pnt3_distance_squaredf(&iref.p, &intr.p) / nrm_abs_dot_vec3f(&intr.n, &-wi);
+            if (*pdf).is_infinite() {
+                *pdf = 0.0 as Float;
+            }
+        }
+        intr
+    }
+    pub fn pdf_with_ref_point(&self, iref: &dyn Interaction, wi: &Vector3f) -> Float {
+        // intersect sample ray with area light geometry
+        let ray: Ray = iref.spawn_ray(wi);
+        let mut t_hit: Float = 0.0;
+        let mut isect_light: SurfaceInteraction = SurfaceInteraction::default();
+        if self.intersect(&ray, &mut t_hit, &mut isect_light) {
+            // convert light sample weight to solid angle measure
+            let mut pdf: Float = pnt3_distance_squaredf(iref.get_p(), &isect_light.common.p)
+                / (nrm_abs_dot_vec3f(&isect_light.common.n, &-(*wi)) * self.area());
+            if pdf.is_infinite() {
+                pdf = 0.0 as Float;
+            }
+            pdf
+        } else {
+            0.0 as Float
+        }
+    }
+}
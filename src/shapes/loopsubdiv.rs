@@ -663,6 +663,7 @@ pub fn loop_subdivide(
         Vec::new(),
         None,
         None,
+        Vec::new(),
     ))
 }
 
@@ -10,6 +10,7 @@
 //! - Curve
 //! - Cylinder
 //! - Disk
+//! - Heightfield
 //! - Hyperboloid
 //! - Paraboloid
 //! - Sphere
@@ -17,7 +18,10 @@
 //!
 //! ## Cones
 //!
-//! TODO
+//! A cone is a quadric centered around the z axis with its base
+//! (given by the *radius*) at $z = 0$ and its apex at $z = height$.
+//! Like the other quadrics it can be clipped by a maximum $\phi$
+//! value.
 //!
 //! ## Curves
 //!
@@ -50,20 +54,36 @@
 //! Another useful quadric is the cylinder. Cylinder shapes are
 //! centered around the z axis.
 //!
+//! ## Heightfields
+//!
+//! A heightfield is a regular grid of heights over the unit square
+//! in object space, e.g. a terrain. Instead of converting it into a
+//! triangle mesh the **Heightfield** shape only stores the heights and
+//! walks through the grid cells pierced by a ray, intersecting the two
+//! triangles of each cell on the fly.
+//!
 //! ## Hyperboloids
 //!
-//! TODO
+//! A hyperboloid of one sheet is defined by sweeping the line segment
+//! between two points around the z axis. Depending on the points this
+//! results in a cylinder, cone, disk or a real hyperboloid.
 //!
 //! ## Paraboloids
 //!
-//! TODO
+//! The paraboloid is the remaining quadric of pbrt. It is defined by
+//! a *radius* (at height $z_{max}$) and is clipped by the $z_{min}$
+//! and $z_{max}$ values as well as by $\phi_{max}$.
 //!
 
+pub mod cone;
 pub mod curve;
 pub mod cylinder;
 pub mod disk;
+pub mod heightfield;
+pub mod hyperboloid;
 pub mod loopsubdiv;
 pub mod nurbs;
+pub mod paraboloid;
 pub mod plymesh;
 pub mod sphere;
 pub mod triangle;
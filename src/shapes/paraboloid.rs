@@ -0,0 +1,364 @@
+// std
+use std::f32::consts::PI;
+use std::sync::Arc;
+// pbrt
+use crate::core::efloat::quadratic_efloat;
+use crate::core::efloat::EFloat;
+use crate::core::geometry::{
+    nrm_abs_dot_vec3f, pnt3_distance_squaredf, vec3_cross_vec3, vec3_dot_vec3f,
+};
+use crate::core::geometry::{Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector3f, XYEnum};
+use crate::core::interaction::{Interaction, InteractionCommon, SurfaceInteraction};
+use crate::core::material::Material;
+use crate::core::pbrt::Float;
+use crate::core::pbrt::{clamp_t, gamma, radians};
+use crate::core::transform::Transform;
+
+// see paraboloid.h
+
+#[derive(Clone)]
+pub struct Paraboloid {
+    pub radius: Float,
+    pub z_min: Float,
+    pub z_max: Float,
+    pub phi_max: Float,
+    // inherited from class Shape (see shape.h)
+    pub object_to_world: Transform,
+    pub world_to_object: Transform,
+    pub reverse_orientation: bool,
+    pub transform_swaps_handedness: bool,
+    pub material: Option<Arc<Material>>,
+}
+
+impl Default for Paraboloid {
+    fn default() -> Self {
+        let object_to_world: Transform = Transform::default();
+        Paraboloid {
+            // Shape
+            object_to_world,
+            world_to_object: Transform::default(),
+            reverse_orientation: false,
+            transform_swaps_handedness: object_to_world.swaps_handedness(),
+            // Paraboloid
+            radius: 1.0,
+            z_min: 0.0,
+            z_max: 1.0,
+            phi_max: radians(360.0),
+            material: None,
+        }
+    }
+}
+
+impl Paraboloid {
+    pub fn new(
+        object_to_world: Transform,
+        world_to_object: Transform,
+        reverse_orientation: bool,
+        radius: Float,
+        z0: Float,
+        z1: Float,
+        phi_max: Float,
+    ) -> Self {
+        Paraboloid {
+            // Shape
+            object_to_world,
+            world_to_object,
+            reverse_orientation,
+            transform_swaps_handedness: object_to_world.swaps_handedness(),
+            // Paraboloid
+            radius,
+            z_min: z0.min(z1),
+            z_max: z0.max(z1),
+            phi_max: radians(clamp_t(phi_max, 0.0, 360.0)),
+            material: None,
+        }
+    }
+    /// Find the nearest valid intersection of the (object space) ray
+    /// with the paraboloid. Returns the parametric distance, the hit point
+    /// and $\phi$.
+    fn find_hit(
+        &self,
+        ray: &Ray,
+        o_err: &Vector3f,
+        d_err: &Vector3f,
+    ) -> Option<(EFloat, Point3f, Float)> {
+        // compute quadratic paraboloid coefficients
+
+        // initialize _EFloat_ ray coordinate values
+        let ox = EFloat::new(ray.o.x, o_err.x);
+        let oy = EFloat::new(ray.o.y, o_err.y);
+        let oz = EFloat::new(ray.o.z, o_err.z);
+        let dx = EFloat::new(ray.d.x, d_err.x);
+        let dy = EFloat::new(ray.d.y, d_err.y);
+        let dz = EFloat::new(ray.d.z, d_err.z);
+        let k: EFloat = EFloat::new(self.z_max, 0.0)
+            / (EFloat::new(self.radius, 0.0) * EFloat::new(self.radius, 0.0));
+        let a: EFloat = k * (dx * dx + dy * dy);
+        let b: EFloat = k * (dx * ox + dy * oy) * 2.0f32 - dz;
+        let c: EFloat = k * (ox * ox + oy * oy) - oz;
+
+        // solve quadratic equation for _t_ values
+        let mut t0: EFloat = EFloat::default();
+        let mut t1: EFloat = EFloat::default();
+        if !quadratic_efloat(a, b, c, &mut t0, &mut t1) {
+            return None;
+        }
+        // check quadric shape _t0_ and _t1_ for nearest intersection
+        if t0.upper_bound() > ray.t_max.get() || t1.lower_bound() <= 0.0f32 {
+            return None;
+        }
+        let mut t_shape_hit: EFloat = t0;
+        if t_shape_hit.lower_bound() <= 0.0f32 {
+            t_shape_hit = t1;
+            if t_shape_hit.upper_bound() > ray.t_max.get() {
+                return None;
+            }
+        }
+        // compute paraboloid inverse mapping
+        let mut p_hit: Point3f = ray.position(t_shape_hit.v);
+        let mut phi: Float = p_hit.y.atan2(p_hit.x);
+        if phi < 0.0 as Float {
+            phi += 2.0 as Float * PI;
+        }
+        // test paraboloid intersection against clipping parameters
+        if p_hit.z < self.z_min || p_hit.z > self.z_max || phi > self.phi_max {
+            if t_shape_hit == t1 {
+                return None;
+            }
+            t_shape_hit = t1;
+            if t1.upper_bound() > ray.t_max.get() {
+                return None;
+            }
+            // compute paraboloid inverse mapping
+            p_hit = ray.position(t_shape_hit.v);
+            phi = p_hit.y.atan2(p_hit.x);
+            if phi < 0.0 as Float {
+                phi += 2.0 as Float * PI;
+            }
+            if p_hit.z < self.z_min || p_hit.z > self.z_max || phi > self.phi_max {
+                return None;
+            }
+        }
+        Some((t_shape_hit, p_hit, phi))
+    }
+    // Shape
+    pub fn object_bound(&self) -> Bounds3f {
+        Bounds3f {
+            p_min: Point3f {
+                x: -self.radius,
+                y: -self.radius,
+                z: self.z_min,
+            },
+            p_max: Point3f {
+                x: self.radius,
+                y: self.radius,
+                z: self.z_max,
+            },
+        }
+    }
+    pub fn world_bound(&self) -> Bounds3f {
+        // in C++: Bounds3f Shape::WorldBound() const { return (*ObjectToWorld)(ObjectBound()); }
+        self.object_to_world.transform_bounds(&self.object_bound())
+    }
+    pub fn intersect(&self, r: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        let (t_shape_hit, p_hit, phi) = match self.find_hit(&ray, &o_err, &d_err) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        // find parametric representation of paraboloid hit
+        let u: Float = phi / self.phi_max;
+        let v: Float = (p_hit.z - self.z_min) / (self.z_max - self.z_min);
+        // compute paraboloid $\dpdu$ and $\dpdv$
+        let dpdu: Vector3f = Vector3f {
+            x: -self.phi_max * p_hit.y,
+            y: self.phi_max * p_hit.x,
+            z: 0.0,
+        };
+        let dpdv: Vector3f = Vector3f {
+            x: p_hit.x / (2.0 as Float * p_hit.z),
+            y: p_hit.y / (2.0 as Float * p_hit.z),
+            z: 1.0,
+        } * (self.z_max - self.z_min);
+        // compute paraboloid $\dndu$ and $\dndv$
+        let d2_p_duu: Vector3f = Vector3f {
+            x: p_hit.x,
+            y: p_hit.y,
+            z: 0.0,
+        } * -self.phi_max
+            * self.phi_max;
+        let d2_p_duv: Vector3f = Vector3f {
+            x: -p_hit.y / (2.0 as Float * p_hit.z),
+            y: p_hit.x / (2.0 as Float * p_hit.z),
+            z: 0.0,
+        } * (self.z_max - self.z_min)
+            * self.phi_max;
+        let d2_p_dvv: Vector3f = Vector3f {
+            x: p_hit.x / (4.0 as Float * p_hit.z * p_hit.z),
+            y: p_hit.y / (4.0 as Float * p_hit.z * p_hit.z),
+            z: 0.0,
+        } * -(self.z_max - self.z_min)
+            * (self.z_max - self.z_min);
+        // compute coefficients for fundamental forms
+        let ec: Float = vec3_dot_vec3f(&dpdu, &dpdu);
+        let fc: Float = vec3_dot_vec3f(&dpdu, &dpdv);
+        let gc: Float = vec3_dot_vec3f(&dpdv, &dpdv);
+        let nc: Vector3f = vec3_cross_vec3(&dpdu, &dpdv).normalize();
+        let el: Float = vec3_dot_vec3f(&nc, &d2_p_duu);
+        let fl: Float = vec3_dot_vec3f(&nc, &d2_p_duv);
+        let gl: Float = vec3_dot_vec3f(&nc, &d2_p_dvv);
+        // compute $\dndu$ and $\dndv$ from fundamental form coefficients
+        let inv_egf2: Float = 1.0 / (ec * gc - fc * fc);
+        let dndu = dpdu * (fl * fc - el * gc) * inv_egf2 + dpdv * (el * fc - fl * ec) * inv_egf2;
+        let dndu = Normal3f {
+            x: dndu.x,
+            y: dndu.y,
+            z: dndu.z,
+        };
+        let dndv = dpdu * (gl * fc - fl * gc) * inv_egf2 + dpdv * (fl * fc - gl * ec) * inv_egf2;
+        let dndv = Normal3f {
+            x: dndv.x,
+            y: dndv.y,
+            z: dndv.z,
+        };
+        // compute error bounds for paraboloid intersection
+        let ox = EFloat::new(ray.o.x, o_err.x);
+        let oy = EFloat::new(ray.o.y, o_err.y);
+        let oz = EFloat::new(ray.o.z, o_err.z);
+        let dx = EFloat::new(ray.d.x, d_err.x);
+        let dy = EFloat::new(ray.d.y, d_err.y);
+        let dz = EFloat::new(ray.d.z, d_err.z);
+        let px: EFloat = ox + t_shape_hit * dx;
+        let py: EFloat = oy + t_shape_hit * dy;
+        let pz: EFloat = oz + t_shape_hit * dz;
+        let p_error: Vector3f = Vector3f {
+            x: px.get_absolute_error(),
+            y: py.get_absolute_error(),
+            z: pz.get_absolute_error(),
+        };
+        // initialize _SurfaceInteraction_ from parametric information
+        let uv_hit: Point2f = Point2f { x: u, y: v };
+        let wo: Vector3f = -ray.d;
+        *isect = SurfaceInteraction::new(
+            &p_hit, &p_error, uv_hit, &wo, &dpdu, &dpdv, &dndu, &dndv, ray.time, None,
+        );
+        self.object_to_world.transform_surface_interaction(isect);
+        *t_hit = t_shape_hit.v;
+        true
+    }
+    pub fn intersect_p(&self, r: &Ray) -> bool {
+        // TODO: ProfilePhase p(Prof::ShapeIntersect);
+        // transform _Ray_ to object space
+        let mut o_err: Vector3f = Vector3f::default();
+        let mut d_err: Vector3f = Vector3f::default();
+        let ray: Ray = self
+            .world_to_object
+            .transform_ray_with_error(r, &mut o_err, &mut d_err);
+        self.find_hit(&ray, &o_err, &d_err).is_some()
+    }
+    pub fn get_reverse_orientation(&self) -> bool {
+        self.reverse_orientation
+    }
+    pub fn get_transform_swaps_handedness(&self) -> bool {
+        self.transform_swaps_handedness
+    }
+    pub fn get_object_to_world(&self) -> Transform {
+        self.object_to_world
+    }
+    pub fn area(&self) -> Float {
+        let radius2: Float = self.radius * self.radius;
+        let k: Float = 4.0 as Float * self.z_max / radius2;
+        (radius2 * radius2 * self.phi_max / (12.0 as Float * self.z_max * self.z_max))
+            * ((k * self.z_max + 1.0 as Float).powf(1.5)
+                - (k * self.z_min + 1.0 as Float).powf(1.5))
+    }
+    /// Uniformly sample a point on the paraboloid's surface. The
+    /// surface area between the apex and height $z$ is proportional
+    /// to $(k z + 1)^{3/2} - 1$, which can be inverted analytically.
+    pub fn sample(&self, u: Point2f, pdf: &mut Float) -> InteractionCommon {
+        let radius2: Float = self.radius * self.radius;
+        let k: Float = 4.0 as Float * self.z_max / radius2;
+        let s_min: Float = (k * self.z_min + 1.0 as Float).powf(1.5);
+        let s_max: Float = (k * self.z_max + 1.0 as Float).powf(1.5);
+        let s: Float = s_min + u[XYEnum::X] * (s_max - s_min);
+        let z: Float = ((s.powf(2.0 / 3.0) - 1.0 as Float) / k).max(0.0 as Float);
+        let phi: Float = u[XYEnum::Y] * self.phi_max;
+        let r: Float = self.radius * (z / self.z_max).sqrt();
+        let p_obj: Point3f = Point3f {
+            x: r * phi.cos(),
+            y: r * phi.sin(),
+            z,
+        };
+        // gradient of the implicit function $x^2 + y^2 - z r^2 / z_{max}$
+        let n_obj: Normal3f = Normal3f {
+            x: 2.0 as Float * p_obj.x,
+            y: 2.0 as Float * p_obj.y,
+            z: -radius2 / self.z_max,
+        };
+        let mut it: InteractionCommon = InteractionCommon {
+            n: self.object_to_world.transform_normal(&n_obj).normalize(),
+            ..Default::default()
+        };
+        if self.reverse_orientation {
+            it.n *= -1.0 as Float;
+        }
+        let p_obj_error: Vector3f = Vector3f {
+            x: p_obj.x,
+            y: p_obj.y,
+            z: p_obj.z,
+        }
+        .abs()
+            * gamma(5_i32);
+        it.p = self.object_to_world.transform_point_with_abs_error(
+            &p_obj,
+            &p_obj_error,
+            &mut it.p_error,
+        );
+        *pdf = 1.0 as Float / self.area();
+        it
+    }
+    pub fn sample_with_ref_point(
+        &self,
+        iref: &InteractionCommon,
+        u: Point2f,
+        pdf: &mut Float,
+    ) -> InteractionCommon {
+        let intr: InteractionCommon = self.sample(u, pdf);
+        let mut wi: Vector3f = intr.p - iref.p;
+        if wi.length_squared() == 0.0 as Float {
+            *pdf = 0.0 as Float;
+        } else {
+            wi = wi.normalize();
+            // convert from area measure, as returned by the Sample()
+            // call above, to solid angle measure.
+            *pdf *= pnt3_distance_squaredf(&iref.p, &intr.p) / nrm_abs_dot_vec3f(&intr.n, &-wi);
+            if (*pdf).is_infinite() {
+                *pdf = 0.0 as Float;
+            }
+        }
+        intr
+    }
+    pub fn pdf_with_ref_point(&self, iref: &dyn Interaction, wi: &Vector3f) -> Float {
+        // intersect sample ray with area light geometry
+        let ray: Ray = iref.spawn_ray(wi);
+        let mut t_hit: Float = 0.0;
+        let mut isect_light: SurfaceInteraction = SurfaceInteraction::default();
+        if self.intersect(&ray, &mut t_hit, &mut isect_light) {
+            // convert light sample weight to solid angle measure
+            let mut pdf: Float = pnt3_distance_squaredf(iref.get_p(), &isect_light.common.p)
+                / (nrm_abs_dot_vec3f(&isect_light.common.n, &-(*wi)) * self.area());
+            if pdf.is_infinite() {
+                pdf = 0.0 as Float;
+            }
+            pdf
+        } else {
+            0.0 as Float
+        }
+    }
+}
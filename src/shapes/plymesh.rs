@@ -63,6 +63,7 @@ pub fn create_ply_mesh<S: BuildHasher>(
     let mut has_normals: bool = false;
     let mut has_uvs: bool = false;
     let mut tm_vertex_indices: Vec<u32> = Vec::new();
+    let mut tm_face_indices: Vec<i32> = Vec::new();
     for (name, list) in payload.into_iter() {
         match name.as_ref() {
             "vertex" => {
@@ -135,6 +136,8 @@ pub fn create_ply_mesh<S: BuildHasher>(
             "face" => {
                 for elem in list.into_iter() {
                     let mut nrm: Normal3f = Normal3f::default();
+                    let n_tris_before: usize = tm_vertex_indices.len() / 3;
+                    let mut face_index: Option<i32> = None;
                     for (name2, list2) in elem.into_iter() {
                         match name2.as_ref() {
                             "vertex_indices" => {
@@ -206,9 +209,20 @@ pub fn create_ply_mesh<S: BuildHasher>(
                                     nrm.z = z;
                                 }
                             }
+                            "face_indices" => match list2 {
+                                ply::Property::Int(i) => face_index = Some(i),
+                                ply::Property::UInt(i) => face_index = Some(i as i32),
+                                _ => {}
+                            },
                             _ => unreachable!(),
                         }
                     }
+                    // quads result in two triangles with the same face index
+                    if let Some(face_index) = face_index {
+                        for _ in n_tris_before..(tm_vertex_indices.len() / 3) {
+                            tm_face_indices.push(face_index);
+                        }
+                    }
                 }
             }
             _ => unreachable!(),
@@ -267,6 +281,10 @@ pub fn create_ply_mesh<S: BuildHasher>(
     } else if params.find_one_float("shadowalpha", 1.0 as Float) == 0.0 as Float {
         shadow_alpha_tex = Some(Arc::new(ConstantTexture::new(0.0 as Float)));
     }
+    if tm_face_indices.len() != tm_vertex_indices.len() / 3 {
+        // face indices are optional, ignore incomplete ones
+        tm_face_indices.clear();
+    }
     let mesh = Arc::new(TriangleMesh::new(
         *o2w,
         *w2o,
@@ -280,6 +298,7 @@ pub fn create_ply_mesh<S: BuildHasher>(
         uvs,
         alpha_tex,
         shadow_alpha_tex,
+        tm_face_indices,
     ));
     let mut shapes: Vec<Arc<Shape>> = Vec::new();
     for id in 0..mesh.n_triangles {
@@ -2,24 +2,78 @@
 use std::cell::Cell;
 use std::mem;
 use std::sync::Arc;
+// others
+use smallvec::SmallVec;
 // pbrt
 use crate::core::geometry::{
-    bnd3_union_pnt3f, nrm_abs_dot_vec3f, nrm_faceforward_nrm, pnt3_abs, pnt3_distance_squaredf,
-    pnt3_permutef, vec3_coordinate_system, vec3_cross_nrm, vec3_cross_vec3, vec3_max_componentf,
-    vec3_max_dimensionf, vec3_permutef,
+    bnd3_intersect_bnd3f, bnd3_split_slabs, bnd3_union_pnt3f, nrm_abs_dot_vec3f, nrm_dot_nrmf,
+    nrm_faceforward_nrm, pnt3_abs, pnt3_distance_squaredf, pnt3_permutef, vec3_coordinate_system,
+    vec3_cross_nrm, vec3_cross_vec3, vec3_max_componentf, vec3_max_dimensionf, vec3_permutef,
 };
 use crate::core::geometry::{
     Bounds3f, Normal3f, Point2f, Point3f, Ray, Vector2f, Vector3f, XYEnum,
 };
 use crate::core::interaction::{Interaction, InteractionCommon, Shading, SurfaceInteraction};
 use crate::core::pbrt::gamma;
-use crate::core::pbrt::Float;
+use crate::core::pbrt::{clamp_t, lerp, Float};
 // use crate::core::sampling::uniform_sample_triangle;
 use crate::core::texture::Texture;
 use crate::core::transform::Transform;
 
 // see triangle.h
 
+type ClipPolygon = SmallVec<[[Float; 3]; 9]>;
+
+/// Keeps the part of the convex polygon |poly| above (or below) the
+/// |plane| perpendicular to |axis|.
+fn clip_polygon(poly: &mut ClipPolygon, axis: usize, plane: Float, above: bool) {
+    let inside = |p: &[Float; 3]| {
+        if above {
+            p[axis] >= plane
+        } else {
+            p[axis] <= plane
+        }
+    };
+    if poly.iter().all(&inside) {
+        return;
+    }
+    let mut clipped: ClipPolygon = SmallVec::new();
+    for i in 0..poly.len() {
+        let a: [Float; 3] = poly[i];
+        let b: [Float; 3] = poly[(i + 1) % poly.len()];
+        if inside(&a) {
+            clipped.push(a);
+        }
+        if inside(&a) != inside(&b) {
+            let t: Float = (plane - a[axis]) / (b[axis] - a[axis]);
+            let mut p: [Float; 3] = [
+                lerp(t, a[0], b[0]),
+                lerp(t, a[1], b[1]),
+                lerp(t, a[2], b[2]),
+            ];
+            p[axis] = plane;
+            clipped.push(p);
+        }
+    }
+    *poly = clipped;
+}
+
+/// Bounds of a polygon, empty if it has no vertices.
+fn polygon_bounds(poly: &ClipPolygon) -> Bounds3f {
+    let mut bounds: Bounds3f = Bounds3f::default();
+    for p in poly {
+        bounds = bnd3_union_pnt3f(
+            &bounds,
+            &Point3f {
+                x: p[0],
+                y: p[1],
+                z: p[2],
+            },
+        );
+    }
+    bounds
+}
+
 #[derive(Clone)]
 pub struct TriangleMesh {
     /// the total number of triangles in the mesh
@@ -30,6 +84,15 @@ pub struct TriangleMesh {
     pub n_vertices: u32,
     /// vector of *n_vertices* vertex positions
     pub p: Vec<Point3f>,
+    /// for deformation motion blur, further vectors of *n_vertices*
+    /// vertex positions (can be empty), evenly spaced in time after
+    /// *p* (at *start_time*) up to *end_time*
+    pub p_motion: Vec<Vec<Point3f>>,
+    /// the normal vectors at the times of *p_motion* (empty if *n*
+    /// is)
+    pub n_motion: Vec<Vec<Normal3f>>,
+    pub start_time: Float,
+    pub end_time: Float,
     /// an optional vector of normal vectors (can be empty)
     pub n: Vec<Normal3f>,
     /// an optional vector of tangent vectors (can be empty)
@@ -38,6 +101,8 @@ pub struct TriangleMesh {
     pub uv: Vec<Point2f>,
     pub alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
     pub shadow_alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+    /// an optional vector of per triangle face indices (can be empty)
+    pub face_indices: Vec<i32>,
     // inherited from class Shape (see shape.h)
     pub object_to_world: Transform, // TODO: not pub?
     pub world_to_object: Transform, // TODO: not pub?
@@ -59,6 +124,7 @@ impl TriangleMesh {
         uv: Vec<Point2f>,
         alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
         shadow_alpha_mask: Option<Arc<dyn Texture<Float> + Send + Sync>>,
+        face_indices: Vec<i32>,
     ) -> Self {
         TriangleMesh {
             // Shape
@@ -71,11 +137,66 @@ impl TriangleMesh {
             vertex_indices,
             n_vertices,
             p,
+            p_motion: Vec::new(),
+            n_motion: Vec::new(),
+            start_time: 0.0 as Float,
+            end_time: 1.0 as Float,
             n,
             s,
             uv,
             alpha_mask,
             shadow_alpha_mask,
+            face_indices,
+        }
+    }
+    /// Lets the vertices move linearly through |p_motion| (in world
+    /// space) after *p*, from |start_time| to |end_time|. If the mesh
+    /// has normals, |n_motion| holds one vector of normals per step.
+    pub fn set_motion(
+        &mut self,
+        p_motion: Vec<Vec<Point3f>>,
+        n_motion: Vec<Vec<Normal3f>>,
+        start_time: Float,
+        end_time: Float,
+    ) {
+        assert!(self.n.is_empty() || n_motion.len() == p_motion.len());
+        self.p_motion = p_motion;
+        self.n_motion = n_motion;
+        self.start_time = start_time;
+        self.end_time = end_time;
+    }
+    pub fn is_moving(&self) -> bool {
+        !self.p_motion.is_empty()
+    }
+    /// The pair of vertex positions to interpolate between at |time|
+    /// and the interpolation parameter.
+    fn motion_key(&self, time: Float) -> (usize, Float) {
+        let n_steps: usize = self.p_motion.len();
+        let duration: Float = self.end_time - self.start_time;
+        let u: Float = if duration > 0.0 as Float {
+            clamp_t(
+                (time - self.start_time) / duration,
+                0.0 as Float,
+                1.0 as Float,
+            )
+        } else {
+            0.0 as Float
+        } * n_steps as Float;
+        let key: usize = (u as usize).min(n_steps - 1);
+        (key, u - key as Float)
+    }
+    fn key_position(&self, key: usize, vertex: u32) -> Point3f {
+        if key == 0 {
+            self.p[vertex as usize]
+        } else {
+            self.p_motion[key - 1][vertex as usize]
+        }
+    }
+    fn key_normal(&self, key: usize, vertex: u32) -> Normal3f {
+        if key == 0 {
+            self.n[vertex as usize]
+        } else {
+            self.n_motion[key - 1][vertex as usize]
         }
     }
 }
@@ -124,6 +245,9 @@ impl Triangle {
         )
     }
     pub fn world_bound(&self) -> Bounds3f {
+        if self.mesh.is_moving() {
+            return self.motion_bound(self.mesh.start_time, self.mesh.end_time);
+        }
         let idx1: usize = (self.id * 3) as usize;
         let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
         let p0: &Point3f = &self.mesh.p[idx[0] as usize];
@@ -131,146 +255,143 @@ impl Triangle {
         let p2: &Point3f = &self.mesh.p[idx[2] as usize];
         bnd3_union_pnt3f(&Bounds3f::new(*p0, *p1), p2)
     }
-    pub fn intersect(&self, ray: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
-        // get triangle vertices in _p0_, _p1_, and _p2_
+    pub fn is_moving(&self) -> bool {
+        self.mesh.is_moving()
+    }
+    pub fn time_range(&self) -> (Float, Float) {
+        (self.mesh.start_time, self.mesh.end_time)
+    }
+    /// The vertex positions (in world space) at |time|.
+    pub fn positions(&self, time: Float) -> [Point3f; 3] {
         let idx1: usize = (self.id * 3) as usize;
         let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
-        let p0: &Point3f = &self.mesh.p[idx[0] as usize];
-        let p1: &Point3f = &self.mesh.p[idx[1] as usize];
-        let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        // translate vertices based on ray origin
-        let mut p0t: Point3f = *p0
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p1t: Point3f = *p1
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p2t: Point3f = *p2
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        // permute components of triangle vertices and ray direction
-        let kz: usize = vec3_max_dimensionf(&ray.d.abs());
-        let mut kx: usize = kz + 1;
-        if kx == 3 {
-            kx = 0;
-        }
-        let mut ky: usize = kx + 1;
-        if ky == 3 {
-            ky = 0;
-        }
-        let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
-        p0t = pnt3_permutef(&p0t, kx, ky, kz);
-        p1t = pnt3_permutef(&p1t, kx, ky, kz);
-        p2t = pnt3_permutef(&p2t, kx, ky, kz);
-        // apply shear transformation to translated vertex positions
-        let sx: Float = -d.x / d.z;
-        let sy: Float = -d.y / d.z;
-        let sz: Float = 1.0 / d.z;
-        p0t.x += sx * p0t.z;
-        p0t.y += sy * p0t.z;
-        p1t.x += sx * p1t.z;
-        p1t.y += sy * p1t.z;
-        p2t.x += sx * p2t.z;
-        p2t.y += sy * p2t.z;
-        // compute edge function coefficients _e0_, _e1_, and _e2_
-        let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
-        let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
-        let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
-        // fall back to double precision test at triangle edges
-        if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0)
-        {
-            let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
-            let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
-            e0 = (p2typ1tx - p2txp1ty) as Float;
-            let p0txp2ty = p0t.x as f64 * p2t.y as f64;
-            let p0typ2tx = p0t.y as f64 * p2t.x as f64;
-            e1 = (p0typ2tx - p0txp2ty) as Float;
-            let p1txp0ty = p1t.x as f64 * p0t.y as f64;
-            let p1typ0tx = p1t.y as f64 * p0t.x as f64;
-            e2 = (p1typ0tx - p1txp0ty) as Float;
-        }
-        // perform triangle edge and determinant tests
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
-        }
-        let det: Float = e0 + e1 + e2;
-        if det == 0.0 {
-            return false;
-        }
-        // compute scaled hit distance to triangle and test against ray $t$ range
-        p0t.z *= sz;
-        p1t.z *= sz;
-        p2t.z *= sz;
-        let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det)
-            || det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det)
+        if !self.mesh.is_moving() {
+            return [
+                self.mesh.p[idx[0] as usize],
+                self.mesh.p[idx[1] as usize],
+                self.mesh.p[idx[2] as usize],
+            ];
+        }
+        let (key, t) = self.mesh.motion_key(time);
+        let mut p: [Point3f; 3] = [Point3f::default(); 3];
+        for (pi, i) in p.iter_mut().zip(idx.iter()) {
+            *pi = self.mesh.key_position(key, *i) * (1.0 as Float - t)
+                + self.mesh.key_position(key + 1, *i) * t;
+        }
+        p
+    }
+    /// The vertex normals (in world space) at |time|. Only valid if
+    /// the mesh has normals.
+    pub fn normals(&self, time: Float) -> [Normal3f; 3] {
+        let idx1: usize = (self.id * 3) as usize;
+        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
+        if !self.mesh.is_moving() {
+            return [
+                self.mesh.n[idx[0] as usize],
+                self.mesh.n[idx[1] as usize],
+                self.mesh.n[idx[2] as usize],
+            ];
+        }
+        let (key, t) = self.mesh.motion_key(time);
+        let mut n: [Normal3f; 3] = [Normal3f::default(); 3];
+        for (ni, i) in n.iter_mut().zip(idx.iter()) {
+            *ni = self.mesh.key_normal(key, *i) * (1.0 as Float - t)
+                + self.mesh.key_normal(key + 1, *i) * t;
+        }
+        n
+    }
+    /// Bounds of the triangle (in world space) while it moves from
+    /// |time0| to |time1|. As the vertices move linearly between the
+    /// positions given, the bounds of the positions at both times and
+    /// at all steps in between are exact.
+    pub fn motion_bound(&self, time0: Float, time1: Float) -> Bounds3f {
+        let mut bounds: Bounds3f = Bounds3f::default();
+        for p in self
+            .positions(time0)
+            .iter()
+            .chain(self.positions(time1).iter())
         {
-            return false;
-        }
-        // compute barycentric coordinates and $t$ value for triangle intersection
-        let inv_det: Float = 1.0 / det;
-        let b0: Float = e0 * inv_det;
-        let b1: Float = e1 * inv_det;
-        let b2: Float = e2 * inv_det;
-        let t: Float = t_scaled * inv_det;
-
-        // ensure that computed triangle $t$ is conservatively greater than zero
-
-        // compute $\delta_z$ term for triangle $t$ error bounds
-        let max_zt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.z,
-                y: p1t.z,
-                z: p2t.z,
-            }
-            .abs(),
-        );
-        let delta_z: Float = gamma(3_i32) * max_zt;
-        // compute $\delta_x$ and $\delta_y$ terms for triangle $t$ error bounds
-        let max_xt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.x,
-                y: p1t.x,
-                z: p2t.x,
-            }
-            .abs(),
-        );
-        let max_yt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.y,
-                y: p1t.y,
-                z: p2t.y,
-            }
-            .abs(),
-        );
-        let delta_x: Float = gamma(5) * (max_xt + max_zt);
-        let delta_y: Float = gamma(5) * (max_yt + max_zt);
-        // compute $\delta_e$ term for triangle $t$ error bounds
-        let delta_e: Float =
-            2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
-        // compute $\delta_t$ term for triangle $t$ error bounds and check _t_
-        let max_e: Float = vec3_max_componentf(
-            &Vector3f {
-                x: e0,
-                y: e1,
-                z: e2,
+            bounds = bnd3_union_pnt3f(&bounds, p);
+        }
+        if self.mesh.is_moving() {
+            let idx1: usize = (self.id * 3) as usize;
+            let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
+            let (key0, _t0) = self.mesh.motion_key(time0);
+            let (key1, _t1) = self.mesh.motion_key(time1);
+            for key in (key0 + 1)..=key1 {
+                for i in idx {
+                    bounds = bnd3_union_pnt3f(&bounds, &self.mesh.key_position(key, *i));
+                }
             }
-            .abs(),
-        );
-        let delta_t: Float =
-            3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
-        if t <= delta_t {
-            return false;
         }
+        bounds
+    }
+    /// Bounds of the part of the triangle inside |clip| (both in world
+    /// space), which can be much tighter than the intersection of the
+    /// bounds for long diagonal triangles. Used for spatial splits of
+    /// the BVH. A moving triangle sweeps a volume over its time range,
+    /// for which only the bounds are intersected.
+    pub fn clipped_world_bound(&self, clip: &Bounds3f) -> Bounds3f {
+        if self.is_moving() {
+            return bnd3_intersect_bnd3f(&self.world_bound(), clip);
+        }
+        let poly: ClipPolygon = self.clipped_polygon(clip);
+        bnd3_intersect_bnd3f(&polygon_bounds(&poly), clip)
+    }
+    /// Like **clipped_world_bound()**, but for the parts between
+    /// consecutive |planes| (in increasing order) along |axis|, which
+    /// are stored in |bounds| (one more than planes).
+    pub fn split_world_bounds(
+        &self,
+        clip: &Bounds3f,
+        axis: usize,
+        planes: &[Float],
+        bounds: &mut [Bounds3f],
+    ) {
+        if self.is_moving() {
+            let clipped: Bounds3f = bnd3_intersect_bnd3f(&self.world_bound(), clip);
+            bnd3_split_slabs(&clipped, axis, planes, bounds);
+            return;
+        }
+        let mut poly: ClipPolygon = self.clipped_polygon(clip);
+        for (i, plane) in planes.iter().enumerate() {
+            let mut below: ClipPolygon = poly.clone();
+            clip_polygon(&mut below, axis, *plane, false);
+            clip_polygon(&mut poly, axis, *plane, true);
+            bounds[i] = bnd3_intersect_bnd3f(&polygon_bounds(&below), clip);
+        }
+        bounds[planes.len()] = bnd3_intersect_bnd3f(&polygon_bounds(&poly), clip);
+    }
+    /// The (static) triangle clipped against the six planes of |clip|
+    /// (Sutherland-Hodgman).
+    fn clipped_polygon(&self, clip: &Bounds3f) -> ClipPolygon {
+        let idx1: usize = (self.id * 3) as usize;
+        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
+        let mut poly: ClipPolygon = SmallVec::new();
+        for i in idx {
+            let p: &Point3f = &self.mesh.p[*i as usize];
+            poly.push([p.x, p.y, p.z]);
+        }
+        let lo: [Float; 3] = [clip.p_min.x, clip.p_min.y, clip.p_min.z];
+        let hi: [Float; 3] = [clip.p_max.x, clip.p_max.y, clip.p_max.z];
+        for axis in 0..3 {
+            clip_polygon(&mut poly, axis, lo[axis], true);
+            clip_polygon(&mut poly, axis, hi[axis], false);
+        }
+        poly
+    }
+    pub fn intersect(&self, ray: &Ray, t_hit: &mut Float, isect: &mut SurfaceInteraction) -> bool {
+        // get triangle vertices in _p0_, _p1_, and _p2_
+        let p: [Point3f; 3] = self.positions(ray.time);
+        let p0: &Point3f = &p[0];
+        let p1: &Point3f = &p[1];
+        let p2: &Point3f = &p[2];
+        // watertight ray-triangle test
+        let (t, b): (Float, [Float; 3]) = match intersect_triangle(ray, &p) {
+            Some(hit) => hit,
+            None => return false,
+        };
+        let (b0, b1, b2): (Float, Float, Float) = (b[0], b[1], b[2]);
         // compute triangle partial derivatives
         let uv: [Point2f; 3] = self.get_uvs();
         // compute deltas for triangle partial derivatives
@@ -350,11 +471,13 @@ impl Triangle {
 
             // compute shading normal _ns_ for triangle
             let mut ns: Normal3f;
+            let n: [Normal3f; 3] = if self.mesh.n.is_empty() {
+                [Normal3f::default(); 3]
+            } else {
+                self.normals(ray.time)
+            };
             if !self.mesh.n.is_empty() {
-                let n0 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize] as usize];
-                let n1 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 1] as usize];
-                let n2 = self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                ns = n0 * b0 + n1 * b1 + n2 * b2;
+                ns = n[0] * b0 + n[1] * b1 + n[2] * b2;
                 if ns.length_squared() > 0.0 {
                     ns = ns.normalize();
                 } else {
@@ -393,12 +516,8 @@ impl Triangle {
                 // compute deltas for triangle partial derivatives of normal
                 let duv02: Vector2f = uv[0] - uv[2];
                 let duv12: Vector2f = uv[1] - uv[2];
-                let dn1: Normal3f = self.mesh.n
-                    [self.mesh.vertex_indices[(self.id * 3) as usize] as usize]
-                    - self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
-                let dn2: Normal3f = self.mesh.n
-                    [self.mesh.vertex_indices[(self.id * 3) as usize + 1] as usize]
-                    - self.mesh.n[self.mesh.vertex_indices[(self.id * 3) as usize + 2] as usize];
+                let dn1: Normal3f = n[0] - n[2];
+                let dn2: Normal3f = n[1] - n[2];
                 let determinant: Float = duv02.x * duv12.y - duv02.y * duv12.x;
                 let degenerate_uv: bool = determinant.abs() < 1e-8;
                 if degenerate_uv {
@@ -444,6 +563,11 @@ impl Triangle {
         isect.bsdf = None;
         // isect.bssrdf = None;
         isect.shape = None;
+        isect.face_index = if self.mesh.face_indices.is_empty() {
+            0
+        } else {
+            self.mesh.face_indices[self.id as usize]
+        };
         *t_hit = t;
         true
     }
@@ -451,144 +575,16 @@ impl Triangle {
         // TODO: ProfilePhase p(Prof::TriIntersectP);
         // TODO: ++nTests;
         // get triangle vertices in _p0_, _p1_, and _p2_
-        let idx1: usize = (self.id * 3) as usize;
-        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
-        let p0: &Point3f = &self.mesh.p[idx[0] as usize];
-        let p1: &Point3f = &self.mesh.p[idx[1] as usize];
-        let p2: &Point3f = &self.mesh.p[idx[2] as usize];
-        // translate vertices based on ray origin
-        let mut p0t: Point3f = *p0
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p1t: Point3f = *p1
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        let mut p2t: Point3f = *p2
-            - Vector3f {
-                x: ray.o.x,
-                y: ray.o.y,
-                z: ray.o.z,
-            };
-        // permute components of triangle vertices and ray direction
-        let kz: usize = vec3_max_dimensionf(&ray.d.abs());
-        let mut kx: usize = kz + 1;
-        if kx == 3 {
-            kx = 0;
-        }
-        let mut ky: usize = kx + 1;
-        if ky == 3 {
-            ky = 0;
-        }
-        let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
-        p0t = pnt3_permutef(&p0t, kx, ky, kz);
-        p1t = pnt3_permutef(&p1t, kx, ky, kz);
-        p2t = pnt3_permutef(&p2t, kx, ky, kz);
-        // apply shear transformation to translated vertex positions
-        let sx: Float = -d.x / d.z;
-        let sy: Float = -d.y / d.z;
-        let sz: Float = 1.0 / d.z;
-        p0t.x += sx * p0t.z;
-        p0t.y += sy * p0t.z;
-        p1t.x += sx * p1t.z;
-        p1t.y += sy * p1t.z;
-        p2t.x += sx * p2t.z;
-        p2t.y += sy * p2t.z;
-        // compute edge function coefficients _e0_, _e1_, and _e2_
-        let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
-        let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
-        let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
-        // fall back to double precision test at triangle edges
-        if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0)
-        {
-            let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
-            let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
-            e0 = (p2typ1tx - p2txp1ty) as Float;
-            let p0txp2ty = p0t.x as f64 * p2t.y as f64;
-            let p0typ2tx = p0t.y as f64 * p2t.x as f64;
-            e1 = (p0typ2tx - p0txp2ty) as Float;
-            let p1txp0ty = p1t.x as f64 * p0t.y as f64;
-            let p1typ0tx = p1t.y as f64 * p0t.x as f64;
-            e2 = (p1typ0tx - p1txp0ty) as Float;
-        }
-        // perform triangle edge and determinant tests
-        if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
-            return false;
-        }
-        let det: Float = e0 + e1 + e2;
-        if det == 0.0 {
-            return false;
-        }
-        // compute scaled hit distance to triangle and test against ray $t$ range
-        p0t.z *= sz;
-        p1t.z *= sz;
-        p2t.z *= sz;
-        let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
-        if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det)
-            || det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det)
-        {
-            return false;
-        }
-        // compute barycentric coordinates and $t$ value for triangle intersection
-        let inv_det: Float = 1.0 / det;
-        let b0: Float = e0 * inv_det;
-        let b1: Float = e1 * inv_det;
-        let b2: Float = e2 * inv_det;
-        let t: Float = t_scaled * inv_det;
-
-        // ensure that computed triangle $t$ is conservatively greater than zero
-
-        // compute $\delta_z$ term for triangle $t$ error bounds
-        let max_zt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.z,
-                y: p1t.z,
-                z: p2t.z,
-            }
-            .abs(),
-        );
-        let delta_z: Float = gamma(3_i32) * max_zt;
-        // compute $\delta_x$ and $\delta_y$ terms for triangle $t$ error bounds
-        let max_xt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.x,
-                y: p1t.x,
-                z: p2t.x,
-            }
-            .abs(),
-        );
-        let max_yt: Float = vec3_max_componentf(
-            &Vector3f {
-                x: p0t.y,
-                y: p1t.y,
-                z: p2t.y,
-            }
-            .abs(),
-        );
-        let delta_x: Float = gamma(5) * (max_xt + max_zt);
-        let delta_y: Float = gamma(5) * (max_yt + max_zt);
-        // compute $\delta_e$ term for triangle $t$ error bounds
-        let delta_e: Float =
-            2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
-        // compute $\delta_t$ term for triangle $t$ error bounds and check _t_
-        let max_e: Float = vec3_max_componentf(
-            &Vector3f {
-                x: e0,
-                y: e1,
-                z: e2,
-            }
-            .abs(),
-        );
-        let delta_t: Float =
-            3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
-        if t <= delta_t {
-            return false;
-        }
+        let p: [Point3f; 3] = self.positions(ray.time);
+        let p0: &Point3f = &p[0];
+        let p1: &Point3f = &p[1];
+        let p2: &Point3f = &p[2];
+        // watertight ray-triangle test
+        let b: [Float; 3] = match intersect_triangle(ray, &p) {
+            Some((_t, b)) => b,
+            None => return false,
+        };
+        let (b0, b1, b2): (Float, Float, Float) = (b[0], b[1], b[2]);
         // TODO: if (testAlphaTexture && (mesh->alphaMask || mesh->shadowAlphaMask)) { ... }
         if self.mesh.alpha_mask.is_some() || self.mesh.shadow_alpha_mask.is_some() {
             // compute triangle partial derivatives
@@ -664,6 +660,39 @@ impl Triangle {
     pub fn get_object_to_world(&self) -> Transform {
         self.mesh.object_to_world
     }
+    /// Returns the geometric normal which sample() reports for all
+    /// points on the triangle, or None if interpolated shading
+    /// normals flip it somewhere. Like area() and sample() it uses
+    /// the vertices of *p*, area lights are never created for
+    /// deforming meshes.
+    pub fn get_normal(&self) -> Option<Normal3f> {
+        let idx1: usize = (self.id * 3) as usize;
+        let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
+        let p0: &Point3f = &self.mesh.p[idx[0] as usize];
+        let p1: &Point3f = &self.mesh.p[idx[1] as usize];
+        let p2: &Point3f = &self.mesh.p[idx[2] as usize];
+        let n: Normal3f = Normal3f::from(vec3_cross_vec3(&(*p1 - *p0), &(*p2 - *p0)));
+        if n.length_squared() == 0.0 as Float {
+            return None;
+        }
+        let mut n: Normal3f = n.normalize();
+        if !self.mesh.n.is_empty() {
+            // same as nrm_faceforward_nrm() in sample(), but for all
+            // interpolated shading normals
+            let d: Vec<Float> = idx
+                .iter()
+                .map(|i| nrm_dot_nrmf(&n, &self.mesh.n[*i as usize]))
+                .collect();
+            if d.iter().all(|d| *d < 0.0 as Float) {
+                n *= -1.0 as Float;
+            } else if !d.iter().all(|d| *d > 0.0 as Float) {
+                return None;
+            }
+        } else if self.mesh.reverse_orientation ^ self.mesh.transform_swaps_handedness {
+            n *= -1.0 as Float;
+        }
+        Some(n)
+    }
     pub fn area(&self) -> Float {
         let idx1: usize = (self.id * 3) as usize;
         let idx = &self.mesh.vertex_indices[idx1..(idx1 + 3)];
@@ -763,3 +792,127 @@ impl Triangle {
         }
     }
 }
+
+/// Watertight ray-triangle intersection test for the vertices |p|,
+/// used by *Triangle::intersect()* and for triangles which are not
+/// stored in a **TriangleMesh**. Returns the parametric distance and
+/// the barycentric coordinates of the hit point.
+pub fn intersect_triangle(ray: &Ray, p: &[Point3f; 3]) -> Option<(Float, [Float; 3])> {
+    let (p0, p1, p2): (&Point3f, &Point3f, &Point3f) = (&p[0], &p[1], &p[2]);
+    // translate vertices based on ray origin
+    let o: Vector3f = Vector3f {
+        x: ray.o.x,
+        y: ray.o.y,
+        z: ray.o.z,
+    };
+    let mut p0t: Point3f = *p0 - o;
+    let mut p1t: Point3f = *p1 - o;
+    let mut p2t: Point3f = *p2 - o;
+    // permute components of triangle vertices and ray direction
+    let kz: usize = vec3_max_dimensionf(&ray.d.abs());
+    let mut kx: usize = kz + 1;
+    if kx == 3 {
+        kx = 0;
+    }
+    let mut ky: usize = kx + 1;
+    if ky == 3 {
+        ky = 0;
+    }
+    let d: Vector3f = vec3_permutef(&ray.d, kx, ky, kz);
+    p0t = pnt3_permutef(&p0t, kx, ky, kz);
+    p1t = pnt3_permutef(&p1t, kx, ky, kz);
+    p2t = pnt3_permutef(&p2t, kx, ky, kz);
+    // apply shear transformation to translated vertex positions
+    let sx: Float = -d.x / d.z;
+    let sy: Float = -d.y / d.z;
+    let sz: Float = 1.0 / d.z;
+    p0t.x += sx * p0t.z;
+    p0t.y += sy * p0t.z;
+    p1t.x += sx * p1t.z;
+    p1t.y += sy * p1t.z;
+    p2t.x += sx * p2t.z;
+    p2t.y += sy * p2t.z;
+    // compute edge function coefficients _e0_, _e1_, and _e2_
+    let mut e0: Float = p1t.x * p2t.y - p1t.y * p2t.x;
+    let mut e1: Float = p2t.x * p0t.y - p2t.y * p0t.x;
+    let mut e2: Float = p0t.x * p1t.y - p0t.y * p1t.x;
+    // fall back to double precision test at triangle edges
+    if mem::size_of::<Float>() == mem::size_of::<f32>() && (e0 == 0.0 || e1 == 0.0 || e2 == 0.0) {
+        let p2txp1ty: f64 = p2t.x as f64 * p1t.y as f64;
+        let p2typ1tx: f64 = p2t.y as f64 * p1t.x as f64;
+        e0 = (p2typ1tx - p2txp1ty) as Float;
+        let p0txp2ty = p0t.x as f64 * p2t.y as f64;
+        let p0typ2tx = p0t.y as f64 * p2t.x as f64;
+        e1 = (p0typ2tx - p0txp2ty) as Float;
+        let p1txp0ty = p1t.x as f64 * p0t.y as f64;
+        let p1typ0tx = p1t.y as f64 * p0t.x as f64;
+        e2 = (p1typ0tx - p1txp0ty) as Float;
+    }
+    // perform triangle edge and determinant tests
+    if (e0 < 0.0 || e1 < 0.0 || e2 < 0.0) && (e0 > 0.0 || e1 > 0.0 || e2 > 0.0) {
+        return None;
+    }
+    let det: Float = e0 + e1 + e2;
+    if det == 0.0 {
+        return None;
+    }
+    // compute scaled hit distance to triangle and test against ray $t$ range
+    p0t.z *= sz;
+    p1t.z *= sz;
+    p2t.z *= sz;
+    let t_scaled: Float = e0 * p0t.z + e1 * p1t.z + e2 * p2t.z;
+    if det < 0.0 && (t_scaled >= 0.0 || t_scaled < ray.t_max.get() * det)
+        || det > 0.0 && (t_scaled <= 0.0 || t_scaled > ray.t_max.get() * det)
+    {
+        return None;
+    }
+    // compute barycentric coordinates and $t$ value for triangle intersection
+    let inv_det: Float = 1.0 / det;
+    let b0: Float = e0 * inv_det;
+    let b1: Float = e1 * inv_det;
+    let b2: Float = e2 * inv_det;
+    let t: Float = t_scaled * inv_det;
+    // ensure that computed triangle $t$ is conservatively greater than zero
+    let max_zt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.z,
+            y: p1t.z,
+            z: p2t.z,
+        }
+        .abs(),
+    );
+    let delta_z: Float = gamma(3_i32) * max_zt;
+    let max_xt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.x,
+            y: p1t.x,
+            z: p2t.x,
+        }
+        .abs(),
+    );
+    let max_yt: Float = vec3_max_componentf(
+        &Vector3f {
+            x: p0t.y,
+            y: p1t.y,
+            z: p2t.y,
+        }
+        .abs(),
+    );
+    let delta_x: Float = gamma(5) * (max_xt + max_zt);
+    let delta_y: Float = gamma(5) * (max_yt + max_zt);
+    let delta_e: Float = 2.0 * (gamma(2) * max_xt * max_yt + delta_y * max_xt + delta_x * max_yt);
+    let max_e: Float = vec3_max_componentf(
+        &Vector3f {
+            x: e0,
+            y: e1,
+            z: e2,
+        }
+        .abs(),
+    );
+    let delta_t: Float =
+        3.0 * (gamma(3) * max_e * max_zt + delta_e * max_zt + delta_z * max_e) * inv_det.abs();
+    if t <= delta_t {
+        return None;
+    }
+    Some((t, [b0, b1, b2]))
+}
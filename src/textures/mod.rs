@@ -34,6 +34,15 @@
 //!
 //! ![MarbleTexture](/doc/img/marble_pbrt_rust.png)
 //!
+//! ## PtexTexture
+//!
+//! Ptex files store a separate texture per face of a mesh, so no
+//! (u,v) parameterization of the whole mesh is needed. The texture is
+//! looked up by the face index of the **SurfaceInteraction**, which
+//! for triangle meshes comes from the *faceIndices* parameter (or the
+//! *face_indices* property of PLY files), and the (u,v) coordinates
+//! within that face.
+//!
 //! ## WindyTexture
 //!
 //! ![WindyTexture](/doc/img/windy_pbrt_rust.png)
@@ -49,6 +58,7 @@ pub mod fbm;
 pub mod imagemap;
 pub mod marble;
 pub mod mix;
+pub mod ptex;
 pub mod scale;
 pub mod windy;
 pub mod wrinkled;
@@ -0,0 +1,560 @@
+// std
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::marker::PhantomData;
+// others
+use byteorder::{LittleEndian, ReadBytesExt};
+use flate2::read::ZlibDecoder;
+// pbrt
+use crate::core::interaction::SurfaceInteraction;
+use crate::core::pbrt::{clamp_t, Float, Spectrum};
+use crate::core::texture::Texture;
+
+// see ptex.h
+
+const PTEX_MAGIC: u32 = 0x7865_7450; // "Ptex"
+const PTEX_HEADER_SIZE: u64 = 64;
+const PTEX_FACE_INFO_SIZE: usize = 20;
+const PTEX_LEVEL_INFO_SIZE: usize = 16;
+const PTEX_FLAG_CONSTANT: u8 = 1;
+// face and tile resolutions are stored as powers of two
+const PTEX_MAX_RES_LOG2: u8 = 15;
+
+/// The mesh type a Ptex file was painted on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PtexMeshType {
+    Triangle,
+    Quad,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PtexDataType {
+    Uint8,
+    Uint16,
+    Half,
+    Float,
+}
+
+impl PtexDataType {
+    fn size(self) -> usize {
+        match self {
+            PtexDataType::Uint8 => 1,
+            PtexDataType::Uint16 => 2,
+            PtexDataType::Half => 2,
+            PtexDataType::Float => 4,
+        }
+    }
+    fn to_float(self, bytes: &[u8]) -> Float {
+        match self {
+            PtexDataType::Uint8 => bytes[0] as Float / 255.0 as Float,
+            PtexDataType::Uint16 => {
+                u16::from_le_bytes([bytes[0], bytes[1]]) as Float / 65535.0 as Float
+            }
+            PtexDataType::Half => half_to_float(u16::from_le_bytes([bytes[0], bytes[1]])),
+            PtexDataType::Float => f32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+        }
+    }
+}
+
+/// Encoding of a block of face (or tile) data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum PtexEncoding {
+    Constant,
+    Zipped,
+    DiffZipped,
+    Tiled,
+}
+
+/// Packed 32-bit header of a face or tile data block: the lower 30
+/// bits hold the block size, the upper two bits the encoding.
+#[derive(Debug, Clone, Copy)]
+struct FaceDataHeader {
+    block_size: usize,
+    encoding: PtexEncoding,
+}
+
+impl FaceDataHeader {
+    fn from_u32(data: u32) -> Self {
+        FaceDataHeader {
+            block_size: (data & 0x3fff_ffff) as usize,
+            encoding: match data >> 30 {
+                0 => PtexEncoding::Constant,
+                1 => PtexEncoding::Zipped,
+                2 => PtexEncoding::DiffZipped,
+                _ => PtexEncoding::Tiled,
+            },
+        }
+    }
+}
+
+/// The full resolution texels of a single face.
+#[derive(Debug, Clone, Default)]
+pub struct PtexFace {
+    pub res_u: usize,
+    pub res_v: usize,
+    /// *res_u* x *res_v* texels with *n_channels* values each, row by row
+    pub data: Vec<Float>,
+}
+
+/// The highest resolution level of a Ptex file, decoded into memory.
+/// Reduction levels, meta data and edits are ignored.
+#[derive(Debug, Clone)]
+pub struct PtexFile {
+    pub mesh_type: PtexMeshType,
+    pub n_channels: usize,
+    pub alpha_channel: i32,
+    pub faces: Vec<PtexFace>,
+}
+
+impl PtexFile {
+    pub fn read(filename: &str) -> std::io::Result<PtexFile> {
+        let mut bytes: Vec<u8> = Vec::new();
+        File::open(filename)?.read_to_end(&mut bytes)?;
+        PtexFile::from_bytes(&bytes)
+    }
+    pub fn from_bytes(bytes: &[u8]) -> std::io::Result<PtexFile> {
+        let mut cursor: Cursor<&[u8]> = Cursor::new(bytes);
+        // header
+        let magic: u32 = cursor.read_u32::<LittleEndian>()?;
+        if magic != PTEX_MAGIC {
+            return Err(invalid_data("not a ptex file"));
+        }
+        let version: u32 = cursor.read_u32::<LittleEndian>()?;
+        if version != 1 {
+            return Err(invalid_data(&format!(
+                "unsupported ptex version {}",
+                version
+            )));
+        }
+        let mesh_type: PtexMeshType = match cursor.read_u32::<LittleEndian>()? {
+            0 => PtexMeshType::Triangle,
+            _ => PtexMeshType::Quad,
+        };
+        let data_type: PtexDataType = match cursor.read_u32::<LittleEndian>()? {
+            0 => PtexDataType::Uint8,
+            1 => PtexDataType::Uint16,
+            2 => PtexDataType::Half,
+            3 => PtexDataType::Float,
+            dt => return Err(invalid_data(&format!("unknown ptex data type {}", dt))),
+        };
+        let alpha_channel: i32 = cursor.read_i32::<LittleEndian>()?;
+        let n_channels: usize = cursor.read_u16::<LittleEndian>()? as usize;
+        let n_levels: usize = cursor.read_u16::<LittleEndian>()? as usize;
+        let n_faces: usize = cursor.read_u32::<LittleEndian>()? as usize;
+        let ext_header_size: u64 = cursor.read_u32::<LittleEndian>()? as u64;
+        let face_info_size: usize = cursor.read_u32::<LittleEndian>()? as usize;
+        let const_data_size: usize = cursor.read_u32::<LittleEndian>()? as usize;
+        let level_info_size: usize = cursor.read_u32::<LittleEndian>()? as usize;
+        if n_channels == 0 || n_levels == 0 {
+            return Err(invalid_data("ptex file without channels or levels"));
+        }
+        let pixel_size: usize = n_channels * data_type.size();
+        // face info
+        cursor.seek(SeekFrom::Start(PTEX_HEADER_SIZE + ext_header_size))?;
+        let face_info: Vec<u8> =
+            read_zip_block(&mut cursor, face_info_size, n_faces * PTEX_FACE_INFO_SIZE)?;
+        // constant data (one texel per face)
+        let const_data: Vec<u8> =
+            read_zip_block(&mut cursor, const_data_size, n_faces * pixel_size)?;
+        // level info
+        if level_info_size < n_levels * PTEX_LEVEL_INFO_SIZE {
+            return Err(invalid_data("truncated ptex level info"));
+        }
+        let level_info: Vec<u8> = read_raw_block(&mut cursor, level_info_size)?;
+        let level_header_size: usize =
+            u32::from_le_bytes([level_info[8], level_info[9], level_info[10], level_info[11]])
+                as usize;
+        let level_n_faces: usize = u32::from_le_bytes([
+            level_info[12],
+            level_info[13],
+            level_info[14],
+            level_info[15],
+        ]) as usize;
+        // level 0 holds all faces at full resolution
+        let face_data_headers: Vec<FaceDataHeader> =
+            read_zip_block(&mut cursor, level_header_size, level_n_faces * 4)?
+                .chunks_exact(4)
+                .map(|c| FaceDataHeader::from_u32(u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+                .collect();
+        let mut faces: Vec<PtexFace> = Vec::new();
+        let mut block_start: u64 = cursor.position();
+        for (face_id, info) in face_info[..n_faces * PTEX_FACE_INFO_SIZE]
+            .chunks_exact(PTEX_FACE_INFO_SIZE)
+            .enumerate()
+        {
+            let res_u: usize = res_from_log2(info[0])?;
+            let res_v: usize = res_from_log2(info[1])?;
+            if mesh_type == PtexMeshType::Triangle && res_u != res_v {
+                return Err(invalid_data("non-square ptex triangle face"));
+            }
+            let flags: u8 = info[3];
+            let header: Option<&FaceDataHeader> = face_data_headers.get(face_id);
+            if flags & PTEX_FLAG_CONSTANT != 0 || header.is_none() {
+                let texel: &[u8] = &const_data[face_id * pixel_size..(face_id + 1) * pixel_size];
+                faces.push(PtexFace {
+                    res_u: 1,
+                    res_v: 1,
+                    data: read_texel(texel, n_channels, data_type)?,
+                });
+                if let Some(header) = header {
+                    block_start += header.block_size as u64;
+                }
+                continue;
+            }
+            let header: FaceDataHeader = *header.unwrap();
+            let block: &[u8] = bytes
+                .get(block_start as usize..block_start as usize + header.block_size)
+                .ok_or_else(|| invalid_data("truncated ptex face data"))?;
+            block_start += header.block_size as u64;
+            let n_values: usize = res_u
+                .checked_mul(res_v)
+                .and_then(|n| n.checked_mul(n_channels))
+                .ok_or_else(|| invalid_data("ptex face too large"))?;
+            let mut data: Vec<Float> = vec![0.0 as Float; n_values];
+            if header.encoding == PtexEncoding::Tiled {
+                decode_tiled(block, res_u, res_v, n_channels, data_type, &mut data)?;
+            } else {
+                decode_block(
+                    block,
+                    header.encoding,
+                    res_u,
+                    res_v,
+                    n_channels,
+                    data_type,
+                    &mut data,
+                    res_u,
+                )?;
+            }
+            faces.push(PtexFace { res_u, res_v, data });
+        }
+        Ok(PtexFile {
+            mesh_type,
+            n_channels,
+            alpha_channel,
+            faces,
+        })
+    }
+    fn texel(&self, face: &PtexFace, u: usize, v: usize, channel: usize) -> Float {
+        face.data[(v * face.res_u + u) * self.n_channels + channel]
+    }
+    /// Look up the first (up to) three channels of face *face_id* at
+    /// the face's local coordinates *(u, v)*. Quad faces are filtered
+    /// bilinearly (clamped at the face's edges), triangle faces use
+    /// the nearest texel. Returns *false* for unknown faces.
+    pub fn lookup(&self, face_id: i32, u: Float, v: Float, result: &mut [Float; 3]) -> bool {
+        let face: &PtexFace = match self.faces.get(face_id.max(0) as usize) {
+            Some(face) if face_id >= 0 => face,
+            _ => return false,
+        };
+        let nc: usize = self.n_channels.min(3);
+        if face.res_u == 1 && face.res_v == 1 {
+            for (c, value) in result.iter_mut().enumerate().take(nc) {
+                *value = face.data[c];
+            }
+        } else if self.mesh_type == PtexMeshType::Triangle {
+            // a triangle face of resolution n is stored in an n x n
+            // square: the upright texels occupy the lower left half,
+            // the inverted ones are rotated by 180 degrees into the
+            // upper right half
+            let res: usize = face.res_u;
+            let su: Float = clamp_t(u, 0.0 as Float, 1.0 as Float) * res as Float;
+            let sv: Float = clamp_t(v, 0.0 as Float, 1.0 as Float) * res as Float;
+            let mut ui: usize = (su as usize).min(res - 1);
+            let mut vi: usize = (sv as usize).min(res - 1);
+            if ui + vi >= res {
+                // outside of the triangle, clamp to the diagonal
+                let excess: usize = ui + vi - (res - 1);
+                ui -= excess.min(ui);
+                vi = res - 1 - ui;
+            }
+            let inverted: bool =
+                (su - ui as Float) + (sv - vi as Float) > 1.0 as Float && ui + vi + 1 < res;
+            let (tu, tv) = if inverted {
+                (res - 1 - vi, res - 1 - ui)
+            } else {
+                (ui, vi)
+            };
+            for (c, value) in result.iter_mut().enumerate().take(nc) {
+                *value = self.texel(face, tu, tv, c);
+            }
+        } else {
+            let su: Float = u * face.res_u as Float - 0.5 as Float;
+            let sv: Float = v * face.res_v as Float - 0.5 as Float;
+            let u0: i64 = su.floor() as i64;
+            let v0: i64 = sv.floor() as i64;
+            let du: Float = su - u0 as Float;
+            let dv: Float = sv - v0 as Float;
+            let cu = |x: i64| clamp_t(x, 0, face.res_u as i64 - 1) as usize;
+            let cv = |y: i64| clamp_t(y, 0, face.res_v as i64 - 1) as usize;
+            for (c, value) in result.iter_mut().enumerate().take(nc) {
+                *value =
+                    (1.0 as Float - du) * (1.0 as Float - dv) * self.texel(face, cu(u0), cv(v0), c)
+                        + du * (1.0 as Float - dv) * self.texel(face, cu(u0 + 1), cv(v0), c)
+                        + (1.0 as Float - du) * dv * self.texel(face, cu(u0), cv(v0 + 1), c)
+                        + du * dv * self.texel(face, cu(u0 + 1), cv(v0 + 1), c);
+            }
+        }
+        if nc == 1 {
+            result[1] = result[0];
+            result[2] = result[0];
+        }
+        true
+    }
+}
+
+fn invalid_data(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg.to_string())
+}
+
+/// Reads |size| bytes, which have to be left in the file.
+fn read_raw_block(cursor: &mut Cursor<&[u8]>, size: usize) -> std::io::Result<Vec<u8>> {
+    let remaining: u64 = (cursor.get_ref().len() as u64).saturating_sub(cursor.position());
+    if size as u64 > remaining {
+        return Err(invalid_data("truncated ptex file"));
+    }
+    let mut block: Vec<u8> = vec![0_u8; size];
+    cursor.read_exact(&mut block)?;
+    Ok(block)
+}
+
+fn read_zip_block(
+    cursor: &mut Cursor<&[u8]>,
+    zip_size: usize,
+    mem_size: usize,
+) -> std::io::Result<Vec<u8>> {
+    let zipped: Vec<u8> = read_raw_block(cursor, zip_size)?;
+    inflate(&zipped, mem_size)
+}
+
+/// Inflates |zipped|, which has to hold at least |mem_size| bytes.
+fn inflate(zipped: &[u8], mem_size: usize) -> std::io::Result<Vec<u8>> {
+    // the expected size comes from the file, don't trust it for
+    // allocating memory
+    let mut data: Vec<u8> = Vec::new();
+    ZlibDecoder::new(zipped).read_to_end(&mut data)?;
+    if data.len() < mem_size {
+        return Err(invalid_data("truncated ptex zip block"));
+    }
+    Ok(data)
+}
+
+/// Decode a (non-tiled) block of *res_u* x *res_v* texels into *out*,
+/// which has a row stride of *stride* texels.
+#[allow(clippy::too_many_arguments)]
+fn decode_block(
+    block: &[u8],
+    encoding: PtexEncoding,
+    res_u: usize,
+    res_v: usize,
+    n_channels: usize,
+    data_type: PtexDataType,
+    out: &mut [Float],
+    stride: usize,
+) -> std::io::Result<()> {
+    let size: usize = data_type.size();
+    if res_u == 0 || res_v == 0 || out.len() < ((res_v - 1) * stride + res_u) * n_channels {
+        return Err(invalid_data("ptex block exceeds its face"));
+    }
+    match encoding {
+        PtexEncoding::Constant => {
+            let texel: Vec<Float> = read_texel(block, n_channels, data_type)?;
+            for v in 0..res_v {
+                for u in 0..res_u {
+                    let start: usize = (v * stride + u) * n_channels;
+                    out[start..start + n_channels].copy_from_slice(&texel);
+                }
+            }
+        }
+        PtexEncoding::Zipped | PtexEncoding::DiffZipped => {
+            let mut planar: Vec<u8> = inflate(block, res_u * res_v * n_channels * size)?;
+            if encoding == PtexEncoding::DiffZipped {
+                decode_difference(&mut planar, data_type);
+            }
+            // channels are stored one after another
+            let plane: usize = res_u * res_v;
+            for c in 0..n_channels {
+                for v in 0..res_v {
+                    for u in 0..res_u {
+                        let src: usize = (c * plane + v * res_u + u) * size;
+                        out[(v * stride + u) * n_channels + c] =
+                            data_type.to_float(&planar[src..src + size]);
+                    }
+                }
+            }
+        }
+        PtexEncoding::Tiled => return Err(invalid_data("nested ptex tiles")),
+    }
+    Ok(())
+}
+
+fn decode_tiled(
+    block: &[u8],
+    res_u: usize,
+    res_v: usize,
+    n_channels: usize,
+    data_type: PtexDataType,
+    out: &mut [Float],
+) -> std::io::Result<()> {
+    let mut cursor: Cursor<&[u8]> = Cursor::new(block);
+    let tile_u: usize = res_from_log2(cursor.read_u8()?)?;
+    let tile_v: usize = res_from_log2(cursor.read_u8()?)?;
+    let tile_header_size: usize = cursor.read_u32::<LittleEndian>()? as usize;
+    let n_tiles_u: usize = (res_u / tile_u).max(1);
+    let n_tiles_v: usize = (res_v / tile_v).max(1);
+    let tile_headers: Vec<FaceDataHeader> =
+        read_zip_block(&mut cursor, tile_header_size, n_tiles_u * n_tiles_v * 4)?
+            .chunks_exact(4)
+            .take(n_tiles_u * n_tiles_v)
+            .map(|c| FaceDataHeader::from_u32(u32::from_le_bytes([c[0], c[1], c[2], c[3]])))
+            .collect();
+    let mut tile_start: usize = cursor.position() as usize;
+    for (tile, header) in tile_headers.iter().enumerate() {
+        let tu: usize = tile % n_tiles_u;
+        let tv: usize = tile / n_tiles_u;
+        let tile_block: &[u8] = block
+            .get(tile_start..tile_start + header.block_size)
+            .ok_or_else(|| invalid_data("truncated ptex tile data"))?;
+        tile_start += header.block_size;
+        let offset: usize = (tv * tile_v * res_u + tu * tile_u) * n_channels;
+        let tile_out: &mut [Float] = out
+            .get_mut(offset..)
+            .ok_or_else(|| invalid_data("ptex tile exceeds its face"))?;
+        decode_block(
+            tile_block,
+            header.encoding,
+            tile_u.min(res_u),
+            tile_v.min(res_v),
+            n_channels,
+            data_type,
+            tile_out,
+            res_u,
+        )?;
+    }
+    Ok(())
+}
+
+/// Resolution stored as its base 2 logarithm.
+fn res_from_log2(log2: u8) -> std::io::Result<usize> {
+    if log2 > PTEX_MAX_RES_LOG2 {
+        return Err(invalid_data(&format!(
+            "ptex resolution 2^{} too large",
+            log2
+        )));
+    }
+    Ok(1_usize << log2)
+}
+
+/// Converts the |n_channels| values of a single texel.
+fn read_texel(
+    bytes: &[u8],
+    n_channels: usize,
+    data_type: PtexDataType,
+) -> std::io::Result<Vec<Float>> {
+    let size: usize = data_type.size();
+    if bytes.len() < n_channels * size {
+        return Err(invalid_data("truncated ptex texel"));
+    }
+    Ok((0..n_channels)
+        .map(|c| data_type.to_float(&bytes[c * size..]))
+        .collect())
+}
+
+/// Undo the difference encoding of integer data (see
+/// PtexUtils::decodeDifference()).
+fn decode_difference(data: &mut [u8], data_type: PtexDataType) {
+    match data_type {
+        PtexDataType::Uint8 => {
+            let mut prev: u8 = 0;
+            for value in data.iter_mut() {
+                prev = value.wrapping_add(prev);
+                *value = prev;
+            }
+        }
+        PtexDataType::Uint16 => {
+            let mut prev: u16 = 0;
+            for chunk in data.chunks_exact_mut(2) {
+                prev = u16::from_le_bytes([chunk[0], chunk[1]]).wrapping_add(prev);
+                chunk.copy_from_slice(&prev.to_le_bytes());
+            }
+        }
+        _ => {}
+    }
+}
+
+fn half_to_float(h: u16) -> Float {
+    let sign: Float = if h & 0x8000 != 0 { -1.0 } else { 1.0 };
+    let exponent: i32 = ((h >> 10) & 0x1f) as i32;
+    let mantissa: Float = (h & 0x3ff) as Float;
+    match exponent {
+        0 => sign * mantissa * (2.0 as Float).powi(-24),
+        31 => {
+            if mantissa == 0.0 as Float {
+                sign * Float::INFINITY
+            } else {
+                Float::NAN
+            }
+        }
+        _ => {
+            sign * (1.0 as Float + mantissa / 1024.0 as Float) * (2.0 as Float).powi(exponent - 15)
+        }
+    }
+}
+
+pub struct PtexTexture<T> {
+    pub filename: String,
+    pub gamma: Float,
+    pub ptex: Option<PtexFile>,
+    phantom: PhantomData<T>,
+}
+
+impl<T> PtexTexture<T> {
+    pub fn new(filename: String, gamma: Float) -> Self {
+        let ptex: Option<PtexFile> = match PtexFile::read(&filename) {
+            Ok(ptex) => Some(ptex),
+            Err(e) => {
+                println!("ERROR: Unable to open Ptex file {:?}: {}", filename, e);
+                None
+            }
+        };
+        PtexTexture {
+            filename,
+            gamma,
+            ptex,
+            phantom: PhantomData,
+        }
+    }
+    /// RGB value of the face hit by *si*, black if the file couldn't
+    /// be read.
+    fn lookup(&self, si: &SurfaceInteraction) -> [Float; 3] {
+        let mut result: [Float; 3] = [0.0 as Float; 3];
+        if let Some(ref ptex) = self.ptex {
+            if ptex.lookup(si.face_index, si.uv.x, si.uv.y, &mut result)
+                && self.gamma != 1.0 as Float
+            {
+                for value in result.iter_mut() {
+                    if *value >= 0.0 as Float && *value <= 1.0 as Float {
+                        *value = value.powf(self.gamma);
+                    }
+                }
+            }
+        }
+        result
+    }
+}
+
+impl Texture<Float> for PtexTexture<Float> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Float {
+        let result: [Float; 3] = self.lookup(si);
+        match self.ptex {
+            Some(ref ptex) if ptex.n_channels == 1 => result[0],
+            _ => (result[0] + result[1] + result[2]) / 3.0 as Float,
+        }
+    }
+}
+
+impl Texture<Spectrum> for PtexTexture<Spectrum> {
+    fn evaluate(&self, si: &SurfaceInteraction) -> Spectrum {
+        let result: [Float; 3] = self.lookup(si);
+        Spectrum::from_rgb(&result)
+    }
+}